@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=proto");
+
+    // protoc is not required: protox parses the definitions in pure Rust.
+    #[cfg(feature = "grpc")]
+    {
+        let descriptors = protox::compile(["proto/engine.proto"], ["proto"])
+            .expect("proto/engine.proto should compile");
+        tonic_prost_build::configure()
+            .compile_fds(descriptors)
+            .expect("gRPC code generation should succeed");
+    }
+}
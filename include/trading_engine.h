@@ -0,0 +1,90 @@
+/*
+ * C API of the trading engine. Build the library with
+ * `cargo build --release --features ffi` and link against
+ * target/release/librust_trading_engine.so (or .dylib / .dll).
+ *
+ * Prices and sizes are passed and returned as decimal strings so no
+ * precision is lost. Strings passed in are only borrowed for the call.
+ * Functions return TE_OK or one of the TE_ERR_* codes; te_last_error
+ * describes the failure.
+ */
+#ifndef TRADING_ENGINE_H
+#define TRADING_ENGINE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TE_OK 0
+#define TE_ERR_NULL (-1)
+#define TE_ERR_INVALID_ARGUMENT (-2)
+#define TE_ERR_ENGINE (-3)
+
+#define TE_SIDE_BUY 0
+#define TE_SIDE_SELL 1
+
+/* Room for any decimal as a NUL-terminated string. */
+#define TE_DECIMAL_LEN 40
+
+enum te_status {
+    TE_STATUS_NEW = 0,
+    TE_STATUS_PARTIALLY_FILLED = 1,
+    TE_STATUS_FILLED = 2,
+    TE_STATUS_CANCELLED = 3,
+    TE_STATUS_REJECTED = 4,
+    TE_STATUS_PENDING = 5,
+};
+
+typedef struct te_engine te_engine;
+
+typedef struct te_report {
+    uint64_t order_id;
+    int status; /* enum te_status */
+    char filled_size[TE_DECIMAL_LEN];
+    char remaining_size[TE_DECIMAL_LEN];
+    char average_price[TE_DECIMAL_LEN]; /* empty if nothing was filled */
+    uint64_t trade_count;
+} te_report;
+
+typedef struct te_trade {
+    const char *pair; /* only valid during the callback */
+    uint64_t maker_order_id;
+    uint64_t taker_order_id;
+    uint64_t maker_account;
+    uint64_t taker_account;
+    int taker_side;
+    char price[TE_DECIMAL_LEN];
+    char size[TE_DECIMAL_LEN];
+    uint64_t timestamp; /* milliseconds since the Unix epoch */
+} te_trade;
+
+/* Called once the call that executed the trade has finished; it may call
+ * back into the engine. */
+typedef void (*te_trade_callback)(void *user_data, const te_trade *trade);
+
+te_engine *te_engine_new(void);
+void te_engine_free(te_engine *engine);
+
+/* Valid until the next call on `engine`; empty after a success. */
+const char *te_last_error(const te_engine *engine);
+
+/* Pass a NULL callback to stop. */
+int te_set_trade_callback(te_engine *engine, te_trade_callback callback, void *user_data);
+
+int te_add_market(te_engine *engine, const char *pair);
+
+/* `report` may be NULL. */
+int te_place_limit_order(te_engine *engine, const char *pair, int side, const char *price,
+                         const char *size, uint64_t account, te_report *report);
+int te_place_market_order(te_engine *engine, const char *pair, int side, const char *size,
+                          uint64_t account, te_report *report);
+
+int te_cancel_order(te_engine *engine, const char *pair, uint64_t order_id);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TRADING_ENGINE_H */
@@ -0,0 +1,50 @@
+//! Order entry against an embedded engine, for demos and manual testing.
+//! Runs the command given as arguments, if any; otherwise reads commands
+//! from stdin, one per line, until `quit` or end of input.
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+
+use rust_trading_engine::matching_engine::cli::{parse, CliCommand, CliSession};
+use rust_trading_engine::matching_engine::engine::MatchingEngine;
+
+fn main() -> ExitCode {
+    let mut session = CliSession::new(MatchingEngine::new());
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        return match session.run(&args.join(" ")) {
+            Ok(output) => {
+                println!("{}", output);
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let interactive = io::stdin().is_terminal();
+    let prompt = || {
+        if interactive {
+            print!("> ");
+            let _ = io::stdout().flush();
+        }
+    };
+    prompt();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if matches!(parse(&line), Ok(Some(CliCommand::Quit))) {
+            break;
+        }
+        match session.run(&line) {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => println!("{}", output),
+            Err(err) => eprintln!("error: {}", err),
+        }
+        prompt();
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,56 @@
+//! Live order book viewer. Runs an embedded engine fed with synthetic order
+//! flow and redraws the depth ladder, stats and trades as events arrive.
+//! Press `q` or Esc to quit.
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use rust_trading_engine::matching_engine::config::MarketConfig;
+use rust_trading_engine::matching_engine::engine::{MatchingEngine, TradingPair};
+use rust_trading_engine::matching_engine::loadgen::{LoadConfig, LoadGenerator};
+use rust_trading_engine::matching_engine::tui::{draw, MarketView};
+
+// Commands the generator sends between two frames.
+const COMMANDS_PER_FRAME: usize = 20;
+const FRAME: Duration = Duration::from_millis(100);
+
+fn main() -> io::Result<()> {
+    let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+    let mut engine = MatchingEngine::new();
+    engine.add_new_market(pair.clone(), MarketConfig::default());
+    let mut view = MarketView::new(
+        pair.clone(),
+        engine
+            .depth(pair.clone(), usize::MAX)
+            .expect("market was just added"),
+    );
+    let events = engine.subscribe();
+    let mut generator = LoadGenerator::new(pair, LoadConfig::default());
+
+    let mut terminal = ratatui::init();
+    let result = loop {
+        generator.run(&mut engine, COMMANDS_PER_FRAME, None);
+        for event in events.try_iter() {
+            view.on_event(&event);
+        }
+        if let Err(err) = terminal.draw(|frame| draw(frame, &view)) {
+            break Err(err);
+        }
+        match event::poll(FRAME) {
+            Ok(true) => match event::read() {
+                Ok(Event::Key(key))
+                    if key.kind == KeyEventKind::Press
+                        && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) =>
+                {
+                    break Ok(())
+                }
+                Ok(_) => {}
+                Err(err) => break Err(err),
+            },
+            Ok(false) => {}
+            Err(err) => break Err(err),
+        }
+    };
+    ratatui::restore();
+    result
+}
@@ -0,0 +1 @@
+pub mod matching_engine;
@@ -0,0 +1,205 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::engine::TradingPair;
+use super::error::EngineError;
+use super::ledger::FEE_ACCOUNT;
+use super::orderbook::{AccountId, BidOrAsk, OrderId};
+use super::trade::Trade;
+
+/// An account's holding of one asset. Funds backing open orders are
+/// `reserved` and cannot be withdrawn or used by other orders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Balance {
+    pub available: Decimal,
+    pub reserved: Decimal,
+}
+
+impl Balance {
+    pub fn total(&self) -> Decimal {
+        self.available + self.reserved
+    }
+}
+
+/// Funds held for one working order: the asset it pays with comes from the
+/// pair and side. Bids reserve `size * price`, asks their size.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(super) struct Reservation {
+    pub(super) owner: AccountId,
+    pub(super) bid_or_ask: BidOrAsk,
+    pub(super) amount: Decimal,
+    /// Limit price the reservation of a bid was sized at; `None` for market
+    /// orders, which reserve their estimated cost.
+    pub(super) price: Option<Decimal>,
+}
+
+/// Per-account balances by asset.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Accounts {
+    #[cfg_attr(feature = "serde", serde(with = "super::engine::map_as_pairs"))]
+    balances: HashMap<(AccountId, String), Balance>,
+}
+
+impl Accounts {
+    pub fn new() -> Accounts {
+        Accounts::default()
+    }
+
+    pub fn balance(&self, account: AccountId, asset: &str) -> Balance {
+        self.balances
+            .get(&(account, asset.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every asset `account` holds, in asset order.
+    pub fn balances(&self, account: AccountId) -> Vec<(String, Balance)> {
+        let mut balances: Vec<(String, Balance)> = self
+            .balances
+            .iter()
+            .filter(|((owner, _), _)| *owner == account)
+            .map(|((_, asset), balance)| (asset.clone(), *balance))
+            .collect();
+        balances.sort_by(|a, b| a.0.cmp(&b.0));
+        balances
+    }
+
+    fn entry(&mut self, account: AccountId, asset: &str) -> &mut Balance {
+        self.balances
+            .entry((account, asset.to_string()))
+            .or_default()
+    }
+
+    pub fn deposit(
+        &mut self,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        if amount <= Decimal::ZERO {
+            return Err(EngineError::InvalidAmount(amount));
+        }
+        self.entry(account, asset).available += amount;
+        Ok(())
+    }
+
+    /// Withdraws from the available balance; reserved funds stay put.
+    pub fn withdraw(
+        &mut self,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        if amount <= Decimal::ZERO {
+            return Err(EngineError::InvalidAmount(amount));
+        }
+        self.reserve(account, asset, amount)?;
+        self.entry(account, asset).reserved -= amount;
+        Ok(())
+    }
+
+    /// Moves `amount` from available to reserved.
+    pub(super) fn reserve(
+        &mut self,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        let balance = self.entry(account, asset);
+        if balance.available < amount {
+            return Err(EngineError::InsufficientBalance {
+                account,
+                asset: asset.to_string(),
+                available: balance.available,
+                required: amount,
+            });
+        }
+        balance.available -= amount;
+        balance.reserved += amount;
+        Ok(())
+    }
+
+    /// Moves `amount` from reserved back to available.
+    pub(super) fn release(&mut self, account: AccountId, asset: &str, amount: Decimal) {
+        let balance = self.entry(account, asset);
+        balance.reserved -= amount;
+        balance.available += amount;
+    }
+
+    /// Settles `trade` against the reservations of its two orders: the
+    /// buyer's reserved quote pays the seller and the seller's reserved base
+    /// goes to the buyer, each less their fee, which is credited to
+    /// `FEE_ACCOUNT`. Quote reserved above the trade price is released.
+    pub(super) fn settle_trade(
+        &mut self,
+        pair: &TradingPair,
+        trade: &Trade,
+        reservations: &mut HashMap<OrderId, Reservation>,
+    ) {
+        let notional = trade.price * trade.size;
+        for order_id in [trade.maker_order_id, trade.taker_order_id] {
+            let Some(reservation) = reservations.get_mut(&order_id) else {
+                continue;
+            };
+            let owner = reservation.owner;
+            let fee = if order_id == trade.maker_order_id {
+                trade.maker_fee
+            } else {
+                trade.taker_fee
+            };
+            match reservation.bid_or_ask {
+                BidOrAsk::Bid => {
+                    let used = trade.size * reservation.price.unwrap_or(trade.price);
+                    reservation.amount -= used;
+                    let quote = self.entry(owner, pair.quote());
+                    quote.reserved -= used;
+                    quote.available += used - notional;
+                    self.entry(owner, pair.base()).available += trade.size - fee;
+                    self.entry(FEE_ACCOUNT, pair.base()).available += fee;
+                }
+                BidOrAsk::Ask => {
+                    reservation.amount -= trade.size;
+                    self.entry(owner, pair.base()).reserved -= trade.size;
+                    self.entry(owner, pair.quote()).available += notional - fee;
+                    self.entry(FEE_ACCOUNT, pair.quote()).available += fee;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_accounts_reserve_and_withdraw() {
+        let mut accounts = Accounts::new();
+        let account = AccountId(1);
+        accounts.deposit(account, "USD", dec!(100)).unwrap();
+        accounts.reserve(account, "USD", dec!(60)).unwrap();
+        assert!(accounts.reserve(account, "USD", dec!(41)).is_err());
+        assert!(accounts.withdraw(account, "USD", dec!(41)).is_err());
+
+        accounts.withdraw(account, "USD", dec!(40)).unwrap();
+        accounts.release(account, "USD", dec!(10));
+        assert_eq!(
+            accounts.balance(account, "USD"),
+            Balance {
+                available: dec!(10),
+                reserved: dec!(50),
+            }
+        );
+        assert!(accounts.deposit(account, "USD", dec!(0)).is_err());
+        assert_eq!(accounts.balances(account).len(), 1);
+        assert_eq!(accounts.balance(AccountId(2), "USD"), Balance::default());
+    }
+}
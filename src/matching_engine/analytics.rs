@@ -0,0 +1,101 @@
+#![allow(dead_code)]
+use std::collections::VecDeque;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::trade::Trade;
+
+/// Number of trades kept per market for trade-based analytics.
+pub const MAX_RECENT_TRADES: usize = 1000;
+
+/// Volume-weighted average price of `trades`, `None` if they carry no volume.
+pub fn vwap<'a>(trades: impl IntoIterator<Item = &'a Trade>) -> Option<Decimal> {
+    let (notional, volume) = trades.into_iter().fold(
+        (Decimal::ZERO, Decimal::ZERO),
+        |(notional, volume), trade| (notional + trade.price * trade.size, volume + trade.size),
+    );
+    if volume.is_zero() {
+        return None;
+    }
+    Some(notional / volume)
+}
+
+/// The most recent trades of a market, oldest first.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TradeHistory {
+    trades: VecDeque<Trade>,
+}
+
+impl TradeHistory {
+    pub fn new() -> TradeHistory {
+        TradeHistory::default()
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        self.trades.push_back(trade.clone());
+        if self.trades.len() > MAX_RECENT_TRADES {
+            self.trades.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    /// The last `count` trades, oldest first.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Trade> {
+        self.trades
+            .iter()
+            .skip(self.trades.len().saturating_sub(count))
+    }
+
+    /// VWAP over the last `count` trades.
+    pub fn vwap(&self, count: usize) -> Option<Decimal> {
+        vwap(self.recent(count))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::{BidOrAsk, OrderId};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_trade_history_vwap() {
+        let mut history = TradeHistory::new();
+        assert_eq!(history.vwap(10), None);
+
+        history.record(&Trade::new(
+            OrderId(1),
+            OrderId(2),
+            dec!(40),
+            dec!(4),
+            BidOrAsk::Bid,
+        ));
+        history.record(&Trade::new(
+            OrderId(1),
+            OrderId(3),
+            dec!(100),
+            dec!(1),
+            BidOrAsk::Bid,
+        ));
+        history.record(&Trade::new(
+            OrderId(1),
+            OrderId(4),
+            dec!(110),
+            dec!(3),
+            BidOrAsk::Bid,
+        ));
+
+        assert_eq!(history.vwap(2), Some(dec!(107.5)));
+        assert_eq!(history.vwap(10), Some(dec!(73.75)));
+    }
+}
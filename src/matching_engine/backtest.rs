@@ -0,0 +1,566 @@
+#![allow(dead_code)]
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead};
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::sync::mpsc;
+
+use rust_decimal::prelude::*;
+use thiserror::Error;
+
+use super::command::{CommandOutput, EngineCommand, SequencedCommand};
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::{EngineError, OrderBookError};
+use super::events::EngineEvent;
+use super::orderbook::{AccountId, BidOrAsk, ExecutionReport, Order, OrderId, PriceProtection};
+use super::trade::Trade;
+use super::triggers::OrderType;
+#[cfg(feature = "serde")]
+use super::wal::WriteAheadLog;
+
+/// What happened at one point of a historical stream.
+#[derive(Debug, Clone)]
+pub enum HistoricalEvent {
+    /// An input applied as is, e.g. one read from the engine's own command
+    /// log.
+    Command(EngineCommand),
+    /// An order that later cancels in the same data refer to by `id`.
+    Order {
+        id: u64,
+        pair: TradingPair,
+        order_type: OrderType,
+        order: Order,
+    },
+    Cancel {
+        id: u64,
+        pair: TradingPair,
+    },
+}
+
+impl HistoricalEvent {
+    fn pair(&self) -> Option<&TradingPair> {
+        match self {
+            HistoricalEvent::Command(command) => command.pair(),
+            HistoricalEvent::Order { pair, .. } | HistoricalEvent::Cancel { pair, .. } => {
+                Some(pair)
+            }
+        }
+    }
+}
+
+/// A historical event and when it happened, in milliseconds since the Unix
+/// epoch.
+#[derive(Debug, Clone)]
+pub struct HistoricalRecord {
+    pub timestamp: u64,
+    pub event: HistoricalEvent,
+}
+
+#[derive(Debug, Error)]
+pub enum BacktestError {
+    #[error("Failed to read historical data: {0}")]
+    Io(#[from] io::Error),
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+/// Reads historical orders for `pair` from CSV with the columns
+/// `timestamp,id,action,side,price,size`. `action` is one of:
+///
+/// - `limit`: a limit order at `price`;
+/// - `market`: a market order, `price` is left empty;
+/// - `cancel`: cancels the order with the same `id`, other fields empty;
+/// - `trade`: a printed trade, replayed as a market order from the `side`
+///   that took liquidity, trading no worse than `price`.
+///
+/// A header line, blank lines and lines starting with `#` are skipped.
+pub fn read_csv(
+    reader: impl BufRead,
+    pair: &TradingPair,
+) -> Result<Vec<HistoricalRecord>, BacktestError> {
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("timestamp") {
+            continue;
+        }
+        let record = parse_csv_record(line, pair).map_err(|message| BacktestError::Parse {
+            line: index + 1,
+            message,
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn parse_csv_record(line: &str, pair: &TradingPair) -> Result<HistoricalRecord, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [timestamp, id, action, side, price, size] = fields[..] else {
+        return Err(format!("Expected 6 fields, got {}", fields.len()));
+    };
+    let timestamp = timestamp
+        .parse()
+        .map_err(|_| format!("Invalid timestamp: {:?}", timestamp))?;
+    let id = id.parse().map_err(|_| format!("Invalid id: {:?}", id))?;
+    let pair = pair.clone();
+    if action == "cancel" {
+        return Ok(HistoricalRecord {
+            timestamp,
+            event: HistoricalEvent::Cancel { id, pair },
+        });
+    }
+
+    let bid_or_ask = match side {
+        "buy" | "bid" => BidOrAsk::Bid,
+        "sell" | "ask" => BidOrAsk::Ask,
+        _ => return Err(format!("Invalid side: {:?}", side)),
+    };
+    let decimal = |name: &str, value: &str| {
+        Decimal::from_str(value).map_err(|_| format!("Invalid {}: {:?}", name, value))
+    };
+    let order = Order::new(bid_or_ask, decimal("size", size)?);
+    let (order_type, order) = match action {
+        "limit" => (
+            OrderType::Limit {
+                price: decimal("price", price)?,
+            },
+            order,
+        ),
+        "market" => (OrderType::Market, order),
+        "trade" => (
+            OrderType::Market,
+            order.with_protection(PriceProtection::Price(decimal("price", price)?)),
+        ),
+        _ => return Err(format!("Invalid action: {:?}", action)),
+    };
+    Ok(HistoricalRecord {
+        timestamp,
+        event: HistoricalEvent::Order {
+            id,
+            pair,
+            order_type,
+            order,
+        },
+    })
+}
+
+/// Reads the inputs recorded in a command log, to be replayed at the times
+/// they were originally accepted.
+#[cfg(feature = "serde")]
+pub fn read_command_log(path: impl AsRef<Path>) -> io::Result<Vec<HistoricalRecord>> {
+    Ok(WriteAheadLog::read(path)?
+        .into_iter()
+        .map(|input| HistoricalRecord {
+            timestamp: input.timestamp,
+            event: HistoricalEvent::Command(input.command),
+        })
+        .collect())
+}
+
+/// A trade one of the strategy's orders took part in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub pair: TradingPair,
+    pub bid_or_ask: BidOrAsk,
+    pub is_maker: bool,
+    pub trade: Trade,
+}
+
+impl Fill {
+    /// Fee paid, in the asset received: base when buying, quote when
+    /// selling.
+    pub fn fee(&self) -> Decimal {
+        if self.is_maker {
+            self.trade.maker_fee
+        } else {
+            self.trade.taker_fee
+        }
+    }
+}
+
+/// The strategy's holdings in one market, net of fees, starting from zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    pub base: Decimal,
+    pub quote: Decimal,
+}
+
+impl Position {
+    fn apply(&mut self, fill: &Fill) {
+        let notional = fill.trade.price * fill.trade.size;
+        match fill.bid_or_ask {
+            BidOrAsk::Bid => {
+                self.base += fill.trade.size - fill.fee();
+                self.quote -= notional;
+            }
+            BidOrAsk::Ask => {
+                self.base -= fill.trade.size;
+                self.quote += notional - fill.fee();
+            }
+        }
+    }
+
+    /// Profit or loss with the base holding valued at `mark_price`.
+    pub fn pnl(&self, mark_price: Decimal) -> Decimal {
+        self.quote + self.base * mark_price
+    }
+}
+
+/// What a market looked like over the backtest. The spread is sampled after
+/// every historical record applied to the market while both sides were
+/// quoted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BookStats {
+    pub trades: usize,
+    pub volume: Decimal,
+    pub last_price: Option<Decimal>,
+    pub max_spread: Option<Decimal>,
+    spread_samples: usize,
+    spread_total: Decimal,
+}
+
+impl BookStats {
+    pub fn average_spread(&self) -> Option<Decimal> {
+        (self.spread_samples > 0).then(|| self.spread_total / Decimal::from(self.spread_samples))
+    }
+
+    fn sample_spread(&mut self, spread: Decimal) {
+        self.spread_samples += 1;
+        self.spread_total += spread;
+        self.max_spread = Some(self.max_spread.map_or(spread, |max| max.max(spread)));
+    }
+}
+
+/// Everything collected while running a backtest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BacktestReport {
+    /// Historical records applied, and how many of them the engine refused.
+    pub records: usize,
+    pub rejected: usize,
+    pub fills: Vec<Fill>,
+    pub positions: HashMap<TradingPair, Position>,
+    pub book: HashMap<TradingPair, BookStats>,
+}
+
+impl BacktestReport {
+    /// Profit or loss in `pair`, marked to its last trade price.
+    pub fn pnl(&self, pair: &TradingPair) -> Option<Decimal> {
+        let mark_price = self.book.get(pair)?.last_price?;
+        Some(self.positions.get(pair).copied()?.pnl(mark_price))
+    }
+}
+
+/// The strategy's view of the engine while it handles a callback. Orders
+/// are placed at the simulated time as the strategy's own account.
+pub struct StrategyContext<'a> {
+    engine: &'a mut MatchingEngine,
+    account: AccountId,
+    now: u64,
+}
+
+impl StrategyContext<'_> {
+    /// Simulated time, in milliseconds since the Unix epoch.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    pub fn engine(&self) -> &MatchingEngine {
+        self.engine
+    }
+
+    pub fn place_order(
+        &mut self,
+        pair: TradingPair,
+        order_type: OrderType,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        let command = EngineCommand::PlaceOrder {
+            pair,
+            order_type,
+            order: order.with_owner(self.account),
+        };
+        match apply(self.engine, self.now, command)? {
+            CommandOutput::Report(report) => Ok(report),
+            output => unreachable!("place order returned {:?}", output),
+        }
+    }
+
+    pub fn cancel_order(
+        &mut self,
+        pair: TradingPair,
+        order_id: OrderId,
+    ) -> Result<Order, EngineError> {
+        match apply(
+            self.engine,
+            self.now,
+            EngineCommand::CancelOrder { pair, order_id },
+        )? {
+            CommandOutput::Cancelled(order) => Ok(order),
+            output => unreachable!("cancel order returned {:?}", output),
+        }
+    }
+}
+
+// Applies `command` as the engine's next input, stamped at `timestamp`.
+fn apply(
+    engine: &mut MatchingEngine,
+    timestamp: u64,
+    command: EngineCommand,
+) -> Result<CommandOutput, EngineError> {
+    engine.process(SequencedCommand {
+        sequence: engine.sequence() + 1,
+        timestamp,
+        command,
+    })
+}
+
+/// The research hooks a backtest drives.
+pub trait Strategy {
+    /// Called after each historical record has been applied.
+    fn on_record(&mut self, context: &mut StrategyContext<'_>, record: &HistoricalRecord);
+
+    /// Called for every trade one of the strategy's orders took part in,
+    /// including trades caused by orders placed from this callback.
+    fn on_fill(&mut self, _context: &mut StrategyContext<'_>, _fill: &Fill) {}
+}
+
+/// Drives a `MatchingEngine` through historical records under a simulated
+/// clock, letting `strategy` trade alongside them as `account`.
+pub struct Backtest<S: Strategy> {
+    engine: MatchingEngine,
+    strategy: S,
+    account: AccountId,
+    events: mpsc::Receiver<EngineEvent>,
+    // Engine ids of the historical orders, by their id in the data.
+    order_ids: HashMap<(TradingPair, u64), OrderId>,
+    now: u64,
+    report: BacktestReport,
+}
+
+impl<S: Strategy> Backtest<S> {
+    /// Starts from `engine` as it is; markets the records trade in must
+    /// already exist or be added by the records themselves.
+    pub fn new(mut engine: MatchingEngine, account: AccountId, strategy: S) -> Backtest<S> {
+        let events = engine.subscribe();
+        Backtest {
+            engine,
+            strategy,
+            account,
+            events,
+            order_ids: HashMap::new(),
+            now: 0,
+            report: BacktestReport::default(),
+        }
+    }
+
+    pub fn engine(&self) -> &MatchingEngine {
+        &self.engine
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    pub fn report(&self) -> &BacktestReport {
+        &self.report
+    }
+
+    /// Applies `records` in order. The simulated clock never runs
+    /// backwards: a record stamped before the previous one happens at the
+    /// previous one's time.
+    pub fn run(&mut self, records: impl IntoIterator<Item = HistoricalRecord>) -> &BacktestReport {
+        for record in records {
+            self.now = self.now.max(record.timestamp);
+            if self.apply_record(&record).is_err() {
+                self.report.rejected += 1;
+            }
+            self.report.records += 1;
+            self.settle();
+            if let Some(pair) = record.event.pair() {
+                self.sample_book(pair);
+            }
+
+            let mut context = StrategyContext {
+                engine: &mut self.engine,
+                account: self.account,
+                now: self.now,
+            };
+            self.strategy.on_record(&mut context, &record);
+            self.settle();
+        }
+        &self.report
+    }
+
+    fn apply_record(&mut self, record: &HistoricalRecord) -> Result<(), EngineError> {
+        let command = match &record.event {
+            HistoricalEvent::Command(command) => command.clone(),
+            HistoricalEvent::Order {
+                id,
+                pair,
+                order_type,
+                order,
+            } => {
+                let command = EngineCommand::PlaceOrder {
+                    pair: pair.clone(),
+                    order_type: *order_type,
+                    order: order.clone(),
+                };
+                if let CommandOutput::Report(report) = apply(&mut self.engine, self.now, command)? {
+                    self.order_ids.insert((pair.clone(), *id), report.order_id);
+                }
+                return Ok(());
+            }
+            HistoricalEvent::Cancel { id, pair } => {
+                // Orders the engine refused, or that have since filled,
+                // have nothing left to cancel.
+                let order_id = self
+                    .order_ids
+                    .remove(&(pair.clone(), *id))
+                    .ok_or(OrderBookError::UnknownOrderId(OrderId(*id)))?;
+                EngineCommand::CancelOrder {
+                    pair: pair.clone(),
+                    order_id,
+                }
+            }
+        };
+        apply(&mut self.engine, self.now, command).map(|_| ())
+    }
+
+    // Records the trades published since the last call and hands the
+    // strategy its fills, until its reactions stop trading.
+    fn settle(&mut self) {
+        let mut fills: VecDeque<Fill> = self.drain_fills().into();
+        while let Some(fill) = fills.pop_front() {
+            let mut context = StrategyContext {
+                engine: &mut self.engine,
+                account: self.account,
+                now: self.now,
+            };
+            self.strategy.on_fill(&mut context, &fill);
+            fills.extend(self.drain_fills());
+        }
+    }
+
+    fn drain_fills(&mut self) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        for event in self.events.try_iter() {
+            let EngineEvent::TradeExecuted { pair, trade } = event else {
+                continue;
+            };
+            let book = self.report.book.entry(pair.clone()).or_default();
+            book.trades += 1;
+            book.volume += trade.size;
+            book.last_price = Some(trade.price);
+
+            let maker_side = match trade.taker_side {
+                BidOrAsk::Bid => BidOrAsk::Ask,
+                BidOrAsk::Ask => BidOrAsk::Bid,
+            };
+            let sides = [
+                (trade.maker_owner, maker_side, true),
+                (trade.taker_owner, trade.taker_side, false),
+            ];
+            for (owner, bid_or_ask, is_maker) in sides {
+                if owner != self.account {
+                    continue;
+                }
+                let fill = Fill {
+                    pair: pair.clone(),
+                    bid_or_ask,
+                    is_maker,
+                    trade: trade.clone(),
+                };
+                self.report
+                    .positions
+                    .entry(pair.clone())
+                    .or_default()
+                    .apply(&fill);
+                self.report.fills.push(fill.clone());
+                fills.push(fill);
+            }
+        }
+        fills
+    }
+
+    fn sample_book(&mut self, pair: &TradingPair) {
+        let Ok(depth) = self.engine.depth(pair.clone(), 1) else {
+            return;
+        };
+        let book = self.report.book.entry(pair.clone()).or_default();
+        if let (Some(bid), Some(ask)) = (depth.bids.first(), depth.asks.first()) {
+            book.sample_spread(ask.price - bid.price);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use rust_decimal_macros::dec;
+
+    const HISTORY: &str = "\
+timestamp,id,action,side,price,size
+1000,1,limit,ask,101,2
+1001,2,limit,bid,98,1
+1002,3,trade,sell,99,1
+1003,1,cancel,,,
+1004,4,limit,ask,100,1
+1005,5,trade,buy,100,1
+";
+
+    struct Bidder {
+        fills: usize,
+    }
+
+    impl Strategy for Bidder {
+        fn on_record(&mut self, context: &mut StrategyContext<'_>, record: &HistoricalRecord) {
+            if record.timestamp == 1000 {
+                let order = Order::new(BidOrAsk::Bid, dec!(1));
+                let price = dec!(99);
+                context
+                    .place_order(btc_usd(), OrderType::Limit { price }, order)
+                    .unwrap();
+            }
+        }
+
+        fn on_fill(&mut self, _context: &mut StrategyContext<'_>, _fill: &Fill) {
+            self.fills += 1;
+        }
+    }
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[test]
+    fn test_backtest_replays_csv_history() {
+        let records = read_csv(HISTORY.as_bytes(), &btc_usd()).unwrap();
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let mut backtest = Backtest::new(engine, AccountId(7), Bidder { fills: 0 });
+        let report = backtest.run(records).clone();
+
+        assert_eq!((report.records, report.rejected), (6, 0));
+        assert_eq!(backtest.strategy().fills, 1);
+        let fill = &report.fills[0];
+        assert_eq!(
+            (fill.bid_or_ask, fill.is_maker, fill.trade.price),
+            (BidOrAsk::Bid, true, dec!(99))
+        );
+        assert_eq!(fill.trade.timestamp, 1002);
+        // Bought 1 at 99, marked at the last trade of 100.
+        assert_eq!(report.pnl(&btc_usd()), Some(dec!(1)));
+        let book = report.book[&btc_usd()];
+        assert_eq!((book.trades, book.volume), (2, dec!(2)));
+        assert_eq!(book.max_spread, Some(dec!(3)));
+
+        let error = read_csv("1000,1,limit,ask,abc,1".as_bytes(), &btc_usd()).unwrap_err();
+        assert!(matches!(error, BacktestError::Parse { line: 1, .. }));
+    }
+}
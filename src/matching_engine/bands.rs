@@ -0,0 +1,103 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::orderbook::BidOrAsk;
+
+/// Limit-up/limit-down protection: trading is confined to within `percent`
+/// of a reference price, which follows the last trade unless the band is
+/// pinned. Without a width or before the first reference price every price
+/// is allowed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PriceBand {
+    percent: Option<Decimal>,
+    reference: Option<Decimal>,
+    // Set while the reference is moved from outside the book, so that its
+    // trades leave the band where it is.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pinned: bool,
+}
+
+impl PriceBand {
+    pub fn set_percent(&mut self, percent: Option<Decimal>) {
+        self.percent = percent;
+    }
+
+    pub fn percent(&self) -> Option<Decimal> {
+        self.percent
+    }
+
+    pub fn reference(&self) -> Option<Decimal> {
+        self.reference
+    }
+
+    /// Moves the band to be centred on `price`.
+    pub fn set_reference(&mut self, price: Decimal) {
+        self.reference = Some(price);
+    }
+
+    /// Re-centres the band on a trade at `price`, unless it is pinned.
+    pub fn on_trade(&mut self, price: Decimal) {
+        if !self.pinned {
+            self.reference = Some(price);
+        }
+    }
+
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    /// Lowest and highest price currently allowed.
+    pub fn bounds(&self) -> Option<(Decimal, Decimal)> {
+        let (percent, reference) = (self.percent?, self.reference?);
+        let width = reference * percent / dec!(100);
+        Some((reference - width, reference + width))
+    }
+
+    pub fn contains(&self, price: Decimal) -> bool {
+        self.bounds()
+            .is_none_or(|(low, high)| low <= price && price <= high)
+    }
+
+    /// The worst price an order on `bid_or_ask` limited to `limit_price` may
+    /// trade at.
+    pub fn cap(&self, bid_or_ask: BidOrAsk, limit_price: Option<Decimal>) -> Option<Decimal> {
+        let Some((low, high)) = self.bounds() else {
+            return limit_price;
+        };
+        Some(match (bid_or_ask, limit_price) {
+            (BidOrAsk::Bid, Some(price)) => price.min(high),
+            (BidOrAsk::Bid, None) => high,
+            (BidOrAsk::Ask, Some(price)) => price.max(low),
+            (BidOrAsk::Ask, None) => low,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_price_band_caps_orders() {
+        let mut band = PriceBand::default();
+        band.set_percent(Some(dec!(10)));
+        assert!(band.contains(dec!(1000)));
+        assert_eq!(band.cap(BidOrAsk::Bid, None), None);
+
+        band.set_reference(dec!(100));
+        assert_eq!(band.bounds(), Some((dec!(90), dec!(110))));
+        assert!(!band.contains(dec!(111)));
+        assert_eq!(band.cap(BidOrAsk::Bid, None), Some(dec!(110)));
+        assert_eq!(band.cap(BidOrAsk::Bid, Some(dec!(105))), Some(dec!(105)));
+        assert_eq!(band.cap(BidOrAsk::Ask, Some(dec!(80))), Some(dec!(90)));
+
+        band.set_pinned(true);
+        band.on_trade(dec!(200));
+        assert_eq!(band.reference(), Some(dec!(100)));
+    }
+}
@@ -0,0 +1,158 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::orderbook::{ExecutionReport, Order, OrderId};
+use super::triggers::OrderType;
+
+/// One step of a `MatchingEngine::batch`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BatchOp {
+    Place {
+        order_type: OrderType,
+        order: Order,
+    },
+    Cancel {
+        order_id: OrderId,
+    },
+    Amend {
+        order_id: OrderId,
+        new_price: Decimal,
+        new_size: Decimal,
+    },
+}
+
+/// What a `BatchOp` returned, in the same position as the op.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BatchOpResult {
+    Placed(ExecutionReport),
+    Cancelled(Order),
+    Amended(ExecutionReport),
+}
+
+impl MatchingEngine {
+    /// Applies `ops` to the market in order, all or nothing. Each op goes
+    /// through the same checks as its standalone method, and later ops see
+    /// the book as the earlier ones left it, so a quote ladder can be
+    /// cancelled and replaced in one call. If any op fails the market is
+    /// left exactly as it was, no events are published, and the error names
+    /// the failing op.
+    pub fn batch(
+        &mut self,
+        pair: TradingPair,
+        ops: Vec<BatchOp>,
+    ) -> Result<Vec<BatchOpResult>, EngineError> {
+        self.atomically(&pair, |engine| {
+            ops.into_iter()
+                .enumerate()
+                .map(|(index, op)| {
+                    let pair = pair.clone();
+                    match op {
+                        BatchOp::Place { order_type, order } => engine
+                            .place_order(pair, order_type, order)
+                            .map(BatchOpResult::Placed),
+                        BatchOp::Cancel { order_id } => engine
+                            .cancel_order(pair, order_id)
+                            .map(BatchOpResult::Cancelled),
+                        BatchOp::Amend {
+                            order_id,
+                            new_price,
+                            new_size,
+                        } => engine
+                            .amend_order(pair, order_id, new_price, new_size)
+                            .map(BatchOpResult::Amended),
+                    }
+                    .map_err(|reason| EngineError::BatchOpFailed {
+                        index,
+                        reason: Box::new(reason),
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::error::OrderBookError;
+    use crate::matching_engine::orderbook::{BidOrAsk, OrderStatus};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_batch_is_all_or_nothing() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        let quote = |price| BatchOp::Place {
+            order_type: OrderType::Limit { price },
+            order: Order::new(BidOrAsk::Ask, dec!(1)),
+        };
+
+        let results = engine
+            .batch(pair.clone(), vec![quote(dec!(101)), quote(dec!(102))])
+            .unwrap();
+        let BatchOpResult::Placed(first) = &results[0] else {
+            panic!("expected a placement, got {:?}", results[0]);
+        };
+        assert_eq!(first.status, OrderStatus::New);
+
+        // Replacing the ladder fails on the last op, so the cancel and the
+        // new quote are rolled back and nothing is published.
+        let events = engine.subscribe();
+        let error = engine
+            .batch(
+                pair.clone(),
+                vec![
+                    BatchOp::Cancel {
+                        order_id: first.order_id,
+                    },
+                    quote(dec!(103)),
+                    BatchOp::Cancel {
+                        order_id: OrderId(99),
+                    },
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(
+            error,
+            EngineError::BatchOpFailed {
+                index: 2,
+                reason: Box::new(OrderBookError::UnknownOrderId(OrderId(99)).into()),
+            }
+        );
+        assert!(events.try_recv().is_err());
+        let depth = engine.depth(pair.clone(), 10).unwrap();
+        let prices: Vec<Decimal> = depth.asks.iter().map(|level| level.price).collect();
+        assert_eq!(prices, [dec!(101), dec!(102)]);
+
+        // The same replacement without the bad op goes through.
+        let results = engine
+            .batch(
+                pair.clone(),
+                vec![
+                    BatchOp::Cancel {
+                        order_id: first.order_id,
+                    },
+                    BatchOp::Amend {
+                        order_id: OrderId(2),
+                        new_price: dec!(102),
+                        new_size: dec!(0.5),
+                    },
+                    quote(dec!(103)),
+                ],
+            )
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(events.try_iter().count() > 0);
+        let depth = engine.depth(pair, 10).unwrap();
+        assert_eq!(depth.asks[0].size, dec!(0.5));
+        assert_eq!(depth.asks[1].price, dec!(103));
+    }
+}
@@ -0,0 +1,209 @@
+#![allow(dead_code)]
+use std::collections::VecDeque;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::trade::Trade;
+
+/// Number of bars kept per interval before the oldest are dropped.
+pub const MAX_CANDLES: usize = 1000;
+
+/// Width of a candle, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CandleInterval(pub u64);
+
+impl CandleInterval {
+    pub const ONE_MINUTE: CandleInterval = CandleInterval(60 * 1000);
+    pub const FIVE_MINUTES: CandleInterval = CandleInterval(5 * 60 * 1000);
+    pub const ONE_HOUR: CandleInterval = CandleInterval(60 * 60 * 1000);
+
+    // Start of the bar containing `timestamp`.
+    fn open_time(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.0
+    }
+}
+
+/// One OHLCV bar. `open_time` is the inclusive start of the interval.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Candle {
+    pub open_time: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub trade_count: usize,
+}
+
+impl Candle {
+    fn new(open_time: u64, trade: &Trade) -> Candle {
+        Candle {
+            open_time,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.size,
+            trade_count: 1,
+        }
+    }
+
+    fn update(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.size;
+        self.trade_count += 1;
+    }
+}
+
+/// Bars for a single interval, oldest first. Intervals without trades
+/// produce no bar.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CandleSeries {
+    interval: CandleInterval,
+    candles: VecDeque<Candle>,
+}
+
+impl CandleSeries {
+    pub fn new(interval: CandleInterval) -> CandleSeries {
+        CandleSeries {
+            interval,
+            candles: VecDeque::new(),
+        }
+    }
+
+    pub fn interval(&self) -> CandleInterval {
+        self.interval
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        let open_time = self.interval.open_time(trade.timestamp);
+        match self.candles.back_mut() {
+            // A trade stamped before the current bar (clock skew) is folded
+            // into it rather than reopening a closed bar.
+            Some(candle) if candle.open_time >= open_time => candle.update(trade),
+            _ => {
+                self.candles.push_back(Candle::new(open_time, trade));
+                if self.candles.len() > MAX_CANDLES {
+                    self.candles.pop_front();
+                }
+            }
+        }
+    }
+
+    /// The most recent `count` bars, oldest first. The last bar may still be
+    /// forming.
+    pub fn recent(&self, count: usize) -> Vec<Candle> {
+        let skip = self.candles.len().saturating_sub(count);
+        self.candles.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// Maintains a `CandleSeries` for each configured interval of one market.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CandleAggregator {
+    series: Vec<CandleSeries>,
+}
+
+impl Default for CandleAggregator {
+    fn default() -> CandleAggregator {
+        CandleAggregator::new(&[
+            CandleInterval::ONE_MINUTE,
+            CandleInterval::FIVE_MINUTES,
+            CandleInterval::ONE_HOUR,
+        ])
+    }
+}
+
+impl CandleAggregator {
+    pub fn new(intervals: &[CandleInterval]) -> CandleAggregator {
+        let mut aggregator = CandleAggregator { series: Vec::new() };
+        for &interval in intervals {
+            aggregator.add_interval(interval);
+        }
+        aggregator
+    }
+
+    /// Starts aggregating another interval. Bars only cover trades recorded
+    /// from now on.
+    pub fn add_interval(&mut self, interval: CandleInterval) {
+        if interval.0 > 0 && self.series(interval).is_none() {
+            self.series.push(CandleSeries::new(interval));
+        }
+    }
+
+    pub fn intervals(&self) -> impl Iterator<Item = CandleInterval> + '_ {
+        self.series.iter().map(CandleSeries::interval)
+    }
+
+    pub fn series(&self, interval: CandleInterval) -> Option<&CandleSeries> {
+        self.series
+            .iter()
+            .find(|series| series.interval == interval)
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        for series in &mut self.series {
+            series.record(trade);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::{BidOrAsk, OrderId};
+    use rust_decimal_macros::dec;
+
+    fn trade_at(timestamp: u64, price: Decimal, size: Decimal) -> Trade {
+        let mut trade = Trade::new(OrderId(1), OrderId(2), price, size, BidOrAsk::Bid);
+        trade.timestamp = timestamp;
+        trade
+    }
+
+    #[test]
+    fn test_candles_aggregate_by_interval() {
+        let mut candles = CandleAggregator::new(&[CandleInterval(1000), CandleInterval(5000)]);
+        candles.record(&trade_at(100, dec!(10), dec!(1)));
+        candles.record(&trade_at(400, dec!(12), dec!(2)));
+        candles.record(&trade_at(900, dec!(9), dec!(1)));
+        candles.record(&trade_at(3500, dec!(11), dec!(4)));
+
+        let bars = candles.series(CandleInterval(1000)).unwrap().recent(10);
+        assert_eq!(
+            bars,
+            vec![
+                Candle {
+                    open_time: 0,
+                    open: dec!(10),
+                    high: dec!(12),
+                    low: dec!(9),
+                    close: dec!(9),
+                    volume: dec!(4),
+                    trade_count: 3,
+                },
+                Candle {
+                    open_time: 3000,
+                    open: dec!(11),
+                    high: dec!(11),
+                    low: dec!(11),
+                    close: dec!(11),
+                    volume: dec!(4),
+                    trade_count: 1,
+                },
+            ]
+        );
+
+        let bars = candles.series(CandleInterval(5000)).unwrap().recent(1);
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].close, dec!(11));
+        assert_eq!(bars[0].volume, dec!(8));
+    }
+}
@@ -0,0 +1,325 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use rust_decimal::prelude::*;
+use thiserror::Error;
+
+use super::command::{CommandOutput, EngineCommand};
+use super::config::MarketConfig;
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::{EngineError, OrderBookError};
+use super::orderbook::{AccountId, BidOrAsk, ExecutionReport, Order, OrderId};
+use super::triggers::OrderType;
+
+pub const USAGE: &str = "\
+commands:
+  market add <PAIR>
+  order limit <buy|sell> <SIZE> @ <PRICE> <PAIR> [account <ID>]
+  order market <buy|sell> <SIZE> <PAIR> [account <ID>]
+  cancel <ORDER ID>
+  book <PAIR> [LEVELS]
+  trades <PAIR> [COUNT]
+  orders <ACCOUNT>
+  help
+  quit";
+
+const DEFAULT_LEVELS: usize = 10;
+const DEFAULT_TRADES: usize = 10;
+
+/// One line of CLI input, parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliCommand {
+    AddMarket(TradingPair),
+    Order {
+        pair: TradingPair,
+        order_type: OrderType,
+        bid_or_ask: BidOrAsk,
+        size: Decimal,
+        account: AccountId,
+    },
+    Cancel(OrderId),
+    Book {
+        pair: TradingPair,
+        levels: usize,
+    },
+    Trades {
+        pair: TradingPair,
+        count: usize,
+    },
+    Orders(AccountId),
+    Help,
+    Quit,
+}
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}; type `help` for the commands")]
+    Usage(String),
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+}
+
+fn usage(message: impl Into<String>) -> CliError {
+    CliError::Usage(message.into())
+}
+
+fn number<T: FromStr>(word: Option<&str>, what: &str) -> Result<T, CliError> {
+    let word = word.ok_or_else(|| usage(format!("Missing {}", what)))?;
+    word.parse()
+        .map_err(|_| usage(format!("Invalid {}: {}", what, word)))
+}
+
+fn pair(word: Option<&str>) -> Result<TradingPair, CliError> {
+    Ok(word.ok_or_else(|| usage("Missing pair"))?.parse()?)
+}
+
+fn side(word: Option<&str>) -> Result<BidOrAsk, CliError> {
+    match word {
+        Some("buy" | "bid") => Ok(BidOrAsk::Bid),
+        Some("sell" | "ask") => Ok(BidOrAsk::Ask),
+        Some(other) => Err(usage(format!("Invalid side: {}", other))),
+        None => Err(usage("Missing side")),
+    }
+}
+
+/// Parses one line, `None` if it is blank or a `#` comment. Words are
+/// case-insensitive, except that pairs are upper-cased anyway.
+pub fn parse(line: &str) -> Result<Option<CliCommand>, CliError> {
+    let line = line.trim().to_lowercase();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some("market") => match words.next() {
+            Some("add") => CliCommand::AddMarket(pair(words.next())?),
+            _ => return Err(usage("Expected `market add <PAIR>`")),
+        },
+        Some("order") => {
+            let limit = match words.next() {
+                Some("limit") => true,
+                Some("market") => false,
+                _ => return Err(usage("Expected `order limit` or `order market`")),
+            };
+            let bid_or_ask = side(words.next())?;
+            let size = number(words.next(), "size")?;
+            let order_type = if limit {
+                if words.next() != Some("@") {
+                    return Err(usage("Expected `@ <PRICE>` after the size"));
+                }
+                OrderType::Limit {
+                    price: number(words.next(), "price")?,
+                }
+            } else {
+                OrderType::Market
+            };
+            let pair = pair(words.next())?;
+            let account = match words.next() {
+                Some("account") => AccountId(number(words.next(), "account")?),
+                Some(other) => return Err(usage(format!("Unexpected `{}`", other))),
+                None => AccountId::default(),
+            };
+            CliCommand::Order {
+                pair,
+                order_type,
+                bid_or_ask,
+                size,
+                account,
+            }
+        }
+        Some("cancel") => CliCommand::Cancel(OrderId(number(words.next(), "order id")?)),
+        Some("book") => CliCommand::Book {
+            pair: pair(words.next())?,
+            levels: words
+                .next()
+                .map_or(Ok(DEFAULT_LEVELS), |word| number(Some(word), "levels"))?,
+        },
+        Some("trades") => CliCommand::Trades {
+            pair: pair(words.next())?,
+            count: words
+                .next()
+                .map_or(Ok(DEFAULT_TRADES), |word| number(Some(word), "count"))?,
+        },
+        Some("orders") => CliCommand::Orders(AccountId(number(words.next(), "account")?)),
+        Some("help") => CliCommand::Help,
+        Some("quit" | "exit") => CliCommand::Quit,
+        Some(other) => return Err(usage(format!("Unknown command `{}`", other))),
+        None => return Ok(None),
+    };
+    if let Some(extra) = words.next() {
+        return Err(usage(format!("Unexpected `{}`", extra)));
+    }
+    Ok(Some(command))
+}
+
+fn describe_report(report: &ExecutionReport) -> String {
+    let mut out = format!(
+        "order {} {:?}: filled {}, remaining {}",
+        report.order_id, report.status, report.filled_size, report.remaining_size
+    );
+    if let Some(price) = report.average_price {
+        let _ = write!(out, ", average price {}", price);
+    }
+    if let Some(reason) = &report.reason {
+        let _ = write!(out, " ({})", reason);
+    }
+    for trade in &report.trades {
+        let _ = write!(
+            out,
+            "\n  trade {} @ {} against order {}",
+            trade.size, trade.price, trade.maker_order_id
+        );
+    }
+    out
+}
+
+/// Drives an embedded engine from CLI commands. Remembers which market each
+/// order it placed went to, so they can be cancelled by id alone.
+pub struct CliSession {
+    engine: MatchingEngine,
+    pairs: HashMap<OrderId, TradingPair>,
+}
+
+impl CliSession {
+    pub fn new(engine: MatchingEngine) -> CliSession {
+        CliSession {
+            engine,
+            pairs: HashMap::new(),
+        }
+    }
+
+    pub fn engine(&self) -> &MatchingEngine {
+        &self.engine
+    }
+
+    /// Runs one line and returns what to print. `Quit` is left to the
+    /// caller; it prints nothing.
+    pub fn run(&mut self, line: &str) -> Result<String, CliError> {
+        let Some(command) = parse(line)? else {
+            return Ok(String::new());
+        };
+        match command {
+            CliCommand::AddMarket(pair) => {
+                self.engine.execute(EngineCommand::AddMarket {
+                    pair: pair.clone(),
+                    config: MarketConfig::default(),
+                })?;
+                Ok(format!("added market {}", pair))
+            }
+            CliCommand::Order {
+                pair,
+                order_type,
+                bid_or_ask,
+                size,
+                account,
+            } => {
+                let output = self.engine.execute(EngineCommand::PlaceOrder {
+                    pair: pair.clone(),
+                    order_type,
+                    order: Order::new(bid_or_ask, size).with_owner(account),
+                })?;
+                let CommandOutput::Report(report) = output else {
+                    unreachable!("Unexpected command output: {:?}", output)
+                };
+                self.pairs.insert(report.order_id, pair);
+                Ok(describe_report(&report))
+            }
+            CliCommand::Cancel(order_id) => {
+                let pair = self
+                    .pairs
+                    .get(&order_id)
+                    .cloned()
+                    .ok_or(EngineError::from(OrderBookError::UnknownOrderId(order_id)))?;
+                self.engine
+                    .execute(EngineCommand::CancelOrder { pair, order_id })?;
+                self.pairs.remove(&order_id);
+                Ok(format!("cancelled order {}", order_id))
+            }
+            CliCommand::Book { pair, levels } => {
+                let depth = self.engine.depth(pair.clone(), levels)?;
+                let mut out = format!("{:>16} {:>16}  ({})", "size", "price", pair);
+                for level in depth.asks.iter().rev() {
+                    let _ = write!(out, "\n{:>16} {:>16}  ask", level.size, level.price);
+                }
+                out.push_str("\n  ---");
+                for level in &depth.bids {
+                    let _ = write!(out, "\n{:>16} {:>16}  bid", level.size, level.price);
+                }
+                Ok(out)
+            }
+            CliCommand::Trades { pair, count } => {
+                let trades = self.engine.recent_trades(pair, count)?;
+                let lines: Vec<String> = trades
+                    .iter()
+                    .map(|trade| {
+                        format!(
+                            "{} {:?} {} @ {}",
+                            trade.timestamp, trade.taker_side, trade.size, trade.price
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            CliCommand::Orders(account) => {
+                let lines: Vec<String> = self
+                    .engine
+                    .open_orders(account)
+                    .iter()
+                    .map(|order| {
+                        format!(
+                            "order {} {} {:?} {:?} remaining {}",
+                            order.order_id,
+                            order.pair,
+                            order.bid_or_ask,
+                            order.order_type,
+                            order.remaining_size
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            CliCommand::Help => Ok(USAGE.to_string()),
+            CliCommand::Quit => Ok(String::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_cli_session_places_and_cancels() {
+        assert_eq!(
+            parse("order limit buy 0.5 @ 30000 BTC/USD account 7").unwrap(),
+            Some(CliCommand::Order {
+                pair: TradingPair::new("BTC".to_string(), "USD".to_string()),
+                order_type: OrderType::Limit { price: dec!(30000) },
+                bid_or_ask: BidOrAsk::Bid,
+                size: dec!(0.5),
+                account: AccountId(7),
+            })
+        );
+        assert_eq!(parse("  # comment").unwrap(), None);
+        assert!(matches!(
+            parse("order limit buy 0.5 30000 BTC/USD"),
+            Err(CliError::Usage(_))
+        ));
+
+        let mut session = CliSession::new(MatchingEngine::new());
+        session.run("market add BTC/USD").unwrap();
+        let maker = session.run("order limit sell 2 @ 30000 BTC/USD").unwrap();
+        assert!(maker.starts_with("order 1 New"));
+        let taker = session.run("order market buy 0.5 btc-usd").unwrap();
+        assert!(taker.contains("Filled"));
+        assert!(taker.contains("trade 0.5 @ 30000 against order 1"));
+
+        let book = session.run("book BTC/USD").unwrap();
+        assert!(book.contains("1.5            30000  ask"));
+        assert_eq!(session.run("cancel 1").unwrap(), "cancelled order 1");
+        assert!(matches!(session.run("cancel 1"), Err(CliError::Engine(_))));
+        assert!(session.run("book ETH/USD").is_err());
+    }
+}
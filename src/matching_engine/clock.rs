@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the engine gets the time from, in milliseconds since the Unix
+/// epoch: order and trade timestamps, good-till-date expiry, rate limits
+/// and stats windows all follow it.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// The wall clock, used unless the engine is given another one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        current_timestamp()
+    }
+}
+
+// Current wall-clock time in milliseconds since the Unix epoch.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// Current wall-clock time in milliseconds since the Unix epoch. The
+// system clock is unavailable in the browser, so this asks JavaScript.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn current_timestamp() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+pub(super) fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when told to, for tests and simulations. Clones
+/// share the same time, so one can be kept to drive the engine's.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::{MatchingEngine, TradingPair};
+    use crate::matching_engine::orderbook::{BidOrAsk, Order, TimeInForce};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_manual_clock_drives_timestamps_and_expiry() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let clock = ManualClock::new(1_000);
+        let mut engine = MatchingEngine::new();
+        engine.set_clock(clock.clone());
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+
+        let resting = engine
+            .place_limit_order(
+                pair.clone(),
+                dec!(100),
+                Order::new(BidOrAsk::Ask, dec!(2))
+                    .with_time_in_force(TimeInForce::GoodTillDate(5_000)),
+            )
+            .unwrap();
+        assert_eq!(resting.timestamp, 1_000);
+
+        clock.advance(1_000);
+        let taker = engine
+            .place_market_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        assert_eq!(taker.trades[0].timestamp, 2_000);
+        assert_eq!(engine.stats(pair.clone()).unwrap().volume, dec!(1));
+
+        assert!(engine.expire_orders().is_empty());
+        clock.set(5_000);
+        assert_eq!(engine.expire_orders(), [(pair.clone(), resting.order_id)]);
+
+        // The 24 hour stats window follows the clock too.
+        clock.advance(24 * 60 * 60 * 1000);
+        assert_eq!(engine.stats(pair).unwrap().volume, dec!(0));
+    }
+}
@@ -0,0 +1,507 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::batch::{BatchOp, BatchOpResult};
+use super::config::{MarketConfig, NonConformingOrders};
+use super::engine::{MarketState, MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::fees::FeeSchedule;
+use super::metrics::Stopwatch;
+use super::orderbook::{
+    AccountId, AuctionResult, ExecutionReport, Order, OrderId, SelfTradePrevention,
+};
+use super::positions::PositionLimits;
+use super::ratelimit::RateLimits;
+use super::reference::ReferenceSource;
+use super::session::{SessionEnd, SessionId};
+use super::triggers::OrderType;
+
+/// Every state-changing request `MatchingEngine` accepts, as plain data, so
+/// inputs can be logged and replayed.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EngineCommand {
+    AddMarket {
+        pair: TradingPair,
+        #[cfg_attr(feature = "serde", serde(default))]
+        config: MarketConfig,
+    },
+    SetSelfTradePrevention {
+        pair: TradingPair,
+        mode: Option<SelfTradePrevention>,
+    },
+    RemoveMarket {
+        pair: TradingPair,
+    },
+    SetMarketState {
+        pair: TradingPair,
+        state: MarketState,
+    },
+    UpdateMarketConfig {
+        pair: TradingPair,
+        config: MarketConfig,
+        #[cfg_attr(feature = "serde", serde(default))]
+        non_conforming: NonConformingOrders,
+    },
+    Uncross {
+        pair: TradingPair,
+    },
+    SetFeeSchedule {
+        pair: TradingPair,
+        schedule: FeeSchedule,
+    },
+    SetPriceBand {
+        pair: TradingPair,
+        percent: Option<Decimal>,
+    },
+    SetReferencePrice {
+        pair: TradingPair,
+        price: Decimal,
+    },
+    SetReferenceSource {
+        pair: TradingPair,
+        source: ReferenceSource,
+    },
+    PlaceOrder {
+        pair: TradingPair,
+        order_type: OrderType,
+        order: Order,
+    },
+    PlaceOcoOrder {
+        pair: TradingPair,
+        first: (OrderType, Order),
+        second: (OrderType, Order),
+    },
+    CancelOrder {
+        pair: TradingPair,
+        order_id: OrderId,
+    },
+    CancelAll {
+        pair: TradingPair,
+    },
+    CancelAllForAccount {
+        account: AccountId,
+    },
+    /// Cancels the good-till-date orders that have expired, in every market.
+    ExpireOrders,
+    AmendOrder {
+        pair: TradingPair,
+        order_id: OrderId,
+        new_price: Decimal,
+        new_size: Decimal,
+    },
+    Batch {
+        pair: TradingPair,
+        ops: Vec<BatchOp>,
+    },
+    ActivateKillSwitch {
+        account: Option<AccountId>,
+    },
+    ResetKillSwitch {
+        account: Option<AccountId>,
+    },
+    SetRateLimits {
+        limits: RateLimits,
+    },
+    SetPositionLimits {
+        account: AccountId,
+        limits: Option<PositionLimits>,
+    },
+    Login {
+        account: AccountId,
+        cancel_on_disconnect: bool,
+        heartbeat_timeout: Option<u64>,
+    },
+    Heartbeat {
+        session: SessionId,
+    },
+    CloseSession {
+        session: SessionId,
+        reason: SessionEnd,
+    },
+    /// Times out the sessions whose heartbeats are overdue.
+    ExpireSessions,
+    EnableBalanceChecks,
+    Deposit {
+        account: AccountId,
+        asset: String,
+        amount: Decimal,
+    },
+    Withdraw {
+        account: AccountId,
+        asset: String,
+        amount: Decimal,
+    },
+}
+
+impl EngineCommand {
+    /// The market the command applies to, if it targets a single one.
+    pub fn pair(&self) -> Option<&TradingPair> {
+        match self {
+            EngineCommand::AddMarket { pair, .. }
+            | EngineCommand::SetSelfTradePrevention { pair, .. }
+            | EngineCommand::RemoveMarket { pair }
+            | EngineCommand::SetMarketState { pair, .. }
+            | EngineCommand::UpdateMarketConfig { pair, .. }
+            | EngineCommand::Uncross { pair }
+            | EngineCommand::SetFeeSchedule { pair, .. }
+            | EngineCommand::SetPriceBand { pair, .. }
+            | EngineCommand::SetReferencePrice { pair, .. }
+            | EngineCommand::SetReferenceSource { pair, .. }
+            | EngineCommand::PlaceOrder { pair, .. }
+            | EngineCommand::PlaceOcoOrder { pair, .. }
+            | EngineCommand::CancelOrder { pair, .. }
+            | EngineCommand::CancelAll { pair }
+            | EngineCommand::AmendOrder { pair, .. }
+            | EngineCommand::Batch { pair, .. } => Some(pair),
+            EngineCommand::CancelAllForAccount { .. }
+            | EngineCommand::ExpireOrders
+            | EngineCommand::ActivateKillSwitch { .. }
+            | EngineCommand::ResetKillSwitch { .. }
+            | EngineCommand::SetRateLimits { .. }
+            | EngineCommand::SetPositionLimits { .. }
+            | EngineCommand::Login { .. }
+            | EngineCommand::Heartbeat { .. }
+            | EngineCommand::CloseSession { .. }
+            | EngineCommand::ExpireSessions
+            | EngineCommand::EnableBalanceChecks
+            | EngineCommand::Deposit { .. }
+            | EngineCommand::Withdraw { .. } => None,
+        }
+    }
+
+    /// Snake-case name of the variant, as used in metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineCommand::AddMarket { .. } => "add_market",
+            EngineCommand::SetSelfTradePrevention { .. } => "set_self_trade_prevention",
+            EngineCommand::RemoveMarket { .. } => "remove_market",
+            EngineCommand::SetMarketState { .. } => "set_market_state",
+            EngineCommand::UpdateMarketConfig { .. } => "update_market_config",
+            EngineCommand::Uncross { .. } => "uncross",
+            EngineCommand::SetFeeSchedule { .. } => "set_fee_schedule",
+            EngineCommand::SetPriceBand { .. } => "set_price_band",
+            EngineCommand::SetReferencePrice { .. } => "set_reference_price",
+            EngineCommand::SetReferenceSource { .. } => "set_reference_source",
+            EngineCommand::PlaceOrder { .. } => "place_order",
+            EngineCommand::PlaceOcoOrder { .. } => "place_oco_order",
+            EngineCommand::CancelOrder { .. } => "cancel_order",
+            EngineCommand::CancelAll { .. } => "cancel_all",
+            EngineCommand::CancelAllForAccount { .. } => "cancel_all_for_account",
+            EngineCommand::ExpireOrders => "expire_orders",
+            EngineCommand::AmendOrder { .. } => "amend_order",
+            EngineCommand::Batch { .. } => "batch",
+            EngineCommand::ActivateKillSwitch { .. } => "activate_kill_switch",
+            EngineCommand::ResetKillSwitch { .. } => "reset_kill_switch",
+            EngineCommand::SetRateLimits { .. } => "set_rate_limits",
+            EngineCommand::SetPositionLimits { .. } => "set_position_limits",
+            EngineCommand::Login { .. } => "login",
+            EngineCommand::Heartbeat { .. } => "heartbeat",
+            EngineCommand::CloseSession { .. } => "close_session",
+            EngineCommand::ExpireSessions => "expire_sessions",
+            EngineCommand::EnableBalanceChecks => "enable_balance_checks",
+            EngineCommand::Deposit { .. } => "deposit",
+            EngineCommand::Withdraw { .. } => "withdraw",
+        }
+    }
+}
+
+/// An input stamped with its position in the engine's input stream and the
+/// time it was accepted. Processing the same sequence of these always
+/// produces the same state, trades and reports, timestamps included.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SequencedCommand {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub command: EngineCommand,
+}
+
+/// What an `EngineCommand` returned; one variant per result type of the
+/// corresponding `MatchingEngine` method.
+#[derive(Debug)]
+pub enum CommandOutput {
+    Done,
+    Report(ExecutionReport),
+    OcoReports(Box<(ExecutionReport, Option<ExecutionReport>)>),
+    Cancelled(Order),
+    CancelledIds(Vec<OrderId>),
+    CancelledInMarkets(Vec<(TradingPair, OrderId)>),
+    Auction(AuctionResult),
+    Batch(Vec<BatchOpResult>),
+    Session(SessionId),
+}
+
+impl MatchingEngine {
+    /// Stamps `command` as the next input, at the engine clock's time. The engine's
+    /// sequence only advances once the result is passed to `process`.
+    pub fn stamp(&self, command: EngineCommand) -> SequencedCommand {
+        SequencedCommand {
+            sequence: self.sequence() + 1,
+            timestamp: self.now(),
+            command,
+        }
+    }
+
+    /// Applies a sequenced input, using its timestamp instead of the system
+    /// clock. Inputs must arrive exactly in sequence; anything else is
+    /// refused without touching the state.
+    pub fn process(&mut self, input: SequencedCommand) -> Result<CommandOutput, EngineError> {
+        self.check_sequence(input.sequence)?;
+        self.set_sequence(input.sequence);
+
+        let pair = input.command.pair().cloned();
+        self.set_time(pair.as_ref(), Some(input.timestamp));
+        let output = self.execute(input.command);
+        self.set_time(pair.as_ref(), None);
+        output
+    }
+
+    /// Processes `inputs` in order, returning each command's result. Stops
+    /// at the first input that is out of sequence.
+    pub fn replay(
+        &mut self,
+        inputs: impl IntoIterator<Item = SequencedCommand>,
+    ) -> Result<Vec<Result<CommandOutput, EngineError>>, EngineError> {
+        let mut outputs = Vec::new();
+        for input in inputs {
+            self.check_sequence(input.sequence)?;
+            outputs.push(self.process(input));
+        }
+        Ok(outputs)
+    }
+
+    fn check_sequence(&self, sequence: u64) -> Result<(), EngineError> {
+        let expected = self.sequence() + 1;
+        if sequence != expected {
+            return Err(EngineError::OutOfSequence {
+                expected,
+                got: sequence,
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies `command` through the matching public method, recording how
+    /// long it took in the engine's metrics.
+    pub fn execute(&mut self, command: EngineCommand) -> Result<CommandOutput, EngineError> {
+        let kind = command.kind();
+        let started = Stopwatch::start();
+        let output = self.apply(command);
+        self.metrics_mut().record_command(kind, started.elapsed());
+        output
+    }
+
+    fn apply(&mut self, command: EngineCommand) -> Result<CommandOutput, EngineError> {
+        match command {
+            EngineCommand::AddMarket { pair, config } => {
+                self.add_new_market(pair, config);
+                Ok(CommandOutput::Done)
+            }
+            EngineCommand::SetSelfTradePrevention { pair, mode } => self
+                .set_self_trade_prevention(pair, mode)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::RemoveMarket { pair } => {
+                self.remove_market(pair).map(CommandOutput::CancelledIds)
+            }
+            EngineCommand::SetMarketState { pair, state } => self
+                .set_market_state(pair, state)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::UpdateMarketConfig {
+                pair,
+                config,
+                non_conforming,
+            } => self
+                .update_market_config(pair, config, non_conforming)
+                .map(CommandOutput::CancelledIds),
+            EngineCommand::Uncross { pair } => self.uncross(pair).map(CommandOutput::Auction),
+            EngineCommand::SetFeeSchedule { pair, schedule } => self
+                .set_fee_schedule(pair, schedule)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::SetPriceBand { pair, percent } => self
+                .set_price_band(pair, percent)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::SetReferencePrice { pair, price } => self
+                .set_reference_price(pair, price)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::SetReferenceSource { pair, source } => self
+                .set_reference_source(pair, source)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::PlaceOrder {
+                pair,
+                order_type,
+                order,
+            } => self
+                .place_order(pair, order_type, order)
+                .map(CommandOutput::Report),
+            EngineCommand::PlaceOcoOrder {
+                pair,
+                first,
+                second,
+            } => self
+                .place_oco_order(pair, first, second)
+                .map(|reports| CommandOutput::OcoReports(Box::new(reports))),
+            EngineCommand::CancelOrder { pair, order_id } => self
+                .cancel_order(pair, order_id)
+                .map(CommandOutput::Cancelled),
+            EngineCommand::CancelAll { pair } => {
+                self.cancel_all(pair).map(CommandOutput::CancelledIds)
+            }
+            EngineCommand::CancelAllForAccount { account } => self
+                .cancel_all_for_account(account)
+                .map(CommandOutput::CancelledInMarkets),
+            EngineCommand::ExpireOrders => {
+                Ok(CommandOutput::CancelledInMarkets(self.expire_orders()))
+            }
+            EngineCommand::AmendOrder {
+                pair,
+                order_id,
+                new_price,
+                new_size,
+            } => self
+                .amend_order(pair, order_id, new_price, new_size)
+                .map(CommandOutput::Report),
+            EngineCommand::Batch { pair, ops } => self.batch(pair, ops).map(CommandOutput::Batch),
+            EngineCommand::ActivateKillSwitch { account } => Ok(CommandOutput::CancelledInMarkets(
+                self.activate_kill_switch(account),
+            )),
+            EngineCommand::ResetKillSwitch { account } => {
+                self.reset_kill_switch(account);
+                Ok(CommandOutput::Done)
+            }
+            EngineCommand::SetRateLimits { limits } => {
+                self.set_rate_limits(limits);
+                Ok(CommandOutput::Done)
+            }
+            EngineCommand::SetPositionLimits { account, limits } => {
+                self.set_position_limits(account, limits);
+                Ok(CommandOutput::Done)
+            }
+            EngineCommand::Login {
+                account,
+                cancel_on_disconnect,
+                heartbeat_timeout,
+            } => Ok(CommandOutput::Session(self.login(
+                account,
+                cancel_on_disconnect,
+                heartbeat_timeout,
+            ))),
+            EngineCommand::Heartbeat { session } => {
+                self.heartbeat(session).map(|_| CommandOutput::Done)
+            }
+            EngineCommand::CloseSession { session, reason } => self
+                .close_session(session, reason)
+                .map(CommandOutput::CancelledInMarkets),
+            EngineCommand::ExpireSessions => {
+                Ok(CommandOutput::CancelledInMarkets(self.expire_sessions()))
+            }
+            EngineCommand::EnableBalanceChecks => {
+                self.enable_balance_checks().map(|_| CommandOutput::Done)
+            }
+            EngineCommand::Deposit {
+                account,
+                asset,
+                amount,
+            } => self
+                .deposit(account, &asset, amount)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::Withdraw {
+                account,
+                asset,
+                amount,
+            } => self
+                .withdraw(account, &asset, amount)
+                .map(|_| CommandOutput::Done),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::BidOrAsk;
+    use rust_decimal_macros::dec;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    fn inputs() -> Vec<SequencedCommand> {
+        let place = |price, bid_or_ask, size| EngineCommand::PlaceOrder {
+            pair: btc_usd(),
+            order_type: OrderType::Limit { price },
+            order: Order::new(bid_or_ask, size),
+        };
+        let mut commands = vec![
+            EngineCommand::AddMarket {
+                pair: btc_usd(),
+                config: MarketConfig::default(),
+            },
+            place(dec!(100), BidOrAsk::Bid, dec!(2)),
+            place(dec!(101), BidOrAsk::Ask, dec!(1)),
+            place(dec!(99), BidOrAsk::Ask, dec!(3)),
+            EngineCommand::CancelOrder {
+                pair: btc_usd(),
+                order_id: OrderId(2),
+            },
+        ];
+        // Enough accounts that an unordered set would hash differently.
+        commands.extend((1..=16).map(|account| EngineCommand::ActivateKillSwitch {
+            account: Some(AccountId(account)),
+        }));
+        commands
+            .into_iter()
+            .enumerate()
+            .map(|(i, command)| SequencedCommand {
+                sequence: i as u64 + 1,
+                timestamp: 1_000 + i as u64,
+                command,
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_replay_is_deterministic() {
+        let mut first = MatchingEngine::new();
+        let mut second = MatchingEngine::new();
+        let first_outputs = first.replay(inputs()).unwrap();
+        let second_outputs = second.replay(inputs()).unwrap();
+
+        assert_eq!(first.sequence(), 21);
+        assert_eq!(first.state_hash(), second.state_hash());
+        assert_eq!(
+            format!("{:?}", first_outputs),
+            format!("{:?}", second_outputs)
+        );
+        match &first_outputs[3] {
+            Ok(CommandOutput::Report(report)) => {
+                assert_eq!(report.trades[0].timestamp, 1_003);
+            }
+            other => panic!("Unexpected output: {:?}", other),
+        }
+
+        let mut diverged = MatchingEngine::new();
+        let mut inputs = inputs();
+        inputs.pop();
+        diverged.replay(inputs).unwrap();
+        assert_ne!(diverged.state_hash(), first.state_hash());
+    }
+
+    #[test]
+    fn test_process_rejects_out_of_sequence_input() {
+        let mut engine = MatchingEngine::new();
+        let mut inputs = inputs();
+        inputs.remove(1);
+        assert!(matches!(
+            engine.replay(inputs),
+            Err(EngineError::OutOfSequence {
+                expected: 2,
+                got: 3
+            })
+        ));
+        assert_eq!(engine.sequence(), 1);
+    }
+}
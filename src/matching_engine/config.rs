@@ -0,0 +1,217 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::error::{EngineError, OrderBookError};
+use super::orderbook::{MarketOrderPolicy, MatchingAlgorithm};
+use super::triggers::OrderType;
+
+/// Trading rules of a market. Every limit is optional; the default accepts
+/// any price and size.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MarketConfig {
+    /// Prices must be a multiple of this.
+    pub tick_size: Option<Decimal>,
+    /// Sizes must be a multiple of this.
+    pub lot_size: Option<Decimal>,
+    /// Smallest `price * size` of a priced order.
+    pub min_notional: Option<Decimal>,
+    pub max_order_size: Option<Decimal>,
+    /// How fills are shared among the resting orders at a price level.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub matching: MatchingAlgorithm,
+    /// What happens to market orders the book cannot fill completely.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub market_orders: MarketOrderPolicy,
+}
+
+impl MarketConfig {
+    pub fn with_tick_size(mut self, tick_size: Decimal) -> MarketConfig {
+        self.tick_size = Some(tick_size);
+        self
+    }
+
+    pub fn with_lot_size(mut self, lot_size: Decimal) -> MarketConfig {
+        self.lot_size = Some(lot_size);
+        self
+    }
+
+    pub fn with_min_notional(mut self, min_notional: Decimal) -> MarketConfig {
+        self.min_notional = Some(min_notional);
+        self
+    }
+
+    pub fn with_max_order_size(mut self, max_order_size: Decimal) -> MarketConfig {
+        self.max_order_size = Some(max_order_size);
+        self
+    }
+
+    pub fn with_matching_algorithm(mut self, matching: MatchingAlgorithm) -> MarketConfig {
+        self.matching = matching;
+        self
+    }
+
+    pub fn with_market_order_policy(mut self, policy: MarketOrderPolicy) -> MarketConfig {
+        self.market_orders = policy;
+        self
+    }
+
+    /// Checks the rules themselves: every increment and limit set must be
+    /// positive.
+    pub fn verify(&self) -> Result<(), EngineError> {
+        for (name, value) in [
+            ("tick size", self.tick_size),
+            ("lot size", self.lot_size),
+            ("max order size", self.max_order_size),
+        ] {
+            if value.is_some_and(|value| value <= Decimal::ZERO) {
+                return Err(EngineError::InvalidMarketConfig(format!(
+                    "{} must be positive",
+                    name
+                )));
+            }
+        }
+        if self.min_notional.is_some_and(|min| min < Decimal::ZERO) {
+            return Err(EngineError::InvalidMarketConfig(
+                "min notional must not be negative".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks an order of `size` entering the market as `order_type`.
+    /// Market orders have no price, so only their size is checked.
+    pub fn validate(&self, order_type: OrderType, size: Decimal) -> Result<(), OrderBookError> {
+        self.validate_working(order_type, size)?;
+        // Stops with no limit price have no notional to check.
+        if let OrderType::Limit { price }
+        | OrderType::StopLimit {
+            limit_price: price, ..
+        } = order_type
+        {
+            self.validate_notional(price, size)?;
+        }
+        Ok(())
+    }
+
+    /// Checks a working order with `size` left against everything
+    /// `validate` does but the notional, which partial fills shrink.
+    pub fn validate_working(
+        &self,
+        order_type: OrderType,
+        size: Decimal,
+    ) -> Result<(), OrderBookError> {
+        self.validate_size(size)?;
+        let prices = match order_type {
+            OrderType::Market => vec![],
+            OrderType::Limit { price } => vec![price],
+            OrderType::Stop { stop_price } => vec![stop_price],
+            OrderType::StopLimit {
+                stop_price,
+                limit_price,
+            } => vec![stop_price, limit_price],
+            OrderType::TrailingStop { offset } if !offset.is_valid() => {
+                return Err(OrderBookError::InvalidTrailingOffset(offset))
+            }
+            OrderType::TrailingStop { .. } => vec![],
+        };
+        for price in &prices {
+            self.validate_price(*price)?;
+        }
+        Ok(())
+    }
+
+    pub fn validate_price(&self, price: Decimal) -> Result<(), OrderBookError> {
+        if price <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidPrice(price));
+        }
+        match self.tick_size {
+            Some(tick_size) if !(price % tick_size).is_zero() => {
+                Err(OrderBookError::OffTick { price, tick_size })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn validate_size(&self, size: Decimal) -> Result<(), OrderBookError> {
+        if size <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidSize(size));
+        }
+        if let Some(lot_size) = self.lot_size {
+            if !(size % lot_size).is_zero() {
+                return Err(OrderBookError::OddLot { size, lot_size });
+            }
+        }
+        match self.max_order_size {
+            Some(max) if size > max => Err(OrderBookError::SizeTooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn validate_notional(&self, price: Decimal, size: Decimal) -> Result<(), OrderBookError> {
+        match self.min_notional {
+            Some(min) if price * size < min => Err(OrderBookError::NotionalTooSmall {
+                notional: price * size,
+                min,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What `MatchingEngine::update_market_config` does with working orders
+/// the new rules would not accept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NonConformingOrders {
+    /// Refuse the update and leave everything as it was.
+    #[default]
+    Reject,
+    /// Cancel them, along with their OCO siblings.
+    Cancel,
+    /// Leave them working; the new rules only apply to new orders and amends.
+    Keep,
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_market_config_validate() {
+        let config = MarketConfig::default()
+            .with_tick_size(dec!(0.5))
+            .with_lot_size(dec!(0.01))
+            .with_min_notional(dec!(10))
+            .with_max_order_size(dec!(100));
+        let limit = |price| OrderType::Limit { price };
+
+        assert!(config.validate(limit(dec!(100.5)), dec!(0.1)).is_ok());
+        assert_eq!(
+            config.validate(limit(dec!(100.25)), dec!(0.1)),
+            Err(OrderBookError::OffTick {
+                price: dec!(100.25),
+                tick_size: dec!(0.5),
+            })
+        );
+        assert!(config.validate(limit(dec!(100)), dec!(0.105)).is_err());
+        assert!(config.validate(limit(dec!(100)), dec!(0.05)).is_err());
+        assert!(config.validate(limit(dec!(100)), dec!(101)).is_err());
+        assert!(config.validate(OrderType::Market, dec!(0.05)).is_ok());
+        assert!(config
+            .validate(
+                OrderType::StopLimit {
+                    stop_price: dec!(99.9),
+                    limit_price: dec!(99.5),
+                },
+                dec!(1),
+            )
+            .is_err());
+        assert!(MarketConfig::default()
+            .validate(limit(dec!(1.2345)), dec!(0.0001))
+            .is_ok());
+    }
+}
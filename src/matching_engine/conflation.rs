@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::orderbook::{BidOrAsk, BookUpdate};
+
+/// What a `Conflator` does with the changes that pile up between flushes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ConflationPolicy {
+    /// Keep the latest update of every changed level, however many there
+    /// are. The backlog never grows past the number of levels in the book.
+    #[default]
+    Merge,
+    /// Like `Merge` up to `max_levels` changed levels; past that the
+    /// changes are dropped and the subscriber is resynchronized with a
+    /// snapshot instead.
+    Drop { max_levels: usize },
+}
+
+/// What a subscriber is sent when a `Conflator` is flushed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflated {
+    /// The latest update of every level changed since the last flush, in
+    /// the order they last changed. `sequence` is the book sequence the
+    /// subscriber is brought up to.
+    Diff {
+        sequence: u64,
+        updates: Vec<BookUpdate>,
+    },
+    /// The changes were dropped; a fresh snapshot is needed.
+    Resync,
+}
+
+/// One subscriber's pending book changes for one market, coalesced so that
+/// a burst of updates to a level costs a single update on the next flush.
+/// Level sizes in `BookUpdate`s are totals, so only each level's last
+/// update matters; sequence numbers in a diff therefore have gaps.
+#[derive(Debug, Clone)]
+pub struct Conflator {
+    policy: ConflationPolicy,
+    sequence: u64,
+    pending: HashMap<(BidOrAsk, Decimal), BookUpdate>,
+    overflowed: bool,
+}
+
+impl Conflator {
+    /// Starts from a snapshot taken at book `sequence`.
+    pub fn new(policy: ConflationPolicy, sequence: u64) -> Conflator {
+        Conflator {
+            policy,
+            sequence,
+            pending: HashMap::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Sequence of the last update taken in.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && !self.overflowed
+    }
+
+    /// Takes in an update. Updates the subscriber already has, with a
+    /// sequence at or below the snapshot's, are skipped.
+    pub fn push(&mut self, update: BookUpdate) {
+        if update.sequence() <= self.sequence {
+            return;
+        }
+        self.sequence = update.sequence();
+        if self.overflowed {
+            return;
+        }
+        let (bid_or_ask, price) = match update {
+            BookUpdate::Add {
+                bid_or_ask, price, ..
+            }
+            | BookUpdate::Reduce {
+                bid_or_ask, price, ..
+            }
+            | BookUpdate::Remove {
+                bid_or_ask, price, ..
+            } => (bid_or_ask, price),
+        };
+        self.pending.insert((bid_or_ask, price), update);
+        if let ConflationPolicy::Drop { max_levels } = self.policy {
+            if self.pending.len() > max_levels {
+                self.pending.clear();
+                self.overflowed = true;
+            }
+        }
+    }
+
+    /// Everything pending since the last flush, or `None` if nothing changed.
+    pub fn flush(&mut self) -> Option<Conflated> {
+        if std::mem::take(&mut self.overflowed) {
+            return Some(Conflated::Resync);
+        }
+        if self.pending.is_empty() {
+            return None;
+        }
+        let mut updates: Vec<BookUpdate> = self.pending.drain().map(|(_, update)| update).collect();
+        updates.sort_by_key(BookUpdate::sequence);
+        Some(Conflated::Diff {
+            sequence: self.sequence,
+            updates,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_conflator_merges_and_drops() {
+        let add = |sequence, price, size| BookUpdate::Add {
+            sequence,
+            bid_or_ask: BidOrAsk::Bid,
+            price,
+            size,
+        };
+        let mut conflator = Conflator::new(ConflationPolicy::Merge, 1);
+        conflator.push(add(1, dec!(100), dec!(1)));
+        assert!(conflator.is_empty());
+        conflator.push(add(2, dec!(100), dec!(2)));
+        conflator.push(add(3, dec!(99), dec!(1)));
+        conflator.push(BookUpdate::Reduce {
+            sequence: 4,
+            bid_or_ask: BidOrAsk::Bid,
+            price: dec!(100),
+            size: dec!(0.5),
+        });
+        assert_eq!(
+            conflator.flush(),
+            Some(Conflated::Diff {
+                sequence: 4,
+                updates: vec![
+                    add(3, dec!(99), dec!(1)),
+                    BookUpdate::Reduce {
+                        sequence: 4,
+                        bid_or_ask: BidOrAsk::Bid,
+                        price: dec!(100),
+                        size: dec!(0.5),
+                    },
+                ],
+            })
+        );
+        assert_eq!(conflator.flush(), None);
+
+        // Too many changed levels: the backlog is dropped for a snapshot.
+        let mut conflator = Conflator::new(ConflationPolicy::Drop { max_levels: 1 }, 0);
+        conflator.push(add(1, dec!(100), dec!(1)));
+        conflator.push(add(2, dec!(99), dec!(1)));
+        conflator.push(add(3, dec!(98), dec!(1)));
+        assert_eq!(conflator.sequence(), 3);
+        assert_eq!(conflator.flush(), Some(Conflated::Resync));
+        assert!(conflator.is_empty());
+    }
+}
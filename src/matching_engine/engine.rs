@@ -1,10 +1,38 @@
-use std::collections::HashMap;
+#![allow(dead_code)]
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
 
 use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use tracing::{debug, field, info, info_span, warn, Span};
 
-use super::orderbook::{Order, OrderBook};
+use super::accounts::{Accounts, Reservation};
+use super::analytics::TradeHistory;
+use super::candles::{Candle, CandleAggregator, CandleInterval};
+use super::clock::{system_clock, Clock};
+use super::config::{MarketConfig, NonConformingOrders};
+use super::error::{EngineError, OrderBookError};
+use super::events::{EngineEvent, EventListener};
+use super::fees::FeeSchedule;
+use super::ledger::{Ledger, LedgerEntry};
+use super::metrics::EngineMetrics;
+use super::orderbook::{
+    AccountId, AuctionResult, BidOrAsk, BookSnapshot, DepthSnapshot, ExecutionReport, FillEstimate,
+    Order, OrderBook, OrderId, OrderStatus, SelfTradePrevention,
+};
+use super::positions::Positions;
+use super::ratelimit::{RateAction, RateLimiter, RateLimits};
+use super::reference::{ReferencePrice, ReferenceSource};
+use super::session::{Session, SessionId, Sessions};
+use super::stats::{MarketStats, MarketSummary};
+use super::trade::Trade;
+use super::triggers::{OrderType, TriggerManager};
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
+#[derive(Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TradingPair {
     base: String,
     quote: String,
@@ -15,43 +43,3032 @@ impl TradingPair {
         TradingPair { base, quote }
     }
 
-    pub fn to_string(&self) -> String {
-        format!("{}/{}", self.base, self.quote)
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn quote(&self) -> &str {
+        &self.quote
+    }
+
+    // The asset an order on `bid_or_ask` pays with.
+    pub(super) fn funding_asset(&self, bid_or_ask: BidOrAsk) -> &str {
+        match bid_or_ask {
+            BidOrAsk::Bid => &self.quote,
+            BidOrAsk::Ask => &self.base,
+        }
+    }
+}
+
+/// Parses a pair written `BASE/QUOTE` or `BASE-QUOTE` (as in URLs). Asset
+/// names must be alphanumeric and are upper-cased, so `btc-usd` and
+/// `BTC/USD` are the same pair.
+impl FromStr for TradingPair {
+    type Err = EngineError;
+
+    fn from_str(pair: &str) -> Result<TradingPair, EngineError> {
+        let invalid = || EngineError::InvalidTradingPair(pair.to_string());
+        let (base, quote) = pair.trim().split_once(['-', '/']).ok_or_else(invalid)?;
+        let valid =
+            |asset: &str| !asset.is_empty() && asset.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid(base) || !valid(quote) || base.eq_ignore_ascii_case(quote) {
+            return Err(invalid());
+        }
+        Ok(TradingPair::new(base.to_uppercase(), quote.to_uppercase()))
+    }
+}
+
+impl TryFrom<&str> for TradingPair {
+    type Error = EngineError;
+
+    fn try_from(pair: &str) -> Result<TradingPair, EngineError> {
+        pair.parse()
+    }
+}
+
+impl fmt::Display for TradingPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
+/// A working order as returned by the open-order queries. Resting orders
+/// are reported as `OrderType::Limit`, pending stops with their stop type.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OpenOrder {
+    pub pair: TradingPair,
+    pub order_id: OrderId,
+    pub owner: AccountId,
+    pub bid_or_ask: BidOrAsk,
+    pub order_type: OrderType,
+    pub remaining_size: Decimal,
+}
+
+/// Which commands a market accepts. Halted markets accept nothing that
+/// changes the book; cancel-only markets let orders be cancelled but not
+/// placed or amended. Markets in an auction accept orders but only match
+/// them when the book is uncrossed on reopening.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MarketState {
+    #[default]
+    Open,
+    CancelOnly,
+    Halted,
+    Auction,
+}
+
+impl fmt::Display for MarketState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MarketState::Open => "open",
+            MarketState::CancelOnly => "cancel-only",
+            MarketState::Halted => "halted",
+            MarketState::Auction => "in auction",
+        };
+        f.write_str(name)
+    }
+}
+
+// Everything the engine keeps for a single trading pair.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Market {
+    pair: TradingPair,
+    #[cfg_attr(feature = "serde", serde(default))]
+    state: MarketState,
+    #[cfg_attr(feature = "serde", serde(default))]
+    config: MarketConfig,
+    orderbook: OrderBook,
+    triggers: TriggerManager,
+    #[cfg_attr(feature = "serde", serde(default))]
+    reference: ReferencePrice,
+    // One-cancels-other siblings, linked in both directions.
+    oco_links: HashMap<OrderId, OrderId>,
+    // Every client order id ever accepted per account, so retried
+    // submissions are caught even after the original order has left the book.
+    #[cfg_attr(feature = "serde", serde(with = "map_as_pairs"))]
+    client_order_ids: HashMap<(AccountId, String), OrderId>,
+    stats: MarketStats,
+    candles: CandleAggregator,
+    recent_trades: TradeHistory,
+    // Funds held for working orders while balance checks are enabled.
+    reservations: HashMap<OrderId, Reservation>,
+    // Good-till-date orders by expiry time. Entries for orders that have
+    // already left the market are skipped when they come due.
+    #[cfg_attr(feature = "serde", serde(default))]
+    expiries: BTreeSet<(u64, OrderId)>,
+    // Orders reserved for during the current operation, settled with it.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unsettled: Vec<OrderId>,
+    // Published to the engine's listeners after every operation.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    events: Vec<EngineEvent>,
+}
+
+impl Market {
+    fn new(pair: TradingPair, config: MarketConfig) -> Market {
+        let mut market = Market {
+            pair,
+            state: MarketState::Open,
+            config,
+            orderbook: OrderBook::new(),
+            triggers: TriggerManager::new(),
+            reference: ReferencePrice::default(),
+            oco_links: HashMap::new(),
+            client_order_ids: HashMap::new(),
+            stats: MarketStats::default(),
+            candles: CandleAggregator::default(),
+            recent_trades: TradeHistory::new(),
+            reservations: HashMap::new(),
+            expiries: BTreeSet::new(),
+            unsettled: Vec::new(),
+            events: Vec::new(),
+        };
+        market.set_config(config);
+        market
+    }
+
+    fn set_config(&mut self, config: MarketConfig) {
+        self.config = config;
+        self.orderbook.set_tick_size(config.tick_size);
+        self.orderbook.set_lot_size(config.lot_size);
+        self.orderbook.set_matching_algorithm(config.matching);
+        self.orderbook.set_market_order_policy(config.market_orders);
+    }
+
+    // Working orders, resting or pending, that `config` would not accept.
+    fn non_conforming(&self, config: &MarketConfig) -> Vec<OrderId> {
+        let resting = self.orderbook.orders().filter(|(price, order)| {
+            config
+                .validate_working(OrderType::Limit { price: *price }, order.remaining_size())
+                .is_err()
+        });
+        let pending = self.triggers.orders().filter(|(order_type, order)| {
+            config
+                .validate_working(*order_type, order.remaining_size())
+                .is_err()
+        });
+        resting
+            .map(|(_, order)| order.id())
+            .chain(pending.map(|(_, order)| order.id()))
+            .collect()
+    }
+
+    fn check_accepts_orders(&self) -> Result<(), EngineError> {
+        match self.state {
+            MarketState::Open | MarketState::Auction => Ok(()),
+            state => Err(self.unavailable(state)),
+        }
+    }
+
+    fn check_accepts_cancels(&self) -> Result<(), EngineError> {
+        match self.state {
+            MarketState::Halted => Err(self.unavailable(MarketState::Halted)),
+            _ => Ok(()),
+        }
+    }
+
+    fn unavailable(&self, state: MarketState) -> EngineError {
+        EngineError::MarketUnavailable {
+            pair: self.pair.clone(),
+            state,
+        }
+    }
+
+    // Span the log lines about one order are recorded under; the id is
+    // filled in once the book assigns it.
+    fn order_span(&self, order_id: Option<OrderId>, owner: AccountId) -> Span {
+        let span = info_span!(
+            "order",
+            order_id = field::Empty,
+            account = owner.0,
+            pair = %self.pair
+        );
+        if let Some(order_id) = order_id {
+            span.record("order_id", order_id.0);
+        }
+        span
+    }
+
+    // Logs how a submission ended, inside its order's span.
+    fn trace_report(&self, report: &ExecutionReport) {
+        let filled = report.filled_size;
+        match report.status {
+            OrderStatus::Rejected => warn!(reason = ?report.reason, "rejected"),
+            OrderStatus::Cancelled => info!(%filled, reason = ?report.reason, "cancelled"),
+            OrderStatus::PartiallyFilled => {
+                info!(%filled, remaining = %report.remaining_size, "partially filled")
+            }
+            OrderStatus::Filled => info!(%filled, "filled"),
+            OrderStatus::New | OrderStatus::Pending => {}
+        }
+    }
+
+    fn place_order(
+        &mut self,
+        order_type: OrderType,
+        order: Order,
+        accounts: Option<&mut Accounts>,
+    ) -> Result<ExecutionReport, EngineError> {
+        let span = self.order_span(None, order.owner());
+        let _entered = span.enter();
+        let result = self.submit_order(order_type, order, accounts);
+        match &result {
+            Ok(report) => self.trace_report(report),
+            Err(reason) => warn!(%reason, "rejected"),
+        }
+        result
+    }
+
+    fn submit_order(
+        &mut self,
+        order_type: OrderType,
+        mut order: Order,
+        accounts: Option<&mut Accounts>,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.check_accepts_orders()?;
+        self.config.validate(order_type, order.remaining_size())?;
+        if let Some(protection) = order.protection() {
+            if !protection.is_valid() {
+                return Err(OrderBookError::InvalidPriceProtection(protection).into());
+            }
+        }
+        if self.orderbook.in_auction()
+            && (order_type == OrderType::Market || !order.time_in_force().rests())
+        {
+            return Err(OrderBookError::NotAcceptedInAuction.into());
+        }
+        let expiry = order.time_in_force().expiry();
+        if let Some(expiry) = expiry {
+            if expiry <= self.orderbook.now() {
+                return Err(OrderBookError::AlreadyExpired(expiry).into());
+            }
+        }
+        let client_order_id = order
+            .client_order_id()
+            .map(|client_order_id| (order.owner(), client_order_id.to_string()));
+        if let Some(key) = &client_order_id {
+            if let Some(&order_id) = self.client_order_ids.get(key) {
+                return Err(OrderBookError::DuplicateClientOrderId {
+                    client_order_id: key.1.clone(),
+                    order_id,
+                }
+                .into());
+            }
+        }
+        if let OrderType::Limit { price } = order_type {
+            if let Some((low, high)) = self.orderbook.price_band().bounds() {
+                if price < low || price > high {
+                    return Err(OrderBookError::OutsidePriceBand { price, low, high }.into());
+                }
+            }
+            self.validate_slide(price, order.remaining_size(), &order)?;
+        }
+        if let Some(accounts) = accounts {
+            self.reserve(accounts, order_type, &order)?;
+        }
+
+        let (owner, bid_or_ask, size) = (order.owner(), order.bid_or_ask(), order.remaining_size());
+        let report = match order_type {
+            OrderType::Market => self.orderbook.fill_market_order(&mut order),
+            OrderType::Limit { price } => self.orderbook.add_limit_order(price, order),
+            OrderType::Stop { .. }
+            | OrderType::StopLimit { .. }
+            | OrderType::TrailingStop { .. } => {
+                let order_id = self.orderbook.reserve_order_id(&mut order);
+                Span::current().record("order_id", order_id.0);
+                let timestamp = order.timestamp();
+                self.triggers.add_order(order_type, order)?;
+                if let Some(expiry) = expiry {
+                    self.expiries.insert((expiry, order_id));
+                }
+                if let Some(client_order_id) = client_order_id {
+                    self.client_order_ids.insert(client_order_id, order_id);
+                }
+                self.events.push(EngineEvent::OrderAccepted {
+                    pair: self.pair.clone(),
+                    order_id,
+                    owner,
+                    bid_or_ask,
+                    order_type,
+                    size,
+                });
+                info!(side = ?bid_or_ask, ?order_type, %size, "accepted");
+
+                // A stop already through the reference price fires
+                // immediately, or once an auction is over.
+                if let Some(price) = self.reference.price() {
+                    self.move_reference(Some(price));
+                }
+                let mut report =
+                    ExecutionReport::new(order_id, OrderStatus::Pending, Vec::new(), size);
+                report.timestamp = timestamp;
+                return Ok(report);
+            }
+        };
+
+        if let Some(client_order_id) = client_order_id {
+            self.client_order_ids
+                .insert(client_order_id, report.order_id);
+        }
+        Span::current().record("order_id", report.order_id.0);
+        if report.status != OrderStatus::Rejected {
+            self.events.push(EngineEvent::OrderAccepted {
+                pair: self.pair.clone(),
+                order_id: report.order_id,
+                owner,
+                bid_or_ask,
+                order_type,
+                size,
+            });
+            info!(side = ?bid_or_ask, ?order_type, %size, "accepted");
+        }
+        if let Some(expiry) = expiry {
+            if self.is_working(report.order_id) {
+                self.expiries.insert((expiry, report.order_id));
+            }
+        }
+        self.process_report(&report);
+        Ok(report)
+    }
+
+    // Checks the price a post-only slide would move `order` to against the
+    // market's rules as well; the book only checks its own.
+    fn validate_slide(
+        &self,
+        price: Decimal,
+        size: Decimal,
+        order: &Order,
+    ) -> Result<(), EngineError> {
+        let slid = self.orderbook.slid_price(price, order);
+        if slid != price {
+            self.config
+                .validate(OrderType::Limit { price: slid }, size)?;
+        }
+        Ok(())
+    }
+
+    // Holds the funds `order` needs under the id the book is about to give
+    // it. Limit bids reserve their full cost at the limit price, market bids
+    // the cost of sweeping the asks for their size, past the owner's own
+    // asks where self-trade prevention would skip them.
+    fn reserve(
+        &mut self,
+        accounts: &mut Accounts,
+        order_type: OrderType,
+        order: &Order,
+    ) -> Result<(), EngineError> {
+        let size = order.remaining_size();
+        let (amount, price) = match (order.bid_or_ask(), order_type) {
+            (BidOrAsk::Ask, _) => (size, None),
+            (BidOrAsk::Bid, OrderType::Limit { price })
+            | (
+                BidOrAsk::Bid,
+                OrderType::StopLimit {
+                    limit_price: price, ..
+                },
+            ) => (size * price, Some(price)),
+            (BidOrAsk::Bid, OrderType::Market) => {
+                (self.orderbook.estimate_fill(order, None).notional, None)
+            }
+            (BidOrAsk::Bid, OrderType::Stop { .. } | OrderType::TrailingStop { .. }) => {
+                return Err(EngineError::UnfundedStop)
+            }
+        };
+        let asset = self.pair.funding_asset(order.bid_or_ask());
+        accounts.reserve(order.owner(), asset, amount)?;
+
+        let order_id = self.orderbook.peek_order_id();
+        self.reservations.insert(
+            order_id,
+            Reservation {
+                owner: order.owner(),
+                bid_or_ask: order.bid_or_ask(),
+                amount,
+                price,
+            },
+        );
+        self.unsettled.push(order_id);
+        Ok(())
+    }
+
+    // Moves the market to `state`. Reopening after an auction uncrosses the
+    // book first; the reference price only follows it once the market is
+    // open.
+    fn set_state(&mut self, state: MarketState) -> AuctionResult {
+        if self.state == state {
+            return AuctionResult::default();
+        }
+        let result = if state == MarketState::Open && self.orderbook.in_auction() {
+            self.uncross()
+        } else {
+            AuctionResult::default()
+        };
+        self.state = state;
+        self.events.push(EngineEvent::MarketStateChanged {
+            pair: self.pair.clone(),
+            state,
+        });
+        if state == MarketState::Auction {
+            self.orderbook.start_auction();
+        } else {
+            self.follow_reference(result.trades.last().map(|trade| trade.price));
+        }
+        if state == MarketState::Open {
+            self.move_reference(self.reference.price());
+        }
+        result
+    }
+
+    fn uncross(&mut self) -> AuctionResult {
+        let result = self.orderbook.uncross();
+        for &order_id in &result.self_trade_cancelled {
+            self.events.push(EngineEvent::OrderCancelled {
+                pair: self.pair.clone(),
+                order_id,
+            });
+        }
+        self.record_trades(&result.trades);
+        self.events.push(EngineEvent::AuctionUncrossed {
+            pair: self.pair.clone(),
+            price: result.price,
+            volume: result.volume(),
+        });
+        self.cancel_oco_siblings(&result.trades);
+        result
+    }
+
+    // Applies the trades buffered during the last operation to the
+    // balances, then releases whatever is still reserved for orders that
+    // are no longer working.
+    fn settle(&mut self, accounts: &mut Accounts) {
+        let mut touched = std::mem::take(&mut self.unsettled);
+        for event in &self.events {
+            match event {
+                EngineEvent::TradeExecuted { trade, .. } => {
+                    accounts.settle_trade(&self.pair, trade, &mut self.reservations);
+                    touched.extend([trade.maker_order_id, trade.taker_order_id]);
+                }
+                EngineEvent::OrderCancelled { order_id, .. }
+                | EngineEvent::OrderRejected {
+                    order_id: Some(order_id),
+                    ..
+                } => touched.push(*order_id),
+                _ => {}
+            }
+        }
+        for order_id in touched {
+            if self.is_working(order_id) {
+                continue;
+            }
+            if let Some(reservation) = self.reservations.remove(&order_id) {
+                let asset = self.pair.funding_asset(reservation.bid_or_ask);
+                accounts.release(reservation.owner, asset, reservation.amount);
+            }
+        }
+    }
+
+    fn amend_order(
+        &mut self,
+        order_id: OrderId,
+        new_price: Decimal,
+        new_size: Decimal,
+        accounts: Option<&mut Accounts>,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.check_accepts_orders()?;
+        self.config
+            .validate(OrderType::Limit { price: new_price }, new_size)?;
+        // The book's own checks (price band, auction, post-only) come
+        // before anything changes, so a refused amend is an error that
+        // leaves the order working as it was.
+        self.orderbook.check_amend(order_id, new_price, new_size)?;
+        if let Some(order) = self.orderbook.order(order_id) {
+            self.validate_slide(new_price, new_size, order)?;
+        }
+        // Resize the reservation first so an amend the account cannot fund
+        // leaves the order untouched.
+        if let (Some(accounts), Some(reservation)) =
+            (accounts, self.reservations.get_mut(&order_id))
+        {
+            let required = match reservation.bid_or_ask {
+                BidOrAsk::Bid => new_size * new_price,
+                BidOrAsk::Ask => new_size,
+            };
+            let asset = self.pair.funding_asset(reservation.bid_or_ask);
+            if required > reservation.amount {
+                accounts.reserve(reservation.owner, asset, required - reservation.amount)?;
+            } else {
+                accounts.release(reservation.owner, asset, reservation.amount - required);
+            }
+            reservation.amount = required;
+            if reservation.bid_or_ask == BidOrAsk::Bid {
+                reservation.price = Some(new_price);
+            }
+        }
+
+        let report = self.orderbook.amend_order(order_id, new_price, new_size)?;
+        // A sliding post-only order may rest away from the price asked for.
+        let price = self.orderbook.order_price(order_id).unwrap_or(new_price);
+        self.events.push(EngineEvent::OrderAmended {
+            pair: self.pair.clone(),
+            order_id,
+            price,
+            size: new_size,
+        });
+        self.process_report(&report);
+        Ok(report)
+    }
+
+    // Runs everything that reacts to an execution: events are recorded,
+    // statistics updated, OCO siblings of filled orders cancelled and the
+    // reference price moved.
+    fn process_report(&mut self, report: &ExecutionReport) {
+        self.record_report(report);
+        self.follow_reference(report.trades.last().map(|trade| trade.price));
+    }
+
+    // Lets the reference price follow the market, which traded at
+    // `last_trade` if it just traded. Auctions leave it where it is.
+    fn follow_reference(&mut self, last_trade: Option<Decimal>) {
+        if self.orderbook.in_auction() {
+            return;
+        }
+        let price = self
+            .reference
+            .observe(last_trade, self.orderbook.mid_price());
+        self.move_reference(price);
+    }
+
+    // Re-centres the price band on the new reference `price` and submits
+    // the stops it fires, repeating while those move the reference again.
+    // Stops wait while the market is not open, so a halted market never
+    // trades; `set_state` fires them on reopening.
+    fn move_reference(&mut self, mut price: Option<Decimal>) {
+        while let Some(reference) = price.take() {
+            self.orderbook.price_band_mut().set_reference(reference);
+            if self.orderbook.in_auction() || self.state != MarketState::Open {
+                return;
+            }
+            let mut last_trade = None;
+            for (order_type, mut order) in self.triggers.on_trade(reference) {
+                let span = self.order_span(Some(order.id()), order.owner());
+                let _entered = span.enter();
+                info!(price = %reference, "triggered");
+                self.events.push(EngineEvent::OrderTriggered {
+                    pair: self.pair.clone(),
+                    order_id: order.id(),
+                });
+                let report = match order_type {
+                    OrderType::StopLimit { limit_price, .. } => {
+                        self.orderbook.place_limit(limit_price, order)
+                    }
+                    _ => self.orderbook.place_market(&mut order),
+                };
+                self.record_report(&report);
+                self.trace_report(&report);
+                if let Some(trade) = report.trades.last() {
+                    last_trade = Some(trade.price);
+                }
+            }
+            price = self
+                .reference
+                .observe(last_trade, self.orderbook.mid_price());
+        }
+    }
+
+    fn open_orders<'a>(
+        &'a self,
+        pair: &'a TradingPair,
+        account: AccountId,
+    ) -> impl Iterator<Item = OpenOrder> + 'a {
+        let resting = self
+            .orderbook
+            .orders()
+            .map(|(price, order)| (OrderType::Limit { price }, order));
+        let pending = self
+            .triggers
+            .orders()
+            .map(|(order_type, order)| (*order_type, order));
+
+        resting
+            .chain(pending)
+            .filter(move |(_, order)| order.owner() == account)
+            .map(move |(order_type, order)| OpenOrder {
+                pair: pair.clone(),
+                order_id: order.id(),
+                owner: order.owner(),
+                bid_or_ask: order.bid_or_ask(),
+                order_type,
+                remaining_size: order.remaining_size(),
+            })
+    }
+
+    fn order_owner(&self, order_id: OrderId) -> Option<AccountId> {
+        self.orderbook
+            .order(order_id)
+            .or_else(|| self.triggers.order(order_id))
+            .map(Order::owner)
+    }
+
+    fn is_working(&self, order_id: OrderId) -> bool {
+        self.orderbook.contains_order(order_id) || self.triggers.contains_order(order_id)
+    }
+
+    // Removes a resting or pending stop order without touching OCO links.
+    fn remove_order(&mut self, order_id: OrderId) -> Option<Order> {
+        let order = self
+            .orderbook
+            .cancel_order(order_id)
+            .or_else(|| self.triggers.cancel_order(order_id))?;
+        self.order_span(Some(order_id), order.owner())
+            .in_scope(|| info!(remaining = %order.remaining_size(), "cancelled"));
+        self.events.push(EngineEvent::OrderCancelled {
+            pair: self.pair.clone(),
+            order_id,
+        });
+        Some(order)
+    }
+
+    fn cancel_order(&mut self, order_id: OrderId) -> Option<Order> {
+        let order = self.remove_order(order_id)?;
+        if let Some(sibling) = self.oco_links.remove(&order_id) {
+            self.oco_links.remove(&sibling);
+            self.remove_order(sibling);
+        }
+        Some(order)
+    }
+
+    // Cancels the good-till-date orders whose expiry is at or before `now`,
+    // along with their OCO siblings. Halted markets keep them until trading
+    // resumes.
+    fn expire_orders(&mut self, now: u64) -> Vec<OrderId> {
+        let mut expired = Vec::new();
+        if self.check_accepts_cancels().is_err() {
+            return expired;
+        }
+        while let Some(&(expiry, order_id)) = self.expiries.first() {
+            if expiry > now {
+                break;
+            }
+            self.expiries.pop_first();
+            if !self.is_working(order_id) {
+                continue;
+            }
+            self.events.push(EngineEvent::OrderExpired {
+                pair: self.pair.clone(),
+                order_id,
+            });
+            self.cancel_order(order_id);
+            expired.push(order_id);
+        }
+        expired
+    }
+
+    // Cancels every working order matching `filter` (and any OCO sibling),
+    // returning the ids that were actually removed.
+    fn cancel_all(&mut self, filter: impl Fn(&Order) -> bool) -> Vec<OrderId> {
+        let candidates: Vec<OrderId> = self
+            .orderbook
+            .orders()
+            .map(|(_, order)| order)
+            .chain(self.triggers.orders().map(|(_, order)| order))
+            .filter(|order| filter(order))
+            .map(|order| order.id())
+            .collect();
+
+        let mut cancelled = Vec::new();
+        for order_id in candidates {
+            if self.remove_order(order_id).is_none() {
+                continue;
+            }
+            cancelled.push(order_id);
+            if let Some(sibling) = self.oco_links.remove(&order_id) {
+                self.oco_links.remove(&sibling);
+                if self.remove_order(sibling).is_some() {
+                    cancelled.push(sibling);
+                }
+            }
+        }
+        cancelled
+    }
+
+    // Records the outcome of a submission that reached the book: makers
+    // cancelled by self-trade prevention, the trades, and the order itself
+    // if it was rejected or its remainder cancelled.
+    fn record_report(&mut self, report: &ExecutionReport) {
+        for &order_id in &report.self_trade_cancelled {
+            info!(
+                maker_order_id = order_id.0,
+                "cancelled by self-trade prevention"
+            );
+            self.events.push(EngineEvent::OrderCancelled {
+                pair: self.pair.clone(),
+                order_id,
+            });
+        }
+        self.record_trades(&report.trades);
+        match report.status {
+            OrderStatus::Rejected => self.events.push(EngineEvent::OrderRejected {
+                pair: self.pair.clone(),
+                order_id: Some(report.order_id),
+                reason: match &report.reason {
+                    Some(reason) => reason.to_string(),
+                    None => "Order was rejected".to_string(),
+                },
+            }),
+            OrderStatus::Cancelled => self.events.push(EngineEvent::OrderCancelled {
+                pair: self.pair.clone(),
+                order_id: report.order_id,
+            }),
+            _ => {}
+        }
+        self.cancel_oco_siblings(&report.trades);
+    }
+
+    fn record_trades(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            self.stats.record(trade);
+            self.candles.record(trade);
+            self.recent_trades.record(trade);
+            debug!(
+                maker_order_id = trade.maker_order_id.0,
+                price = %trade.price,
+                size = %trade.size,
+                "trade"
+            );
+            let maker = self.order_span(Some(trade.maker_order_id), trade.maker_owner);
+            if self.orderbook.contains_order(trade.maker_order_id) {
+                maker.in_scope(
+                    || info!(price = %trade.price, size = %trade.size, "partially filled"),
+                );
+            } else {
+                maker.in_scope(|| info!(price = %trade.price, size = %trade.size, "filled"));
+            }
+            self.events.push(EngineEvent::TradeExecuted {
+                pair: self.pair.clone(),
+                trade: trade.clone(),
+            });
+        }
+    }
+
+    fn cancel_oco_siblings(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            for order_id in [trade.maker_order_id, trade.taker_order_id] {
+                if let Some(sibling) = self.oco_links.remove(&order_id) {
+                    self.oco_links.remove(&sibling);
+                    self.remove_order(sibling);
+                }
+            }
+        }
+    }
+
+    fn place_oco_order(
+        &mut self,
+        first: (OrderType, Order),
+        second: (OrderType, Order),
+        mut accounts: Option<&mut Accounts>,
+    ) -> Result<(ExecutionReport, Option<ExecutionReport>), EngineError> {
+        let mut first_report = self.place_order(first.0, first.1, accounts.as_deref_mut())?;
+        if !self.is_working(first_report.order_id) {
+            // The first leg already completed, so the second is never placed.
+            return Ok((first_report, None));
+        }
+
+        let mut second_report = match self.place_order(second.0, second.1, accounts) {
+            Ok(report) => report,
+            Err(err) => {
+                self.remove_order(first_report.order_id);
+                return Err(err);
+            }
+        };
+
+        let first_working = self.is_working(first_report.order_id);
+        let second_working = self.is_working(second_report.order_id);
+        if first_working && second_working && second_report.trades.is_empty() {
+            self.oco_links
+                .insert(first_report.order_id, second_report.order_id);
+            self.oco_links
+                .insert(second_report.order_id, first_report.order_id);
+        } else if first_working {
+            self.remove_order(first_report.order_id);
+            first_report.status = OrderStatus::Cancelled;
+        } else if second_working {
+            self.remove_order(second_report.order_id);
+            second_report.status = OrderStatus::Cancelled;
+        }
+        Ok((first_report, Some(second_report)))
+    }
+}
+
+// Serializes a map as a list of entries, for keys JSON cannot represent.
+#[cfg(feature = "serde")]
+pub(super) mod map_as_pairs {
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // Entries are sorted so equal maps always serialize identically.
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize + Ord,
+        V: Serialize,
+        S: Serializer,
+    {
+        let mut entries: Vec<(&K, &V)> = map.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        serializer.collect_seq(entries)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+// Order flow refused by the kill switch: everyone's, or that of the listed
+// accounts.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct KillSwitch {
+    all: bool,
+    accounts: BTreeSet<AccountId>,
+}
+
+impl KillSwitch {
+    fn check(&self, account: AccountId) -> Result<(), EngineError> {
+        if self.all || self.accounts.contains(&account) {
+            return Err(EngineError::KillSwitchActive(account));
+        }
+        Ok(())
+    }
+}
+
+/// Complete state of every market, for writing to disk and restoring later.
+/// Event listeners are not part of it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EngineSnapshot {
+    sequence: u64,
+    markets: Vec<Market>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    accounts: Option<Accounts>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    ledger: Ledger,
+    #[cfg_attr(feature = "serde", serde(default))]
+    kill_switch: KillSwitch,
+    #[cfg_attr(feature = "serde", serde(default))]
+    rate_limiter: RateLimiter,
+    #[cfg_attr(feature = "serde", serde(default))]
+    sessions: Sessions,
+    #[cfg_attr(feature = "serde", serde(default))]
+    positions: Positions,
+}
+
+impl EngineSnapshot {
+    /// Sequence number of the last input the snapshot reflects.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
     }
 }
 
+// 64-bit FNV-1a: fixed by its definition, unlike the std hashers, so the
+// result is the same in every process and build.
+pub(super) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 pub struct MatchingEngine {
-    orderbooks: HashMap<TradingPair, OrderBook>,
+    markets: HashMap<TradingPair, Market>,
+    listeners: Vec<Box<dyn EventListener>>,
+    // Sequence number of the last processed `SequencedCommand`.
+    sequence: u64,
+    // Balances orders are checked and settled against; `None` until balance
+    // checks are enabled.
+    accounts: Option<Accounts>,
+    ledger: Ledger,
+    kill_switch: KillSwitch,
+    rate_limiter: RateLimiter,
+    sessions: Sessions,
+    positions: Positions,
+    // Pinned time while processing a `SequencedCommand`.
+    time: Option<u64>,
+    clock: Arc<dyn Clock>,
+    metrics: EngineMetrics,
+    // Events published inside `atomically`, held back until it succeeds.
+    held_events: Option<Vec<EngineEvent>>,
+}
+
+impl Default for MatchingEngine {
+    fn default() -> MatchingEngine {
+        MatchingEngine::new()
+    }
 }
 
 impl MatchingEngine {
     pub fn new() -> MatchingEngine {
         MatchingEngine {
-            orderbooks: HashMap::new(),
+            markets: HashMap::new(),
+            listeners: Vec::new(),
+            sequence: 0,
+            accounts: None,
+            ledger: Ledger::new(),
+            kill_switch: KillSwitch::default(),
+            rate_limiter: RateLimiter::default(),
+            sessions: Sessions::default(),
+            positions: Positions::default(),
+            time: None,
+            clock: system_clock(),
+            metrics: EngineMetrics::new(),
+            held_events: None,
         }
     }
 
-    pub fn add_new_market(&mut self, pair: TradingPair) {
-        self.orderbooks.insert(pair.clone(), OrderBook::new());
+    /// Sequence number of the last processed `SequencedCommand`.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
 
-        println!("Added new market: {:?}", pair.to_string());
+    pub(super) fn set_sequence(&mut self, sequence: u64) {
+        self.sequence = sequence;
     }
 
-    pub fn place_limit_order(
+    // Pins the time the market stamps on orders and trades.
+    pub(super) fn set_time(&mut self, pair: Option<&TradingPair>, time: Option<u64>) {
+        self.time = time;
+        if let Some(market) = pair.and_then(|pair| self.markets.get_mut(pair)) {
+            market.orderbook.set_time(time);
+        }
+    }
+
+    /// Captures the state of every market, in pair order.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let mut markets: Vec<Market> = self.markets.values().cloned().collect();
+        markets.sort_by_key(|market| market.pair.to_string());
+        EngineSnapshot {
+            sequence: self.sequence,
+            markets,
+            accounts: self.accounts.clone(),
+            ledger: self.ledger.clone(),
+            kill_switch: self.kill_switch.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            sessions: self.sessions.clone(),
+            positions: self.positions.clone(),
+        }
+    }
+
+    /// Hash of the complete engine state, equal on two engines exactly when
+    /// their snapshots are. Uses FNV-1a over a canonical encoding, so it is
+    /// stable across processes and builds.
+    #[cfg(feature = "serde")]
+    pub fn state_hash(&self) -> u64 {
+        // Going through `Value` sorts every map by key.
+        let canonical = serde_json::to_value(self.snapshot())
+            .and_then(|value| serde_json::to_vec(&value))
+            .expect("engine state is always serializable");
+        fnv1a(&canonical)
+    }
+
+    /// Builds an engine holding exactly the state in `snapshot`, with no
+    /// listeners registered.
+    pub fn restore(snapshot: EngineSnapshot) -> MatchingEngine {
+        let mut engine = MatchingEngine::new();
+        engine.sequence = snapshot.sequence;
+        engine.accounts = snapshot.accounts;
+        engine.ledger = snapshot.ledger;
+        engine.ledger.reindex();
+        engine.kill_switch = snapshot.kill_switch;
+        engine.rate_limiter = snapshot.rate_limiter;
+        engine.sessions = snapshot.sessions;
+        engine.positions = snapshot.positions;
+        for mut market in snapshot.markets {
+            market.orderbook.set_clock(engine.clock.clone());
+            engine.markets.insert(market.pair.clone(), market);
+        }
+        engine
+    }
+
+    /// Registers a listener for every event published from now on.
+    pub fn add_listener(&mut self, listener: impl EventListener + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    /// Subscribes a channel to every event published from now on.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<EngineEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.add_listener(move |event: &EngineEvent| {
+            let _ = sender.send(event.clone());
+        });
+        receiver
+    }
+
+    pub fn metrics(&self) -> &EngineMetrics {
+        &self.metrics
+    }
+
+    pub(super) fn metrics_mut(&mut self) -> &mut EngineMetrics {
+        &mut self.metrics
+    }
+
+    pub(super) fn sessions_mut(&mut self) -> &mut Sessions {
+        &mut self.sessions
+    }
+
+    /// Every open session, oldest first.
+    pub fn sessions(&self) -> impl Iterator<Item = (SessionId, &Session)> {
+        self.sessions.iter()
+    }
+
+    pub(super) fn positions_mut(&mut self) -> &mut Positions {
+        &mut self.positions
+    }
+
+    /// Every account's position in every market it traded.
+    pub fn positions(&self) -> &Positions {
+        &self.positions
+    }
+
+    /// The metrics in the Prometheus text format, with the current depth of
+    /// every market.
+    pub fn prometheus_metrics(&self) -> String {
+        let mut books: Vec<(TradingPair, DepthSnapshot)> = self
+            .markets
+            .values()
+            .map(|market| (market.pair.clone(), market.orderbook.depth(usize::MAX)))
+            .collect();
+        books.sort_by_key(|(pair, _)| pair.to_string());
+        self.metrics.render(&books)
+    }
+
+    pub(super) fn publish(&mut self, event: &EngineEvent) {
+        if let Some(held) = &mut self.held_events {
+            held.push(event.clone());
+            return;
+        }
+        self.metrics.record_event(event);
+        for listener in &mut self.listeners {
+            listener.on_event(event);
+        }
+    }
+
+    // Settles and publishes everything the market buffered during the last
+    // operation, followed by the book updates it caused.
+    fn flush_events(&mut self, pair: &TradingPair) {
+        let Some(market) = self.markets.get_mut(pair) else {
+            return;
+        };
+        if let Some(accounts) = &mut self.accounts {
+            market.settle(accounts);
+        }
+        for event in &market.events {
+            if let EngineEvent::TradeExecuted { trade, .. } = event {
+                self.ledger.record(pair, trade);
+                self.positions.record(pair, trade);
+            }
+        }
+        let mut events = std::mem::take(&mut market.events);
+        events.extend(market.orderbook.drain_updates().into_iter().map(|update| {
+            EngineEvent::BookUpdated {
+                pair: pair.clone(),
+                update,
+            }
+        }));
+        for event in events {
+            self.publish(&event);
+        }
+    }
+
+    // Runs `f` against the market and publishes the events it produced.
+    fn with_market<T>(
         &mut self,
-        pair: TradingPair,
-        price: Decimal,
-        order: Order,
-    ) -> Result<(), String> {
-        match self.orderbooks.get_mut(&pair) {
-            Some(orderbook) => {
-                orderbook.add_limit_order(price, order);
-                Ok(())
+        pair: &TradingPair,
+        f: impl FnOnce(&mut Market) -> Result<T, EngineError>,
+    ) -> Result<T, EngineError> {
+        self.with_accounts(pair, |market, _| f(market))
+    }
+
+    // Like `with_market`, also handing `f` the balances when they are
+    // enforced.
+    fn with_accounts<T>(
+        &mut self,
+        pair: &TradingPair,
+        f: impl FnOnce(&mut Market, Option<&mut Accounts>) -> Result<T, EngineError>,
+    ) -> Result<T, EngineError> {
+        let now = self.now();
+        let result = match self.markets.get_mut(pair) {
+            Some(market) => {
+                market.expire_orders(now);
+                let result = f(market, self.accounts.as_mut());
+                // Cancels and resting orders move the mid price too.
+                market.follow_reference(None);
+                result
+            }
+            None => Err(EngineError::MarketNotFound(pair.clone())),
+        };
+        self.flush_events(pair);
+        result
+    }
+
+    // Like `with_accounts` for order submissions: failures are published as
+    // rejections.
+    fn submit<T>(
+        &mut self,
+        pair: &TradingPair,
+        f: impl FnOnce(&mut Market, Option<&mut Accounts>) -> Result<T, EngineError>,
+    ) -> Result<T, EngineError> {
+        let result = self.with_accounts(pair, f);
+        if let Err(reason) = &result {
+            self.publish(&EngineEvent::OrderRejected {
+                pair: pair.clone(),
+                order_id: None,
+                reason: reason.to_string(),
+            });
+        }
+        result
+    }
+
+    // Runs `f`, which may only change the `pair` market, all or nothing: if
+    // it fails, the market, balances, ledger, positions and rate limits are
+    // put back as they were and none of its events are published. The market
+    // is copied up front, so this costs time in proportion to its size.
+    pub(super) fn atomically<T>(
+        &mut self,
+        pair: &TradingPair,
+        f: impl FnOnce(&mut MatchingEngine) -> Result<T, EngineError>,
+    ) -> Result<T, EngineError> {
+        let market = self.market(pair)?.clone();
+        let accounts = self.accounts.clone();
+        let ledger_len = self.ledger.entries().len();
+        let rate_limiter = self.rate_limiter.clone();
+        let positions = self.positions.clone();
+
+        let outer = self.held_events.replace(Vec::new());
+        let result = f(self);
+        let held = std::mem::replace(&mut self.held_events, outer).unwrap_or_default();
+        match &result {
+            Ok(_) => {
+                for event in &held {
+                    self.publish(event);
+                }
             }
-            None => {
-                let err = format!("No market found for pair: {:?}", pair.to_string());
-                Err(err)
+            Err(_) => {
+                self.markets.insert(pair.clone(), market);
+                self.accounts = accounts;
+                self.ledger.truncate(ledger_len);
+                self.rate_limiter = rate_limiter;
+                self.positions = positions;
             }
         }
+        result
+    }
+
+    fn market(&self, pair: &TradingPair) -> Result<&Market, EngineError> {
+        self.markets
+            .get(pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))
+    }
+
+    fn market_mut(&mut self, pair: &TradingPair) -> Result<&mut Market, EngineError> {
+        self.markets
+            .get_mut(pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))
+    }
+
+    /// Adds a market trading under `config`'s rules; incoming orders with
+    /// off-tick prices, odd lots or too small a notional are rejected.
+    pub fn add_new_market(&mut self, pair: TradingPair, config: MarketConfig) {
+        let mut market = Market::new(pair.clone(), config);
+        market.orderbook.set_clock(self.clock.clone());
+        self.markets.insert(pair.clone(), market);
+
+        info!(pair = %pair, "market added");
+        self.publish(&EngineEvent::MarketAdded { pair });
+    }
+
+    /// Sets the market-wide self-trade prevention mode; orders may still
+    /// override it individually.
+    pub fn set_self_trade_prevention(
+        &mut self,
+        pair: TradingPair,
+        mode: Option<SelfTradePrevention>,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?
+            .orderbook
+            .set_self_trade_prevention(mode);
+        Ok(())
+    }
+
+    /// Delists the market: it goes cancel-only, every working order is
+    /// cancelled and then the market and its book are dropped. Returns the
+    /// cancelled order ids.
+    pub fn remove_market(&mut self, pair: TradingPair) -> Result<Vec<OrderId>, EngineError> {
+        self.set_market_state(pair.clone(), MarketState::CancelOnly)?;
+        let cancelled = self.with_market(&pair, |market| Ok(market.cancel_all(|_| true)))?;
+        self.markets.remove(&pair);
+
+        info!(pair = %pair, "market removed");
+        self.publish(&EngineEvent::MarketRemoved { pair });
+        Ok(cancelled)
+    }
+
+    /// Stops all activity in the market until it is resumed.
+    pub fn halt_market(&mut self, pair: TradingPair) -> Result<(), EngineError> {
+        self.set_market_state(pair, MarketState::Halted)
+    }
+
+    pub fn resume_market(&mut self, pair: TradingPair) -> Result<(), EngineError> {
+        self.set_market_state(pair, MarketState::Open)
+    }
+
+    /// Moves the market to `state`; working orders are left in place.
+    pub fn set_market_state(
+        &mut self,
+        pair: TradingPair,
+        state: MarketState,
+    ) -> Result<(), EngineError> {
+        self.with_market(&pair, |market| {
+            market.set_state(state);
+            Ok(())
+        })
+    }
+
+    /// Puts the market into an auction: orders accumulate without matching
+    /// until `uncross`. Only good-till-cancel limit and stop orders are
+    /// accepted meanwhile.
+    pub fn start_auction(&mut self, pair: TradingPair) -> Result<(), EngineError> {
+        self.set_market_state(pair, MarketState::Auction)
+    }
+
+    /// Ends the auction: all crossing orders trade at the single price that
+    /// matches the most volume, then the market reopens for continuous
+    /// trading. Opening a market in an auction with `set_market_state`
+    /// uncrosses it the same way.
+    pub fn uncross(&mut self, pair: TradingPair) -> Result<AuctionResult, EngineError> {
+        self.with_market(&pair, |market| {
+            if !market.orderbook.in_auction() {
+                return Err(EngineError::NotInAuction(market.pair.clone()));
+            }
+            Ok(market.set_state(MarketState::Open))
+        })
+    }
+
+    /// Indicative uncrossing price and volume of a market in an auction.
+    pub fn indicative_uncross(
+        &self,
+        pair: TradingPair,
+    ) -> Result<Option<(Decimal, Decimal)>, EngineError> {
+        Ok(self.market(&pair)?.orderbook.equilibrium())
+    }
+
+    /// Every listed market with its config, in pair order.
+    pub fn markets(&self) -> Vec<(TradingPair, MarketConfig)> {
+        let mut markets: Vec<(TradingPair, MarketConfig)> = self
+            .markets
+            .values()
+            .map(|market| (market.pair.clone(), market.config))
+            .collect();
+        markets.sort_by_key(|(pair, _)| pair.to_string());
+        markets
+    }
+
+    pub fn market_config(&self, pair: TradingPair) -> Result<MarketConfig, EngineError> {
+        Ok(self.market(&pair)?.config)
+    }
+
+    pub fn market_state(&self, pair: TradingPair) -> Result<MarketState, EngineError> {
+        Ok(self.market(&pair)?.state)
+    }
+
+    /// Replaces a live market's trading rules. Working orders the new rules
+    /// would not accept are dealt with as `non_conforming` says; the
+    /// cancelled ones are returned. A `MarketConfigChanged` event precedes
+    /// the cancellations.
+    pub fn update_market_config(
+        &mut self,
+        pair: TradingPair,
+        config: MarketConfig,
+        non_conforming: NonConformingOrders,
+    ) -> Result<Vec<OrderId>, EngineError> {
+        config.verify()?;
+        self.with_market(&pair, |market| {
+            let order_ids = market.non_conforming(&config);
+            if non_conforming == NonConformingOrders::Reject && !order_ids.is_empty() {
+                return Err(EngineError::NonConformingOrders {
+                    pair: market.pair.clone(),
+                    order_ids,
+                });
+            }
+            market.set_config(config);
+            market.events.push(EngineEvent::MarketConfigChanged {
+                pair: market.pair.clone(),
+                config,
+            });
+            info!(pair = %market.pair, "market config updated");
+            if non_conforming == NonConformingOrders::Keep {
+                return Ok(Vec::new());
+            }
+            Ok(market.cancel_all(|order| order_ids.contains(&order.id())))
+        })
+    }
+
+    /// Confines trading to within `percent` of the reference price: limit
+    /// orders outside the band are rejected and market orders stop matching
+    /// at its edge. `None` removes the band.
+    pub fn set_price_band(
+        &mut self,
+        pair: TradingPair,
+        percent: Option<Decimal>,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?
+            .orderbook
+            .price_band_mut()
+            .set_percent(percent);
+        self.publish(&EngineEvent::PriceBandChanged { pair, percent });
+        Ok(())
+    }
+
+    /// Pushes a reference price: the feed of a `ReferenceSource::External`
+    /// market, or a seed for the others until their source moves it, e.g.
+    /// before the first trade. The price band is re-centred on it and the
+    /// stops it reaches fire.
+    pub fn set_reference_price(
+        &mut self,
+        pair: TradingPair,
+        price: Decimal,
+    ) -> Result<(), EngineError> {
+        self.with_market(&pair, |market| {
+            market.reference.set(price);
+            market.move_reference(Some(price));
+            Ok(())
+        })
+    }
+
+    /// Chooses what the reference price of `pair` follows. It is the last
+    /// trade unless set otherwise.
+    pub fn set_reference_source(
+        &mut self,
+        pair: TradingPair,
+        source: ReferenceSource,
+    ) -> Result<(), EngineError> {
+        self.with_market(&pair, |market| {
+            market.reference.set_source(source);
+            market
+                .orderbook
+                .price_band_mut()
+                .set_pinned(source != ReferenceSource::LastTrade);
+            Ok(())
+        })
+    }
+
+    pub fn reference_price(&self, pair: TradingPair) -> Result<ReferencePrice, EngineError> {
+        Ok(self.market(&pair)?.reference)
+    }
+
+    /// Sets the per-account limits on order and cancel rates. Refused
+    /// requests fail with a "Rate limit exceeded" error.
+    pub fn set_rate_limits(&mut self, limits: RateLimits) {
+        self.rate_limiter.set_limits(limits);
+    }
+
+    /// Replaces the system clock, in every market and in those added later.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+        for market in self.markets.values_mut() {
+            market.orderbook.set_clock(self.clock.clone());
+        }
+    }
+
+    /// The engine's time: that of the `SequencedCommand` being processed,
+    /// otherwise its clock's.
+    pub fn now(&self) -> u64 {
+        self.time.unwrap_or_else(|| self.clock.now())
+    }
+
+    fn check_rate(&mut self, account: AccountId, action: RateAction) -> Result<(), EngineError> {
+        let now = self.now();
+        self.rate_limiter.check(account, action, now)
+    }
+
+    // Rate limits a request against the order it targets, which is charged
+    // to the order's owner. Unknown orders are left for the request to
+    // reject.
+    fn check_order_rate(
+        &mut self,
+        pair: &TradingPair,
+        order_id: OrderId,
+        action: RateAction,
+    ) -> Result<(), EngineError> {
+        match self.market(pair)?.order_owner(order_id) {
+            Some(owner) => self.check_rate(owner, action),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the maker/taker fees charged on the market's trades from now on.
+    pub fn set_fee_schedule(
+        &mut self,
+        pair: TradingPair,
+        schedule: FeeSchedule,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?
+            .orderbook
+            .set_fee_schedule(schedule.clone());
+        self.publish(&EngineEvent::FeeScheduleChanged { pair, schedule });
+        Ok(())
+    }
+
+    pub fn place_limit_order(
+        &mut self,
+        pair: TradingPair,
+        price: Decimal,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.place_order(pair, OrderType::Limit { price }, order)
+    }
+
+    pub fn place_market_order(
+        &mut self,
+        pair: TradingPair,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.place_order(pair, OrderType::Market, order)
+    }
+
+    /// Places an order of any `OrderType`. Stop orders are held until the
+    /// market trades through their stop price and reported as `Pending`.
+    /// Submissions repeating a client order id the same account already used
+    /// in this market are rejected, so retries after a timeout never create
+    /// a second order.
+    pub fn place_order(
+        &mut self,
+        pair: TradingPair,
+        order_type: OrderType,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        let allowed = self
+            .kill_switch
+            .check(order.owner())
+            .and_then(|_| self.check_rate(order.owner(), RateAction::PlaceOrder))
+            .and_then(|_| self.check_position_limits(&pair, order_type, &order));
+        self.submit(&pair, |market, accounts| {
+            allowed?;
+            market.place_order(order_type, order, accounts)
+        })
+    }
+
+    /// Places two linked orders, e.g. a take-profit limit and a protective
+    /// stop. Once either is filled (even partially) or cancelled, the other
+    /// is cancelled. If one leg trades on arrival, the other is cancelled
+    /// straight away and reported as `Cancelled`; if the first completes on
+    /// arrival, the second is never placed and has no report.
+    pub fn place_oco_order(
+        &mut self,
+        pair: TradingPair,
+        first: (OrderType, Order),
+        second: (OrderType, Order),
+    ) -> Result<(ExecutionReport, Option<ExecutionReport>), EngineError> {
+        let allowed = self
+            .kill_switch
+            .check(first.1.owner())
+            .and_then(|_| self.kill_switch.check(second.1.owner()))
+            .and_then(|_| self.check_rate(first.1.owner(), RateAction::PlaceOcoOrder))
+            .and_then(|_| self.check_position_limits(&pair, first.0, &first.1))
+            .and_then(|_| self.check_position_limits(&pair, second.0, &second.1));
+        self.submit(&pair, |market, accounts| {
+            allowed?;
+            market.place_oco_order(first, second, accounts)
+        })
+    }
+
+    /// All resting and pending stop orders owned by `account`, across markets.
+    pub fn open_orders(&self, account: AccountId) -> Vec<OpenOrder> {
+        let mut markets: Vec<(&TradingPair, &Market)> = self.markets.iter().collect();
+        markets.sort_by_key(|(pair, _)| *pair);
+        markets
+            .into_iter()
+            .flat_map(|(pair, market)| market.open_orders(pair, account))
+            .collect()
+    }
+
+    pub fn open_orders_for_market(
+        &self,
+        account: AccountId,
+        pair: TradingPair,
+    ) -> Result<Vec<OpenOrder>, EngineError> {
+        let market = self.market(&pair)?;
+        Ok(market.open_orders(&pair, account).collect())
+    }
+
+    pub fn depth(&self, pair: TradingPair, levels: usize) -> Result<DepthSnapshot, EngineError> {
+        Ok(self.market(&pair)?.orderbook.depth(levels))
+    }
+
+    /// A snapshot of the market's book that other threads can read while
+    /// the engine keeps matching; see `OrderBook::snapshot`.
+    pub fn book_snapshot(&self, pair: TradingPair) -> Result<BookSnapshot, EngineError> {
+        Ok(self.market(&pair)?.orderbook.snapshot())
+    }
+
+    /// Last price, high, low, volume and price change over the past 24 hours.
+    pub fn stats(&self, pair: TradingPair) -> Result<MarketSummary, EngineError> {
+        let market = self.market(&pair)?;
+        let mut summary = market.stats.summary_at(self.now());
+        summary.mark_price = market.reference.price();
+        Ok(summary)
+    }
+
+    /// The last `count` trades in the market, oldest first.
+    pub fn recent_trades(
+        &self,
+        pair: TradingPair,
+        count: usize,
+    ) -> Result<Vec<Trade>, EngineError> {
+        Ok(self
+            .market(&pair)?
+            .recent_trades
+            .recent(count)
+            .cloned()
+            .collect())
+    }
+
+    /// VWAP over the last `count` trades in the market.
+    pub fn vwap(&self, pair: TradingPair, count: usize) -> Result<Option<Decimal>, EngineError> {
+        Ok(self.market(&pair)?.recent_trades.vwap(count))
+    }
+
+    /// Bid/ask imbalance of the visible volume within `distance` of the mid.
+    pub fn imbalance(
+        &self,
+        pair: TradingPair,
+        distance: Decimal,
+    ) -> Result<Option<Decimal>, EngineError> {
+        Ok(self.market(&pair)?.orderbook.imbalance(distance))
+    }
+
+    /// Notional and average price of a market buy of `size`, without placing it.
+    pub fn cost_to_buy(
+        &self,
+        pair: TradingPair,
+        size: Decimal,
+    ) -> Result<FillEstimate, EngineError> {
+        Ok(self.market(&pair)?.orderbook.cost_to_buy(size))
+    }
+
+    /// Notional and average price of a market sell of `size`, without placing it.
+    pub fn cost_to_sell(
+        &self,
+        pair: TradingPair,
+        size: Decimal,
+    ) -> Result<FillEstimate, EngineError> {
+        Ok(self.market(&pair)?.orderbook.cost_to_sell(size))
+    }
+
+    /// Visible `(bid, ask)` volume within `ticks` ticks of the mid.
+    pub fn volume_within(
+        &self,
+        pair: TradingPair,
+        ticks: u32,
+    ) -> Result<Option<(Decimal, Decimal)>, EngineError> {
+        Ok(self.market(&pair)?.orderbook.volume_within(ticks))
+    }
+
+    /// Volume a `bid_or_ask` aggressor must take to move the price by `distance`.
+    pub fn volume_to_move(
+        &self,
+        pair: TradingPair,
+        bid_or_ask: BidOrAsk,
+        distance: Decimal,
+    ) -> Result<Decimal, EngineError> {
+        Ok(self
+            .market(&pair)?
+            .orderbook
+            .volume_to_move(bid_or_ask, distance))
+    }
+
+    /// Starts building candles of `interval` for the market, in addition to
+    /// the default 1m, 5m and 1h bars.
+    pub fn add_candle_interval(
+        &mut self,
+        pair: TradingPair,
+        interval: CandleInterval,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?.candles.add_interval(interval);
+        Ok(())
+    }
+
+    /// The most recent `count` candles of `interval`, oldest first.
+    pub fn candles(
+        &self,
+        pair: TradingPair,
+        interval: CandleInterval,
+        count: usize,
+    ) -> Result<Vec<Candle>, EngineError> {
+        let series = self
+            .market(&pair)?
+            .candles
+            .series(interval)
+            .ok_or(EngineError::UnknownCandleInterval(interval))?;
+        Ok(series.recent(count))
+    }
+
+    /// Cancels a resting or pending stop order, along with its OCO sibling.
+    pub fn cancel_order(
+        &mut self,
+        pair: TradingPair,
+        order_id: OrderId,
+    ) -> Result<Order, EngineError> {
+        self.check_order_rate(&pair, order_id, RateAction::CancelOrder)?;
+        self.with_market(&pair, |market| {
+            market.check_accepts_cancels()?;
+            market
+                .cancel_order(order_id)
+                .ok_or_else(|| OrderBookError::UnknownOrderId(order_id).into())
+        })
+    }
+
+    /// Cancels every resting and pending stop order in the market.
+    pub fn cancel_all(&mut self, pair: TradingPair) -> Result<Vec<OrderId>, EngineError> {
+        self.with_market(&pair, |market| {
+            market.check_accepts_cancels()?;
+            Ok(market.cancel_all(|_| true))
+        })
+    }
+
+    /// Cancels every order owned by `account` in all markets that accept
+    /// cancels. Order ids are only unique within a market, so each is
+    /// returned with its pair.
+    pub fn cancel_all_for_account(
+        &mut self,
+        account: AccountId,
+    ) -> Result<Vec<(TradingPair, OrderId)>, EngineError> {
+        self.check_rate(account, RateAction::CancelAll)?;
+        Ok(
+            self.in_every_market(|market| match market.check_accepts_cancels() {
+                Ok(()) => market.cancel_all(|order| order.owner() == account),
+                Err(_) => Vec::new(),
+            }),
+        )
+    }
+
+    /// Cancels every good-till-date order whose expiry has passed, in all
+    /// markets that accept cancels. Each market also sweeps its own expired
+    /// orders before handling any request for it, so this only needs to run
+    /// periodically. Order ids are returned with their pair.
+    pub fn expire_orders(&mut self) -> Vec<(TradingPair, OrderId)> {
+        let now = self.now();
+        self.in_every_market(|market| market.expire_orders(now))
+    }
+
+    pub fn cancel_all_for_account_in_market(
+        &mut self,
+        account: AccountId,
+        pair: TradingPair,
+    ) -> Result<Vec<OrderId>, EngineError> {
+        self.check_rate(account, RateAction::CancelAll)?;
+        self.with_market(&pair, |market| {
+            market.check_accepts_cancels()?;
+            Ok(market.cancel_all(|order| order.owner() == account))
+        })
+    }
+
+    pub fn amend_order(
+        &mut self,
+        pair: TradingPair,
+        order_id: OrderId,
+        new_price: Decimal,
+        new_size: Decimal,
+    ) -> Result<ExecutionReport, EngineError> {
+        if new_size <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidSize(new_size).into());
+        }
+        if let Some(owner) = self.market(&pair)?.order_owner(order_id) {
+            self.kill_switch.check(owner)?;
+        }
+        self.check_order_rate(&pair, order_id, RateAction::AmendOrder)?;
+        if let Some(order) = self.market(&pair)?.orderbook.order(order_id) {
+            self.check_amend_position_limits(&pair, order, new_price, new_size)?;
+        }
+
+        self.with_accounts(&pair, |market, accounts| {
+            market.amend_order(order_id, new_price, new_size, accounts)
+        })
+    }
+
+    /// Emergency stop: cancels every working order in every market, halted
+    /// ones included, and refuses new orders until `reset_kill_switch`.
+    /// With `account` only that account's orders and order flow are
+    /// affected. A single `KillSwitchActivated` event precedes the
+    /// cancellations.
+    pub fn activate_kill_switch(
+        &mut self,
+        account: Option<AccountId>,
+    ) -> Vec<(TradingPair, OrderId)> {
+        match account {
+            Some(account) => {
+                self.kill_switch.accounts.insert(account);
+            }
+            None => self.kill_switch.all = true,
+        }
+        self.publish(&EngineEvent::KillSwitchActivated { account });
+        self.cancel_everywhere(account)
+    }
+
+    // Cancels every working order of `account`, or of everyone, in every
+    // market, halted ones included.
+    pub(super) fn cancel_everywhere(
+        &mut self,
+        account: Option<AccountId>,
+    ) -> Vec<(TradingPair, OrderId)> {
+        self.in_every_market(|market| {
+            market.cancel_all(|order| account.is_none_or(|account| order.owner() == account))
+        })
+    }
+
+    // Runs `f` on every market in pair order, publishing each market's
+    // events before moving on, so replicas see the same outputs and events
+    // in the same order. Returns the order ids `f` gave with their pair.
+    fn in_every_market(
+        &mut self,
+        mut f: impl FnMut(&mut Market) -> Vec<OrderId>,
+    ) -> Vec<(TradingPair, OrderId)> {
+        let mut pairs: Vec<TradingPair> = self.markets.keys().cloned().collect();
+        pairs.sort();
+        let mut order_ids = Vec::new();
+        for pair in pairs {
+            if let Some(market) = self.markets.get_mut(&pair) {
+                order_ids.extend(
+                    f(market)
+                        .into_iter()
+                        .map(|order_id| (pair.clone(), order_id)),
+                );
+            }
+            self.flush_events(&pair);
+        }
+        order_ids
+    }
+
+    /// Accepts order flow again, from everyone or from `account`.
+    pub fn reset_kill_switch(&mut self, account: Option<AccountId>) {
+        match account {
+            Some(account) => {
+                self.kill_switch.accounts.remove(&account);
+            }
+            None => self.kill_switch = KillSwitch::default(),
+        }
+        self.publish(&EngineEvent::KillSwitchReset { account });
+    }
+
+    /// Starts enforcing balances: from now on orders reserve the funds they
+    /// need when placed, are rejected if the owner cannot cover them, and
+    /// settle against the balances as they trade. Accounts start empty, so
+    /// fund them with `deposit`. Only possible while no orders are working.
+    pub fn enable_balance_checks(&mut self) -> Result<(), EngineError> {
+        if self.accounts.is_some() {
+            return Ok(());
+        }
+        if let Some(market) = self.markets.values().find(|market| {
+            market.orderbook.orders().next().is_some() || market.triggers.orders().next().is_some()
+        }) {
+            return Err(EngineError::OrdersWorking(market.pair.clone()));
+        }
+        self.accounts = Some(Accounts::new());
+        Ok(())
+    }
+
+    /// Ledger entries of every trade `account` took part in, oldest first.
+    pub fn ledger_history(&self, account: AccountId) -> Vec<&LedgerEntry> {
+        self.ledger.history(account)
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Balances, if balance checks are enabled.
+    pub fn accounts(&self) -> Option<&Accounts> {
+        self.accounts.as_ref()
+    }
+
+    fn accounts_mut(&mut self) -> Result<&mut Accounts, EngineError> {
+        self.accounts
+            .as_mut()
+            .ok_or(EngineError::BalanceChecksDisabled)
+    }
+
+    pub fn deposit(
+        &mut self,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        self.accounts_mut()?.deposit(account, asset, amount)
+    }
+
+    /// Withdraws funds not reserved for open orders.
+    pub fn withdraw(
+        &mut self,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        self.accounts_mut()?.withdraw(account, asset, amount)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::accounts::Balance;
+    use crate::matching_engine::ledger::FEE_ACCOUNT;
+    use crate::matching_engine::orderbook::{PostOnly, TimeInForce};
+    use crate::matching_engine::ratelimit::RateLimit;
+    use rust_decimal_macros::dec;
+    use std::sync::Mutex;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    // Records each event's message with the order id of the span it was
+    // logged in.
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Recorded>>);
+
+    #[derive(Default)]
+    struct Recorded {
+        spans: Vec<(Option<u64>, &'static tracing::Metadata<'static>)>,
+        entered: Vec<usize>,
+        events: Vec<(Option<u64>, String)>,
+    }
+
+    #[derive(Default)]
+    struct Fields {
+        order_id: Option<u64>,
+        message: String,
+    }
+
+    impl field::Visit for Fields {
+        fn record_u64(&mut self, field: &field::Field, value: u64) {
+            if field.name() == "order_id" {
+                self.order_id = Some(value);
+            }
+        }
+
+        fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            let mut recorded = self.0.lock().unwrap();
+            recorded.spans.push((fields.order_id, attrs.metadata()));
+            tracing::span::Id::from_u64(recorded.spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut fields = Fields::default();
+            values.record(&mut fields);
+            if let Some(order_id) = fields.order_id {
+                self.0.lock().unwrap().spans[span.into_u64() as usize - 1].0 = Some(order_id);
+            }
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let mut recorded = self.0.lock().unwrap();
+            let order_id = recorded
+                .entered
+                .last()
+                .and_then(|&index| recorded.spans[index].0);
+            recorded.events.push((order_id, fields.message));
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            let index = span.into_u64() as usize - 1;
+            self.0.lock().unwrap().entered.push(index);
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+
+        fn current_span(&self) -> tracing_core::span::Current {
+            let recorded = self.0.lock().unwrap();
+            match recorded.entered.last() {
+                Some(&index) => tracing_core::span::Current::new(
+                    tracing::span::Id::from_u64(index as u64 + 1),
+                    recorded.spans[index].1,
+                ),
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_engine_traces_orders_in_spans() {
+        let recorder = SpanRecorder::default();
+        let (maker, taker) = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut engine = MatchingEngine::new();
+            engine.add_new_market(btc_usd(), MarketConfig::default());
+            let maker = engine
+                .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(2)))
+                .unwrap();
+            let taker = engine
+                .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(2)))
+                .unwrap();
+            (maker.order_id.0, taker.order_id.0)
+        });
+        let events = &recorder.0.lock().unwrap().events;
+        for expected in [
+            (Some(maker), "accepted"),
+            (Some(maker), "filled"),
+            (Some(taker), "accepted"),
+            (Some(taker), "filled"),
+        ] {
+            assert!(
+                events
+                    .iter()
+                    .any(|(order_id, message)| (*order_id, message.as_str()) == expected),
+                "{:?} not in {:?}",
+                expected,
+                events
+            );
+        }
+    }
+
+    #[test]
+    fn test_engine_stop_order_triggers_on_trade() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        engine
+            .place_limit_order(btc_usd(), dec!(110), Order::new(BidOrAsk::Ask, dec!(5)))
+            .unwrap();
+
+        let stop = OrderType::Stop {
+            stop_price: dec!(100),
+        };
+        let report = engine
+            .place_order(btc_usd(), stop, Order::new(BidOrAsk::Bid, dec!(2)))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Pending);
+
+        let report = engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        assert_eq!(report.trades[0].price, dec!(100));
+
+        // The triggered stop bought 2 of the 5 resting at 110.
+        let market = engine.markets.get_mut(&btc_usd()).unwrap();
+        assert_eq!(market.orderbook.ask_limits()[0].total_volume(), dec!(3));
+        assert!(market.triggers.is_empty());
+    }
+
+    #[test]
+    fn test_engine_cancel_stop_order() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+
+        let stop_limit = OrderType::StopLimit {
+            stop_price: dec!(90),
+            limit_price: dec!(89),
+        };
+        let report = engine
+            .place_order(btc_usd(), stop_limit, Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+
+        assert!(engine.cancel_order(btc_usd(), report.order_id).is_ok());
+        assert!(engine.cancel_order(btc_usd(), report.order_id).is_err());
+    }
+
+    #[test]
+    fn test_engine_oco_fill_cancels_sibling() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+
+        let take_profit = (
+            OrderType::Limit { price: dec!(120) },
+            Order::new(BidOrAsk::Ask, dec!(1)),
+        );
+        let stop_loss = (
+            OrderType::Stop {
+                stop_price: dec!(90),
+            },
+            Order::new(BidOrAsk::Ask, dec!(1)),
+        );
+        let (take_profit, stop_loss) = engine
+            .place_oco_order(btc_usd(), take_profit, stop_loss)
+            .unwrap();
+        let stop_loss = stop_loss.unwrap();
+        assert_eq!(take_profit.status, OrderStatus::New);
+        assert_eq!(stop_loss.status, OrderStatus::Pending);
+
+        engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+
+        let market = &engine.markets[&btc_usd()];
+        assert!(!market.is_working(take_profit.order_id));
+        assert!(!market.is_working(stop_loss.order_id));
+        assert!(market.oco_links.is_empty());
+
+        // A first leg that fills on arrival leaves the second unplaced.
+        engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        let (filled, unplaced) = engine
+            .place_oco_order(
+                btc_usd(),
+                (OrderType::Market, Order::new(BidOrAsk::Bid, dec!(1))),
+                (
+                    OrderType::Limit { price: dec!(90) },
+                    Order::new(BidOrAsk::Bid, dec!(1)),
+                ),
+            )
+            .unwrap();
+        assert_eq!(filled.status, OrderStatus::Filled);
+        assert_eq!(unplaced, None);
+        assert!(engine.depth(btc_usd(), 1).unwrap().bids.is_empty());
+    }
+
+    #[test]
+    fn test_engine_oco_cancel_cancels_sibling() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+
+        let (first, second) = engine
+            .place_oco_order(
+                btc_usd(),
+                (
+                    OrderType::Limit { price: dec!(95) },
+                    Order::new(BidOrAsk::Bid, dec!(1)),
+                ),
+                (
+                    OrderType::Limit { price: dec!(90) },
+                    Order::new(BidOrAsk::Bid, dec!(1)),
+                ),
+            )
+            .unwrap();
+        let second = second.unwrap();
+
+        engine.cancel_order(btc_usd(), first.order_id).unwrap();
+        assert!(engine.cancel_order(btc_usd(), second.order_id).is_err());
+    }
+
+    #[test]
+    fn test_engine_rejects_duplicate_client_order_id() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+
+        let order = || Order::new(BidOrAsk::Bid, dec!(1)).with_client_order_id("abc".to_string());
+        engine
+            .place_limit_order(btc_usd(), dec!(100), order())
+            .unwrap();
+        let err = engine
+            .place_limit_order(btc_usd(), dec!(100), order())
+            .unwrap_err();
+        assert!(matches!(
+            err.rejection(),
+            Some(OrderBookError::DuplicateClientOrderId { .. })
+        ));
+
+        let market = engine.markets.get_mut(&btc_usd()).unwrap();
+        assert_eq!(market.orderbook.bid_limits()[0].total_volume(), dec!(1));
+    }
+
+    #[test]
+    fn test_engine_client_order_id_is_scoped_to_account() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+
+        for owner in [AccountId(1), AccountId(2)] {
+            let order = Order::new(BidOrAsk::Bid, dec!(1))
+                .with_owner(owner)
+                .with_client_order_id("abc".to_string());
+            engine
+                .place_limit_order(btc_usd(), dec!(100), order)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_engine_open_orders() {
+        let mut engine = MatchingEngine::new();
+        let eth_usd = TradingPair::new("ETH".to_string(), "USD".to_string());
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.add_new_market(eth_usd.clone(), MarketConfig::default());
+
+        let alice = AccountId(1);
+        let bob = AccountId(2);
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(1)).with_owner(alice),
+            )
+            .unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(101),
+                Order::new(BidOrAsk::Ask, dec!(1)).with_owner(bob),
+            )
+            .unwrap();
+        let stop = OrderType::Stop {
+            stop_price: dec!(50),
+        };
+        engine
+            .place_order(
+                eth_usd.clone(),
+                stop,
+                Order::new(BidOrAsk::Ask, dec!(2)).with_owner(alice),
+            )
+            .unwrap();
+
+        assert_eq!(engine.open_orders(alice).len(), 2);
+        assert_eq!(engine.open_orders(bob).len(), 1);
+
+        let eth_orders = engine.open_orders_for_market(alice, eth_usd).unwrap();
+        assert_eq!(eth_orders.len(), 1);
+        assert_eq!(eth_orders[0].order_type, stop);
+        assert_eq!(eth_orders[0].remaining_size, dec!(2));
+
+        let btc_orders = engine.open_orders_for_market(alice, btc_usd()).unwrap();
+        assert_eq!(
+            btc_orders[0].order_type,
+            OrderType::Limit { price: dec!(100) }
+        );
+    }
+
+    #[test]
+    fn test_engine_mass_cancel() {
+        let mut engine = MatchingEngine::new();
+        let eth_usd = TradingPair::new("ETH".to_string(), "USD".to_string());
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.add_new_market(eth_usd.clone(), MarketConfig::default());
+
+        let alice = AccountId(1);
+        let bob = AccountId(2);
+        for (pair, owner) in [
+            (btc_usd(), alice),
+            (btc_usd(), bob),
+            (eth_usd.clone(), alice),
+        ] {
+            let order = Order::new(BidOrAsk::Bid, dec!(1)).with_owner(owner);
+            engine.place_limit_order(pair, dec!(100), order).unwrap();
+        }
+        let stop = OrderType::Stop {
+            stop_price: dec!(120),
+        };
+        let order = Order::new(BidOrAsk::Bid, dec!(1)).with_owner(alice);
+        engine.place_order(btc_usd(), stop, order).unwrap();
+
+        let cancelled = engine
+            .cancel_all_for_account_in_market(alice, btc_usd())
+            .unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert_eq!(engine.open_orders(bob).len(), 1);
+
+        let cancelled = engine.cancel_all_for_account(alice).unwrap();
+        assert_eq!(cancelled, vec![(eth_usd.clone(), OrderId(1))]);
+
+        assert_eq!(engine.cancel_all(btc_usd()).unwrap().len(), 1);
+        assert!(engine.open_orders(bob).is_empty());
+
+        // Markets are visited in pair order, whatever order they were used in.
+        let mut placed = Vec::new();
+        for pair in [eth_usd, btc_usd()] {
+            let order = Order::new(BidOrAsk::Bid, dec!(1)).with_owner(bob);
+            let report = engine.place_limit_order(pair.clone(), dec!(100), order);
+            placed.push((pair, report.unwrap().order_id));
+        }
+        placed.reverse();
+        assert_eq!(engine.cancel_all_for_account(bob).unwrap(), placed);
+    }
+
+    #[test]
+    fn test_engine_stats() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        engine
+            .place_limit_order(btc_usd(), dec!(105), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(3)))
+            .unwrap();
+
+        let stats = engine.stats(btc_usd()).unwrap();
+        assert_eq!(stats.last_price, Some(dec!(105)));
+        assert_eq!(stats.high, Some(dec!(105)));
+        assert_eq!(stats.low, Some(dec!(100)));
+        assert_eq!(stats.volume, dec!(3));
+        assert_eq!(stats.price_change(), Some(dec!(5)));
+        assert_eq!(
+            engine.vwap(btc_usd(), 10).unwrap(),
+            Some(dec!(310) / dec!(3))
+        );
+        assert_eq!(
+            engine.recent_trades(btc_usd(), 1).unwrap()[0].price,
+            dec!(105)
+        );
+    }
+
+    #[test]
+    fn test_engine_candles() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        engine
+            .place_limit_order(btc_usd(), dec!(105), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(3)))
+            .unwrap();
+
+        let candles = engine
+            .candles(btc_usd(), CandleInterval::ONE_HOUR, 10)
+            .unwrap();
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].open, dec!(100));
+        assert_eq!(candles[0].close, dec!(105));
+        assert_eq!(candles[0].volume, dec!(3));
+        assert!(engine.candles(btc_usd(), CandleInterval(1234), 10).is_err());
+    }
+
+    #[test]
+    fn test_engine_publishes_events() {
+        let mut engine = MatchingEngine::new();
+        let events = engine.subscribe();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let maker = engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        let taker = engine
+            .place_order(
+                btc_usd(),
+                OrderType::Limit { price: dec!(100) },
+                Order::new(BidOrAsk::Bid, dec!(2))
+                    .with_time_in_force(TimeInForce::ImmediateOrCancel),
+            )
+            .unwrap();
+        assert!(engine
+            .place_market_order(
+                TradingPair::new("ETH".to_string(), "USD".to_string()),
+                Order::new(BidOrAsk::Bid, dec!(1))
+            )
+            .is_err());
+
+        let (book_updates, events): (Vec<EngineEvent>, Vec<EngineEvent>) = events
+            .try_iter()
+            .partition(|event| matches!(event, EngineEvent::BookUpdated { .. }));
+        assert_eq!(book_updates.len(), 2);
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[0], EngineEvent::MarketAdded { pair: btc_usd() });
+        assert!(matches!(
+            events[1],
+            EngineEvent::OrderAccepted { order_id, .. } if order_id == maker.order_id
+        ));
+        assert!(matches!(
+            events[2],
+            EngineEvent::OrderAccepted { order_id, size, .. }
+                if order_id == taker.order_id && size == dec!(2)
+        ));
+        assert_eq!(
+            events[3],
+            EngineEvent::TradeExecuted {
+                pair: btc_usd(),
+                trade: taker.trades[0].clone(),
+            }
+        );
+        assert_eq!(
+            events[4],
+            EngineEvent::OrderCancelled {
+                pair: btc_usd(),
+                order_id: taker.order_id,
+            }
+        );
+        assert!(matches!(
+            events[5],
+            EngineEvent::OrderRejected { order_id: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_engine_enforces_balances() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let alice = AccountId(1);
+        let bob = AccountId(2);
+        assert!(engine.deposit(alice, "USD", dec!(1000)).is_err());
+        engine.enable_balance_checks().unwrap();
+        engine.deposit(alice, "USD", dec!(1000)).unwrap();
+        engine.deposit(bob, "BTC", dec!(5)).unwrap();
+        let balance = |engine: &MatchingEngine, account, asset| {
+            engine.accounts().unwrap().balance(account, asset)
+        };
+
+        let bid = engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(8)).with_owner(alice),
+            )
+            .unwrap();
+        assert_eq!(balance(&engine, alice, "USD").reserved, dec!(800));
+        let err = engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(3)).with_owner(alice),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::InsufficientBalance { ref asset, .. } if asset == "USD"
+        ));
+        assert!(engine
+            .place_market_order(
+                btc_usd(),
+                Order::new(BidOrAsk::Ask, dec!(6)).with_owner(bob)
+            )
+            .is_err());
+
+        // Both sides settle, and Bob's filled ask releases nothing.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(99),
+                Order::new(BidOrAsk::Ask, dec!(4)).with_owner(bob),
+            )
+            .unwrap();
+        assert_eq!(balance(&engine, alice, "BTC").available, dec!(4));
+        assert_eq!(
+            balance(&engine, alice, "USD"),
+            Balance {
+                available: dec!(200),
+                reserved: dec!(400),
+            }
+        );
+        assert_eq!(balance(&engine, bob, "USD").available, dec!(400));
+        assert_eq!(balance(&engine, bob, "BTC").available, dec!(1));
+
+        engine.cancel_order(btc_usd(), bid.order_id).unwrap();
+        assert_eq!(
+            balance(&engine, alice, "USD"),
+            Balance {
+                available: dec!(600),
+                reserved: dec!(0),
+            }
+        );
+
+        // Buying below the limit price releases the difference.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(95),
+                Order::new(BidOrAsk::Ask, dec!(1)).with_owner(bob),
+            )
+            .unwrap();
+        let bid = engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(2)).with_owner(alice),
+            )
+            .unwrap();
+        assert_eq!(
+            balance(&engine, alice, "USD"),
+            Balance {
+                available: dec!(405),
+                reserved: dec!(100),
+            }
+        );
+        assert_eq!(balance(&engine, bob, "BTC").total(), dec!(0));
+
+        // A market bid is funded for the asks it would really reach, not
+        // the owner's own that self-trade prevention cancels.
+        engine.cancel_order(btc_usd(), bid.order_id).unwrap();
+        engine.deposit(bob, "BTC", dec!(1)).unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(10),
+                Order::new(BidOrAsk::Ask, dec!(1)).with_owner(alice),
+            )
+            .unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(600),
+                Order::new(BidOrAsk::Ask, dec!(1)).with_owner(bob),
+            )
+            .unwrap();
+        let sweep = Order::new(BidOrAsk::Bid, dec!(1))
+            .with_owner(alice)
+            .with_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        assert!(matches!(
+            engine.place_market_order(btc_usd(), sweep),
+            Err(EngineError::InsufficientBalance { .. })
+        ));
+        assert_eq!(balance(&engine, alice, "USD").available, dec!(505));
+    }
+
+    #[test]
+    fn test_engine_charges_fees() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine
+            .set_fee_schedule(btc_usd(), FeeSchedule::new(dec!(10), dec!(50)))
+            .unwrap();
+        engine.enable_balance_checks().unwrap();
+        let alice = AccountId(1);
+        let bob = AccountId(2);
+        engine.deposit(alice, "BTC", dec!(2)).unwrap();
+        engine.deposit(bob, "USD", dec!(1000)).unwrap();
+
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Ask, dec!(2)).with_owner(alice),
+            )
+            .unwrap();
+        let report = engine
+            .place_market_order(
+                btc_usd(),
+                Order::new(BidOrAsk::Bid, dec!(2)).with_owner(bob),
+            )
+            .unwrap();
+        assert_eq!(report.fee, dec!(0.01));
+        assert_eq!(report.trades[0].maker_fee, dec!(0.2));
+
+        let accounts = engine.accounts().unwrap();
+        assert_eq!(accounts.balance(alice, "USD").available, dec!(199.8));
+        assert_eq!(accounts.balance(bob, "BTC").available, dec!(1.99));
+        assert_eq!(accounts.balance(bob, "USD").total(), dec!(800));
+        assert_eq!(accounts.balance(FEE_ACCOUNT, "USD").available, dec!(0.2));
+
+        let history = engine.ledger_history(bob);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].net(bob, "BTC"), dec!(1.99));
+        assert_eq!(engine.ledger().net(FEE_ACCOUNT, "BTC"), dec!(0.01));
+    }
+
+    #[test]
+    fn test_engine_price_band() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.set_price_band(btc_usd(), Some(dec!(10))).unwrap();
+        engine.set_reference_price(btc_usd(), dec!(100)).unwrap();
+
+        let err = engine
+            .place_limit_order(btc_usd(), dec!(111), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap_err();
+        assert_eq!(
+            err.rejection(),
+            Some(&OrderBookError::OutsidePriceBand {
+                price: dec!(111),
+                low: dec!(90),
+                high: dec!(110),
+            })
+        );
+        let asks: Vec<OrderId> = [dec!(105), dec!(110)]
+            .into_iter()
+            .map(|price| {
+                let order = Order::new(BidOrAsk::Ask, dec!(1));
+                engine
+                    .place_limit_order(btc_usd(), price, order)
+                    .unwrap()
+                    .order_id
+            })
+            .collect();
+        // Amends out of the band are refused and leave the order alone.
+        let err = engine
+            .amend_order(btc_usd(), asks[0], dec!(80), dec!(1))
+            .unwrap_err();
+        assert!(matches!(
+            err.rejection(),
+            Some(OrderBookError::OutsidePriceBand { .. })
+        ));
+        assert_eq!(engine.depth(btc_usd(), 1).unwrap().asks[0].price, dec!(105));
+        // The market order stops at the band's 108.9 edge.
+        engine.set_reference_price(btc_usd(), dec!(99)).unwrap();
+        let report = engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(2)))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.filled_size, dec!(1));
+
+        // Re-centring on the last trade lets the next level into the band.
+        let report = engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        assert_eq!(report.trades[0].price, dec!(110));
+    }
+
+    #[test]
+    fn test_engine_market_state() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let order = engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+
+        engine.halt_market(btc_usd()).unwrap();
+        assert!(engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Bid, dec!(1)))
+            .is_err());
+        assert!(engine.cancel_order(btc_usd(), order.order_id).is_err());
+        assert!(engine
+            .amend_order(btc_usd(), order.order_id, dec!(101), dec!(1))
+            .is_err());
+
+        engine
+            .set_market_state(btc_usd(), MarketState::CancelOnly)
+            .unwrap();
+        assert!(engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Ask, dec!(1)))
+            .is_err());
+        engine.cancel_order(btc_usd(), order.order_id).unwrap();
+
+        engine.resume_market(btc_usd()).unwrap();
+        assert_eq!(engine.market_state(btc_usd()).unwrap(), MarketState::Open);
+        engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+
+        // A stop reached while the market is halted waits for it to reopen.
+        let stop = OrderType::Stop {
+            stop_price: dec!(90),
+        };
+        engine
+            .place_order(btc_usd(), stop, Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        engine.halt_market(btc_usd()).unwrap();
+        engine.set_reference_price(btc_usd(), dec!(80)).unwrap();
+        assert_eq!(engine.depth(btc_usd(), 1).unwrap().bids.len(), 1);
+        engine.resume_market(btc_usd()).unwrap();
+        assert!(engine.depth(btc_usd(), 1).unwrap().bids.is_empty());
+    }
+
+    #[test]
+    fn test_engine_kill_switch() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let events = engine.subscribe();
+        let alice = AccountId(1);
+        let bob = AccountId(2);
+        for owner in [alice, bob] {
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    dec!(100),
+                    Order::new(BidOrAsk::Bid, dec!(1)).with_owner(owner),
+                )
+                .unwrap();
+        }
+        engine.halt_market(btc_usd()).unwrap();
+
+        let cancelled = engine.activate_kill_switch(Some(alice));
+        assert_eq!(cancelled, vec![(btc_usd(), OrderId(1))]);
+        engine.resume_market(btc_usd()).unwrap();
+        let order = |owner| Order::new(BidOrAsk::Bid, dec!(1)).with_owner(owner);
+        assert!(engine
+            .place_limit_order(btc_usd(), dec!(100), order(alice))
+            .is_err());
+        engine
+            .place_limit_order(btc_usd(), dec!(100), order(bob))
+            .unwrap();
+
+        assert_eq!(engine.activate_kill_switch(None).len(), 2);
+        assert!(engine
+            .place_limit_order(btc_usd(), dec!(100), order(bob))
+            .is_err());
+        engine.reset_kill_switch(None);
+        engine
+            .place_limit_order(btc_usd(), dec!(100), order(alice))
+            .unwrap();
+
+        let kills = events
+            .try_iter()
+            .filter(|event| matches!(event, EngineEvent::KillSwitchActivated { .. }))
+            .count();
+        assert_eq!(kills, 2);
+    }
+
+    #[test]
+    fn test_engine_rate_limits() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.set_rate_limits(RateLimits {
+            orders: Some(RateLimit::new(1, 1)),
+            cancels: Some(RateLimit::new(1, 1)),
+            weight: None,
+        });
+        let alice = AccountId(1);
+        let order = || Order::new(BidOrAsk::Bid, dec!(1)).with_owner(alice);
+
+        engine.set_time(Some(&btc_usd()), Some(1_000));
+        let placed = engine
+            .place_limit_order(btc_usd(), dec!(100), order())
+            .unwrap();
+        let err = engine
+            .place_limit_order(btc_usd(), dec!(100), order())
+            .unwrap_err();
+        assert!(matches!(err, EngineError::RateLimited { .. }));
+        // Other accounts and cancels have their own buckets.
+        engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        engine.cancel_order(btc_usd(), placed.order_id).unwrap();
+        assert!(engine.cancel_all_for_account(alice).is_err());
+
+        engine.set_time(Some(&btc_usd()), Some(2_000));
+        engine
+            .place_limit_order(btc_usd(), dec!(100), order())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_engine_enforces_market_config() {
+        let mut engine = MatchingEngine::new();
+        let config = MarketConfig::default()
+            .with_tick_size(dec!(0.5))
+            .with_lot_size(dec!(0.1))
+            .with_min_notional(dec!(10));
+        engine.add_new_market(btc_usd(), config);
+        assert_eq!(engine.market_config(btc_usd()).unwrap(), config);
+
+        let err = engine
+            .place_limit_order(btc_usd(), dec!(100.3), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap_err();
+        assert!(matches!(
+            err.rejection(),
+            Some(OrderBookError::OffTick { .. })
+        ));
+        assert!(engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(0.05)))
+            .is_err());
+        let ask = engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        assert!(engine
+            .amend_order(btc_usd(), ask.order_id, dec!(100), dec!(0.01))
+            .is_err());
+
+        // Post-only orders slide by a whole tick.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Slide),
+            )
+            .unwrap();
+        assert_eq!(
+            engine.depth(btc_usd(), 1).unwrap().bids[0].price,
+            dec!(99.5)
+        );
+        // The price slid to must meet the rules too.
+        let err = engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(0.1)).with_post_only(PostOnly::Slide),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.rejection(),
+            Some(OrderBookError::NotionalTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn test_engine_remove_market() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.enable_balance_checks().unwrap();
+        engine.deposit(AccountId(1), "USD", dec!(100)).unwrap();
+        let events = engine.subscribe();
+        let bid = engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(1)).with_owner(AccountId(1)),
+            )
+            .unwrap();
+
+        assert_eq!(engine.remove_market(btc_usd()).unwrap(), vec![bid.order_id]);
+        assert!(engine.depth(btc_usd(), 1).is_err());
+        assert!(engine.remove_market(btc_usd()).is_err());
+        let balance = engine.accounts().unwrap().balance(AccountId(1), "USD");
+        assert_eq!(balance.available, dec!(100));
+
+        let events: Vec<EngineEvent> = events.try_iter().collect();
+        assert!(events.contains(&EngineEvent::OrderCancelled {
+            pair: btc_usd(),
+            order_id: bid.order_id,
+        }));
+        assert_eq!(
+            events.last(),
+            Some(&EngineEvent::MarketRemoved { pair: btc_usd() })
+        );
+    }
+
+    #[test]
+    fn test_trading_pair_from_str() {
+        assert_eq!(" btc/usd ".parse::<TradingPair>().unwrap(), btc_usd());
+        assert_eq!(TradingPair::try_from("BTC-USD").unwrap(), btc_usd());
+        for invalid in [
+            "BTCUSD",
+            "BTC/",
+            "/USD",
+            "BTC/US D",
+            "BTC/btc",
+            "BTC/USD/EUR",
+        ] {
+            assert!(invalid.parse::<TradingPair>().is_err(), "{}", invalid);
+        }
+
+        let mut engine = MatchingEngine::new();
+        let eth_usd: TradingPair = "ETH/USD".parse().unwrap();
+        engine.add_new_market(eth_usd.clone(), MarketConfig::default());
+        engine.add_new_market(
+            btc_usd(),
+            MarketConfig::default().with_tick_size(dec!(0.01)),
+        );
+        let markets = engine.markets();
+        assert_eq!(markets[0].0, btc_usd());
+        assert_eq!(markets[0].1.tick_size, Some(dec!(0.01)));
+        assert_eq!(markets[1].0, eth_usd);
+    }
+
+    #[test]
+    fn test_engine_auction_uncross() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.set_reference_price(btc_usd(), dec!(100.5)).unwrap();
+        engine.start_auction(btc_usd()).unwrap();
+        let events = engine.subscribe();
+
+        let bid = engine
+            .place_limit_order(btc_usd(), dec!(101), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        let ask = engine
+            .place_limit_order(btc_usd(), dec!(99), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        assert_eq!(ask.status, OrderStatus::New);
+        let err = engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.rejection(), Some(&OrderBookError::NotAcceptedInAuction));
+
+        // 99 and 101 both match 1 with no surplus; 101 is nearer the reference.
+        assert_eq!(
+            engine.indicative_uncross(btc_usd()).unwrap(),
+            Some((dec!(101), dec!(1)))
+        );
+        let result = engine.uncross(btc_usd()).unwrap();
+        assert_eq!(result.price, Some(dec!(101)));
+        assert_eq!(result.trades[0].maker_order_id, bid.order_id);
+        assert_eq!(result.trades[0].taker_order_id, ask.order_id);
+        assert_eq!(engine.market_state(btc_usd()).unwrap(), MarketState::Open);
+        assert!(matches!(
+            engine.uncross(btc_usd()),
+            Err(EngineError::NotInAuction(_))
+        ));
+
+        let events: Vec<EngineEvent> = events.try_iter().collect();
+        assert!(events.contains(&EngineEvent::AuctionUncrossed {
+            pair: btc_usd(),
+            price: Some(dec!(101)),
+            volume: dec!(1),
+        }));
+        assert!(events.contains(&EngineEvent::MarketStateChanged {
+            pair: btc_usd(),
+            state: MarketState::Open,
+        }));
+    }
+
+    #[test]
+    fn test_engine_good_till_date_expiry() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        engine.set_time(Some(&btc_usd()), Some(1_000));
+        let gtd = |expiry| {
+            Order::new(BidOrAsk::Bid, dec!(1)).with_time_in_force(TimeInForce::GoodTillDate(expiry))
+        };
+
+        let err = engine
+            .place_limit_order(btc_usd(), dec!(100), gtd(1_000))
+            .unwrap_err();
+        assert_eq!(
+            err.rejection(),
+            Some(&OrderBookError::AlreadyExpired(1_000))
+        );
+        let first = engine
+            .place_limit_order(btc_usd(), dec!(100), gtd(2_000))
+            .unwrap();
+        let second = engine
+            .place_limit_order(btc_usd(), dec!(101), gtd(3_000))
+            .unwrap();
+        engine
+            .place_limit_order(btc_usd(), dec!(99), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        let events = engine.subscribe();
+
+        engine.set_time(Some(&btc_usd()), Some(2_000));
+        assert_eq!(engine.expire_orders(), vec![(btc_usd(), first.order_id)]);
+        let events: Vec<EngineEvent> = events.try_iter().collect();
+        assert_eq!(
+            events[..2],
+            [
+                EngineEvent::OrderExpired {
+                    pair: btc_usd(),
+                    order_id: first.order_id,
+                },
+                EngineEvent::OrderCancelled {
+                    pair: btc_usd(),
+                    order_id: first.order_id,
+                },
+            ]
+        );
+
+        // The market sweeps before handling a request, so the sell misses
+        // the bid that expired at 3_000.
+        engine.set_time(Some(&btc_usd()), Some(3_000));
+        let report = engine
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        assert_eq!(report.average_price, Some(dec!(99)));
+        assert!(engine.open_orders(AccountId::default()).is_empty());
+        assert!(engine.cancel_order(btc_usd(), second.order_id).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_engine_snapshot_round_trip() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let events = engine.subscribe();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(5))
+                    .with_display_size(dec!(1))
+                    .with_client_order_id("a".to_string()),
+            )
+            .unwrap();
+        engine
+            .place_limit_order(btc_usd(), dec!(101), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        let stop = OrderType::Stop {
+            stop_price: dec!(95),
+        };
+        let stop_order = engine
+            .place_order(btc_usd(), stop, Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+
+        let json = serde_json::to_string(&engine.snapshot()).unwrap();
+        let mut restored = MatchingEngine::restore(serde_json::from_str(&json).unwrap());
+
+        assert_eq!(
+            restored.depth(btc_usd(), 5).unwrap(),
+            engine.depth(btc_usd(), 5).unwrap()
+        );
+        assert_eq!(
+            restored.open_orders(AccountId::default()),
+            engine.open_orders(AccountId::default())
+        );
+        // Id assignment and client order id dedupe carry over.
+        assert!(restored
+            .place_limit_order(
+                btc_usd(),
+                dec!(90),
+                Order::new(BidOrAsk::Bid, dec!(1)).with_client_order_id("a".to_string()),
+            )
+            .is_err());
+        let report = restored
+            .place_limit_order(btc_usd(), dec!(90), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        assert_eq!(report.order_id, OrderId(stop_order.order_id.0 + 1));
+        // The iceberg still hides its reserve.
+        let report = restored
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Ask, dec!(3)))
+            .unwrap();
+        assert_eq!(report.trades.len(), 3);
+
+        // Events and everything in them round-trip too.
+        let published: Vec<EngineEvent> = events.try_iter().collect();
+        assert!(!published.is_empty());
+        for event in published {
+            let json = serde_json::to_string(&event).unwrap();
+            assert_eq!(serde_json::from_str::<EngineEvent>(&json).unwrap(), event);
+        }
+        let trade = &report.trades[0];
+        let json = serde_json::to_string(trade).unwrap();
+        assert_eq!(&serde_json::from_str::<Trade>(&json).unwrap(), trade);
+    }
+
+    #[test]
+    fn test_engine_updates_market_config_live() {
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(btc_usd(), MarketConfig::default());
+        let on_tick = engine
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        let off_tick = engine
+            .place_limit_order(btc_usd(), dec!(100.3), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        let events = engine.subscribe();
+        let config = MarketConfig::default().with_tick_size(dec!(0.5));
+
+        assert_eq!(
+            engine.update_market_config(btc_usd(), config, NonConformingOrders::Reject),
+            Err(EngineError::NonConformingOrders {
+                pair: btc_usd(),
+                order_ids: vec![off_tick.order_id],
+            })
+        );
+        assert!(matches!(
+            engine.update_market_config(
+                btc_usd(),
+                MarketConfig::default().with_tick_size(dec!(0)),
+                NonConformingOrders::Cancel,
+            ),
+            Err(EngineError::InvalidMarketConfig(_))
+        ));
+        assert_eq!(
+            engine.market_config(btc_usd()).unwrap(),
+            MarketConfig::default()
+        );
+        assert!(events.try_recv().is_err());
+
+        assert_eq!(
+            engine.update_market_config(btc_usd(), config, NonConformingOrders::Cancel),
+            Ok(vec![off_tick.order_id])
+        );
+        assert_eq!(engine.market_config(btc_usd()).unwrap(), config);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            EngineEvent::MarketConfigChanged {
+                pair: btc_usd(),
+                config,
+            }
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            EngineEvent::OrderCancelled {
+                pair: btc_usd(),
+                order_id: off_tick.order_id,
+            }
+        );
+        assert!(engine
+            .market(&btc_usd())
+            .unwrap()
+            .orderbook
+            .order(on_tick.order_id)
+            .is_some());
+        assert!(engine
+            .place_limit_order(btc_usd(), dec!(100.3), Order::new(BidOrAsk::Ask, dec!(1)))
+            .is_err());
+
+        engine.set_price_band(btc_usd(), Some(dec!(10))).unwrap();
+        assert_eq!(
+            events.try_iter().last(),
+            Some(EngineEvent::PriceBandChanged {
+                pair: btc_usd(),
+                percent: Some(dec!(10)),
+            })
+        );
     }
 }
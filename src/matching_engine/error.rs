@@ -0,0 +1,286 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::candles::CandleInterval;
+use super::engine::{MarketState, TradingPair};
+use super::orderbook::{AccountId, BidOrAsk, OrderId, PriceProtection};
+use super::ratelimit::RateAction;
+use super::session::SessionId;
+use super::triggers::{OrderType, TrailingOffset};
+
+/// Why an order was refused by the market it was sent to, or why a request
+/// naming an order could not find it.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OrderBookError {
+    #[error("No open order found with id: {0}")]
+    UnknownOrderId(OrderId),
+    #[error("Invalid price: {0}")]
+    InvalidPrice(Decimal),
+    #[error("Invalid order size: {0}")]
+    InvalidSize(Decimal),
+    #[error("Price {price} is not a multiple of the tick size {tick_size}")]
+    OffTick { price: Decimal, tick_size: Decimal },
+    #[error("Size {size} is not a multiple of the lot size {lot_size}")]
+    OddLot { size: Decimal, lot_size: Decimal },
+    #[error("Size {size} exceeds the maximum order size {max}")]
+    SizeTooLarge { size: Decimal, max: Decimal },
+    #[error("Notional {notional} is below the minimum of {min}")]
+    NotionalTooSmall { notional: Decimal, min: Decimal },
+    #[error("Limit price {price} is outside the price band {low} - {high}")]
+    OutsidePriceBand {
+        price: Decimal,
+        low: Decimal,
+        high: Decimal,
+    },
+    #[error("Duplicate client order id: {client_order_id:?} (order {order_id})")]
+    DuplicateClientOrderId {
+        client_order_id: String,
+        order_id: OrderId,
+    },
+    #[error("Not a stop order type: {0:?}")]
+    NotAStopOrder(OrderType),
+    #[error("Invalid trailing offset: {0:?}")]
+    InvalidTrailingOffset(TrailingOffset),
+    #[error("Trailing stops need a last trade price to trail from")]
+    NoLastTradePrice,
+    #[error("Only resting limit and stop orders are accepted during an auction")]
+    NotAcceptedInAuction,
+    #[error("Good-till-date expiry {0} has already passed")]
+    AlreadyExpired(u64),
+    #[error("Post-only order at {price} would cross the best opposite price {best}")]
+    PostOnlyWouldCross { price: Decimal, best: Decimal },
+    #[error("Invalid price protection: {0:?}")]
+    InvalidPriceProtection(PriceProtection),
+    #[error("Matching stopped at the protection price {0}")]
+    ProtectionPriceReached(Decimal),
+    #[error("Only {available} of {size} could be filled")]
+    InsufficientLiquidity { size: Decimal, available: Decimal },
+}
+
+impl OrderBookError {
+    /// Stable machine-readable name of the reason, for clients that branch
+    /// on rejections without parsing the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            OrderBookError::UnknownOrderId(_) => "unknown_order_id",
+            OrderBookError::InvalidPrice(_) => "invalid_price",
+            OrderBookError::InvalidSize(_) => "invalid_size",
+            OrderBookError::OffTick { .. } => "off_tick",
+            OrderBookError::OddLot { .. } => "odd_lot",
+            OrderBookError::SizeTooLarge { .. } => "size_too_large",
+            OrderBookError::NotionalTooSmall { .. } => "notional_too_small",
+            OrderBookError::OutsidePriceBand { .. } => "outside_price_band",
+            OrderBookError::DuplicateClientOrderId { .. } => "duplicate_client_order_id",
+            OrderBookError::NotAStopOrder(_) => "not_a_stop_order",
+            OrderBookError::InvalidTrailingOffset(_) => "invalid_trailing_offset",
+            OrderBookError::NoLastTradePrice => "no_last_trade_price",
+            OrderBookError::NotAcceptedInAuction => "not_accepted_in_auction",
+            OrderBookError::AlreadyExpired(_) => "already_expired",
+            OrderBookError::PostOnlyWouldCross { .. } => "post_only_would_cross",
+            OrderBookError::InvalidPriceProtection(_) => "invalid_price_protection",
+            OrderBookError::ProtectionPriceReached(_) => "protection_price_reached",
+            OrderBookError::InsufficientLiquidity { .. } => "insufficient_liquidity",
+        }
+    }
+}
+
+/// Every way a `MatchingEngine` request can fail.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum EngineError {
+    #[error("No market found for pair: {0}")]
+    MarketNotFound(TradingPair),
+    #[error("Invalid trading pair: {0:?}")]
+    InvalidTradingPair(String),
+    #[error("Market {pair} is {state}")]
+    MarketUnavailable {
+        pair: TradingPair,
+        state: MarketState,
+    },
+    #[error(transparent)]
+    OrderRejected {
+        #[from]
+        reason: OrderBookError,
+    },
+    #[error("Market {0} is not in an auction")]
+    NotInAuction(TradingPair),
+    #[error("Kill switch is active for account {0}")]
+    KillSwitchActive(AccountId),
+    #[error(
+        "Rate limit exceeded for account {account}: {action:?} allows {per_second} per second"
+    )]
+    RateLimited {
+        account: AccountId,
+        action: RateAction,
+        per_second: u32,
+    },
+    #[error("Order would take account {account} to a position of {position} in {pair}, over its limit of {max}")]
+    PositionLimitExceeded {
+        account: AccountId,
+        pair: TradingPair,
+        position: Decimal,
+        max: Decimal,
+    },
+    #[error("Order would take account {account} to an exposure of {exposure} in {pair}, over its limit of {max}")]
+    ExposureLimitExceeded {
+        account: AccountId,
+        pair: TradingPair,
+        exposure: Decimal,
+        max: Decimal,
+    },
+    #[error("Balance checks are not enabled")]
+    BalanceChecksDisabled,
+    #[error("Cannot enable balance checks with orders working in {0}")]
+    OrdersWorking(TradingPair),
+    #[error(
+        "Insufficient {asset} balance for account {account}: {available} available, {required} required"
+    )]
+    InsufficientBalance {
+        account: AccountId,
+        asset: String,
+        available: Decimal,
+        required: Decimal,
+    },
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(Decimal),
+    #[error("Stop buy orders need a limit price to reserve funds against")]
+    UnfundedStop,
+    #[error("No candles kept for interval: {}ms", .0.0)]
+    UnknownCandleInterval(CandleInterval),
+    #[error("Out of sequence command: expected {expected}, got {got}")]
+    OutOfSequence { expected: u64, got: u64 },
+    #[error("Engine service has stopped")]
+    ServiceStopped,
+    #[error("Invalid market config: {0}")]
+    InvalidMarketConfig(String),
+    #[error("Working orders in {pair} break the new market config: {order_ids:?}")]
+    NonConformingOrders {
+        pair: TradingPair,
+        order_ids: Vec<OrderId>,
+    },
+    #[error("No open session with id: {0}")]
+    UnknownSession(SessionId),
+    #[error("Batch operation {index} failed, nothing was applied: {reason}")]
+    BatchOpFailed {
+        index: usize,
+        reason: Box<EngineError>,
+    },
+}
+
+/// A broken `OrderBook` invariant, as reported by `OrderBook::validate`.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BookViolation {
+    #[error("Book is crossed: best bid {best_bid} >= best ask {best_ask}")]
+    Crossed {
+        best_bid: Decimal,
+        best_ask: Decimal,
+    },
+    #[error("Empty {bid_or_ask:?} level left at {price}")]
+    EmptyLevel {
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+    },
+    #[error("{bid_or_ask:?} level keyed at {key} has price {price}")]
+    LevelPriceMismatch {
+        bid_or_ask: BidOrAsk,
+        key: Decimal,
+        price: Decimal,
+    },
+    #[error("Queue links of the {bid_or_ask:?} level at {price} are inconsistent")]
+    CorruptQueue {
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+    },
+    #[error("Order {order_id} rests with non-positive size {size}")]
+    NonPositiveSize { order_id: OrderId, size: Decimal },
+    #[error("{bid_or_ask:?} order {order_id} rests on the other side")]
+    WrongSide {
+        order_id: OrderId,
+        bid_or_ask: BidOrAsk,
+    },
+    #[error("Order {0} rests more than once")]
+    DuplicateOrderId(OrderId),
+    #[error("Order {0} rests at a level the id index does not point to")]
+    UnindexedOrder(OrderId),
+    #[error("Id index lists order {0}, which is not resting")]
+    StaleIndexEntry(OrderId),
+}
+
+impl EngineError {
+    /// The order-level reason, if the market refused the order itself.
+    pub fn rejection(&self) -> Option<&OrderBookError> {
+        match self {
+            EngineError::OrderRejected { reason } => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Stable machine-readable name of the error, as `OrderBookError::code`
+    /// for refused orders. A failed batch operation reports its own reason.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::MarketNotFound(_) => "market_not_found",
+            EngineError::InvalidTradingPair(_) => "invalid_trading_pair",
+            EngineError::MarketUnavailable { .. } => "market_unavailable",
+            EngineError::OrderRejected { reason } => reason.code(),
+            EngineError::NotInAuction(_) => "not_in_auction",
+            EngineError::KillSwitchActive(_) => "kill_switch_active",
+            EngineError::RateLimited { .. } => "rate_limited",
+            EngineError::PositionLimitExceeded { .. } => "position_limit_exceeded",
+            EngineError::ExposureLimitExceeded { .. } => "exposure_limit_exceeded",
+            EngineError::BalanceChecksDisabled => "balance_checks_disabled",
+            EngineError::OrdersWorking(_) => "orders_working",
+            EngineError::InsufficientBalance { .. } => "insufficient_balance",
+            EngineError::InvalidAmount(_) => "invalid_amount",
+            EngineError::UnfundedStop => "unfunded_stop",
+            EngineError::UnknownCandleInterval(_) => "unknown_candle_interval",
+            EngineError::OutOfSequence { .. } => "out_of_sequence",
+            EngineError::ServiceStopped => "service_stopped",
+            EngineError::InvalidMarketConfig(_) => "invalid_market_config",
+            EngineError::NonConformingOrders { .. } => "non_conforming_orders",
+            EngineError::UnknownSession(_) => "unknown_session",
+            EngineError::BatchOpFailed { reason, .. } => reason.code(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_engine_error_messages() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        assert_eq!(
+            EngineError::MarketNotFound(pair.clone()).to_string(),
+            "No market found for pair: BTC/USD"
+        );
+        let rejected = EngineError::from(OrderBookError::OffTick {
+            price: dec!(100.3),
+            tick_size: dec!(0.5),
+        });
+        assert_eq!(
+            rejected.to_string(),
+            "Price 100.3 is not a multiple of the tick size 0.5"
+        );
+        assert!(matches!(
+            rejected.rejection(),
+            Some(OrderBookError::OffTick { .. })
+        ));
+        assert_eq!(
+            rejected.rejection().map(OrderBookError::code),
+            Some("off_tick")
+        );
+        assert_eq!(rejected.code(), "off_tick");
+        assert_eq!(EngineError::MarketNotFound(pair.clone()).rejection(), None);
+        assert_eq!(
+            EngineError::KillSwitchActive(AccountId(1)).code(),
+            "kill_switch_active"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::config::MarketConfig;
+use super::engine::{MarketState, TradingPair};
+use super::fees::FeeSchedule;
+use super::orderbook::{AccountId, BidOrAsk, BookUpdate, OrderId};
+use super::session::{SessionEnd, SessionId};
+use super::trade::Trade;
+use super::triggers::OrderType;
+
+/// Everything observable that happens inside `MatchingEngine`, in the order
+/// it happened. Persistence, market data and metrics consume these instead
+/// of hooking into the matching path.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EngineEvent {
+    MarketAdded {
+        pair: TradingPair,
+    },
+    /// The market was delisted, after its orders were cancelled.
+    MarketRemoved {
+        pair: TradingPair,
+    },
+    /// The kill switch was pulled, for everyone or for one account. The
+    /// cancellations it caused follow.
+    KillSwitchActivated {
+        account: Option<AccountId>,
+    },
+    KillSwitchReset {
+        account: Option<AccountId>,
+    },
+    SessionOpened {
+        session: SessionId,
+        account: AccountId,
+    },
+    /// A session ended. Any cancellations it caused follow.
+    SessionClosed {
+        session: SessionId,
+        account: AccountId,
+        reason: SessionEnd,
+    },
+    /// The market was halted, resumed, put into cancel-only mode or
+    /// switched to or from an auction.
+    MarketStateChanged {
+        pair: TradingPair,
+        state: MarketState,
+    },
+    /// The market's trading rules were replaced. Cancellations of working
+    /// orders that broke the new rules follow.
+    MarketConfigChanged {
+        pair: TradingPair,
+        config: MarketConfig,
+    },
+    FeeScheduleChanged {
+        pair: TradingPair,
+        schedule: FeeSchedule,
+    },
+    /// The price band was resized; `None` means it was removed.
+    PriceBandChanged {
+        pair: TradingPair,
+        percent: Option<Decimal>,
+    },
+    /// An auction ended; its trades were published just before. `price` is
+    /// `None` if the book did not cross.
+    AuctionUncrossed {
+        pair: TradingPair,
+        price: Option<Decimal>,
+        volume: Decimal,
+    },
+    /// An order passed validation and entered the market. Stops are accepted
+    /// when they are parked, not when they trigger.
+    OrderAccepted {
+        pair: TradingPair,
+        order_id: OrderId,
+        owner: AccountId,
+        bid_or_ask: BidOrAsk,
+        order_type: OrderType,
+        size: Decimal,
+    },
+    /// An order was refused. `order_id` is `None` if it was refused before
+    /// one was assigned.
+    OrderRejected {
+        pair: TradingPair,
+        order_id: Option<OrderId>,
+        reason: String,
+    },
+    /// A good-till-date order reached its expiry. Its cancellation follows.
+    OrderExpired {
+        pair: TradingPair,
+        order_id: OrderId,
+    },
+    /// A working order left the market without filling completely, whether
+    /// cancelled by its owner, by an OCO sibling, by self-trade prevention
+    /// or because its time in force expired.
+    OrderCancelled {
+        pair: TradingPair,
+        order_id: OrderId,
+    },
+    OrderAmended {
+        pair: TradingPair,
+        order_id: OrderId,
+        price: Decimal,
+        size: Decimal,
+    },
+    /// A pending stop fired and was submitted to the book.
+    OrderTriggered {
+        pair: TradingPair,
+        order_id: OrderId,
+    },
+    TradeExecuted {
+        pair: TradingPair,
+        trade: Trade,
+    },
+    /// A price level changed. Published after the other events of the
+    /// operation that caused it.
+    BookUpdated {
+        pair: TradingPair,
+        update: BookUpdate,
+    },
+}
+
+impl EngineEvent {
+    /// The market the event is about; kill switch and session events have
+    /// none.
+    pub fn pair(&self) -> Option<&TradingPair> {
+        match self {
+            EngineEvent::MarketAdded { pair }
+            | EngineEvent::MarketRemoved { pair }
+            | EngineEvent::MarketStateChanged { pair, .. }
+            | EngineEvent::MarketConfigChanged { pair, .. }
+            | EngineEvent::FeeScheduleChanged { pair, .. }
+            | EngineEvent::PriceBandChanged { pair, .. }
+            | EngineEvent::AuctionUncrossed { pair, .. }
+            | EngineEvent::OrderAccepted { pair, .. }
+            | EngineEvent::OrderRejected { pair, .. }
+            | EngineEvent::OrderExpired { pair, .. }
+            | EngineEvent::OrderCancelled { pair, .. }
+            | EngineEvent::OrderAmended { pair, .. }
+            | EngineEvent::OrderTriggered { pair, .. }
+            | EngineEvent::TradeExecuted { pair, .. }
+            | EngineEvent::BookUpdated { pair, .. } => Some(pair),
+            EngineEvent::KillSwitchActivated { .. }
+            | EngineEvent::KillSwitchReset { .. }
+            | EngineEvent::SessionOpened { .. }
+            | EngineEvent::SessionClosed { .. } => None,
+        }
+    }
+}
+
+/// Receives every `EngineEvent` the engine publishes. Listeners run
+/// synchronously on the engine's thread, so anything slow belongs behind a
+/// channel.
+pub trait EventListener: Send {
+    fn on_event(&mut self, event: &EngineEvent);
+}
+
+impl<F: FnMut(&EngineEvent) + Send> EventListener for F {
+    fn on_event(&mut self, event: &EngineEvent) {
+        self(event)
+    }
+}
@@ -0,0 +1,435 @@
+#![allow(dead_code)]
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::clock::{system_clock, Clock};
+use super::events::EngineEvent;
+use super::orderbook::BidOrAsk;
+use super::trade::Trade;
+use super::triggers::OrderType;
+
+/// Columns of the trade files, in order. New columns are only ever added at
+/// the end.
+pub const TRADE_COLUMNS: [&str; 11] = [
+    "timestamp",
+    "pair",
+    "price",
+    "size",
+    "taker_side",
+    "maker_order_id",
+    "taker_order_id",
+    "maker_account",
+    "taker_account",
+    "maker_fee",
+    "taker_fee",
+];
+
+/// Columns of the order files, in order. `event` is one of `accepted`,
+/// `rejected`, `triggered`, `amended`, `expired` and `cancelled`; columns
+/// an event does not carry are left empty.
+pub const ORDER_COLUMNS: [&str; 11] = [
+    "timestamp",
+    "pair",
+    "event",
+    "order_id",
+    "account",
+    "side",
+    "order_type",
+    "price",
+    "stop_price",
+    "size",
+    "reason",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma-separated, with a header row in every file.
+    Csv,
+    /// One JSON object per line, keyed by column name. Prices and sizes are
+    /// strings so no precision is lost.
+    #[cfg(feature = "serde")]
+    JsonLines,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            #[cfg(feature = "serde")]
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// Where and how history is written. Files are named
+/// `trades-00001.csv`, `orders-00001.csv` and so on; a new file is started
+/// once the current one reaches `max_file_bytes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportConfig {
+    pub dir: PathBuf,
+    pub format: ExportFormat,
+    pub max_file_bytes: u64,
+}
+
+impl ExportConfig {
+    pub fn new(dir: impl Into<PathBuf>, format: ExportFormat) -> ExportConfig {
+        ExportConfig {
+            dir: dir.into(),
+            format,
+            max_file_bytes: 64 * 1024 * 1024,
+        }
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> ExportConfig {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+}
+
+// One cell of an exported row; shared with the SQL store.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Field {
+    Empty,
+    Number(u64),
+    Text(String),
+}
+
+impl Field {
+    fn text(value: impl ToString) -> Field {
+        Field::Text(value.to_string())
+    }
+
+    fn csv(&self) -> String {
+        match self {
+            Field::Empty => String::new(),
+            Field::Number(number) => number.to_string(),
+            Field::Text(text) if text.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            }
+            Field::Text(text) => text.clone(),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn json(&self) -> serde_json::Value {
+        match self {
+            Field::Empty => serde_json::Value::Null,
+            Field::Number(number) => (*number).into(),
+            Field::Text(text) => text.clone().into(),
+        }
+    }
+}
+
+fn side(bid_or_ask: BidOrAsk) -> Field {
+    Field::text(match bid_or_ask {
+        BidOrAsk::Bid => "buy",
+        BidOrAsk::Ask => "sell",
+    })
+}
+
+pub(super) fn trade_row(pair: &str, trade: &Trade) -> Vec<Field> {
+    vec![
+        Field::Number(trade.timestamp),
+        Field::text(pair),
+        Field::text(trade.price),
+        Field::text(trade.size),
+        side(trade.taker_side),
+        Field::Number(trade.maker_order_id.0),
+        Field::Number(trade.taker_order_id.0),
+        Field::Number(trade.maker_owner.0),
+        Field::Number(trade.taker_owner.0),
+        Field::text(trade.maker_fee),
+        Field::text(trade.taker_fee),
+    ]
+}
+
+// The order lifecycle row for `event`, if it is one.
+pub(super) fn order_row(event: &EngineEvent, timestamp: u64) -> Option<Vec<Field>> {
+    let mut row = vec![Field::Empty; ORDER_COLUMNS.len()];
+    row[0] = Field::Number(timestamp);
+    let (pair, name, order_id) = match event {
+        EngineEvent::OrderAccepted {
+            pair,
+            order_id,
+            owner,
+            bid_or_ask,
+            order_type,
+            size,
+        } => {
+            row[4] = Field::Number(owner.0);
+            row[5] = side(*bid_or_ask);
+            let (kind, price) = match order_type {
+                OrderType::Market => ("market", None),
+                OrderType::Limit { price } => ("limit", Some(*price)),
+                OrderType::Stop { .. } => ("stop", None),
+                OrderType::StopLimit { limit_price, .. } => ("stop_limit", Some(*limit_price)),
+                OrderType::TrailingStop { .. } => ("trailing_stop", None),
+            };
+            row[6] = Field::text(kind);
+            row[7] = price.map_or(Field::Empty, Field::text);
+            row[8] = order_type.stop_price().map_or(Field::Empty, Field::text);
+            row[9] = Field::text(size);
+            (pair, "accepted", Some(*order_id))
+        }
+        EngineEvent::OrderRejected {
+            pair,
+            order_id,
+            reason,
+        } => {
+            row[10] = Field::text(reason);
+            (pair, "rejected", *order_id)
+        }
+        EngineEvent::OrderTriggered { pair, order_id } => (pair, "triggered", Some(*order_id)),
+        EngineEvent::OrderAmended {
+            pair,
+            order_id,
+            price,
+            size,
+        } => {
+            row[7] = Field::text(price);
+            row[9] = Field::text(size);
+            (pair, "amended", Some(*order_id))
+        }
+        EngineEvent::OrderExpired { pair, order_id } => (pair, "expired", Some(*order_id)),
+        EngineEvent::OrderCancelled { pair, order_id } => (pair, "cancelled", Some(*order_id)),
+        _ => return None,
+    };
+    row[1] = Field::text(pair);
+    row[2] = Field::text(name);
+    row[3] = order_id.map_or(Field::Empty, |order_id| Field::Number(order_id.0));
+    Some(row)
+}
+
+// A series of files `{stem}-{n}.{extension}`, moving on to the next once
+// one is full.
+#[derive(Debug)]
+struct RotatingFile {
+    dir: PathBuf,
+    stem: &'static str,
+    columns: &'static [&'static str],
+    format: ExportFormat,
+    max_bytes: u64,
+    index: u32,
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn path(dir: &Path, stem: &str, index: u32, format: ExportFormat) -> PathBuf {
+        dir.join(format!("{}-{:05}.{}", stem, index, format.extension()))
+    }
+
+    // Starts after the last existing file, so earlier exports are never
+    // overwritten.
+    fn create(
+        config: &ExportConfig,
+        stem: &'static str,
+        columns: &'static [&'static str],
+    ) -> io::Result<RotatingFile> {
+        let mut index = 1;
+        while RotatingFile::path(&config.dir, stem, index, config.format).exists() {
+            index += 1;
+        }
+        let mut file = RotatingFile {
+            dir: config.dir.clone(),
+            stem,
+            columns,
+            format: config.format,
+            max_bytes: config.max_file_bytes,
+            index,
+            file: BufWriter::new(File::create(RotatingFile::path(
+                &config.dir,
+                stem,
+                index,
+                config.format,
+            ))?),
+            written: 0,
+        };
+        file.write_header()?;
+        Ok(file)
+    }
+
+    fn path_of_current(&self) -> PathBuf {
+        RotatingFile::path(&self.dir, self.stem, self.index, self.format)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.format == ExportFormat::Csv {
+            let header = format!("{}\n", self.columns.join(","));
+            self.file.write_all(header.as_bytes())?;
+            self.written += header.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.index += 1;
+        self.file = BufWriter::new(
+            OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(self.path_of_current())?,
+        );
+        self.written = 0;
+        self.write_header()
+    }
+
+    fn write_row(&mut self, row: &[Field]) -> io::Result<()> {
+        let line = match self.format {
+            ExportFormat::Csv => {
+                let fields: Vec<String> = row.iter().map(Field::csv).collect();
+                fields.join(",")
+            }
+            #[cfg(feature = "serde")]
+            ExportFormat::JsonLines => {
+                let object: serde_json::Map<String, serde_json::Value> = self
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, field)| (column.to_string(), field.json()))
+                    .collect();
+                serde_json::Value::Object(object).to_string()
+            }
+        };
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.written += line.len() as u64 + 1;
+        if self.written >= self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes trades and order lifecycle events to flat files for compliance
+/// and offline analysis. Feed it the events of `MatchingEngine::subscribe`.
+/// Order events carry no time of their own, so they are stamped with the
+/// time they are exported at, from the system clock unless given another.
+#[derive(Debug)]
+pub struct HistoryExporter {
+    trades: RotatingFile,
+    orders: RotatingFile,
+    clock: Arc<dyn Clock>,
+}
+
+impl HistoryExporter {
+    /// Creates `config.dir` if needed and opens the first files.
+    pub fn create(config: &ExportConfig) -> io::Result<HistoryExporter> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(HistoryExporter {
+            trades: RotatingFile::create(config, "trades", &TRADE_COLUMNS)?,
+            orders: RotatingFile::create(config, "orders", &ORDER_COLUMNS)?,
+            clock: system_clock(),
+        })
+    }
+
+    /// Replaces the system clock order events are stamped with, e.g. with
+    /// the engine's own when replaying or backtesting.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Writes `event` if it is a trade or an order lifecycle event; returns
+    /// whether it was.
+    pub fn write(&mut self, event: &EngineEvent) -> io::Result<bool> {
+        if let EngineEvent::TradeExecuted { pair, trade } = event {
+            self.trades
+                .write_row(&trade_row(&pair.to_string(), trade))?;
+            return Ok(true);
+        }
+        match order_row(event, self.clock.now()) {
+            Some(row) => {
+                self.orders.write_row(&row)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Writes every event in `events`, returning how many were exported.
+    pub fn export(&mut self, events: impl IntoIterator<Item = EngineEvent>) -> io::Result<usize> {
+        let mut exported = 0;
+        for event in events {
+            if self.write(&event)? {
+                exported += 1;
+            }
+        }
+        Ok(exported)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.trades.file.flush()?;
+        self.orders.file.flush()
+    }
+
+    /// Paths of the files currently being written, trades first.
+    pub fn current_files(&self) -> (PathBuf, PathBuf) {
+        (self.trades.path_of_current(), self.orders.path_of_current())
+    }
+}
+
+impl Drop for HistoryExporter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::clock::ManualClock;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::{MatchingEngine, TradingPair};
+    use crate::matching_engine::orderbook::Order;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_history_exporter_writes_rotating_csv() {
+        let dir = std::env::temp_dir().join(format!("export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        let events = engine.subscribe();
+
+        let maker = engine
+            .place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        engine
+            .place_market_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        engine.cancel_order(pair.clone(), maker.order_id).unwrap();
+        assert!(engine
+            .place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Bid, dec!(0)))
+            .is_err());
+
+        let config = ExportConfig::new(&dir, ExportFormat::Csv).with_max_file_bytes(1);
+        let mut exporter = HistoryExporter::create(&config).unwrap();
+        exporter.set_clock(ManualClock::new(7_000));
+        // Two acceptances, a trade, a cancellation and a rejection.
+        assert_eq!(exporter.export(events.try_iter()).unwrap(), 5);
+        drop(exporter);
+
+        let trades = fs::read_to_string(dir.join("trades-00001.csv")).unwrap();
+        let mut lines = trades.lines();
+        assert_eq!(lines.next().unwrap(), TRADE_COLUMNS.join(","));
+        let trade: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(&trade[1..7], ["BTC/USD", "100", "1", "buy", "1", "2"]);
+
+        // Every order event went to its own file, the first with a header.
+        let orders = fs::read_to_string(dir.join("orders-00001.csv")).unwrap();
+        assert!(orders.starts_with("timestamp,pair,event"));
+        assert!(orders.contains("\n7000,BTC/USD,accepted,1,0,sell,limit,100,,2,"));
+        let rejected = fs::read_to_string(dir.join("orders-00004.csv")).unwrap();
+        assert!(rejected.contains(",BTC/USD,rejected,,,,,,,,"));
+        assert!(dir.join("orders-00005.csv").exists());
+
+        // A new exporter leaves the earlier files alone.
+        let exporter = HistoryExporter::create(&config).unwrap();
+        assert_eq!(exporter.current_files().0, dir.join("trades-00003.csv"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
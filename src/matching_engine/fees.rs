@@ -0,0 +1,154 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::orderbook::{AccountId, BidOrAsk};
+use super::trade::Trade;
+
+const BPS: Decimal = dec!(10000);
+
+/// Maker and taker rates in basis points that apply once an account has
+/// traded `min_volume` (in quote) in the market.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_bps: Decimal,
+    pub taker_bps: Decimal,
+}
+
+/// A market's fee rates in basis points. Negative rates are rebates. Fees
+/// are charged in the asset each side receives: base for the buyer, quote
+/// for the seller.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FeeSchedule {
+    pub maker_bps: Decimal,
+    pub taker_bps: Decimal,
+    /// Volume discounts, in ascending `min_volume` order.
+    pub tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    pub fn new(maker_bps: Decimal, taker_bps: Decimal) -> FeeSchedule {
+        FeeSchedule {
+            maker_bps,
+            taker_bps,
+            tiers: Vec::new(),
+        }
+    }
+
+    pub fn with_tier(
+        mut self,
+        min_volume: Decimal,
+        maker_bps: Decimal,
+        taker_bps: Decimal,
+    ) -> FeeSchedule {
+        self.tiers.push(FeeTier {
+            min_volume,
+            maker_bps,
+            taker_bps,
+        });
+        self.tiers.sort_by_key(|tier| tier.min_volume);
+        self
+    }
+
+    /// Maker and taker rates for an account that has traded `volume`.
+    pub fn rates(&self, volume: Decimal) -> (Decimal, Decimal) {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume <= volume)
+            .map_or((self.maker_bps, self.taker_bps), |tier| {
+                (tier.maker_bps, tier.taker_bps)
+            })
+    }
+}
+
+/// A fee schedule together with the per-account volume its tiers are
+/// based on.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Fees {
+    schedule: FeeSchedule,
+    volumes: HashMap<AccountId, Decimal>,
+}
+
+impl Fees {
+    pub fn schedule(&self) -> &FeeSchedule {
+        &self.schedule
+    }
+
+    /// Replaces the schedule; traded volume carries over.
+    pub fn set_schedule(&mut self, schedule: FeeSchedule) {
+        self.schedule = schedule;
+    }
+
+    /// Quote volume `account` has traded so far.
+    pub fn volume(&self, account: AccountId) -> Decimal {
+        self.volumes.get(&account).copied().unwrap_or_default()
+    }
+
+    /// Sets the fees of a freshly matched trade at the rates each side
+    /// qualifies for before it, then counts it towards their volume, once
+    /// if both sides have the same owner.
+    pub fn apply(&mut self, trade: &mut Trade) {
+        let notional = trade.price * trade.size;
+        let (maker_bps, _) = self.schedule.rates(self.volume(trade.maker_owner));
+        let (_, taker_bps) = self.schedule.rates(self.volume(trade.taker_owner));
+        let (maker_received, taker_received) = match trade.taker_side {
+            BidOrAsk::Bid => (notional, trade.size),
+            BidOrAsk::Ask => (trade.size, notional),
+        };
+        trade.maker_fee = maker_received * maker_bps / BPS;
+        trade.taker_fee = taker_received * taker_bps / BPS;
+
+        *self.volumes.entry(trade.maker_owner).or_default() += notional;
+        if trade.taker_owner != trade.maker_owner {
+            *self.volumes.entry(trade.taker_owner).or_default() += notional;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::OrderId;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_fees_apply_tiers() {
+        let mut fees = Fees::default();
+        fees.set_schedule(FeeSchedule::new(dec!(10), dec!(20)).with_tier(
+            dec!(1000),
+            dec!(-1),
+            dec!(5),
+        ));
+        let trade = |fees: &mut Fees| {
+            let mut trade = Trade::new(OrderId(1), OrderId(2), dec!(100), dec!(10), BidOrAsk::Bid)
+                .with_owners(AccountId(1), AccountId(2));
+            fees.apply(&mut trade);
+            trade
+        };
+
+        // The maker sells for 1000 quote; the taker buys 10 base.
+        let first = trade(&mut fees);
+        assert_eq!(first.maker_fee, dec!(1));
+        assert_eq!(first.taker_fee, dec!(0.02));
+        assert_eq!(fees.volume(AccountId(1)), dec!(1000));
+
+        let second = trade(&mut fees);
+        assert_eq!(second.maker_fee, dec!(-0.1));
+        assert_eq!(second.taker_fee, dec!(0.005));
+
+        // A self-trade counts once.
+        let mut own = Trade::new(OrderId(3), OrderId(4), dec!(100), dec!(1), BidOrAsk::Bid)
+            .with_owners(AccountId(3), AccountId(3));
+        fees.apply(&mut own);
+        assert_eq!(fees.volume(AccountId(3)), dec!(100));
+    }
+}
@@ -0,0 +1,438 @@
+#![allow(dead_code)]
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::mpsc;
+
+use rust_decimal::prelude::*;
+
+use super::config::MarketConfig;
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::events::EngineEvent;
+use super::orderbook::{AccountId, BidOrAsk, ExecutionReport, Order, OrderId, OrderStatus};
+use super::trade::Trade;
+
+pub const TE_OK: c_int = 0;
+/// The engine handle was null.
+pub const TE_ERR_NULL: c_int = -1;
+/// A string was null, not UTF-8, or not a valid pair, side or decimal.
+pub const TE_ERR_INVALID_ARGUMENT: c_int = -2;
+/// The engine refused the request; see `te_last_error`.
+pub const TE_ERR_ENGINE: c_int = -3;
+
+pub const TE_SIDE_BUY: c_int = 0;
+pub const TE_SIDE_SELL: c_int = 1;
+
+/// Room for any `Decimal` as a NUL-terminated string.
+pub const TE_DECIMAL_LEN: usize = 40;
+
+/// `ExecutionReport`, in C. `status` numbers `OrderStatus` in declaration
+/// order; `average_price` is empty if nothing was filled.
+#[repr(C)]
+pub struct TeReport {
+    pub order_id: u64,
+    pub status: c_int,
+    pub filled_size: [c_char; TE_DECIMAL_LEN],
+    pub remaining_size: [c_char; TE_DECIMAL_LEN],
+    pub average_price: [c_char; TE_DECIMAL_LEN],
+    pub trade_count: u64,
+}
+
+/// A trade handed to the trade callback. `pair` is only valid during the
+/// callback.
+#[repr(C)]
+pub struct TeTrade {
+    pub pair: *const c_char,
+    pub maker_order_id: u64,
+    pub taker_order_id: u64,
+    pub maker_account: u64,
+    pub taker_account: u64,
+    pub taker_side: c_int,
+    pub price: [c_char; TE_DECIMAL_LEN],
+    pub size: [c_char; TE_DECIMAL_LEN],
+    pub timestamp: u64,
+}
+
+pub type TeTradeCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, trade: *const TeTrade)>;
+
+/// The engine behind the C API's opaque `te_engine *`, declared in
+/// `include/trading_engine.h`. The caller owns it from `te_engine_new` to
+/// `te_engine_free`. Strings passed in are only borrowed for the call.
+/// Prices and sizes cross as decimal strings so nothing is rounded through
+/// a double, and results go into fixed buffers in caller-owned structs, so
+/// no memory is ever handed out.
+pub struct TeEngine {
+    engine: MatchingEngine,
+    events: mpsc::Receiver<EngineEvent>,
+    trade_callback: TeTradeCallback,
+    user_data: *mut c_void,
+    last_error: CString,
+}
+
+enum Failure {
+    Invalid(String),
+    Engine(EngineError),
+}
+
+impl From<EngineError> for Failure {
+    fn from(error: EngineError) -> Failure {
+        Failure::Engine(error)
+    }
+}
+
+unsafe fn text<'a>(value: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    if value.is_null() {
+        return Err(Failure::Invalid(format!("Missing {}", what)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| Failure::Invalid(format!("{} is not UTF-8", what)))
+}
+
+unsafe fn pair(value: *const c_char) -> Result<TradingPair, Failure> {
+    text(value, "pair")?
+        .parse()
+        .map_err(|error: EngineError| Failure::Invalid(error.to_string()))
+}
+
+unsafe fn decimal(value: *const c_char, what: &str) -> Result<Decimal, Failure> {
+    let value = text(value, what)?;
+    value
+        .parse()
+        .map_err(|_| Failure::Invalid(format!("Invalid {}: {}", what, value)))
+}
+
+fn side(value: c_int) -> Result<BidOrAsk, Failure> {
+    match value {
+        TE_SIDE_BUY => Ok(BidOrAsk::Bid),
+        TE_SIDE_SELL => Ok(BidOrAsk::Ask),
+        other => Err(Failure::Invalid(format!("Invalid side: {}", other))),
+    }
+}
+
+fn write_decimal(buffer: &mut [c_char; TE_DECIMAL_LEN], value: Option<Decimal>) {
+    *buffer = [0; TE_DECIMAL_LEN];
+    if let Some(value) = value {
+        let text = value.to_string();
+        for (slot, byte) in buffer.iter_mut().zip(text.bytes().take(TE_DECIMAL_LEN - 1)) {
+            *slot = byte as c_char;
+        }
+    }
+}
+
+fn status(status: OrderStatus) -> c_int {
+    match status {
+        OrderStatus::New => 0,
+        OrderStatus::PartiallyFilled => 1,
+        OrderStatus::Filled => 2,
+        OrderStatus::Cancelled => 3,
+        OrderStatus::Rejected => 4,
+        OrderStatus::Pending => 5,
+    }
+}
+
+fn write_report(out: &mut TeReport, report: &ExecutionReport) {
+    out.order_id = report.order_id.0;
+    out.status = status(report.status);
+    write_decimal(&mut out.filled_size, Some(report.filled_size));
+    write_decimal(&mut out.remaining_size, Some(report.remaining_size));
+    write_decimal(&mut out.average_price, report.average_price);
+    out.trade_count = report.trades.len() as u64;
+}
+
+fn c_trade(pair: &CStr, trade: &Trade) -> TeTrade {
+    let mut out = TeTrade {
+        pair: pair.as_ptr(),
+        maker_order_id: trade.maker_order_id.0,
+        taker_order_id: trade.taker_order_id.0,
+        maker_account: trade.maker_owner.0,
+        taker_account: trade.taker_owner.0,
+        taker_side: match trade.taker_side {
+            BidOrAsk::Bid => TE_SIDE_BUY,
+            BidOrAsk::Ask => TE_SIDE_SELL,
+        },
+        price: [0; TE_DECIMAL_LEN],
+        size: [0; TE_DECIMAL_LEN],
+        timestamp: trade.timestamp,
+    };
+    write_decimal(&mut out.price, Some(trade.price));
+    write_decimal(&mut out.size, Some(trade.size));
+    out
+}
+
+// Records the outcome of a call and hands its trades to the callback. No
+// reference to the engine is held while the callback runs, so it may call
+// back into the engine.
+unsafe fn finish(engine: *mut TeEngine, result: Result<(), Failure>) -> c_int {
+    let (code, trades, callback, user_data) = {
+        let handle = &mut *engine;
+        let (code, message) = match result {
+            Ok(()) => (TE_OK, String::new()),
+            Err(Failure::Invalid(message)) => (TE_ERR_INVALID_ARGUMENT, message),
+            Err(Failure::Engine(error)) => (TE_ERR_ENGINE, error.to_string()),
+        };
+        handle.last_error = CString::new(message).unwrap_or_default();
+        let trades: Vec<(TradingPair, Trade)> = handle
+            .events
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::TradeExecuted { pair, trade } => Some((pair, trade)),
+                _ => None,
+            })
+            .collect();
+        (code, trades, handle.trade_callback, handle.user_data)
+    };
+    if let Some(callback) = callback {
+        for (pair, trade) in &trades {
+            let pair = CString::new(pair.to_string()).unwrap_or_default();
+            let trade = c_trade(&pair, trade);
+            callback(user_data, &trade);
+        }
+    }
+    code
+}
+
+/// Creates an engine with no markets. Free it with `te_engine_free`.
+#[no_mangle]
+pub extern "C" fn te_engine_new() -> *mut TeEngine {
+    let mut engine = MatchingEngine::new();
+    let events = engine.subscribe();
+    Box::into_raw(Box::new(TeEngine {
+        engine,
+        events,
+        trade_callback: None,
+        user_data: std::ptr::null_mut(),
+        last_error: CString::default(),
+    }))
+}
+
+/// # Safety
+///
+/// `engine` must be null or come from `te_engine_new`, and must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn te_engine_free(engine: *mut TeEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Why the last call on `engine` failed; empty if it succeeded. Valid until
+/// the next call on `engine`.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn te_last_error(engine: *const TeEngine) -> *const c_char {
+    match engine.as_ref() {
+        Some(handle) => handle.last_error.as_ptr(),
+        None => c"".as_ptr(),
+    }
+}
+
+/// Calls `callback` with `user_data` for every trade, once the call that
+/// executed it has finished. Pass a null callback to stop.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle; `user_data` must stay valid for
+/// as long as the callback is set.
+#[no_mangle]
+pub unsafe extern "C" fn te_set_trade_callback(
+    engine: *mut TeEngine,
+    callback: TeTradeCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(handle) = engine.as_mut() else {
+        return TE_ERR_NULL;
+    };
+    handle.trade_callback = callback;
+    handle.user_data = user_data;
+    TE_OK
+}
+
+/// Lists `pair`, e.g. `"BTC/USD"`, with the default market config.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle; `pair` must be null or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn te_add_market(engine: *mut TeEngine, pair: *const c_char) -> c_int {
+    let Some(handle) = engine.as_mut() else {
+        return TE_ERR_NULL;
+    };
+    let result = self::pair(pair).map(|pair| {
+        handle.engine.add_new_market(pair, MarketConfig::default());
+    });
+    finish(engine, result)
+}
+
+/// Places a limit order; `report` may be null if the outcome is not needed.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle; the strings must be null or
+/// NUL-terminated; `report` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn te_place_limit_order(
+    engine: *mut TeEngine,
+    pair: *const c_char,
+    side: c_int,
+    price: *const c_char,
+    size: *const c_char,
+    account: u64,
+    report: *mut TeReport,
+) -> c_int {
+    let Some(handle) = engine.as_mut() else {
+        return TE_ERR_NULL;
+    };
+    let result = (|| -> Result<(), Failure> {
+        let order =
+            Order::new(self::side(side)?, decimal(size, "size")?).with_owner(AccountId(account));
+        let placed =
+            handle
+                .engine
+                .place_limit_order(self::pair(pair)?, decimal(price, "price")?, order)?;
+        if let Some(out) = report.as_mut() {
+            write_report(out, &placed);
+        }
+        Ok(())
+    })();
+    finish(engine, result)
+}
+
+/// Places a market order; `report` may be null if the outcome is not
+/// needed.
+///
+/// # Safety
+///
+/// As for `te_place_limit_order`.
+#[no_mangle]
+pub unsafe extern "C" fn te_place_market_order(
+    engine: *mut TeEngine,
+    pair: *const c_char,
+    side: c_int,
+    size: *const c_char,
+    account: u64,
+    report: *mut TeReport,
+) -> c_int {
+    let Some(handle) = engine.as_mut() else {
+        return TE_ERR_NULL;
+    };
+    let result = (|| -> Result<(), Failure> {
+        let order =
+            Order::new(self::side(side)?, decimal(size, "size")?).with_owner(AccountId(account));
+        let placed = handle.engine.place_market_order(self::pair(pair)?, order)?;
+        if let Some(out) = report.as_mut() {
+            write_report(out, &placed);
+        }
+        Ok(())
+    })();
+    finish(engine, result)
+}
+
+/// Cancels a resting order.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle; `pair` must be null or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn te_cancel_order(
+    engine: *mut TeEngine,
+    pair: *const c_char,
+    order_id: u64,
+) -> c_int {
+    let Some(handle) = engine.as_mut() else {
+        return TE_ERR_NULL;
+    };
+    let result = (|| -> Result<(), Failure> {
+        handle
+            .engine
+            .cancel_order(self::pair(pair)?, OrderId(order_id))?;
+        Ok(())
+    })();
+    finish(engine, result)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::mem::MaybeUninit;
+
+    unsafe extern "C" fn collect(user_data: *mut c_void, trade: *const TeTrade) {
+        let trades = &mut *(user_data as *mut Vec<(String, Decimal)>);
+        let trade = &*trade;
+        let pair = CStr::from_ptr(trade.pair).to_str().unwrap().to_string();
+        let price = CStr::from_ptr(trade.price.as_ptr()).to_str().unwrap();
+        trades.push((pair, price.parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ffi_places_cancels_and_reports_trades() {
+        unsafe {
+            let engine = te_engine_new();
+            let mut trades: Vec<(String, Decimal)> = Vec::new();
+            te_set_trade_callback(
+                engine,
+                Some(collect),
+                &mut trades as *mut Vec<(String, Decimal)> as *mut c_void,
+            );
+            assert_eq!(te_add_market(engine, c"BTC/USD".as_ptr()), TE_OK);
+
+            let mut report = MaybeUninit::<TeReport>::zeroed();
+            let code = te_place_limit_order(
+                engine,
+                c"BTC/USD".as_ptr(),
+                TE_SIDE_SELL,
+                c"30000.5".as_ptr(),
+                c"2".as_ptr(),
+                1,
+                report.as_mut_ptr(),
+            );
+            assert_eq!(code, TE_OK);
+            let maker = report.assume_init_ref().order_id;
+
+            let code = te_place_market_order(
+                engine,
+                c"BTC/USD".as_ptr(),
+                TE_SIDE_BUY,
+                c"0.5".as_ptr(),
+                2,
+                report.as_mut_ptr(),
+            );
+            assert_eq!(code, TE_OK);
+            let report = report.assume_init_ref();
+            assert_eq!(report.status, 2);
+            assert_eq!(
+                CStr::from_ptr(report.average_price.as_ptr()).to_str(),
+                Ok("30000.5")
+            );
+            assert_eq!(trades, [("BTC/USD".to_string(), dec!(30000.5))]);
+
+            assert_eq!(te_cancel_order(engine, c"BTC/USD".as_ptr(), maker), TE_OK);
+            assert_eq!(
+                te_cancel_order(engine, c"BTC/USD".as_ptr(), maker),
+                TE_ERR_ENGINE
+            );
+            assert!(!CStr::from_ptr(te_last_error(engine)).is_empty());
+            let code = te_place_market_order(
+                engine,
+                c"BTC/USD".as_ptr(),
+                7,
+                c"1".as_ptr(),
+                0,
+                std::ptr::null_mut(),
+            );
+            assert_eq!(code, TE_ERR_INVALID_ARGUMENT);
+            assert_eq!(
+                te_add_market(std::ptr::null_mut(), c"ETH/USD".as_ptr()),
+                TE_ERR_NULL
+            );
+            te_engine_free(engine);
+        }
+    }
+}
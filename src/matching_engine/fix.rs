@@ -0,0 +1,995 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_decimal::prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+use super::clock::{system_clock, Clock};
+use super::engine::TradingPair;
+use super::events::EngineEvent;
+use super::orderbook::{
+    AccountId, BidOrAsk, ExecutionReport, Order, OrderId, OrderStatus, TimeInForce,
+};
+use super::service::{EngineHandle, MarketDataFeed};
+use super::trade::Trade;
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+/// Largest message accepted from a counterparty.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Tags used by the gateway.
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const EXPIRE_TIME: u32 = 126;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    /// User-defined: `EngineError::code` of a rejection, next to its `TEXT`.
+    pub const REJECT_CODE: u32 = 5001;
+}
+
+/// Message types used by the gateway.
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const REJECT: &str = "3";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+}
+
+/// A FIX message as an ordered list of fields, without the framing
+/// `BeginString`, `BodyLength` and `CheckSum`, which are added on encoding
+/// and checked on decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> FixMessage {
+        FixMessage {
+            fields: vec![(tag::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> FixMessage {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    /// The first value of `tag`.
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn require(&self, tag: u32) -> Result<&str, String> {
+        self.get(tag)
+            .ok_or_else(|| format!("Missing required tag {}", tag))
+    }
+
+    fn parse<T: FromStr>(&self, tag: u32) -> Result<T, String> {
+        self.require(tag)?
+            .parse()
+            .map_err(|_| format!("Invalid value for tag {}", tag))
+    }
+
+    /// Frames the message for the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = String::new();
+        for (tag, value) in &self.fields {
+            let _ = write!(body, "{}={}\x01", tag, value);
+        }
+        let mut message = format!("8={}\x019={}\x01{}", BEGIN_STRING, body.len(), body);
+        let checksum = checksum(message.as_bytes());
+        let _ = write!(message, "10={:03}\x01", checksum);
+        message.into_bytes()
+    }
+
+    /// Decodes the first complete message in `buffer`, returning it with
+    /// the number of bytes it took up, or `None` if more data is needed.
+    pub fn decode(buffer: &[u8]) -> Result<Option<(FixMessage, usize)>, String> {
+        let prefix = format!("8={}\x019=", BEGIN_STRING);
+        if buffer.len() < prefix.len() {
+            return Ok(None);
+        }
+        if !buffer.starts_with(prefix.as_bytes()) {
+            return Err("Message must start with BeginString FIX.4.4".to_string());
+        }
+        let Some(length_end) = find_soh(buffer, prefix.len()) else {
+            return Ok(None);
+        };
+        let body_length: usize = std::str::from_utf8(&buffer[prefix.len()..length_end])
+            .ok()
+            .and_then(|length| length.parse().ok())
+            .filter(|length| *length <= MAX_MESSAGE_SIZE)
+            .ok_or("Invalid BodyLength")?;
+
+        let body_end = length_end + 1 + body_length;
+        // "10=" + three digits + SOH
+        let message_end = body_end + 7;
+        if buffer.len() < message_end {
+            return Ok(None);
+        }
+        let trailer = &buffer[body_end..message_end];
+        if !trailer.starts_with(b"10=") || trailer[6] != SOH {
+            return Err("BodyLength does not match the message".to_string());
+        }
+        let expected = std::str::from_utf8(&trailer[3..6])
+            .ok()
+            .and_then(|checksum| checksum.parse::<u8>().ok())
+            .ok_or("Invalid CheckSum")?;
+        if checksum(&buffer[..body_end]) != expected {
+            return Err("CheckSum mismatch".to_string());
+        }
+
+        let body = std::str::from_utf8(&buffer[length_end + 1..body_end])
+            .map_err(|_| "Message is not valid UTF-8".to_string())?;
+        let mut fields = Vec::new();
+        for field in body.split('\x01').filter(|field| !field.is_empty()) {
+            let (tag, value) = field
+                .split_once('=')
+                .and_then(|(tag, value)| Some((tag.parse().ok()?, value)))
+                .ok_or_else(|| format!("Malformed field {:?}", field))?;
+            fields.push((tag, value.to_string()));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tag::MSG_TYPE) {
+            return Err("MsgType must be the first body field".to_string());
+        }
+        Ok(Some((FixMessage { fields }, message_end)))
+    }
+}
+
+fn find_soh(buffer: &[u8], from: usize) -> Option<usize> {
+    buffer[from..]
+        .iter()
+        .position(|&byte| byte == SOH)
+        .map(|position| from + position)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// `timestamp` (milliseconds since the Unix epoch) as a FIX UTCTimestamp,
+/// `YYYYMMDD-HH:MM:SS.sss`.
+pub fn utc_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400_000) as i64;
+    let millis = timestamp % 86_400_000;
+    // Civil date from days since the epoch (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Parses a FIX UTCTimestamp, `YYYYMMDD-HH:MM:SS` with optional `.sss`
+/// milliseconds, into milliseconds since the Unix epoch.
+pub fn parse_utc_timestamp(value: &str) -> Option<u64> {
+    let (date, time) = value.split_once('-')?;
+    if date.len() != 8 || !date.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = date[..4].parse().ok()?;
+    let month: i64 = date[4..6].parse().ok()?;
+    let day: i64 = date[6..].parse().ok()?;
+    let (time, millis) = match time.split_once('.') {
+        Some((time, millis)) if millis.len() == 3 => (time, millis.parse::<u64>().ok()?),
+        Some(_) => return None,
+        None => (time, 0),
+    };
+    let fields: Vec<u64> = time
+        .split(':')
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let [hours, minutes, seconds] = fields[..] else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    // Days since the epoch from the civil date (Howard Hinnant's algorithm).
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+    Some(days * 86_400_000 + hours * 3_600_000 + minutes * 60_000 + seconds * 1000 + millis)
+}
+
+/// Accepts FIX 4.4 order-entry sessions and runs each against the engine.
+#[derive(Debug, Clone)]
+pub struct FixGateway {
+    comp_id: String,
+    handle: EngineHandle,
+    feed: MarketDataFeed,
+    clock: Arc<dyn Clock>,
+}
+
+impl FixGateway {
+    /// A gateway identifying itself as `comp_id`; counterparties must send
+    /// it as their `TargetCompID`.
+    pub fn new(comp_id: impl Into<String>, handle: EngineHandle, feed: MarketDataFeed) -> Self {
+        FixGateway {
+            comp_id: comp_id.into(),
+            handle,
+            feed,
+            clock: system_clock(),
+        }
+    }
+
+    /// Replaces the system clock outgoing messages take their SendingTime
+    /// from, e.g. with the engine's own when it runs on a manual clock.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Listens on `addr` and serves sessions until the process exits.
+    pub async fn serve(self, addr: SocketAddr) -> io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr).await?).await
+    }
+
+    pub async fn serve_listener(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let session = FixSession::new(self.clone());
+            tokio::spawn(session.run(stream));
+        }
+    }
+}
+
+// An order entered through the session, tracked until it is done so fills
+// against it can be reported.
+#[derive(Debug, Clone)]
+struct SessionOrder {
+    pair: TradingPair,
+    order_id: OrderId,
+    cl_ord_id: String,
+    symbol: String,
+    bid_or_ask: BidOrAsk,
+    order_qty: Decimal,
+    cum_qty: Decimal,
+    notional: Decimal,
+}
+
+impl SessionOrder {
+    fn leaves_qty(&self) -> Decimal {
+        self.order_qty - self.cum_qty
+    }
+
+    fn avg_px(&self) -> Decimal {
+        if self.cum_qty.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.notional / self.cum_qty
+        }
+    }
+
+    fn fill(&mut self, trade: &Trade) {
+        self.cum_qty += trade.size;
+        self.notional += trade.price * trade.size;
+    }
+}
+
+/// One counterparty connection. Sequence numbers start at 1 on every
+/// connection, and gaps are not recovered: a message numbered below the
+/// expected sequence ends the session, one numbered above is accepted.
+struct FixSession {
+    gateway: FixGateway,
+    counterparty: Option<String>,
+    heartbeat_interval: Duration,
+    incoming_sequence: u64,
+    outgoing_sequence: u64,
+    next_exec_id: u64,
+    orders: HashMap<(TradingPair, OrderId), SessionOrder>,
+    logged_out: bool,
+}
+
+impl FixSession {
+    fn new(gateway: FixGateway) -> FixSession {
+        FixSession {
+            gateway,
+            counterparty: None,
+            heartbeat_interval: Duration::from_secs(30),
+            incoming_sequence: 1,
+            outgoing_sequence: 1,
+            next_exec_id: 1,
+            orders: HashMap::new(),
+            logged_out: false,
+        }
+    }
+
+    async fn run(mut self, mut stream: TcpStream) {
+        let mut events = self.gateway.feed.subscribe();
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let mut heartbeat = tokio::time::interval(self.heartbeat_interval);
+        heartbeat.reset();
+        while !self.logged_out {
+            let replies = tokio::select! {
+                read = stream.read(&mut chunk) => match read {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => {
+                        buffer.extend_from_slice(&chunk[..n]);
+                        let mut replies = Vec::new();
+                        loop {
+                            match FixMessage::decode(&buffer) {
+                                Ok(Some((message, len))) => {
+                                    buffer.drain(..len);
+                                    replies.extend(self.on_message(message).await);
+                                    if self.logged_out {
+                                        break;
+                                    }
+                                }
+                                Ok(None) => break,
+                                // Framing is lost; nothing after this can be trusted.
+                                Err(_) => return,
+                            }
+                        }
+                        if self.heartbeat_interval != heartbeat.period() {
+                            heartbeat = tokio::time::interval(self.heartbeat_interval);
+                        }
+                        heartbeat.reset();
+                        replies
+                    }
+                },
+                event = events.recv() => match event {
+                    Ok(event) => self.on_event(&event),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // Fills may have been missed; the counterparty has
+                        // to reconcile its orders.
+                        self.logged_out = true;
+                        vec![FixMessage::new(msg_type::LOGOUT).with(
+                            tag::TEXT,
+                            format!("Gateway fell behind by {} events", skipped),
+                        )]
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                _ = heartbeat.tick(), if self.counterparty.is_some() => {
+                    vec![FixMessage::new(msg_type::HEARTBEAT)]
+                }
+            };
+            for reply in replies {
+                let bytes = self.stamp(reply).encode();
+                if stream.write_all(&bytes).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Adds the standard header to an outgoing message.
+    fn stamp(&mut self, message: FixMessage) -> FixMessage {
+        let mut fields = message.fields.into_iter();
+        let msg_type = fields.next().expect("messages start with MsgType");
+        let mut stamped = FixMessage {
+            fields: vec![msg_type],
+        }
+        .with(tag::SENDER_COMP_ID, &self.gateway.comp_id)
+        .with(
+            tag::TARGET_COMP_ID,
+            self.counterparty.as_deref().unwrap_or_default(),
+        )
+        .with(tag::MSG_SEQ_NUM, self.outgoing_sequence)
+        .with(tag::SENDING_TIME, utc_timestamp(self.gateway.clock.now()));
+        stamped.fields.extend(fields);
+        self.outgoing_sequence += 1;
+        stamped
+    }
+
+    async fn on_message(&mut self, message: FixMessage) -> Vec<FixMessage> {
+        let sequence = match message.parse::<u64>(tag::MSG_SEQ_NUM) {
+            Ok(sequence) => sequence,
+            Err(err) => return self.logout(err),
+        };
+        if sequence < self.incoming_sequence {
+            return self.logout(format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                self.incoming_sequence, sequence
+            ));
+        }
+        let Some(next) = sequence.checked_add(1) else {
+            return self.logout(format!("MsgSeqNum {} is out of range", sequence));
+        };
+        self.incoming_sequence = next;
+
+        if self.counterparty.is_none() {
+            return self.logon(&message);
+        }
+        match message.msg_type() {
+            msg_type::HEARTBEAT => Vec::new(),
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(msg_type::HEARTBEAT);
+                if let Some(id) = message.get(tag::TEST_REQ_ID) {
+                    heartbeat = heartbeat.with(tag::TEST_REQ_ID, id);
+                }
+                vec![heartbeat]
+            }
+            msg_type::LOGOUT => {
+                self.logged_out = true;
+                vec![FixMessage::new(msg_type::LOGOUT)]
+            }
+            msg_type::NEW_ORDER_SINGLE => self.new_order_single(&message).await,
+            msg_type::ORDER_CANCEL_REQUEST => self.order_cancel_request(&message).await,
+            other => vec![FixMessage::new(msg_type::REJECT)
+                .with(tag::REF_SEQ_NUM, sequence)
+                .with(tag::TEXT, format!("Unsupported MsgType {}", other))],
+        }
+    }
+
+    fn logout(&mut self, reason: String) -> Vec<FixMessage> {
+        self.logged_out = true;
+        vec![FixMessage::new(msg_type::LOGOUT).with(tag::TEXT, reason)]
+    }
+
+    fn logon(&mut self, message: &FixMessage) -> Vec<FixMessage> {
+        if message.msg_type() != msg_type::LOGON {
+            return self.logout("First message must be Logon".to_string());
+        }
+        if message.get(tag::TARGET_COMP_ID) != Some(self.gateway.comp_id.as_str()) {
+            return self.logout("Unknown TargetCompID".to_string());
+        }
+        let (Ok(counterparty), Ok(interval)) = (
+            message.require(tag::SENDER_COMP_ID),
+            message.parse::<u64>(tag::HEART_BT_INT),
+        ) else {
+            return self.logout("Logon requires SenderCompID and HeartBtInt".to_string());
+        };
+        self.counterparty = Some(counterparty.to_string());
+        self.heartbeat_interval = Duration::from_secs(interval.max(1));
+        vec![FixMessage::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, interval)]
+    }
+
+    // Execution report fields describing the state of `order`.
+    fn execution_report(&mut self, order: &SessionOrder, exec_type: &str) -> FixMessage {
+        let ord_status = match exec_type {
+            "4" => "4",
+            "8" => "8",
+            _ if order.leaves_qty().is_zero() => "2",
+            _ if order.cum_qty.is_zero() => "0",
+            _ => "1",
+        };
+        let exec_id = self.next_exec_id;
+        self.next_exec_id += 1;
+        FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, order.order_id)
+            .with(tag::CL_ORD_ID, &order.cl_ord_id)
+            .with(tag::EXEC_ID, exec_id)
+            .with(tag::EXEC_TYPE, exec_type)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::SYMBOL, &order.symbol)
+            .with(tag::SIDE, fix_side(order.bid_or_ask))
+            .with(tag::ORDER_QTY, order.order_qty)
+            .with(
+                tag::LEAVES_QTY,
+                if matches!(exec_type, "4" | "8") {
+                    Decimal::ZERO
+                } else {
+                    order.leaves_qty()
+                },
+            )
+            .with(tag::CUM_QTY, order.cum_qty)
+            .with(tag::AVG_PX, order.avg_px())
+    }
+
+    fn fill_report(&mut self, order: &SessionOrder, trade: &Trade) -> FixMessage {
+        self.execution_report(order, "F")
+            .with(tag::LAST_PX, trade.price)
+            .with(tag::LAST_QTY, trade.size)
+    }
+
+    fn reject(
+        &mut self,
+        message: &FixMessage,
+        reason: String,
+        code: Option<&str>,
+    ) -> Vec<FixMessage> {
+        let exec_id = self.next_exec_id;
+        self.next_exec_id += 1;
+        let rejected = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, "NONE")
+            .with(
+                tag::CL_ORD_ID,
+                message.get(tag::CL_ORD_ID).unwrap_or_default(),
+            )
+            .with(tag::EXEC_ID, exec_id)
+            .with(tag::EXEC_TYPE, "8")
+            .with(tag::ORD_STATUS, "8")
+            .with(tag::SYMBOL, message.get(tag::SYMBOL).unwrap_or_default())
+            .with(tag::SIDE, message.get(tag::SIDE).unwrap_or_default())
+            .with(tag::LEAVES_QTY, 0)
+            .with(tag::CUM_QTY, 0)
+            .with(tag::AVG_PX, 0)
+            .with(tag::TEXT, reason);
+        vec![with_code(rejected, code)]
+    }
+
+    async fn new_order_single(&mut self, message: &FixMessage) -> Vec<FixMessage> {
+        let request = match parse_new_order(message) {
+            Ok(request) => request,
+            Err(reason) => return self.reject(message, reason, None),
+        };
+        let NewOrder {
+            pair,
+            price,
+            order,
+            cl_ord_id,
+            symbol,
+        } = request;
+        let bid_or_ask = order.bid_or_ask();
+        let order_qty = order.size();
+        let result = match price {
+            Some(price) => {
+                self.gateway
+                    .handle
+                    .place_limit_order(pair.clone(), price, order)
+                    .await
+            }
+            None => {
+                self.gateway
+                    .handle
+                    .place_market_order(pair.clone(), order)
+                    .await
+            }
+        };
+        let report = match result {
+            Ok(report) => report,
+            Err(err) => return self.reject(message, err.to_string(), Some(err.code())),
+        };
+
+        let mut order = SessionOrder {
+            pair,
+            order_id: report.order_id,
+            cl_ord_id,
+            symbol,
+            bid_or_ask,
+            order_qty,
+            cum_qty: Decimal::ZERO,
+            notional: Decimal::ZERO,
+        };
+        self.report_placement(&mut order, &report)
+    }
+
+    // Acknowledges a placed order and reports what happened to it: fills,
+    // cancellation of an unfilled remainder, or rejection.
+    fn report_placement(
+        &mut self,
+        order: &mut SessionOrder,
+        report: &ExecutionReport,
+    ) -> Vec<FixMessage> {
+        if report.status == OrderStatus::Rejected {
+            let rejected = self.execution_report(order, "8");
+            return vec![with_reason(rejected, report)];
+        }
+        let mut replies = vec![self
+            .execution_report(order, "0")
+            .with(tag::TRANSACT_TIME, utc_timestamp(report.timestamp))];
+        for trade in &report.trades {
+            order.fill(trade);
+            replies.push(self.fill_report(order, trade));
+        }
+        match report.status {
+            OrderStatus::Cancelled => {
+                let cancelled = self.execution_report(order, "4");
+                replies.push(with_reason(cancelled, report));
+            }
+            OrderStatus::New | OrderStatus::PartiallyFilled | OrderStatus::Pending => {
+                self.orders
+                    .insert((order.pair.clone(), order.order_id), order.clone());
+            }
+            OrderStatus::Filled | OrderStatus::Rejected => {}
+        }
+        replies
+    }
+
+    async fn order_cancel_request(&mut self, message: &FixMessage) -> Vec<FixMessage> {
+        let (Ok(orig_cl_ord_id), Ok(cl_ord_id)) = (
+            message.require(tag::ORIG_CL_ORD_ID),
+            message.require(tag::CL_ORD_ID),
+        ) else {
+            return vec![cancel_reject(
+                message,
+                "Missing ClOrdID or OrigClOrdID",
+                None,
+            )];
+        };
+        let key = self
+            .orders
+            .iter()
+            .find(|(_, order)| order.cl_ord_id == orig_cl_ord_id)
+            .map(|(key, _)| key.clone());
+        let Some(key) = key else {
+            return vec![cancel_reject(message, "Unknown order", None)];
+        };
+        if let Err(err) = self.gateway.handle.cancel_order(key.0.clone(), key.1).await {
+            return vec![cancel_reject(message, &err.to_string(), Some(err.code()))];
+        }
+        // The report answers the cancel request, so it carries that
+        // request's ClOrdID and refers back to the order's own.
+        let mut order = self.orders.remove(&key).expect("order was found above");
+        order.cl_ord_id = cl_ord_id.to_string();
+        vec![self
+            .execution_report(&order, "4")
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)]
+    }
+
+    // Reports fills against resting orders entered through this session.
+    fn on_event(&mut self, event: &EngineEvent) -> Vec<FixMessage> {
+        let EngineEvent::TradeExecuted { pair, trade } = event else {
+            return Vec::new();
+        };
+        let key = (pair.clone(), trade.maker_order_id);
+        let Some(mut order) = self.orders.remove(&key) else {
+            return Vec::new();
+        };
+        order.fill(trade);
+        let report = self.fill_report(&order, trade);
+        if !order.leaves_qty().is_zero() {
+            self.orders.insert(key, order);
+        }
+        vec![report]
+    }
+}
+
+// Adds the book's reason for a rejection or cancelled remainder, if any.
+fn with_reason(message: FixMessage, report: &ExecutionReport) -> FixMessage {
+    match &report.reason {
+        Some(reason) => message
+            .with(tag::TEXT, reason)
+            .with(tag::REJECT_CODE, reason.code()),
+        None => message,
+    }
+}
+
+fn with_code(message: FixMessage, code: Option<&str>) -> FixMessage {
+    match code {
+        Some(code) => message.with(tag::REJECT_CODE, code),
+        None => message,
+    }
+}
+
+fn fix_side(bid_or_ask: BidOrAsk) -> &'static str {
+    match bid_or_ask {
+        BidOrAsk::Bid => "1",
+        BidOrAsk::Ask => "2",
+    }
+}
+
+fn cancel_reject(message: &FixMessage, reason: &str, code: Option<&str>) -> FixMessage {
+    let rejected = FixMessage::new(msg_type::ORDER_CANCEL_REJECT)
+        .with(tag::ORDER_ID, "NONE")
+        .with(
+            tag::CL_ORD_ID,
+            message.get(tag::CL_ORD_ID).unwrap_or_default(),
+        )
+        .with(
+            tag::ORIG_CL_ORD_ID,
+            message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default(),
+        )
+        .with(tag::ORD_STATUS, "8")
+        .with(tag::CXL_REJ_RESPONSE_TO, 1)
+        .with(tag::CXL_REJ_REASON, 1)
+        .with(tag::TEXT, reason);
+    with_code(rejected, code)
+}
+
+struct NewOrder {
+    pair: TradingPair,
+    price: Option<Decimal>,
+    order: Order,
+    cl_ord_id: String,
+    symbol: String,
+}
+
+// Translates a NewOrderSingle into an engine order.
+fn parse_new_order(message: &FixMessage) -> Result<NewOrder, String> {
+    let cl_ord_id = message.require(tag::CL_ORD_ID)?.to_string();
+    let symbol = message.require(tag::SYMBOL)?.to_string();
+    let pair = symbol
+        .parse::<TradingPair>()
+        .map_err(|err| err.to_string())?;
+    let bid_or_ask = match message.require(tag::SIDE)? {
+        "1" => BidOrAsk::Bid,
+        "2" => BidOrAsk::Ask,
+        other => return Err(format!("Unsupported Side {}", other)),
+    };
+    let size: Decimal = message.parse(tag::ORDER_QTY)?;
+    if size <= Decimal::ZERO {
+        return Err(format!("Invalid OrderQty {}", size));
+    }
+    let price = match message.require(tag::ORD_TYPE)? {
+        "1" => None,
+        "2" => Some(message.parse::<Decimal>(tag::PRICE)?),
+        other => return Err(format!("Unsupported OrdType {}", other)),
+    };
+    // Day orders rest like good-till-cancel ones; there are no sessions.
+    let time_in_force = match message.get(tag::TIME_IN_FORCE).unwrap_or("0") {
+        "0" | "1" => TimeInForce::GoodTillCancel,
+        "3" => TimeInForce::ImmediateOrCancel,
+        "4" => TimeInForce::FillOrKill,
+        "6" => message
+            .get(tag::EXPIRE_TIME)
+            .and_then(parse_utc_timestamp)
+            .map(TimeInForce::GoodTillDate)
+            .ok_or_else(|| "GoodTillDate orders require a valid ExpireTime".to_string())?,
+        other => return Err(format!("Unsupported TimeInForce {}", other)),
+    };
+    let owner = match message.get(tag::ACCOUNT) {
+        Some(_) => AccountId(message.parse(tag::ACCOUNT)?),
+        None => AccountId::default(),
+    };
+    let order = Order::new(bid_or_ask, size)
+        .with_owner(owner)
+        .with_time_in_force(time_in_force)
+        .with_client_order_id(cl_ord_id.clone());
+    Ok(NewOrder {
+        pair,
+        price,
+        order,
+        cl_ord_id,
+        symbol,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::clock::ManualClock;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::service::EngineService;
+
+    #[test]
+    fn test_fix_message_round_trip() {
+        let message = FixMessage::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "ENGINE")
+            .with(tag::MSG_SEQ_NUM, 1);
+        let mut bytes = message.encode();
+        assert!(bytes.starts_with(b"8=FIX.4.4\x019=30\x0135=0\x01"));
+
+        assert_eq!(FixMessage::decode(&bytes[..10]).unwrap(), None);
+        let len = bytes.len();
+        bytes.extend_from_slice(b"8=FIX");
+        let (decoded, used) = FixMessage::decode(&bytes).unwrap().unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(used, len);
+
+        bytes[len - 2] = b'0';
+        assert!(FixMessage::decode(&bytes).is_err());
+        assert_eq!(utc_timestamp(1_700_000_000_123), "20231114-22:13:20.123");
+        assert_eq!(
+            parse_utc_timestamp("20231114-22:13:20.123"),
+            Some(1_700_000_000_123)
+        );
+        assert_eq!(
+            parse_utc_timestamp("20000229-00:00:00"),
+            Some(951_782_400_000)
+        );
+        assert_eq!(parse_utc_timestamp("20231114-25:13:20"), None);
+    }
+
+    struct Client {
+        stream: TcpStream,
+        buffer: Vec<u8>,
+        sequence: u64,
+    }
+
+    impl Client {
+        async fn send(&mut self, message: FixMessage) {
+            let mut fields = message.fields.into_iter();
+            let mut stamped = FixMessage {
+                fields: vec![fields.next().unwrap()],
+            }
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "ENGINE")
+            .with(tag::MSG_SEQ_NUM, self.sequence);
+            stamped.fields.extend(fields);
+            self.sequence = self.sequence.wrapping_add(1);
+            self.stream.write_all(&stamped.encode()).await.unwrap();
+        }
+
+        async fn receive(&mut self) -> FixMessage {
+            let mut chunk = [0u8; 4096];
+            loop {
+                if let Some((message, len)) = FixMessage::decode(&self.buffer).unwrap() {
+                    self.buffer.drain(..len);
+                    return message;
+                }
+                let n = self.stream.read(&mut chunk).await.unwrap();
+                assert!(n > 0, "Gateway closed the connection");
+                self.buffer.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
+
+    fn new_order(cl_ord_id: &str, side: &str, qty: &str, price: Option<&str>) -> FixMessage {
+        let message = FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, "BTC/USD")
+            .with(tag::SIDE, side)
+            .with(tag::ORDER_QTY, qty);
+        match price {
+            Some(price) => message.with(tag::ORD_TYPE, "2").with(tag::PRICE, price),
+            None => message.with(tag::ORD_TYPE, "1"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fix_session_reports_orders_and_fills() {
+        let mut engine = MatchingEngine::new();
+        let feed = MarketDataFeed::attach(&mut engine);
+        let handle = EngineService::spawn(engine);
+        handle
+            .add_market(
+                TradingPair::new("BTC".to_string(), "USD".to_string()),
+                MarketConfig::default(),
+            )
+            .await
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut gateway = FixGateway::new("ENGINE", handle, feed);
+        gateway.set_clock(ManualClock::new(1_700_000_000_123));
+        tokio::spawn(gateway.serve_listener(listener));
+
+        let mut client = Client {
+            stream: TcpStream::connect(addr).await.unwrap(),
+            buffer: Vec::new(),
+            sequence: 1,
+        };
+        client
+            .send(
+                FixMessage::new(msg_type::LOGON)
+                    .with(tag::ENCRYPT_METHOD, 0)
+                    .with(tag::HEART_BT_INT, 30),
+            )
+            .await;
+        let logon = client.receive().await;
+        assert_eq!(logon.msg_type(), msg_type::LOGON);
+        assert_eq!(logon.get(tag::TARGET_COMP_ID), Some("CLIENT"));
+        assert_eq!(logon.get(tag::SENDING_TIME), Some("20231114-22:13:20.123"));
+
+        client.send(new_order("ask-1", "2", "3", Some("100"))).await;
+        let ack = client.receive().await;
+        assert_eq!(ack.get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(ack.get(tag::LEAVES_QTY), Some("3"));
+        assert!(ack.get(tag::TRANSACT_TIME).is_some());
+
+        client.send(new_order("bid-1", "1", "1", None)).await;
+        let ack = client.receive().await;
+        assert_eq!(ack.get(tag::CL_ORD_ID), Some("bid-1"));
+        let fill = client.receive().await;
+        assert_eq!(fill.get(tag::EXEC_TYPE), Some("F"));
+        assert_eq!(fill.get(tag::ORD_STATUS), Some("2"));
+        assert_eq!(fill.get(tag::LAST_PX), Some("100"));
+        // The resting ask is filled too, reported from the trade event.
+        let maker_fill = client.receive().await;
+        assert_eq!(maker_fill.get(tag::CL_ORD_ID), Some("ask-1"));
+        assert_eq!(maker_fill.get(tag::ORD_STATUS), Some("1"));
+        assert_eq!(maker_fill.get(tag::LEAVES_QTY), Some("2"));
+
+        client
+            .send(
+                FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+                    .with(tag::ORIG_CL_ORD_ID, "ask-1")
+                    .with(tag::CL_ORD_ID, "cancel-1")
+                    .with(tag::SYMBOL, "BTC/USD")
+                    .with(tag::SIDE, "2"),
+            )
+            .await;
+        let cancelled = client.receive().await;
+        assert_eq!(cancelled.get(tag::EXEC_TYPE), Some("4"));
+        assert_eq!(cancelled.get(tag::CUM_QTY), Some("1"));
+        assert_eq!(cancelled.get(tag::CL_ORD_ID), Some("cancel-1"));
+        assert_eq!(cancelled.get(tag::ORIG_CL_ORD_ID), Some("ask-1"));
+
+        client
+            .send(
+                FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+                    .with(tag::ORIG_CL_ORD_ID, "ask-1")
+                    .with(tag::CL_ORD_ID, "cancel-2"),
+            )
+            .await;
+        assert_eq!(
+            client.receive().await.msg_type(),
+            msg_type::ORDER_CANCEL_REJECT
+        );
+
+        client.send(new_order("bad", "7", "1", None)).await;
+        let rejected = client.receive().await;
+        assert_eq!(rejected.get(tag::ORD_STATUS), Some("8"));
+        assert!(rejected.get(tag::TEXT).unwrap().contains("Side"));
+        assert_eq!(rejected.get(tag::REJECT_CODE), None);
+
+        // Orders the engine refuses carry its code.
+        let unknown = FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, "eth")
+            .with(tag::SYMBOL, "ETH/USD")
+            .with(tag::SIDE, "1")
+            .with(tag::ORDER_QTY, "1")
+            .with(tag::ORD_TYPE, "1");
+        client.send(unknown).await;
+        let rejected = client.receive().await;
+        assert_eq!(rejected.get(tag::ORD_STATUS), Some("8"));
+        assert_eq!(rejected.get(tag::REJECT_CODE), Some("market_not_found"));
+
+        // A sequence number with no successor ends the session.
+        client.sequence = u64::MAX;
+        client.send(FixMessage::new(msg_type::HEARTBEAT)).await;
+        let logout = client.receive().await;
+        assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+        assert!(logout.get(tag::TEXT).unwrap().contains("MsgSeqNum"));
+    }
+}
@@ -0,0 +1,463 @@
+#![allow(dead_code)]
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use rust_decimal::prelude::*;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use super::engine::TradingPair;
+use super::error::{EngineError, OrderBookError};
+use super::events::EngineEvent;
+use super::orderbook::{
+    AccountId, BidOrAsk, BookUpdate, DepthLevel, DepthSnapshot, ExecutionReport, Order, OrderId,
+    OrderStatus, PriceProtection, TimeInForce,
+};
+use super::service::{EngineHandle, MarketDataFeed};
+use super::trade::Trade;
+
+/// Types and stubs generated from `proto/engine.proto`.
+pub mod proto {
+    tonic::include_proto!("trading_engine");
+}
+
+use proto::matching_engine_server::{self, MatchingEngineServer};
+
+/// Levels returned by `GetOrderBook` when the request leaves `levels` at 0.
+pub const DEFAULT_DEPTH: usize = 20;
+
+/// Implements the `MatchingEngine` gRPC service on top of an engine task.
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    handle: EngineHandle,
+    feed: MarketDataFeed,
+}
+
+impl GrpcService {
+    pub fn new(handle: EngineHandle, feed: MarketDataFeed) -> GrpcService {
+        GrpcService { handle, feed }
+    }
+
+    pub fn into_server(self) -> MatchingEngineServer<GrpcService> {
+        MatchingEngineServer::new(self)
+    }
+}
+
+/// Serves the gRPC service on `addr` until the process exits.
+pub async fn serve(
+    addr: SocketAddr,
+    handle: EngineHandle,
+    feed: MarketDataFeed,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(GrpcService::new(handle, feed).into_server())
+        .serve(addr)
+        .await
+}
+
+/// Metadata key carrying `EngineError::code` on statuses built from engine
+/// errors.
+pub const ERROR_CODE_KEY: &str = "error-code";
+
+impl From<EngineError> for Status {
+    fn from(err: EngineError) -> Status {
+        let message = err.to_string();
+        let code = err.code();
+        let mut status = match err {
+            EngineError::MarketNotFound(_)
+            | EngineError::OrderRejected {
+                reason: OrderBookError::UnknownOrderId(_),
+            } => Status::not_found(message),
+            EngineError::RateLimited { .. } => Status::resource_exhausted(message),
+            EngineError::MarketUnavailable { .. }
+            | EngineError::KillSwitchActive(_)
+            | EngineError::NonConformingOrders { .. } => Status::failed_precondition(message),
+            EngineError::ServiceStopped => Status::unavailable(message),
+            _ => Status::invalid_argument(message),
+        };
+        status.metadata_mut().insert(
+            ERROR_CODE_KEY,
+            tonic::metadata::MetadataValue::from_static(code),
+        );
+        status
+    }
+}
+
+fn parse_pair(pair: &str) -> Result<TradingPair, Status> {
+    Ok(pair.parse()?)
+}
+
+fn parse_decimal(field: &str, value: &str) -> Result<Decimal, Status> {
+    Decimal::from_str(value)
+        .map_err(|err| Status::invalid_argument(format!("Invalid {}: {}", field, err)))
+}
+
+#[tonic::async_trait]
+impl matching_engine_server::MatchingEngine for GrpcService {
+    async fn place_order(
+        &self,
+        request: Request<proto::PlaceOrderRequest>,
+    ) -> Result<Response<proto::ExecutionReport>, Status> {
+        let request = request.into_inner();
+        let pair = parse_pair(&request.pair)?;
+        let bid_or_ask = match request.side() {
+            proto::Side::Bid => BidOrAsk::Bid,
+            proto::Side::Ask => BidOrAsk::Ask,
+            proto::Side::Unspecified => {
+                return Err(Status::invalid_argument("Order side is required"))
+            }
+        };
+        let size = parse_decimal("size", &request.size)?;
+        if size <= Decimal::ZERO {
+            return Err(EngineError::from(OrderBookError::InvalidSize(size)).into());
+        }
+        let time_in_force = match request.time_in_force() {
+            proto::TimeInForce::GoodTillCancel => TimeInForce::GoodTillCancel,
+            proto::TimeInForce::ImmediateOrCancel => TimeInForce::ImmediateOrCancel,
+            proto::TimeInForce::FillOrKill => TimeInForce::FillOrKill,
+            proto::TimeInForce::GoodTillDate => match request.expire_time {
+                Some(expiry) => TimeInForce::GoodTillDate(expiry),
+                None => {
+                    return Err(Status::invalid_argument(
+                        "Good-till-date orders require an expire time",
+                    ))
+                }
+            },
+        };
+
+        let mut order = Order::new(bid_or_ask, size)
+            .with_owner(AccountId(request.owner))
+            .with_time_in_force(time_in_force)
+            .with_hidden(request.hidden);
+        if let Some(client_order_id) = request.client_order_id {
+            order = order.with_client_order_id(client_order_id);
+        }
+        match (&request.protection_price, &request.max_slippage_percent) {
+            (Some(_), Some(_)) => {
+                return Err(Status::invalid_argument(
+                    "Set either a protection price or a maximum slippage, not both",
+                ))
+            }
+            (Some(price), None) => {
+                let price = parse_decimal("protection_price", price)?;
+                order = order.with_protection(PriceProtection::Price(price));
+            }
+            (None, Some(percent)) => {
+                let percent = parse_decimal("max_slippage_percent", percent)?;
+                order = order.with_protection(PriceProtection::Percent(percent));
+            }
+            (None, None) => {}
+        }
+        let report = match request.price {
+            Some(price) => {
+                let price = parse_decimal("price", &price)?;
+                self.handle.place_limit_order(pair, price, order).await
+            }
+            None => self.handle.place_market_order(pair, order).await,
+        }?;
+        Ok(Response::new((&report).into()))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<proto::CancelOrderRequest>,
+    ) -> Result<Response<proto::Order>, Status> {
+        let request = request.into_inner();
+        let pair = parse_pair(&request.pair)?;
+        let order = self
+            .handle
+            .cancel_order(pair, OrderId(request.order_id))
+            .await?;
+        Ok(Response::new((&order).into()))
+    }
+
+    async fn get_order_book(
+        &self,
+        request: Request<proto::GetOrderBookRequest>,
+    ) -> Result<Response<proto::OrderBook>, Status> {
+        let request = request.into_inner();
+        let pair = parse_pair(&request.pair)?;
+        let levels = match request.levels {
+            0 => DEFAULT_DEPTH,
+            levels => levels as usize,
+        };
+        let depth = self.handle.depth(pair, levels).await?;
+        Ok(Response::new((&depth).into()))
+    }
+
+    type StreamEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::MarketEvent, Status>> + Send>>;
+
+    async fn stream_events(
+        &self,
+        request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let pairs = request
+            .into_inner()
+            .pairs
+            .iter()
+            .map(|pair| parse_pair(pair))
+            .collect::<Result<Vec<_>, _>>()?;
+        // An error item ends the stream; a client that lagged resubscribes
+        // and re-reads the book.
+        let events =
+            BroadcastStream::new(self.feed.subscribe()).filter_map(move |event| match event {
+                Ok(event) => market_event(&event, &pairs).map(Ok),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => Some(Err(Status::data_loss(
+                    format!("Fell behind by {} events", skipped),
+                ))),
+            });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+// The wire form of `event`, if it is market data for one of `pairs` (any
+// pair when empty).
+fn market_event(event: &EngineEvent, pairs: &[TradingPair]) -> Option<proto::MarketEvent> {
+    let (pair, event) = match event {
+        EngineEvent::TradeExecuted { pair, trade } => {
+            (pair, proto::market_event::Event::Trade(trade.into()))
+        }
+        EngineEvent::BookUpdated { pair, update } => {
+            (pair, proto::market_event::Event::BookUpdate(update.into()))
+        }
+        _ => return None,
+    };
+    if !pairs.is_empty() && !pairs.contains(pair) {
+        return None;
+    }
+    Some(proto::MarketEvent {
+        pair: pair.to_string(),
+        event: Some(event),
+    })
+}
+
+impl From<BidOrAsk> for proto::Side {
+    fn from(bid_or_ask: BidOrAsk) -> proto::Side {
+        match bid_or_ask {
+            BidOrAsk::Bid => proto::Side::Bid,
+            BidOrAsk::Ask => proto::Side::Ask,
+        }
+    }
+}
+
+impl From<OrderStatus> for proto::OrderStatus {
+    fn from(status: OrderStatus) -> proto::OrderStatus {
+        match status {
+            OrderStatus::New => proto::OrderStatus::New,
+            OrderStatus::PartiallyFilled => proto::OrderStatus::PartiallyFilled,
+            OrderStatus::Filled => proto::OrderStatus::Filled,
+            OrderStatus::Cancelled => proto::OrderStatus::Cancelled,
+            OrderStatus::Rejected => proto::OrderStatus::Rejected,
+            OrderStatus::Pending => proto::OrderStatus::Pending,
+        }
+    }
+}
+
+impl From<&Trade> for proto::Trade {
+    fn from(trade: &Trade) -> proto::Trade {
+        proto::Trade {
+            maker_order_id: trade.maker_order_id.0,
+            taker_order_id: trade.taker_order_id.0,
+            price: trade.price.to_string(),
+            size: trade.size.to_string(),
+            timestamp: trade.timestamp,
+        }
+    }
+}
+
+impl From<&ExecutionReport> for proto::ExecutionReport {
+    fn from(report: &ExecutionReport) -> proto::ExecutionReport {
+        proto::ExecutionReport {
+            order_id: report.order_id.0,
+            status: proto::OrderStatus::from(report.status) as i32,
+            filled_size: report.filled_size.to_string(),
+            average_price: report.average_price.map(|price| price.to_string()),
+            remaining_size: report.remaining_size.to_string(),
+            trades: report.trades.iter().map(proto::Trade::from).collect(),
+            self_trade_cancelled: report.self_trade_cancelled.iter().map(|id| id.0).collect(),
+            timestamp: report.timestamp,
+            sequence: report.sequence,
+            reason: report.reason.as_ref().map(|reason| reason.to_string()),
+        }
+    }
+}
+
+impl From<&Order> for proto::Order {
+    fn from(order: &Order) -> proto::Order {
+        proto::Order {
+            order_id: order.id().0,
+            owner: order.owner().0,
+            side: proto::Side::from(order.bid_or_ask()) as i32,
+            remaining_size: order.remaining_size().to_string(),
+            timestamp: order.timestamp(),
+            sequence: order.sequence(),
+            client_order_id: order.client_order_id().map(str::to_string),
+        }
+    }
+}
+
+impl From<&DepthLevel> for proto::DepthLevel {
+    fn from(level: &DepthLevel) -> proto::DepthLevel {
+        proto::DepthLevel {
+            price: level.price.to_string(),
+            size: level.size.to_string(),
+            order_count: level.order_count as u64,
+        }
+    }
+}
+
+impl From<&DepthSnapshot> for proto::OrderBook {
+    fn from(depth: &DepthSnapshot) -> proto::OrderBook {
+        proto::OrderBook {
+            sequence: depth.sequence,
+            bids: depth.bids.iter().map(proto::DepthLevel::from).collect(),
+            asks: depth.asks.iter().map(proto::DepthLevel::from).collect(),
+        }
+    }
+}
+
+impl From<&BookUpdate> for proto::BookUpdate {
+    fn from(update: &BookUpdate) -> proto::BookUpdate {
+        let (bid_or_ask, price, size) = match *update {
+            BookUpdate::Add {
+                bid_or_ask,
+                price,
+                size,
+                ..
+            }
+            | BookUpdate::Reduce {
+                bid_or_ask,
+                price,
+                size,
+                ..
+            } => (bid_or_ask, price, size),
+            BookUpdate::Remove {
+                bid_or_ask, price, ..
+            } => (bid_or_ask, price, Decimal::ZERO),
+        };
+        proto::BookUpdate {
+            sequence: update.sequence(),
+            side: proto::Side::from(bid_or_ask) as i32,
+            price: price.to_string(),
+            size: size.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::service::EngineService;
+    use proto::matching_engine_client::MatchingEngineClient;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    fn place(side: proto::Side, price: Option<&str>, size: &str) -> proto::PlaceOrderRequest {
+        proto::PlaceOrderRequest {
+            pair: "BTC-USD".to_string(),
+            side: side as i32,
+            price: price.map(str::to_string),
+            size: size.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_order_entry_and_event_stream() {
+        let mut engine = MatchingEngine::new();
+        let feed = MarketDataFeed::attach(&mut engine);
+        let handle = EngineService::spawn(engine);
+        handle
+            .add_market(
+                TradingPair::new("BTC".to_string(), "USD".to_string()),
+                MarketConfig::default(),
+            )
+            .await
+            .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tonic::transport::Server::builder()
+            .add_service(GrpcService::new(handle, feed).into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+
+        let mut client = MatchingEngineClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let mut events = client
+            .stream_events(proto::StreamEventsRequest {
+                pairs: vec!["BTC/USD".to_string()],
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let maker = client
+            .place_order(place(proto::Side::Ask, Some("100"), "3"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(maker.status(), proto::OrderStatus::New);
+        let taker = client
+            .place_order(place(proto::Side::Bid, None, "1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(taker.status(), proto::OrderStatus::Filled);
+        assert_eq!(taker.average_price.as_deref(), Some("100"));
+
+        let book = client
+            .get_order_book(proto::GetOrderBookRequest {
+                pair: "BTC-USD".to_string(),
+                levels: 0,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(book.asks[0].size, "2");
+
+        // The resting ask, the trade, then the ask level shrinking.
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(events.message().await.unwrap().unwrap().event.unwrap());
+        }
+        assert!(matches!(&received[0],
+            proto::market_event::Event::BookUpdate(update) if update.size == "3"));
+        assert!(matches!(&received[1],
+            proto::market_event::Event::Trade(trade) if trade.maker_order_id == maker.order_id));
+        assert!(matches!(&received[2],
+            proto::market_event::Event::BookUpdate(update) if update.size == "2"));
+
+        let cancelled = client
+            .cancel_order(proto::CancelOrderRequest {
+                pair: "BTC-USD".to_string(),
+                order_id: maker.order_id,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(cancelled.remaining_size, "2");
+        let error = client
+            .cancel_order(proto::CancelOrderRequest {
+                pair: "BTC-USD".to_string(),
+                order_id: maker.order_id,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::NotFound);
+        assert_eq!(
+            error.metadata().get(ERROR_CODE_KEY).unwrap(),
+            "unknown_order_id"
+        );
+        let error = client
+            .place_order(place(proto::Side::Unspecified, None, "1"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+}
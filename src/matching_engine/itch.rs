@@ -0,0 +1,498 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use rust_decimal::prelude::*;
+
+use super::engine::TradingPair;
+use super::events::EngineEvent;
+use super::orderbook::{BidOrAsk, BookUpdate, OrderId};
+use super::trade::Trade;
+
+/// Prices and sizes go on the wire as integers in units of 10^-8.
+pub const PRICE_SCALE: u32 = 8;
+/// Width of the space-padded pair name in `MarketDirectory` messages.
+pub const PAIR_WIDTH: usize = 16;
+// Type, locate and sequence, common to every message.
+const HEADER_LEN: usize = 1 + 2 + 8;
+
+/// One message of the binary feed. Every message is fixed-size for its
+/// type and little-endian:
+///
+/// | field    | bytes | notes                                  |
+/// |----------|-------|----------------------------------------|
+/// | type     | 1     | `R`, `A`, `U`, `D` or `P`              |
+/// | locate   | 2     | market number from `MarketDirectory`   |
+/// | sequence | 8     | feed sequence, increasing by one       |
+/// | body     | fixed | see `ItchBody`                         |
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItchMessage {
+    pub sequence: u64,
+    pub locate: u16,
+    pub body: ItchBody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItchBody {
+    /// `R`: names the market behind a locate; sent before its first use.
+    /// Body: 16-byte pair, e.g. `BTC/USD` padded with spaces.
+    MarketDirectory { pair: TradingPair },
+    /// `A`: a level was created or grew. Body: side (`B`/`S`), price,
+    /// new level size.
+    AddLevel {
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+        size: Decimal,
+    },
+    /// `U`: a level shrank. Same body as `AddLevel`.
+    ReduceLevel {
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+        size: Decimal,
+    },
+    /// `D`: a level was removed. Body: side, price.
+    RemoveLevel {
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+    },
+    /// `P`: a trade. Body: maker id, taker id, taker side, price, size,
+    /// timestamp.
+    Trade { trade: Trade },
+}
+
+impl ItchBody {
+    fn type_code(&self) -> u8 {
+        match self {
+            ItchBody::MarketDirectory { .. } => b'R',
+            ItchBody::AddLevel { .. } => b'A',
+            ItchBody::ReduceLevel { .. } => b'U',
+            ItchBody::RemoveLevel { .. } => b'D',
+            ItchBody::Trade { .. } => b'P',
+        }
+    }
+}
+
+/// Encoded size of messages of type `type_code`.
+pub fn message_len(type_code: u8) -> Option<usize> {
+    let body = match type_code {
+        b'R' => PAIR_WIDTH,
+        b'A' | b'U' => 1 + 8 + 8,
+        b'D' => 1 + 8,
+        b'P' => 8 + 8 + 1 + 8 + 8 + 8,
+        _ => return None,
+    };
+    Some(HEADER_LEN + body)
+}
+
+fn to_fixed(value: Decimal) -> Result<i64, String> {
+    let scaled = value * Decimal::from(10i64.pow(PRICE_SCALE));
+    match scaled.fract().is_zero() {
+        true => scaled
+            .to_i64()
+            .ok_or_else(|| format!("{} is out of range for the binary feed", value)),
+        false => Err(format!(
+            "{} has more than {} decimal places",
+            value, PRICE_SCALE
+        )),
+    }
+}
+
+fn from_fixed(value: i64) -> Decimal {
+    Decimal::new(value, PRICE_SCALE).normalize()
+}
+
+fn side_code(bid_or_ask: BidOrAsk) -> u8 {
+    match bid_or_ask {
+        BidOrAsk::Bid => b'B',
+        BidOrAsk::Ask => b'S',
+    }
+}
+
+impl ItchMessage {
+    /// Appends the encoded message to `out`. Fails without writing anything
+    /// if a value cannot be represented.
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + 40);
+        bytes.push(self.body.type_code());
+        bytes.extend_from_slice(&self.locate.to_le_bytes());
+        bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        match &self.body {
+            ItchBody::MarketDirectory { pair } => {
+                let name = pair.to_string();
+                if name.len() > PAIR_WIDTH || !name.is_ascii() {
+                    return Err(format!("Pair {} does not fit the binary feed", name));
+                }
+                bytes.extend_from_slice(format!("{:<16}", name).as_bytes());
+            }
+            ItchBody::AddLevel {
+                bid_or_ask,
+                price,
+                size,
+            }
+            | ItchBody::ReduceLevel {
+                bid_or_ask,
+                price,
+                size,
+            } => {
+                bytes.push(side_code(*bid_or_ask));
+                bytes.extend_from_slice(&to_fixed(*price)?.to_le_bytes());
+                bytes.extend_from_slice(&to_fixed(*size)?.to_le_bytes());
+            }
+            ItchBody::RemoveLevel { bid_or_ask, price } => {
+                bytes.push(side_code(*bid_or_ask));
+                bytes.extend_from_slice(&to_fixed(*price)?.to_le_bytes());
+            }
+            ItchBody::Trade { trade } => {
+                bytes.extend_from_slice(&trade.maker_order_id.0.to_le_bytes());
+                bytes.extend_from_slice(&trade.taker_order_id.0.to_le_bytes());
+                bytes.push(side_code(trade.taker_side));
+                bytes.extend_from_slice(&to_fixed(trade.price)?.to_le_bytes());
+                bytes.extend_from_slice(&to_fixed(trade.size)?.to_le_bytes());
+                bytes.extend_from_slice(&trade.timestamp.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Decodes the message at the start of `buffer`, returning it with its
+    /// length, or `None` if the buffer holds only part of it.
+    pub fn decode(buffer: &[u8]) -> Result<Option<(ItchMessage, usize)>, String> {
+        let Some(&type_code) = buffer.first() else {
+            return Ok(None);
+        };
+        let len = message_len(type_code)
+            .ok_or_else(|| format!("Unknown message type {:#04x}", type_code))?;
+        if buffer.len() < len {
+            return Ok(None);
+        }
+        let mut reader = Reader {
+            bytes: &buffer[1..len],
+        };
+        let locate = u16::from_le_bytes(reader.take());
+        let sequence = reader.u64();
+        let body = match type_code {
+            b'R' => {
+                let name: [u8; PAIR_WIDTH] = reader.take();
+                let name =
+                    std::str::from_utf8(&name).map_err(|_| "Pair name is not ASCII".to_string())?;
+                ItchBody::MarketDirectory {
+                    pair: name
+                        .trim_end()
+                        .parse::<TradingPair>()
+                        .map_err(|err| err.to_string())?,
+                }
+            }
+            b'A' | b'U' => {
+                let bid_or_ask = reader.side()?;
+                let price = from_fixed(reader.i64());
+                let size = from_fixed(reader.i64());
+                match type_code {
+                    b'A' => ItchBody::AddLevel {
+                        bid_or_ask,
+                        price,
+                        size,
+                    },
+                    _ => ItchBody::ReduceLevel {
+                        bid_or_ask,
+                        price,
+                        size,
+                    },
+                }
+            }
+            b'D' => ItchBody::RemoveLevel {
+                bid_or_ask: reader.side()?,
+                price: from_fixed(reader.i64()),
+            },
+            _ => {
+                let maker_order_id = OrderId(reader.u64());
+                let taker_order_id = OrderId(reader.u64());
+                let taker_side = reader.side()?;
+                let price = from_fixed(reader.i64());
+                let size = from_fixed(reader.i64());
+                // Owners and fees are private and not on the wire.
+                ItchBody::Trade {
+                    trade: Trade {
+                        timestamp: reader.u64(),
+                        ..Trade::new(maker_order_id, taker_order_id, price, size, taker_side)
+                    },
+                }
+            }
+        };
+        let message = ItchMessage {
+            sequence,
+            locate,
+            body,
+        };
+        Ok(Some((message, len)))
+    }
+}
+
+// Reads fixed-width fields off a message already checked to be long enough.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        field.try_into().expect("split at N")
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take())
+    }
+
+    fn i64(&mut self) -> i64 {
+        i64::from_le_bytes(self.take())
+    }
+
+    fn side(&mut self) -> Result<BidOrAsk, String> {
+        match self.take::<1>()[0] {
+            b'B' => Ok(BidOrAsk::Bid),
+            b'S' => Ok(BidOrAsk::Ask),
+            other => Err(format!("Unknown side {:#04x}", other)),
+        }
+    }
+}
+
+/// Turns engine events into feed messages, numbering them and assigning
+/// each market a locate the first time it appears.
+#[derive(Debug, Default)]
+pub struct ItchEncoder {
+    sequence: u64,
+    locates: HashMap<TradingPair, u16>,
+}
+
+impl ItchEncoder {
+    pub fn new() -> ItchEncoder {
+        ItchEncoder::default()
+    }
+
+    /// Sequence of the last message written.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Appends the messages for `event` to `out`, returning how many were
+    /// written. Events other than book updates and trades produce none.
+    pub fn encode_event(
+        &mut self,
+        event: &EngineEvent,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, String> {
+        let (pair, body) = match event {
+            EngineEvent::BookUpdated { pair, update } => (pair, ItchEncoder::book_body(update)),
+            EngineEvent::TradeExecuted { pair, trade } => (
+                pair,
+                ItchBody::Trade {
+                    trade: trade.clone(),
+                },
+            ),
+            _ => return Ok(0),
+        };
+
+        let mut written = 0;
+        let locate = match self.locates.get(pair) {
+            Some(&locate) => locate,
+            None => {
+                let locate = u16::try_from(self.locates.len() + 1)
+                    .map_err(|_| "Too many markets for the binary feed".to_string())?;
+                let directory = ItchBody::MarketDirectory { pair: pair.clone() };
+                self.write(locate, directory, out)?;
+                self.locates.insert(pair.clone(), locate);
+                written += 1;
+                locate
+            }
+        };
+        self.write(locate, body, out)?;
+        Ok(written + 1)
+    }
+
+    fn write(&mut self, locate: u16, body: ItchBody, out: &mut Vec<u8>) -> Result<(), String> {
+        let message = ItchMessage {
+            sequence: self.sequence + 1,
+            locate,
+            body,
+        };
+        message.encode(out)?;
+        self.sequence += 1;
+        Ok(())
+    }
+
+    fn book_body(update: &BookUpdate) -> ItchBody {
+        match *update {
+            BookUpdate::Add {
+                bid_or_ask,
+                price,
+                size,
+                ..
+            } => ItchBody::AddLevel {
+                bid_or_ask,
+                price,
+                size,
+            },
+            BookUpdate::Reduce {
+                bid_or_ask,
+                price,
+                size,
+                ..
+            } => ItchBody::ReduceLevel {
+                bid_or_ask,
+                price,
+                size,
+            },
+            BookUpdate::Remove {
+                bid_or_ask, price, ..
+            } => ItchBody::RemoveLevel { bid_or_ask, price },
+        }
+    }
+}
+
+/// Reads a feed written by `ItchEncoder`, checking that no message was
+/// skipped and resolving locates back to pairs.
+#[derive(Debug, Default)]
+pub struct ItchDecoder {
+    sequence: u64,
+    pairs: HashMap<u16, TradingPair>,
+}
+
+impl ItchDecoder {
+    pub fn new() -> ItchDecoder {
+        ItchDecoder::default()
+    }
+
+    pub fn pair(&self, locate: u16) -> Option<&TradingPair> {
+        self.pairs.get(&locate)
+    }
+
+    /// Decodes the next message from `buffer`, as `ItchMessage::decode`.
+    /// A message out of sequence is an error.
+    pub fn decode(&mut self, buffer: &[u8]) -> Result<Option<(ItchMessage, usize)>, String> {
+        let Some((message, len)) = ItchMessage::decode(buffer)? else {
+            return Ok(None);
+        };
+        if message.sequence != self.sequence + 1 {
+            return Err(format!(
+                "Feed sequence gap: expected {}, got {}",
+                self.sequence + 1,
+                message.sequence
+            ));
+        }
+        self.sequence = message.sequence;
+        if let ItchBody::MarketDirectory { pair } = &message.body {
+            self.pairs.insert(message.locate, pair.clone());
+        }
+        Ok(Some((message, len)))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[test]
+    fn test_itch_round_trip() {
+        let events = vec![
+            EngineEvent::BookUpdated {
+                pair: btc_usd(),
+                update: BookUpdate::Add {
+                    sequence: 1,
+                    bid_or_ask: BidOrAsk::Ask,
+                    price: dec!(100.25),
+                    size: dec!(3),
+                },
+            },
+            EngineEvent::TradeExecuted {
+                pair: btc_usd(),
+                trade: Trade {
+                    timestamp: 1_700_000_000_000,
+                    ..Trade::new(
+                        OrderId(1),
+                        OrderId(2),
+                        dec!(100.25),
+                        dec!(0.00000001),
+                        BidOrAsk::Ask,
+                    )
+                },
+            },
+            EngineEvent::BookUpdated {
+                pair: btc_usd(),
+                update: BookUpdate::Remove {
+                    sequence: 2,
+                    bid_or_ask: BidOrAsk::Ask,
+                    price: dec!(100.25),
+                },
+            },
+        ];
+        let mut encoder = ItchEncoder::new();
+        let mut bytes = Vec::new();
+        for event in &events {
+            encoder.encode_event(event, &mut bytes).unwrap();
+        }
+        // The directory message for the new market comes first.
+        assert_eq!(encoder.sequence(), 4);
+        assert_eq!(bytes.len(), 27 + 28 + 52 + 20);
+
+        let mut decoder = ItchDecoder::new();
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while let Some((message, len)) = decoder.decode(&bytes[offset..]).unwrap() {
+            messages.push(message);
+            offset += len;
+        }
+        assert_eq!(offset, bytes.len());
+        assert_eq!(decoder.pair(1), Some(&btc_usd()));
+        assert_eq!(
+            messages[1].body,
+            ItchBody::AddLevel {
+                bid_or_ask: BidOrAsk::Ask,
+                price: dec!(100.25),
+                size: dec!(3),
+            }
+        );
+        match &messages[2].body {
+            ItchBody::Trade { trade } => assert_eq!(trade, events_trade(&events[1])),
+            other => panic!("Expected a trade, got {:?}", other),
+        }
+        assert_eq!(
+            messages[3].body,
+            ItchBody::RemoveLevel {
+                bid_or_ask: BidOrAsk::Ask,
+                price: dec!(100.25),
+            }
+        );
+
+        // A partial message waits for more data; a skipped one is an error.
+        assert_eq!(ItchMessage::decode(&bytes[..10]).unwrap(), None);
+        let mut decoder = ItchDecoder::new();
+        assert!(decoder.decode(&bytes[27..]).is_err());
+    }
+
+    fn events_trade(event: &EngineEvent) -> &Trade {
+        match event {
+            EngineEvent::TradeExecuted { trade, .. } => trade,
+            other => panic!("Expected a trade event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_itch_rejects_unrepresentable_values() {
+        let message = ItchMessage {
+            sequence: 1,
+            locate: 1,
+            body: ItchBody::RemoveLevel {
+                bid_or_ask: BidOrAsk::Bid,
+                price: dec!(0.000000001),
+            },
+        };
+        let mut bytes = Vec::new();
+        assert!(message.encode(&mut bytes).is_err());
+        assert!(bytes.is_empty());
+    }
+}
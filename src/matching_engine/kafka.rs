@@ -0,0 +1,414 @@
+#![allow(dead_code)]
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rskafka::chrono::{TimeZone, Utc};
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::{Client, ClientBuilder};
+use rskafka::record::Record;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::clock::{system_clock, Clock};
+use super::engine::{MatchingEngine, TradingPair};
+use super::events::EngineEvent;
+
+/// Header carrying the envelope's sequence number, so consumers can dedup
+/// without parsing the payload.
+pub const SEQUENCE_HEADER: &str = "sequence";
+
+/// How events are spread over the partitions of their topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partitioning {
+    /// Everything goes to partition 0.
+    Single,
+    /// Every event of a market goes to the same one of `partitions`, so
+    /// each market's events stay in order.
+    ByPair { partitions: i32 },
+}
+
+impl Partitioning {
+    /// Partition for events about `pair`. Uses FNV-1a over the pair's name,
+    /// so the mapping is the same in every process and build.
+    pub fn partition(&self, pair: Option<&TradingPair>) -> i32 {
+        match (self, pair) {
+            (Partitioning::ByPair { partitions }, Some(pair)) if *partitions > 1 => {
+                let hash = pair
+                    .to_string()
+                    .bytes()
+                    .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+                        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+                    });
+                (hash % *partitions as u64) as i32
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KafkaConfig {
+    pub brokers: Vec<String>,
+    pub trades_topic: String,
+    pub book_topic: String,
+    /// Order acknowledgements, cancellations and every other event.
+    pub orders_topic: String,
+    pub partitioning: Partitioning,
+    /// Wait between attempts to deliver a batch that failed.
+    pub retry_backoff: Duration,
+}
+
+impl KafkaConfig {
+    pub fn new(brokers: Vec<String>) -> KafkaConfig {
+        KafkaConfig {
+            brokers,
+            trades_topic: "engine.trades".to_string(),
+            book_topic: "engine.book".to_string(),
+            orders_topic: "engine.orders".to_string(),
+            partitioning: Partitioning::Single,
+            retry_backoff: Duration::from_millis(500),
+        }
+    }
+
+    pub fn with_partitioning(mut self, partitioning: Partitioning) -> KafkaConfig {
+        self.partitioning = partitioning;
+        self
+    }
+
+    fn topic(&self, event: &EngineEvent) -> &str {
+        match event {
+            EngineEvent::TradeExecuted { .. } => &self.trades_topic,
+            EngineEvent::BookUpdated { .. } => &self.book_topic,
+            _ => &self.orders_topic,
+        }
+    }
+}
+
+/// The payload of every message. `sequence` increases by one per event
+/// across all topics; delivery is at least once, so a consumer should drop
+/// any sequence it has already seen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub sequence: u64,
+    pub event: EngineEvent,
+}
+
+/// One message ready to be produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KafkaMessage {
+    pub sequence: u64,
+    /// The pair's name, if the event has one.
+    pub key: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum KafkaError {
+    #[error("Failed to connect to Kafka: {0}")]
+    Connect(String),
+    #[error("Failed to produce to {topic}/{partition}: {message}")]
+    Produce {
+        topic: String,
+        partition: i32,
+        message: String,
+    },
+}
+
+/// Somewhere batches of messages can be produced to. Implemented for the
+/// real cluster by `RsKafkaProducer`.
+pub trait Producer: Send {
+    /// Produces `messages` in order; on error none of them may be assumed
+    /// delivered.
+    fn produce(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        messages: Vec<KafkaMessage>,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// `Producer` backed by an `rskafka` client. Partition clients are created
+/// on first use and kept. Records are stamped with the time they are
+/// produced, from the system clock unless given another.
+pub struct RsKafkaProducer {
+    client: Client,
+    partitions: HashMap<(String, i32), Arc<PartitionClient>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RsKafkaProducer {
+    pub async fn connect(brokers: Vec<String>) -> Result<RsKafkaProducer, KafkaError> {
+        let client = ClientBuilder::new(brokers)
+            .build()
+            .await
+            .map_err(|err| KafkaError::Connect(err.to_string()))?;
+        Ok(RsKafkaProducer {
+            client,
+            partitions: HashMap::new(),
+            clock: system_clock(),
+        })
+    }
+
+    /// Replaces the system clock records are stamped with, e.g. with the
+    /// engine's own when replaying or backtesting.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+}
+
+impl Producer for RsKafkaProducer {
+    async fn produce(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        messages: Vec<KafkaMessage>,
+    ) -> Result<(), String> {
+        let key = (topic.to_string(), partition);
+        let client = match self.partitions.get(&key) {
+            Some(client) => Arc::clone(client),
+            None => {
+                let client = Arc::new(
+                    self.client
+                        .partition_client(topic, partition, UnknownTopicHandling::Retry)
+                        .await
+                        .map_err(|err| err.to_string())?,
+                );
+                self.partitions.insert(key, Arc::clone(&client));
+                client
+            }
+        };
+        let timestamp = Utc
+            .timestamp_millis_opt(self.clock.now() as i64)
+            .single()
+            .unwrap_or_default();
+        let records = messages
+            .into_iter()
+            .map(|message| Record {
+                key: message.key,
+                value: Some(message.payload),
+                headers: BTreeMap::from([(
+                    SEQUENCE_HEADER.to_string(),
+                    message.sequence.to_string().into_bytes(),
+                )]),
+                timestamp,
+            })
+            .collect();
+        client
+            .produce(records, Compression::NoCompression)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    topic: String,
+    partition: i32,
+    message: KafkaMessage,
+}
+
+/// Publishes engine events to Kafka. Events are queued by `publish` and
+/// only dropped from the queue once `flush` has delivered them, so nothing
+/// is lost while the cluster is unreachable.
+pub struct KafkaPublisher<P> {
+    config: KafkaConfig,
+    producer: P,
+    sequence: u64,
+    // In sequence order.
+    pending: VecDeque<Pending>,
+}
+
+impl<P: Producer> KafkaPublisher<P> {
+    pub fn new(config: KafkaConfig, producer: P) -> KafkaPublisher<P> {
+        KafkaPublisher {
+            config,
+            producer,
+            sequence: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Continues numbering after `sequence`, e.g. the last one a restarted
+    /// publisher delivered.
+    pub fn with_sequence(mut self, sequence: u64) -> KafkaPublisher<P> {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Sequence number of the last event queued.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Queues `event` under the next sequence number.
+    pub fn publish(&mut self, event: EngineEvent) {
+        self.sequence += 1;
+        let pair = event.pair().cloned();
+        let topic = self.config.topic(&event).to_string();
+        let envelope = EventEnvelope {
+            sequence: self.sequence,
+            event,
+        };
+        self.pending.push_back(Pending {
+            topic,
+            partition: self.config.partitioning.partition(pair.as_ref()),
+            message: KafkaMessage {
+                sequence: envelope.sequence,
+                key: pair.map(|pair| pair.to_string().into_bytes()),
+                payload: serde_json::to_vec(&envelope).expect("events are always serializable"),
+            },
+        });
+    }
+
+    /// Produces everything queued, one batch per partition. Batches that
+    /// fail stay queued for the next flush; the first failure is returned.
+    pub async fn flush(&mut self) -> Result<(), KafkaError> {
+        let mut batches: BTreeMap<(String, i32), Vec<KafkaMessage>> = BTreeMap::new();
+        for pending in self.pending.drain(..) {
+            batches
+                .entry((pending.topic, pending.partition))
+                .or_default()
+                .push(pending.message);
+        }
+
+        let mut failed = Vec::new();
+        let mut error = None;
+        for ((topic, partition), messages) in batches {
+            if let Err(message) = self
+                .producer
+                .produce(&topic, partition, messages.clone())
+                .await
+            {
+                error.get_or_insert(KafkaError::Produce {
+                    topic: topic.clone(),
+                    partition,
+                    message,
+                });
+                failed.extend(messages.into_iter().map(|message| Pending {
+                    topic: topic.clone(),
+                    partition,
+                    message,
+                }));
+            }
+        }
+        failed.sort_by_key(|pending| pending.message.sequence);
+        self.pending.extend(failed);
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Registers a listener on `engine` and spawns a task publishing every
+    /// event from now on, retrying failed batches every `retry_backoff`.
+    /// The task ends once the engine has been dropped and the queue
+    /// delivered.
+    pub fn attach(mut self, engine: &mut MatchingEngine) -> JoinHandle<()>
+    where
+        P: 'static,
+    {
+        let (sender, mut events) = mpsc::unbounded_channel();
+        engine.add_listener(move |event: &EngineEvent| {
+            let _ = sender.send(event.clone());
+        });
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                self.publish(event);
+                while let Ok(event) = events.try_recv() {
+                    self.publish(event);
+                }
+                while self.flush().await.is_err() {
+                    tokio::time::sleep(self.config.retry_backoff).await;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use rust_decimal_macros::dec;
+
+    // Fails the first batch sent to each partition, then records.
+    #[derive(Default)]
+    struct FlakyProducer {
+        failed: Vec<(String, i32)>,
+        delivered: Vec<(String, i32, KafkaMessage)>,
+    }
+
+    impl Producer for FlakyProducer {
+        async fn produce(
+            &mut self,
+            topic: &str,
+            partition: i32,
+            messages: Vec<KafkaMessage>,
+        ) -> Result<(), String> {
+            let key = (topic.to_string(), partition);
+            if !self.failed.contains(&key) {
+                self.failed.push(key);
+                return Err("broker unavailable".to_string());
+            }
+            for message in messages {
+                self.delivered.push((topic.to_string(), partition, message));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kafka_publisher_retries_until_delivered() {
+        let btc = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let eth = TradingPair::new("ETH".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        let events = engine.subscribe();
+        for pair in [&btc, &eth] {
+            engine.add_new_market(pair.clone(), MarketConfig::default());
+            engine
+                .place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+                .unwrap();
+        }
+        engine
+            .place_market_order(btc.clone(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+
+        let partitioning = Partitioning::ByPair { partitions: 8 };
+        let config = KafkaConfig::new(vec![]).with_partitioning(partitioning);
+        let mut publisher = KafkaPublisher::new(config, FlakyProducer::default());
+        let events: Vec<EngineEvent> = events.try_iter().collect();
+        for event in events.clone() {
+            publisher.publish(event);
+        }
+        assert!(publisher.flush().await.is_err());
+        assert_eq!(publisher.pending(), events.len());
+        publisher.flush().await.unwrap();
+        assert_eq!(publisher.pending(), 0);
+
+        let delivered = &publisher.producer.delivered;
+        let mut sequences: Vec<u64> = delivered.iter().map(|(_, _, m)| m.sequence).collect();
+        sequences.sort();
+        assert_eq!(sequences, (1..=events.len() as u64).collect::<Vec<_>>());
+        for (topic, partition, message) in delivered {
+            let envelope: EventEnvelope = serde_json::from_slice(&message.payload).unwrap();
+            assert_eq!(envelope.sequence, message.sequence);
+            assert_eq!(envelope.event, events[message.sequence as usize - 1]);
+            assert_eq!(topic, publisher.config.topic(&envelope.event));
+            assert_eq!(*partition, partitioning.partition(envelope.event.pair()));
+        }
+        assert!(delivered
+            .iter()
+            .any(|(topic, _, _)| topic == "engine.trades"));
+        assert_ne!(
+            partitioning.partition(Some(&btc)),
+            partitioning.partition(Some(&eth))
+        );
+    }
+}
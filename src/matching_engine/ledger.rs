@@ -0,0 +1,193 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::engine::TradingPair;
+use super::orderbook::{AccountId, BidOrAsk, OrderId};
+use super::trade::Trade;
+
+/// Account collecting trading fees and paying rebates.
+pub const FEE_ACCOUNT: AccountId = AccountId(u64::MAX);
+
+/// One leg of a ledger entry: `amount` of `asset` credited to `account`,
+/// or debited if negative.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Posting {
+    pub account: AccountId,
+    pub asset: String,
+    pub amount: Decimal,
+}
+
+/// The asset movements of one trade. Postings of each asset sum to zero.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LedgerEntry {
+    pub id: u64,
+    pub pair: TradingPair,
+    pub maker_order_id: OrderId,
+    pub taker_order_id: OrderId,
+    pub timestamp: u64,
+    pub postings: Vec<Posting>,
+}
+
+impl LedgerEntry {
+    /// Net amount of `asset` this entry moved into `account`.
+    pub fn net(&self, account: AccountId, asset: &str) -> Decimal {
+        self.postings
+            .iter()
+            .filter(|posting| posting.account == account && posting.asset == asset)
+            .map(|posting| posting.amount)
+            .sum()
+    }
+}
+
+/// Double-entry record of every trade's settlement.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+    // Positions in `entries` of each account's entries.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    by_account: HashMap<AccountId, Vec<usize>>,
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger::default()
+    }
+
+    /// Posts `trade`: the seller delivers base to the buyer, the buyer pays
+    /// quote to the seller, and each side's fee goes to `FEE_ACCOUNT`.
+    pub fn record(&mut self, pair: &TradingPair, trade: &Trade) -> &LedgerEntry {
+        let (buyer, buyer_fee, seller, seller_fee) = match trade.taker_side {
+            BidOrAsk::Bid => (
+                trade.taker_owner,
+                trade.taker_fee,
+                trade.maker_owner,
+                trade.maker_fee,
+            ),
+            BidOrAsk::Ask => (
+                trade.maker_owner,
+                trade.maker_fee,
+                trade.taker_owner,
+                trade.taker_fee,
+            ),
+        };
+        let notional = trade.price * trade.size;
+        let mut postings = Vec::new();
+        let mut post = |account, asset: &str, amount: Decimal| {
+            if !amount.is_zero() {
+                postings.push(Posting {
+                    account,
+                    asset: asset.to_string(),
+                    amount,
+                });
+            }
+        };
+        post(seller, pair.base(), -trade.size);
+        post(buyer, pair.base(), trade.size - buyer_fee);
+        post(FEE_ACCOUNT, pair.base(), buyer_fee);
+        post(buyer, pair.quote(), -notional);
+        post(seller, pair.quote(), notional - seller_fee);
+        post(FEE_ACCOUNT, pair.quote(), seller_fee);
+
+        self.push(LedgerEntry {
+            id: self.entries.len() as u64 + 1,
+            pair: pair.clone(),
+            maker_order_id: trade.maker_order_id,
+            taker_order_id: trade.taker_order_id,
+            timestamp: trade.timestamp,
+            postings,
+        })
+    }
+
+    fn push(&mut self, entry: LedgerEntry) -> &LedgerEntry {
+        let position = self.entries.len();
+        for account in entry.postings.iter().map(|posting| posting.account) {
+            let positions = self.by_account.entry(account).or_default();
+            if positions.last() != Some(&position) {
+                positions.push(position);
+            }
+        }
+        self.entries.push(entry);
+        &self.entries[position]
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    // Drops every entry after the first `len`.
+    pub(super) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+        for positions in self.by_account.values_mut() {
+            positions.retain(|&position| position < len);
+        }
+    }
+
+    /// Entries with postings to `account`, oldest first.
+    pub fn history(&self, account: AccountId) -> Vec<&LedgerEntry> {
+        self.by_account
+            .get(&account)
+            .map(|positions| positions.iter().map(|&i| &self.entries[i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Net amount of `asset` the ledger moved into `account`.
+    pub fn net(&self, account: AccountId, asset: &str) -> Decimal {
+        self.history(account)
+            .iter()
+            .map(|entry| entry.net(account, asset))
+            .sum()
+    }
+
+    /// Rebuilds the per-account index, which is not serialized.
+    pub(super) fn reindex(&mut self) {
+        let entries = std::mem::take(&mut self.entries);
+        self.by_account.clear();
+        for entry in entries {
+            self.push(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_ledger_records_balanced_postings() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let alice = AccountId(1);
+        let bob = AccountId(2);
+        let mut trade = Trade::new(OrderId(1), OrderId(2), dec!(100), dec!(2), BidOrAsk::Ask)
+            .with_owners(alice, bob);
+        trade.maker_fee = dec!(0.002);
+        trade.taker_fee = dec!(1);
+
+        let mut ledger = Ledger::new();
+        let entry = ledger.record(&pair, &trade).clone();
+        for asset in ["BTC", "USD"] {
+            let total: Decimal = entry
+                .postings
+                .iter()
+                .filter(|posting| posting.asset == asset)
+                .map(|posting| posting.amount)
+                .sum();
+            assert_eq!(total, dec!(0));
+        }
+
+        // Bob sold 2 BTC into Alice's bid.
+        assert_eq!(ledger.net(alice, "BTC"), dec!(1.998));
+        assert_eq!(ledger.net(alice, "USD"), dec!(-200));
+        assert_eq!(ledger.net(bob, "USD"), dec!(199));
+        assert_eq!(ledger.net(FEE_ACCOUNT, "USD"), dec!(1));
+        assert_eq!(ledger.history(bob).len(), 1);
+        assert!(ledger.history(AccountId(3)).is_empty());
+    }
+}
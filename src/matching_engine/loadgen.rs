@@ -0,0 +1,262 @@
+#![allow(dead_code)]
+use std::time::{Duration, Instant};
+
+use rust_decimal::prelude::*;
+
+use super::command::{CommandOutput, EngineCommand, SequencedCommand};
+use super::engine::{MatchingEngine, TradingPair};
+use super::orderbook::{AccountId, BidOrAsk, Order, OrderId, OrderStatus};
+use super::triggers::OrderType;
+
+/// Shape of the random order flow. Limit prices are normally distributed
+/// around `mid_price`, in whole ticks, so some orders cross and trade.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadConfig {
+    /// The same seed always produces the same flow against the same engine.
+    pub seed: u64,
+    /// Mean arrivals per second; gaps between them are exponential.
+    pub arrival_rate: f64,
+    /// Simulated time of the first arrival, in milliseconds since the Unix
+    /// epoch.
+    pub start_time: u64,
+    pub mid_price: Decimal,
+    pub tick_size: Decimal,
+    /// Standard deviation of limit prices from the mid, in ticks.
+    pub price_std_ticks: f64,
+    /// Order sizes are a uniform number of lots, from 1 to `max_lots`.
+    pub lot_size: Decimal,
+    pub max_lots: u32,
+    /// Share of arrivals that cancel a random order placed earlier.
+    pub cancel_ratio: f64,
+    /// Share of the remaining arrivals that are market orders.
+    pub market_ratio: f64,
+    /// Orders are owned by accounts 1 to `accounts`.
+    pub accounts: u64,
+}
+
+impl Default for LoadConfig {
+    fn default() -> LoadConfig {
+        LoadConfig {
+            seed: 1,
+            arrival_rate: 1000.0,
+            start_time: 0,
+            mid_price: Decimal::ONE_HUNDRED,
+            tick_size: Decimal::new(1, 2),
+            price_std_ticks: 20.0,
+            lot_size: Decimal::ONE,
+            max_lots: 10,
+            cancel_ratio: 0.3,
+            market_ratio: 0.05,
+            accounts: 10,
+        }
+    }
+}
+
+/// Counts of what a run generated and how the engine took it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadReport {
+    pub commands: usize,
+    pub orders: usize,
+    pub cancels: usize,
+    /// Commands the engine refused, e.g. cancels of orders that had
+    /// already filled.
+    pub rejected: usize,
+    pub trades: usize,
+    /// Wall-clock time the engine spent processing.
+    pub elapsed: Duration,
+}
+
+impl LoadReport {
+    /// Commands processed per second of engine time.
+    pub fn throughput(&self) -> f64 {
+        self.commands as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// SplitMix64: small, fast and reproducible across platforms.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [1, n].
+    fn between_one_and(&mut self, n: u64) -> u64 {
+        1 + self.next_u64() % n.max(1)
+    }
+
+    // Standard normal, by Box-Muller.
+    fn next_normal(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+
+    fn next_exponential(&mut self, rate: f64) -> f64 {
+        -(1.0 - self.next_f64()).ln() / rate
+    }
+}
+
+/// Generates random order flow for one market and feeds it to an engine.
+/// Cancels pick among the orders it saw rest, so some of them miss orders
+/// that have since filled. The inputs it sends can be kept as a corpus and
+/// replayed.
+#[derive(Debug, Clone)]
+pub struct LoadGenerator {
+    pair: TradingPair,
+    config: LoadConfig,
+    rng: Rng,
+    // Simulated time of the last arrival, in fractional milliseconds.
+    time: f64,
+    resting: Vec<OrderId>,
+}
+
+impl LoadGenerator {
+    pub fn new(pair: TradingPair, config: LoadConfig) -> LoadGenerator {
+        LoadGenerator {
+            pair,
+            config,
+            rng: Rng(config.seed),
+            time: config.start_time as f64,
+            resting: Vec::new(),
+        }
+    }
+
+    fn next_timestamp(&mut self) -> u64 {
+        self.time += self.rng.next_exponential(self.config.arrival_rate) * 1000.0;
+        self.time as u64
+    }
+
+    fn next_command(&mut self) -> EngineCommand {
+        let config = self.config;
+        if !self.resting.is_empty() && self.rng.next_f64() < config.cancel_ratio {
+            let index = self.rng.next_u64() as usize % self.resting.len();
+            return EngineCommand::CancelOrder {
+                pair: self.pair.clone(),
+                order_id: self.resting.swap_remove(index),
+            };
+        }
+
+        let bid_or_ask = if self.rng.next_f64() < 0.5 {
+            BidOrAsk::Bid
+        } else {
+            BidOrAsk::Ask
+        };
+        let lots = self.rng.between_one_and(u64::from(config.max_lots));
+        let owner = AccountId(self.rng.between_one_and(config.accounts));
+        let order = Order::new(bid_or_ask, config.lot_size * Decimal::from(lots)).with_owner(owner);
+        let order_type = if self.rng.next_f64() < config.market_ratio {
+            OrderType::Market
+        } else {
+            let ticks = (self.rng.next_normal() * config.price_std_ticks).round() as i64;
+            let price = config.mid_price + config.tick_size * Decimal::from(ticks);
+            OrderType::Limit {
+                price: price.max(config.tick_size),
+            }
+        };
+        EngineCommand::PlaceOrder {
+            pair: self.pair.clone(),
+            order_type,
+            order,
+        }
+    }
+
+    /// Sends `count` inputs to `engine`, stamped with the simulated arrival
+    /// times, and appends them to `corpus` if one is given.
+    pub fn run(
+        &mut self,
+        engine: &mut MatchingEngine,
+        count: usize,
+        mut corpus: Option<&mut Vec<SequencedCommand>>,
+    ) -> LoadReport {
+        let mut report = LoadReport::default();
+        for _ in 0..count {
+            let input = SequencedCommand {
+                sequence: engine.sequence() + 1,
+                timestamp: self.next_timestamp(),
+                command: self.next_command(),
+            };
+            match input.command {
+                EngineCommand::CancelOrder { .. } => report.cancels += 1,
+                _ => report.orders += 1,
+            }
+            if let Some(corpus) = corpus.as_deref_mut() {
+                corpus.push(input.clone());
+            }
+
+            let started = Instant::now();
+            let output = engine.process(input);
+            report.elapsed += started.elapsed();
+            report.commands += 1;
+            match output {
+                Ok(CommandOutput::Report(execution)) => {
+                    report.trades += execution.trades.len();
+                    if matches!(
+                        execution.status,
+                        OrderStatus::New | OrderStatus::PartiallyFilled
+                    ) {
+                        self.resting.push(execution.order_id);
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => report.rejected += 1,
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+
+    #[test]
+    fn test_load_generator_is_reproducible() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let run = || {
+            let mut engine = MatchingEngine::new();
+            engine.add_new_market(pair.clone(), MarketConfig::default());
+            let mut corpus = Vec::new();
+            let mut generator = LoadGenerator::new(pair.clone(), LoadConfig::default());
+            let report = generator.run(&mut engine, 2000, Some(&mut corpus));
+            (engine, corpus, report)
+        };
+        let (engine, corpus, report) = run();
+        let (_, _, again) = run();
+
+        assert_eq!(report.commands, 2000);
+        assert_eq!(report.orders + report.cancels, report.commands);
+        assert!(report.trades > 0 && report.cancels > 0);
+        assert_eq!(
+            (again.orders, again.cancels, again.rejected, again.trades),
+            (
+                report.orders,
+                report.cancels,
+                report.rejected,
+                report.trades
+            )
+        );
+        assert!(corpus.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+        // The corpus rebuilds the same book from scratch.
+        let mut replayed = MatchingEngine::new();
+        replayed.add_new_market(pair.clone(), MarketConfig::default());
+        replayed.replay(corpus).unwrap();
+        assert_eq!(
+            replayed.depth(pair.clone(), 20).unwrap(),
+            engine.depth(pair, 20).unwrap()
+        );
+    }
+}
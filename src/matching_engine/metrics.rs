@@ -0,0 +1,393 @@
+#![allow(dead_code)]
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+
+use rust_decimal::prelude::*;
+
+use super::engine::TradingPair;
+use super::events::EngineEvent;
+use super::orderbook::{DepthLevel, DepthSnapshot};
+
+/// Times a command. `Instant` panics on wasm32-unknown-unknown, so there
+/// the browser's millisecond clock is used instead.
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    started: Instant,
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    started: f64,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            started: Instant::now(),
+            #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+            started: js_sys::Date::now(),
+        }
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64((js_sys::Date::now() - self.started).max(0.0) / 1000.0)
+    }
+}
+
+/// Upper bounds of the command latency buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.000_001,
+    0.000_002_5,
+    0.000_005,
+    0.000_01,
+    0.000_025,
+    0.000_05,
+    0.000_1,
+    0.000_25,
+    0.000_5,
+    0.001,
+    0.01,
+    0.1,
+];
+
+/// Latency distribution over `LATENCY_BUCKETS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    // Observations per bucket, not cumulative; the last one is +Inf.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: Duration,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: [0; LATENCY_BUCKETS.len() + 1],
+            sum: Duration::ZERO,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += elapsed;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, or `None` if
+    /// nothing was observed or it fell past the last bound.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank && self.count > 0 {
+                return LATENCY_BUCKETS.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+/// Running totals for one market.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarketCounters {
+    pub orders_accepted: u64,
+    pub orders_rejected: u64,
+    pub orders_cancelled: u64,
+    pub trades: u64,
+    pub volume: Decimal,
+}
+
+impl MarketCounters {
+    /// Cancellations per accepted order.
+    pub fn cancel_ratio(&self) -> Option<f64> {
+        if self.orders_accepted == 0 {
+            return None;
+        }
+        Some(self.orders_cancelled as f64 / self.orders_accepted as f64)
+    }
+}
+
+/// Counters fed from the engine's events and latencies of the commands it
+/// executed. Not part of snapshots; a restored engine starts from zero, as
+/// Prometheus expects of a restarted process.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineMetrics {
+    markets: BTreeMap<TradingPair, MarketCounters>,
+    commands: BTreeMap<&'static str, Histogram>,
+}
+
+impl EngineMetrics {
+    pub fn new() -> EngineMetrics {
+        EngineMetrics::default()
+    }
+
+    pub fn record_event(&mut self, event: &EngineEvent) {
+        match event {
+            EngineEvent::OrderAccepted { pair, .. } => self.market(pair).orders_accepted += 1,
+            EngineEvent::OrderRejected { pair, .. } => self.market(pair).orders_rejected += 1,
+            EngineEvent::OrderCancelled { pair, .. } => self.market(pair).orders_cancelled += 1,
+            EngineEvent::TradeExecuted { pair, trade } => {
+                let market = self.market(pair);
+                market.trades += 1;
+                market.volume += trade.size;
+            }
+            _ => {}
+        }
+    }
+
+    /// Records how long one `command` took; names are snake case, like
+    /// `place_order`.
+    pub fn record_command(&mut self, command: &'static str, elapsed: Duration) {
+        self.commands.entry(command).or_default().observe(elapsed);
+    }
+
+    fn market(&mut self, pair: &TradingPair) -> &mut MarketCounters {
+        self.markets.entry(pair.clone()).or_default()
+    }
+
+    pub fn counters(&self, pair: &TradingPair) -> Option<&MarketCounters> {
+        self.markets.get(pair)
+    }
+
+    pub fn latency(&self, command: &str) -> Option<&Histogram> {
+        self.commands.get(command)
+    }
+
+    /// Renders everything in the Prometheus text exposition format, with
+    /// book depth gauges for the markets in `books`.
+    pub fn render(&self, books: &[(TradingPair, DepthSnapshot)]) -> String {
+        let mut out = String::new();
+        let per_market = |value: fn(&MarketCounters) -> String| {
+            self.markets
+                .iter()
+                .map(move |(pair, market)| (format!("pair=\"{}\"", pair), value(market)))
+        };
+        family(
+            &mut out,
+            "engine_orders_accepted_total",
+            "Orders that passed validation and entered the market.",
+            "counter",
+            per_market(|market| market.orders_accepted.to_string()),
+        );
+        family(
+            &mut out,
+            "engine_orders_rejected_total",
+            "Orders refused by the engine.",
+            "counter",
+            per_market(|market| market.orders_rejected.to_string()),
+        );
+        family(
+            &mut out,
+            "engine_orders_cancelled_total",
+            "Working orders that left the market without filling completely.",
+            "counter",
+            per_market(|market| market.orders_cancelled.to_string()),
+        );
+        family(
+            &mut out,
+            "engine_trades_total",
+            "Trades executed.",
+            "counter",
+            per_market(|market| market.trades.to_string()),
+        );
+        family(
+            &mut out,
+            "engine_traded_volume_total",
+            "Base quantity traded.",
+            "counter",
+            per_market(|market| market.volume.to_string()),
+        );
+        family(
+            &mut out,
+            "engine_cancel_ratio",
+            "Cancellations per accepted order.",
+            "gauge",
+            per_market(|market| market.cancel_ratio().unwrap_or(0.0).to_string()),
+        );
+
+        let latencies = self.commands.iter().flat_map(|(command, histogram)| {
+            let mut cumulative = 0;
+            let buckets =
+                LATENCY_BUCKETS
+                    .iter()
+                    .zip(&histogram.buckets)
+                    .map(move |(bound, count)| {
+                        cumulative += count;
+                        (
+                            format!("_bucket{{command=\"{}\",le=\"{}\"}}", command, bound),
+                            cumulative.to_string(),
+                        )
+                    });
+            buckets.chain([
+                (
+                    format!("_bucket{{command=\"{}\",le=\"+Inf\"}}", command),
+                    histogram.count.to_string(),
+                ),
+                (
+                    format!("_sum{{command=\"{}\"}}", command),
+                    histogram.sum.as_secs_f64().to_string(),
+                ),
+                (
+                    format!("_count{{command=\"{}\"}}", command),
+                    histogram.count.to_string(),
+                ),
+            ])
+        });
+        let name = "engine_command_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "Time spent executing a command.",
+            "histogram",
+        );
+        for (suffix, value) in latencies {
+            let _ = writeln!(out, "{}{} {}", name, suffix, value);
+        }
+
+        let per_side = |value: fn(&[DepthLevel]) -> String| {
+            books.iter().flat_map(move |(pair, depth)| {
+                [("bid", &depth.bids), ("ask", &depth.asks)].map(|(side, levels)| {
+                    (
+                        format!("pair=\"{}\",side=\"{}\"", pair, side),
+                        value(levels),
+                    )
+                })
+            })
+        };
+        family(
+            &mut out,
+            "engine_book_levels",
+            "Price levels with displayed size.",
+            "gauge",
+            per_side(|levels| levels.len().to_string()),
+        );
+        family(
+            &mut out,
+            "engine_book_orders",
+            "Displayed orders resting in the book.",
+            "gauge",
+            per_side(|levels| {
+                let orders: usize = levels.iter().map(|level| level.order_count).sum();
+                orders.to_string()
+            }),
+        );
+        family(
+            &mut out,
+            "engine_book_size",
+            "Displayed base quantity resting in the book.",
+            "gauge",
+            per_side(|levels| {
+                let size: Decimal = levels.iter().map(|level| level.size).sum();
+                size.to_string()
+            }),
+        );
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Writes one metric family: its header, then a sample per set of labels.
+fn family(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    samples: impl Iterator<Item = (String, String)>,
+) {
+    header(out, name, help, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::command::EngineCommand;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order, OrderId};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_engine_metrics_count_orders_and_render() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+
+        let resting = engine
+            .place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        engine
+            .place_limit_order(pair.clone(), dec!(99), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        engine
+            .place_market_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        engine.cancel_order(pair.clone(), resting.order_id).unwrap();
+        assert!(engine
+            .place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Bid, dec!(0)))
+            .is_err());
+        assert!(engine.cancel_order(pair.clone(), OrderId(999)).is_err());
+        engine
+            .execute(EngineCommand::CancelAll { pair: pair.clone() })
+            .unwrap();
+        assert_eq!(engine.metrics().latency("cancel_all").unwrap().count(), 1);
+
+        let counters = engine.metrics().counters(&pair).unwrap();
+        assert_eq!(
+            (
+                counters.orders_accepted,
+                counters.orders_rejected,
+                counters.orders_cancelled,
+                counters.trades,
+                counters.volume
+            ),
+            (3, 1, 2, 1, dec!(1))
+        );
+        assert_eq!(counters.cancel_ratio(), Some(2.0 / 3.0));
+
+        let text = engine.prometheus_metrics();
+        assert!(text.contains("# TYPE engine_orders_accepted_total counter"));
+        assert!(text.contains("engine_orders_accepted_total{pair=\"BTC/USD\"} 3"));
+        assert!(text.contains("engine_trades_total{pair=\"BTC/USD\"} 1"));
+        assert!(text.contains("engine_book_orders{pair=\"BTC/USD\",side=\"bid\"} 0"));
+        assert!(text.contains("engine_book_levels{pair=\"BTC/USD\",side=\"ask\"} 0"));
+
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(3));
+        histogram.observe(Duration::from_secs(1));
+        assert_eq!(histogram.count(), 2);
+        assert_eq!(histogram.quantile(0.5), Some(0.000_005));
+        assert_eq!(histogram.quantile(1.0), None);
+    }
+}
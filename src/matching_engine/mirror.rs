@@ -0,0 +1,290 @@
+#![allow(dead_code)]
+use std::collections::BTreeMap;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::orderbook::{BidOrAsk, BookUpdate, DepthLevel, DepthSnapshot};
+
+/// The mirror could not take the update it was given: it is behind or ahead
+/// of the feed and must be resynchronized from a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Book sequence gap: expected {expected}, got {got}")]
+pub struct SequenceGap {
+    pub expected: u64,
+    pub got: u64,
+}
+
+/// Every level change between two depth snapshots of the same book, for
+/// bringing a mirror at `from` up to `to` without sending the whole book.
+/// All its updates carry sequence `to`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BookDiff {
+    pub from: u64,
+    pub to: u64,
+    pub updates: Vec<BookUpdate>,
+}
+
+impl BookDiff {
+    /// The changes from `older` to `newer`. Both should be full depth, or
+    /// levels past the shallower one show up as removed.
+    pub fn between(older: &DepthSnapshot, newer: &DepthSnapshot) -> BookDiff {
+        let mut updates = Vec::new();
+        for (bid_or_ask, before, after) in [
+            (BidOrAsk::Bid, &older.bids, &newer.bids),
+            (BidOrAsk::Ask, &older.asks, &newer.asks),
+        ] {
+            let before: BTreeMap<Decimal, Decimal> = before
+                .iter()
+                .map(|level| (level.price, level.size))
+                .collect();
+            let after: BTreeMap<Decimal, Decimal> = after
+                .iter()
+                .map(|level| (level.price, level.size))
+                .collect();
+            let sequence = newer.sequence;
+            updates.extend(
+                before
+                    .keys()
+                    .filter(|price| !after.contains_key(price))
+                    .map(|&price| BookUpdate::Remove {
+                        sequence,
+                        bid_or_ask,
+                        price,
+                    }),
+            );
+            updates.extend(
+                after
+                    .iter()
+                    .filter_map(|(&price, &size)| match before.get(&price) {
+                        Some(&old) if old == size => None,
+                        Some(&old) if size < old => Some(BookUpdate::Reduce {
+                            sequence,
+                            bid_or_ask,
+                            price,
+                            size,
+                        }),
+                        _ => Some(BookUpdate::Add {
+                            sequence,
+                            bid_or_ask,
+                            price,
+                            size,
+                        }),
+                    }),
+            );
+        }
+        BookDiff {
+            from: older.sequence,
+            to: newer.sequence,
+            updates,
+        }
+    }
+}
+
+/// A remote copy of a book's levels, built from a `DepthSnapshot` and kept
+/// current with the `BookUpdate`s that follow it. An update out of sequence
+/// leaves the mirror stale: everything is refused until `reset` is given a
+/// fresh snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct BookMirror {
+    sequence: u64,
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    stale: bool,
+}
+
+impl BookMirror {
+    pub fn new(snapshot: &DepthSnapshot) -> BookMirror {
+        let mut mirror = BookMirror::default();
+        mirror.reset(snapshot);
+        mirror
+    }
+
+    /// Starts over from `snapshot`, which should be full depth.
+    pub fn reset(&mut self, snapshot: &DepthSnapshot) {
+        let levels = |levels: &[DepthLevel]| {
+            levels
+                .iter()
+                .map(|level| (level.price, level.size))
+                .collect()
+        };
+        self.sequence = snapshot.sequence;
+        self.bids = levels(&snapshot.bids);
+        self.asks = levels(&snapshot.asks);
+        self.stale = false;
+    }
+
+    /// Sequence of the last update reflected in the mirror.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Whether an update was missed and a new snapshot is needed.
+    pub fn needs_resync(&self) -> bool {
+        self.stale
+    }
+
+    /// Applies the next update. Updates the mirror already reflects are
+    /// ignored, so a feed subscribed before the snapshot was taken can be
+    /// applied from its start.
+    pub fn apply(&mut self, update: &BookUpdate) -> Result<(), SequenceGap> {
+        if !self.stale && update.sequence() <= self.sequence {
+            return Ok(());
+        }
+        let Some(from) = update.sequence().checked_sub(1) else {
+            self.stale = true;
+            return Err(SequenceGap {
+                expected: self.sequence + 1,
+                got: update.sequence(),
+            });
+        };
+        self.check(from)?;
+        self.set_level(update);
+        self.sequence = update.sequence();
+        Ok(())
+    }
+
+    /// Applies a diff taken from the sequence the mirror is at.
+    pub fn merge(&mut self, diff: &BookDiff) -> Result<(), SequenceGap> {
+        self.check(diff.from)?;
+        for update in &diff.updates {
+            self.set_level(update);
+        }
+        self.sequence = diff.to;
+        Ok(())
+    }
+
+    // Refuses to go on from anywhere but the mirror's own sequence.
+    fn check(&mut self, from: u64) -> Result<(), SequenceGap> {
+        if self.stale || from != self.sequence {
+            self.stale = true;
+            return Err(SequenceGap {
+                expected: self.sequence + 1,
+                got: from + 1,
+            });
+        }
+        Ok(())
+    }
+
+    fn set_level(&mut self, update: &BookUpdate) {
+        let (levels, price, size) = match *update {
+            BookUpdate::Add {
+                bid_or_ask,
+                price,
+                size,
+                ..
+            }
+            | BookUpdate::Reduce {
+                bid_or_ask,
+                price,
+                size,
+                ..
+            } => (self.levels_mut(bid_or_ask), price, Some(size)),
+            BookUpdate::Remove {
+                bid_or_ask, price, ..
+            } => (self.levels_mut(bid_or_ask), price, None),
+        };
+        match size {
+            Some(size) => levels.insert(price, size),
+            None => levels.remove(&price),
+        };
+    }
+
+    fn levels_mut(&mut self, bid_or_ask: BidOrAsk) -> &mut BTreeMap<Decimal, Decimal> {
+        match bid_or_ask {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    /// Bid (price, size) levels from the best price down.
+    pub fn bids(&self) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.bids.iter().rev().map(|(price, size)| (*price, *size))
+    }
+
+    /// Ask (price, size) levels from the best price up.
+    pub fn asks(&self) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.asks.iter().map(|(price, size)| (*price, *size))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::{Order, OrderBook};
+    use rust_decimal_macros::dec;
+
+    fn levels(levels: &[DepthLevel]) -> Vec<(Decimal, Decimal)> {
+        levels
+            .iter()
+            .map(|level| (level.price, level.size))
+            .collect()
+    }
+
+    #[test]
+    fn test_mirror_tracks_book_and_detects_gaps() {
+        let mut book = OrderBook::new();
+        book.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(2)));
+        book.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(1)));
+        book.drain_updates();
+        let base = book.depth(usize::MAX);
+        let mut mirror = BookMirror::new(&base);
+
+        book.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(1)));
+        book.fill_market_order(&mut Order::new(BidOrAsk::Ask, dec!(1.5)));
+        book.add_limit_order(dec!(102), Order::new(BidOrAsk::Ask, dec!(3)));
+        let updates = book.drain_updates();
+        for update in &updates {
+            mirror.apply(update).unwrap();
+        }
+        let depth = book.depth(usize::MAX);
+        assert_eq!(mirror.sequence(), depth.sequence);
+        assert_eq!(mirror.bids().collect::<Vec<_>>(), levels(&depth.bids));
+        assert_eq!(mirror.asks().collect::<Vec<_>>(), levels(&depth.asks));
+
+        // A missed update makes the mirror refuse the rest until reset.
+        let mut behind = BookMirror::new(&base);
+        assert_eq!(
+            behind.apply(&updates[1]),
+            Err(SequenceGap {
+                expected: base.sequence + 1,
+                got: base.sequence + 2,
+            })
+        );
+        assert!(behind.needs_resync());
+        assert!(behind.apply(&updates[0]).is_err());
+        let first = BookUpdate::Remove {
+            sequence: 0,
+            bid_or_ask: BidOrAsk::Bid,
+            price: dec!(99),
+        };
+        assert_eq!(
+            behind.apply(&first),
+            Err(SequenceGap {
+                expected: base.sequence + 1,
+                got: 0,
+            })
+        );
+
+        // A diff between the snapshots brings it up to date in one go.
+        let diff = BookDiff::between(&base, &depth);
+        assert!(behind.merge(&diff).is_err());
+        behind.reset(&base);
+        behind.merge(&diff).unwrap();
+        assert_eq!(behind.bids().collect::<Vec<_>>(), levels(&depth.bids));
+        assert_eq!(behind.asks().collect::<Vec<_>>(), levels(&depth.asks));
+        assert_eq!(behind.best_bid(), Some(dec!(99)));
+        assert_eq!(behind.sequence(), depth.sequence);
+    }
+}
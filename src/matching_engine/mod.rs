@@ -1,2 +1,59 @@
+pub mod accounts;
+pub mod analytics;
+pub mod backtest;
+pub mod bands;
+pub mod batch;
+pub mod candles;
+pub mod cli;
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod conflation;
 pub mod engine;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod fees;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fix")]
+pub mod fix;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod itch;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod ledger;
+pub mod loadgen;
+pub mod metrics;
+pub mod mirror;
 pub mod orderbook;
+pub mod paper;
+pub mod positions;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod queue;
+pub mod ratelimit;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+pub mod reference;
+#[cfg(feature = "serde")]
+pub mod replication;
+#[cfg(feature = "rest-api")]
+pub mod rest;
+#[cfg(feature = "async")]
+pub mod service;
+pub mod session;
+#[cfg(feature = "sql")]
+pub mod sql_store;
+pub mod stats;
+pub mod trade;
+pub mod triggers;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "serde")]
+pub mod wal;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "websocket")]
+pub mod websocket;
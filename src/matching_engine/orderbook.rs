@@ -1,174 +1,3058 @@
 #![allow(dead_code)]
 use rust_decimal::prelude::*;
-use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug, PartialEq)]
+use super::bands::PriceBand;
+use super::clock::{system_clock, Clock};
+use super::error::{BookViolation, OrderBookError};
+use super::fees::{FeeSchedule, Fees};
+use super::queue::OrderQueue;
+use super::trade::Trade;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BidOrAsk {
     Bid,
     Ask,
 }
 
-#[derive(Debug)]
+/// How long an order may remain working in the book.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TimeInForce {
+    /// Rest any unfilled remainder until cancelled.
+    #[default]
+    GoodTillCancel,
+    /// Fill what is immediately available and cancel the rest.
+    ImmediateOrCancel,
+    /// Fill the whole order immediately or reject it.
+    FillOrKill,
+    /// Rest until cancelled or until the expiry time, in milliseconds since
+    /// the Unix epoch. The book itself rests these like good-till-cancel
+    /// orders; the engine cancels them once they expire.
+    GoodTillDate(u64),
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder is left resting in the book.
+    pub fn rests(self) -> bool {
+        matches!(
+            self,
+            TimeInForce::GoodTillCancel | TimeInForce::GoodTillDate(_)
+        )
+    }
+
+    pub fn expiry(self) -> Option<u64> {
+        match self {
+            TimeInForce::GoodTillDate(expiry) => Some(expiry),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with a post-only limit order that would take liquidity.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PostOnly {
+    /// Reject the order without trading.
+    Reject,
+    /// Reprice the order one increment behind the best opposite price so it
+    /// rests as a maker.
+    Slide,
+}
+
+/// How to resolve an incoming order that would trade against a resting
+/// order from the same owner.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SelfTradePrevention {
+    /// Cancel the remainder of the incoming order.
+    CancelNewest,
+    /// Cancel the resting order and keep matching.
+    CancelOldest,
+    /// Cancel both the resting order and the incoming remainder.
+    CancelBoth,
+}
+
+/// How an incoming order's fill at a price level is shared among the
+/// resting orders there.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MatchingAlgorithm {
+    /// Price-time priority: the oldest resting order is filled first.
+    #[default]
+    Fifo,
+    /// Each resting order receives a share proportional to its displayed
+    /// size, rounded down to the lot size; what rounding leaves over goes
+    /// to the oldest orders first.
+    ProRata,
+}
+
+/// What happens to a market order the book cannot fill completely.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MarketOrderPolicy {
+    /// Fill what is available and cancel the rest.
+    #[default]
+    CancelRemainder,
+    /// Reject the order before it trades unless the opposite side holds
+    /// enough size within the price band and the order's price protection.
+    /// Self-trade prevention may still cancel the remainder.
+    Reject,
+    /// Trade only if the whole order fills, self-trade prevention included;
+    /// otherwise reject it without trading.
+    AllOrNone,
+}
+
+/// Worst price a market order may trade at; matching stops there and the
+/// remainder is cancelled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PriceProtection {
+    Price(Decimal),
+    /// Largest slippage from the mid price when the order arrives, as a
+    /// percentage between 0 and 100. With a one-sided book the best
+    /// opposite price is used instead of the mid.
+    Percent(Decimal),
+}
+
+impl PriceProtection {
+    pub fn is_valid(self) -> bool {
+        match self {
+            PriceProtection::Price(price) => price > Decimal::ZERO,
+            PriceProtection::Percent(percent) => {
+                percent > Decimal::ZERO && percent < Decimal::ONE_HUNDRED
+            }
+        }
+    }
+
+    /// Protection price of a `bid_or_ask` order arriving when the mid (or
+    /// best opposite) price is `reference`.
+    pub fn limit_price(self, bid_or_ask: BidOrAsk, reference: Decimal) -> Decimal {
+        let percent = match self {
+            PriceProtection::Price(price) => return price,
+            PriceProtection::Percent(percent) => percent,
+        };
+        let slippage = reference * percent / Decimal::ONE_HUNDRED;
+        match bid_or_ask {
+            BidOrAsk::Bid => reference + slippage,
+            BidOrAsk::Ask => reference - slippage,
+        }
+    }
+}
+
+/// Identifier assigned by the order book when an order is placed.
+#[derive(Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OrderId(pub u64);
+
+impl fmt::Display for OrderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Owner of an order. Orders placed without an explicit owner belong to the
+/// default account `AccountId(0)`.
+#[derive(Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AccountId(pub u64);
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OrderBook {
-    bids: HashMap<Decimal, Limit>,
-    asks: HashMap<Decimal, Limit>,
+    // Shared with any live `BookSnapshot`; a side is copied on its first
+    // change while a snapshot still holds it.
+    bids: Arc<BTreeMap<Decimal, Limit>>,
+    asks: Arc<BTreeMap<Decimal, Limit>>,
+    next_order_id: u64,
+    // Side and price level of every resting order; the level's queue keeps
+    // its own id index, so finding the order there is O(1) too.
+    order_index: HashMap<OrderId, (BidOrAsk, Decimal)>,
+    self_trade_prevention: Option<SelfTradePrevention>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    fees: Fees,
+    #[cfg_attr(feature = "serde", serde(default))]
+    price_band: PriceBand,
+    #[cfg_attr(feature = "serde", serde(default))]
+    tick_size: Option<Decimal>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    lot_size: Option<Decimal>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    matching: MatchingAlgorithm,
+    #[cfg_attr(feature = "serde", serde(default))]
+    market_order_policy: MarketOrderPolicy,
+    // Orders rest without matching until the book is uncrossed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    auction: bool,
+    // Sequence number of the last emitted `BookUpdate`.
+    sequence: u64,
+    // Last queue sequence given to an order joining the back of a level.
+    #[cfg_attr(feature = "serde", serde(default))]
+    queue_sequence: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    updates: Vec<BookUpdate>,
+    // Pinned time for accepted orders and trades, for deterministic replay.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    time: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "system_clock"))]
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for OrderBook {
+    fn default() -> OrderBook {
+        OrderBook::new()
+    }
 }
 
 impl OrderBook {
     pub fn new() -> OrderBook {
         OrderBook {
-            bids: HashMap::new(),
-            asks: HashMap::new(),
+            bids: Arc::new(BTreeMap::new()),
+            asks: Arc::new(BTreeMap::new()),
+            next_order_id: 1,
+            order_index: HashMap::new(),
+            self_trade_prevention: None,
+            fees: Fees::default(),
+            price_band: PriceBand::default(),
+            tick_size: None,
+            lot_size: None,
+            matching: MatchingAlgorithm::Fifo,
+            market_order_policy: MarketOrderPolicy::CancelRemainder,
+            auction: false,
+            sequence: 0,
+            queue_sequence: 0,
+            updates: Vec::new(),
+            time: None,
+            clock: system_clock(),
         }
     }
 
-    pub fn fill_market_order(&mut self, market_order: &mut Order) {
-        let limits = match market_order.bid_or_ask {
-            BidOrAsk::Bid => self.ask_limits(),
-            BidOrAsk::Ask => self.bid_limits(),
+    /// Pins the time stamped on accepted orders and trades; `None` follows
+    /// the book's clock.
+    pub fn set_time(&mut self, time: Option<u64>) {
+        self.time = time;
+    }
+
+    /// Stamps accepted orders and trades from `clock` instead of the system
+    /// clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(super) fn now(&self) -> u64 {
+        self.time.unwrap_or_else(|| self.clock.now())
+    }
+
+    /// Sequence number of the most recent book update.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Takes the level updates emitted since the last call. Updates are
+    /// buffered until drained, so a consumer mirroring the book should drain
+    /// after every operation.
+    pub fn drain_updates(&mut self) -> Vec<BookUpdate> {
+        std::mem::take(&mut self.updates)
+    }
+
+    fn level_size(&self, bid_or_ask: BidOrAsk, price: Decimal) -> Option<Decimal> {
+        let limits = match bid_or_ask {
+            BidOrAsk::Bid => &self.bids,
+            BidOrAsk::Ask => &self.asks,
         };
+        limits.get(&price).and_then(Limit::visible_size)
+    }
 
-        for limit in limits {
-            limit.fill_order(market_order);
-            if market_order.is_filled() {
+    // Emits the update describing how a level changed from `before`.
+    fn record_level_change(
+        &mut self,
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+        before: Option<Decimal>,
+    ) {
+        let update = match (before, self.level_size(bid_or_ask, price)) {
+            (Some(_), None) => BookUpdate::Remove {
+                sequence: self.sequence + 1,
+                bid_or_ask,
+                price,
+            },
+            (None, Some(size)) => BookUpdate::Add {
+                sequence: self.sequence + 1,
+                bid_or_ask,
+                price,
+                size,
+            },
+            (Some(before), Some(size)) if size > before => BookUpdate::Add {
+                sequence: self.sequence + 1,
+                bid_or_ask,
+                price,
+                size,
+            },
+            (Some(before), Some(size)) if size < before => BookUpdate::Reduce {
+                sequence: self.sequence + 1,
+                bid_or_ask,
+                price,
+                size,
+            },
+            _ => return,
+        };
+        self.sequence += 1;
+        self.updates.push(update);
+    }
+
+    fn next_queue_sequence(&mut self) -> u64 {
+        self.queue_sequence += 1;
+        self.queue_sequence
+    }
+
+    fn generate_order_id(&mut self) -> OrderId {
+        let id = OrderId(self.next_order_id);
+        self.next_order_id += 1;
+        id
+    }
+
+    // The id the next order submitted to the book will get.
+    pub(super) fn peek_order_id(&self) -> OrderId {
+        OrderId(self.next_order_id)
+    }
+
+    /// Assigns an id and acceptance time to an order. Used directly for
+    /// orders that only enter the book later, such as pending stops.
+    pub(super) fn reserve_order_id(&mut self, order: &mut Order) -> OrderId {
+        order.id = self.generate_order_id();
+        order.timestamp = self.now();
+        order.id
+    }
+
+    /// Market orders never rest, so any unfilled remainder is reported as
+    /// cancelled regardless of the order's time in force. They also stop
+    /// matching at the edge of the price band and at the order's own
+    /// `PriceProtection`, if it has one. Depending on the book's
+    /// `MarketOrderPolicy`, an order the book cannot fill completely is
+    /// rejected before it trades instead.
+    pub fn fill_market_order(&mut self, market_order: &mut Order) -> ExecutionReport {
+        self.reserve_order_id(market_order);
+        self.place_market(market_order)
+    }
+
+    // Like `fill_market_order`, for an order that already carries its id.
+    pub(super) fn place_market(&mut self, market_order: &mut Order) -> ExecutionReport {
+        let size = market_order.remaining_size();
+        let protection_limit = self.protection_limit(market_order);
+        let limit_price = self
+            .price_band
+            .cap(market_order.bid_or_ask, protection_limit);
+        let rejection = if self.auction {
+            Some(OrderBookError::NotAcceptedInAuction)
+        } else {
+            self.required_liquidity(market_order, limit_price)
+                .filter(|available| *available < size)
+                .map(|available| OrderBookError::InsufficientLiquidity { size, available })
+        };
+        if let Some(reason) = rejection {
+            let mut report = ExecutionReport::rejected(market_order.id, size, reason);
+            report.timestamp = market_order.timestamp;
+            return report;
+        }
+
+        let result = self.match_order(market_order, limit_price);
+        let status = if market_order.is_filled() {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::Cancelled
+        };
+        let mut report = ExecutionReport::new(
+            market_order.id,
+            status,
+            result.trades,
+            market_order.remaining_size(),
+        );
+        if status == OrderStatus::Cancelled && !result.taker_cancelled {
+            let next_price = match market_order.bid_or_ask {
+                BidOrAsk::Bid => self.best_ask(),
+                BidOrAsk::Ask => self.best_bid(),
+            };
+            let beyond = |limit: Decimal, price: Decimal| match market_order.bid_or_ask {
+                BidOrAsk::Bid => price > limit,
+                BidOrAsk::Ask => price < limit,
+            };
+            report.reason = Some(match (protection_limit, next_price) {
+                (Some(limit), Some(price)) if beyond(limit, price) => {
+                    OrderBookError::ProtectionPriceReached(limit)
+                }
+                _ => OrderBookError::InsufficientLiquidity {
+                    size,
+                    available: report.filled_size,
+                },
+            });
+        }
+        report.timestamp = market_order.timestamp;
+        report.self_trade_cancelled = result.self_trade_cancelled;
+        self.debug_validate();
+        report
+    }
+
+    /// Sets the self-trade prevention mode applied to orders that do not
+    /// carry their own. `None` (the default) allows self-trades.
+    pub fn set_self_trade_prevention(&mut self, mode: Option<SelfTradePrevention>) {
+        self.self_trade_prevention = mode;
+    }
+
+    /// Price increment post-only orders slide by.
+    pub fn set_tick_size(&mut self, tick_size: Option<Decimal>) {
+        self.tick_size = tick_size;
+    }
+
+    /// Granularity pro-rata allocations are rounded down to. Without one,
+    /// the finest decimal place among the sizes involved is used.
+    pub fn set_lot_size(&mut self, lot_size: Option<Decimal>) {
+        self.lot_size = lot_size;
+    }
+
+    pub fn set_matching_algorithm(&mut self, matching: MatchingAlgorithm) {
+        self.matching = matching;
+    }
+
+    pub fn matching_algorithm(&self) -> MatchingAlgorithm {
+        self.matching
+    }
+
+    pub fn set_market_order_policy(&mut self, policy: MarketOrderPolicy) {
+        self.market_order_policy = policy;
+    }
+
+    pub fn market_order_policy(&self) -> MarketOrderPolicy {
+        self.market_order_policy
+    }
+
+    /// Starts an auction: from now on limit orders rest without matching,
+    /// even at crossing prices, while market, immediate-or-cancel and
+    /// fill-or-kill orders are rejected. `uncross` ends it.
+    pub fn start_auction(&mut self) {
+        self.auction = true;
+    }
+
+    pub fn in_auction(&self) -> bool {
+        self.auction
+    }
+
+    /// Price the book would uncross at and the volume that would trade
+    /// there: the price matching the most volume, then leaving the smallest
+    /// surplus, then closest to the price band's reference, then the lowest.
+    /// `None` if the book does not cross.
+    pub fn equilibrium(&self) -> Option<(Decimal, Decimal)> {
+        let reference = self.price_band.reference();
+        self.bids
+            .keys()
+            .chain(self.asks.keys())
+            .map(|&price| {
+                let demand = self.available_liquidity(BidOrAsk::Ask, price);
+                let supply = self.available_liquidity(BidOrAsk::Bid, price);
+                (price, demand.min(supply), (demand - supply).abs())
+            })
+            .filter(|(_, volume, _)| !volume.is_zero())
+            .min_by_key(|&(price, volume, surplus)| {
+                let distance = reference.map(|reference| (price - reference).abs());
+                (Reverse(volume), surplus, distance, price)
+            })
+            .map(|(price, volume, _)| (price, volume))
+    }
+
+    /// Ends the auction: every order crossing the equilibrium price trades
+    /// at that single price, best prices first and in arrival order within
+    /// a level, and the book returns to continuous matching. Of each pair
+    /// of orders the later arrival is the taker. Self-trade prevention
+    /// applies as in continuous matching, the later arrival being the
+    /// incoming order.
+    pub fn uncross(&mut self) -> AuctionResult {
+        self.auction = false;
+        let Some((price, _)) = self.equilibrium() else {
+            return AuctionResult::default();
+        };
+        let mut result = AuctionResult {
+            price: Some(price),
+            ..AuctionResult::default()
+        };
+        let mut touched: Vec<(BidOrAsk, Decimal, Option<Decimal>)> = Vec::new();
+        while let (Some(&bid_price), Some(&ask_price)) =
+            (self.bids.keys().next_back(), self.asks.keys().next())
+        {
+            if bid_price < price || ask_price > price {
                 break;
             }
+            for (bid_or_ask, level) in [(BidOrAsk::Bid, bid_price), (BidOrAsk::Ask, ask_price)] {
+                if !touched
+                    .iter()
+                    .any(|&(side, touched_price, _)| side == bid_or_ask && touched_price == level)
+                {
+                    let before = self.level_size(bid_or_ask, level);
+                    touched.push((bid_or_ask, level, before));
+                }
+            }
+
+            let bids = Arc::make_mut(&mut self.bids)
+                .get_mut(&bid_price)
+                .expect("best bid level exists");
+            let asks = Arc::make_mut(&mut self.asks)
+                .get_mut(&ask_price)
+                .expect("best ask level exists");
+            let bid = bids.front_mut().expect("best bid level has orders");
+            let ask = asks.front_mut().expect("best ask level has orders");
+            let bid_is_newer = bid.id > ask.id;
+            let newer = if bid_is_newer { &*bid } else { &*ask };
+            let self_trade_prevention = match bid.owner == ask.owner {
+                true => newer.self_trade_prevention.or(self.self_trade_prevention),
+                false => None,
+            };
+            let (cancel_bid, cancel_ask) = match self_trade_prevention {
+                None => (false, false),
+                Some(SelfTradePrevention::CancelNewest) => (bid_is_newer, !bid_is_newer),
+                Some(SelfTradePrevention::CancelOldest) => (!bid_is_newer, bid_is_newer),
+                Some(SelfTradePrevention::CancelBoth) => (true, true),
+            };
+            if self_trade_prevention.is_none() {
+                let size = bid.remaining_size().min(ask.remaining_size());
+                bid.resize(bid.remaining_size() - size);
+                ask.resize(ask.remaining_size() - size);
+                let (maker, taker) = if bid_is_newer {
+                    (&*ask, &*bid)
+                } else {
+                    (&*bid, &*ask)
+                };
+                result.trades.push(
+                    Trade::new(maker.id, taker.id, price, size, taker.bid_or_ask)
+                        .with_owners(maker.owner, taker.owner),
+                );
+            }
+
+            for (limit, cancel) in [(bids, cancel_bid), (asks, cancel_ask)] {
+                if cancel || limit.front_mut().is_some_and(|order| order.is_filled()) {
+                    let order = limit.pop_front().expect("front order exists");
+                    self.order_index.remove(&order.id);
+                    if cancel {
+                        result.self_trade_cancelled.push(order.id);
+                    }
+                }
+            }
+            if self.bids[&bid_price].is_empty() {
+                Arc::make_mut(&mut self.bids).remove(&bid_price);
+            }
+            if self.asks[&ask_price].is_empty() {
+                Arc::make_mut(&mut self.asks).remove(&ask_price);
+            }
+        }
+
+        let now = self.now();
+        for trade in &mut result.trades {
+            trade.timestamp = now;
+            self.fees.apply(trade);
+        }
+        if !result.trades.is_empty() {
+            self.price_band.on_trade(price);
+        }
+        for (bid_or_ask, level, before) in touched {
+            self.record_level_change(bid_or_ask, level, before);
         }
+        self.debug_validate();
+        result
     }
 
-    pub fn ask_limits(&mut self) -> Vec<&mut Limit> {
-        let mut limits: Vec<&mut Limit> = self.asks.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a, b| a.price.cmp(&b.price));
-        limits
+    pub fn set_fee_schedule(&mut self, schedule: FeeSchedule) {
+        self.fees.set_schedule(schedule);
     }
 
-    pub fn bid_limits(&mut self) -> Vec<&mut Limit> {
-        let mut limits: Vec<&mut Limit> = self.bids.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a, b| b.price.cmp(&a.price));
-        limits
+    pub fn fees(&self) -> &Fees {
+        &self.fees
     }
 
-    pub fn add_limit_order(&mut self, price: Decimal, order: Order) {
-        match order.bid_or_ask {
-            BidOrAsk::Bid => match self.bids.get_mut(&price) {
-                Some(limit) => {
-                    limit.add_order(order);
+    pub fn price_band(&self) -> &PriceBand {
+        &self.price_band
+    }
+
+    pub fn price_band_mut(&mut self) -> &mut PriceBand {
+        &mut self.price_band
+    }
+
+    // Protection price of a market order arriving now, if it carries one.
+    fn protection_limit(&self, order: &Order) -> Option<Decimal> {
+        let reference = self.mid_price().or(match order.bid_or_ask {
+            BidOrAsk::Bid => self.best_ask(),
+            BidOrAsk::Ask => self.best_bid(),
+        })?;
+        Some(order.protection?.limit_price(order.bid_or_ask, reference))
+    }
+
+    // The liquidity the market order policy checks `order` against before
+    // it may trade; `None` if it trades regardless.
+    fn required_liquidity(&self, order: &Order, limit_price: Option<Decimal>) -> Option<Decimal> {
+        match self.market_order_policy {
+            MarketOrderPolicy::CancelRemainder => None,
+            MarketOrderPolicy::Reject => Some(match limit_price {
+                Some(limit_price) => self.available_liquidity(order.bid_or_ask, limit_price),
+                None => self.total_liquidity(order.bid_or_ask),
+            }),
+            MarketOrderPolicy::AllOrNone => Some(self.fillable_size(order, limit_price)),
+        }
+    }
+
+    // Total size on the opposite side of a `bid_or_ask` order.
+    fn total_liquidity(&self, bid_or_ask: BidOrAsk) -> Decimal {
+        let limits = match bid_or_ask {
+            BidOrAsk::Bid => &self.asks,
+            BidOrAsk::Ask => &self.bids,
+        };
+        limits.values().map(Limit::total_quantity).sum()
+    }
+
+    // How much of `order` matching would fill up to `limit_price`.
+    fn fillable_size(&self, order: &Order, limit_price: Option<Decimal>) -> Decimal {
+        self.estimate_fill(order, limit_price).size
+    }
+
+    /// What matching `order` up to `limit_price` would fill and pay, stopping
+    /// where self-trade prevention would cancel it and skipping the resting
+    /// orders it would cancel instead. The book is left as it is.
+    pub fn estimate_fill(&self, order: &Order, limit_price: Option<Decimal>) -> FillEstimate {
+        let self_trade_prevention = order.self_trade_prevention.or(self.self_trade_prevention);
+        let levels: Box<dyn Iterator<Item = &Limit>> = match order.bid_or_ask {
+            BidOrAsk::Bid => Box::new(self.asks.values()),
+            BidOrAsk::Ask => Box::new(self.bids.values().rev()),
+        };
+        let size = order.remaining_size();
+        let mut estimate = FillEstimate::default();
+        'levels: for limit in levels {
+            let crosses = match (limit_price, order.bid_or_ask) {
+                (None, _) => true,
+                (Some(price), BidOrAsk::Bid) => limit.price <= price,
+                (Some(price), BidOrAsk::Ask) => limit.price >= price,
+            };
+            if !crosses {
+                break;
+            }
+            let own =
+                |resting: &&Order| self_trade_prevention.is_some() && resting.owner == order.owner;
+            let stops = self_trade_prevention != Some(SelfTradePrevention::CancelOldest);
+            // Pro-rata applies self-trade prevention to the whole level first.
+            if stops
+                && self.matching == MatchingAlgorithm::ProRata
+                && limit.orders().any(|resting| own(&resting))
+            {
+                break;
+            }
+            for resting in limit.orders() {
+                if own(&resting) {
+                    if stops {
+                        break 'levels;
+                    }
+                    continue;
                 }
-                None => {
-                    let mut limit = Limit::new(price);
-                    limit.add_order(order);
-                    self.bids.insert(price, limit);
+                let fill = (size - estimate.size).min(resting.remaining_size());
+                estimate.size += fill;
+                estimate.notional += fill * limit.price;
+                estimate.worst_price = Some(limit.price);
+                if estimate.size >= size {
+                    break 'levels;
                 }
+            }
+        }
+        if !estimate.size.is_zero() {
+            estimate.average_price = Some(estimate.notional / estimate.size);
+        }
+        estimate
+    }
+
+    // Total size on the opposite side that an order limited to `limit_price`
+    // could trade against.
+    fn available_liquidity(&self, bid_or_ask: BidOrAsk, limit_price: Decimal) -> Decimal {
+        match bid_or_ask {
+            BidOrAsk::Bid => self
+                .asks
+                .range(..=limit_price)
+                .map(|(_, limit)| limit.total_quantity())
+                .sum(),
+            BidOrAsk::Ask => self
+                .bids
+                .range(limit_price..)
+                .map(|(_, limit)| limit.total_quantity())
+                .sum(),
+        }
+    }
+
+    // Walks the opposite side from the best price, filling `order` until it is
+    // exhausted or, for limit orders, the next level is worse than `limit_price`.
+    // Filled makers and the levels they emptied are removed from the book.
+    fn match_order(&mut self, order: &mut Order, limit_price: Option<Decimal>) -> MatchResult {
+        // Leave the opposite side untouched, so a snapshot sharing it isn't
+        // copied, when the order can't trade.
+        let crosses = match (self.best_opposite_price(order.bid_or_ask), limit_price) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(best), Some(price)) => match order.bid_or_ask {
+                BidOrAsk::Bid => best <= price,
+                BidOrAsk::Ask => best >= price,
             },
-            BidOrAsk::Ask => match self.asks.get_mut(&price) {
-                Some(limit) => {
-                    limit.add_order(order);
+        };
+        if !crosses {
+            return MatchResult::default();
+        }
+        let self_trade_prevention = order.self_trade_prevention.or(self.self_trade_prevention);
+        let (matching, lot_size) = (self.matching, self.lot_size);
+        let mut queue_sequence = self.queue_sequence;
+        let fill = |limit: &mut Limit, order: &mut Order| match matching {
+            MatchingAlgorithm::Fifo => {
+                limit.fill_order(order, self_trade_prevention, &mut queue_sequence)
+            }
+            MatchingAlgorithm::ProRata => limit.fill_order_pro_rata(
+                order,
+                self_trade_prevention,
+                lot_size,
+                &mut queue_sequence,
+            ),
+        };
+        let mut filled = Vec::new();
+        let mut touched = Vec::new();
+        let mut result = match order.bid_or_ask {
+            BidOrAsk::Bid => {
+                let asks = Arc::make_mut(&mut self.asks);
+                let result = Self::match_against(
+                    asks.values_mut(),
+                    order,
+                    limit_price,
+                    fill,
+                    &mut filled,
+                    &mut touched,
+                );
+                while let Some(entry) = asks.first_entry() {
+                    if !entry.get().is_empty() {
+                        break;
+                    }
+                    entry.remove();
                 }
-                None => {
-                    let mut limit = Limit::new(price);
-                    limit.add_order(order);
-                    self.asks.insert(price, limit);
+                result
+            }
+            BidOrAsk::Ask => {
+                let bids = Arc::make_mut(&mut self.bids);
+                let result = Self::match_against(
+                    bids.values_mut().rev(),
+                    order,
+                    limit_price,
+                    fill,
+                    &mut filled,
+                    &mut touched,
+                );
+                while let Some(entry) = bids.last_entry() {
+                    if !entry.get().is_empty() {
+                        break;
+                    }
+                    entry.remove();
                 }
-            },
+                result
+            }
+        };
+
+        self.queue_sequence = queue_sequence;
+        for id in filled.iter().chain(&result.self_trade_cancelled) {
+            self.order_index.remove(id);
+        }
+        let now = self.now();
+        for trade in &mut result.trades {
+            trade.timestamp = now;
+            self.fees.apply(trade);
         }
+        if let Some(trade) = result.trades.last() {
+            self.price_band.on_trade(trade.price);
+        }
+        let maker_side = match order.bid_or_ask {
+            BidOrAsk::Bid => BidOrAsk::Ask,
+            BidOrAsk::Ask => BidOrAsk::Bid,
+        };
+        for (price, before) in touched {
+            self.record_level_change(maker_side, price, before);
+        }
+        result
     }
-}
 
-#[derive(Debug)]
-pub struct Limit {
-    price: Decimal,
-    orders: Vec<Order>,
-}
+    fn match_against<'a>(
+        limits: impl Iterator<Item = &'a mut Limit>,
+        order: &mut Order,
+        limit_price: Option<Decimal>,
+        mut fill: impl FnMut(&mut Limit, &mut Order) -> MatchResult,
+        filled: &mut Vec<OrderId>,
+        touched: &mut Vec<(Decimal, Option<Decimal>)>,
+    ) -> MatchResult {
+        let mut result = MatchResult::default();
+        for limit in limits {
+            let crosses = match (limit_price, order.bid_or_ask) {
+                (None, _) => true,
+                (Some(price), BidOrAsk::Bid) => limit.price <= price,
+                (Some(price), BidOrAsk::Ask) => limit.price >= price,
+            };
+            if !crosses {
+                break;
+            }
 
-impl Limit {
-    pub fn new(price: Decimal) -> Limit {
-        Limit {
-            price,
-            orders: Vec::new(),
+            touched.push((limit.price, limit.visible_size()));
+            let level = fill(limit, order);
+            filled.extend(level.filled);
+            result.trades.extend(level.trades);
+            result
+                .self_trade_cancelled
+                .extend(level.self_trade_cancelled);
+            if level.taker_cancelled {
+                result.taker_cancelled = true;
+                break;
+            }
+            if order.is_filled() {
+                break;
+            }
         }
+        result
     }
 
-    pub fn total_volume(&self) -> f64 {
-        self.orders.iter().map(|order| order.size).sum()
+    /// Read-only L3 view of the bid side: levels from the best price down,
+    /// each exposing its queue via `Limit::orders`.
+    pub fn bid_levels(&self) -> impl Iterator<Item = &Limit> {
+        self.bids.values().rev()
     }
 
-    pub fn fill_order(&mut self, market_order: &mut Order) {
-        for limit_order in self.orders.iter_mut() {
-            if market_order.size >= limit_order.size {
-                market_order.size -= limit_order.size;
-                limit_order.size = 0.0;
-            } else {
-                limit_order.size -= market_order.size;
-                market_order.size = 0.0;
-            }
+    /// Read-only L3 view of the ask side, from the best price up.
+    pub fn ask_levels(&self) -> impl Iterator<Item = &Limit> {
+        self.asks.values()
+    }
 
-            if market_order.is_filled() {
-                break;
-            }
+    /// Ask levels ordered from the lowest price.
+    pub fn ask_limits(&mut self) -> Vec<&mut Limit> {
+        Arc::make_mut(&mut self.asks).values_mut().collect()
+    }
+
+    /// Bid levels ordered from the highest price.
+    pub fn bid_limits(&mut self) -> Vec<&mut Limit> {
+        Arc::make_mut(&mut self.bids).values_mut().rev().collect()
+    }
+
+    /// Matches the order against the opposite side up to `price` and handles
+    /// any remainder according to the order's time in force.
+    pub fn add_limit_order(&mut self, price: Decimal, mut order: Order) -> ExecutionReport {
+        self.reserve_order_id(&mut order);
+        self.place_limit(price, order)
+    }
+
+    // The `bid_or_ask` side for changing, copied first if a snapshot still
+    // shares it.
+    fn limits_mut(&mut self, bid_or_ask: BidOrAsk) -> &mut BTreeMap<Decimal, Limit> {
+        match bid_or_ask {
+            BidOrAsk::Bid => Arc::make_mut(&mut self.bids),
+            BidOrAsk::Ask => Arc::make_mut(&mut self.asks),
         }
     }
 
-    pub fn add_order(&mut self, order: Order) {
-        self.orders.push(order);
+    // Best price on the side an incoming order would trade against.
+    fn best_opposite_price(&self, bid_or_ask: BidOrAsk) -> Option<Decimal> {
+        match bid_or_ask {
+            BidOrAsk::Bid => self.best_ask(),
+            BidOrAsk::Ask => self.best_bid(),
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct Order {
-    size: f64,
-    bid_or_ask: BidOrAsk,
-}
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.last_key_value().map(|(price, _)| *price)
+    }
 
-impl Order {
-    pub fn new(bid_or_ask: BidOrAsk, size: f64) -> Order {
-        Order { size, bid_or_ask }
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.first_key_value().map(|(price, _)| *price)
     }
 
-    pub fn is_filled(&self) -> bool {
-        self.size == 0.0
+    /// What a market buy of `size` would pay sweeping the asks now, iceberg
+    /// reserves and hidden orders included. The book is left as it is.
+    pub fn cost_to_buy(&self, size: Decimal) -> FillEstimate {
+        FillEstimate::walk(self.ask_levels(), size)
     }
-}
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
-    use rust_decimal_macros::dec;
+    /// What a market sell of `size` would receive sweeping the bids now.
+    pub fn cost_to_sell(&self, size: Decimal) -> FillEstimate {
+        FillEstimate::walk(self.bid_levels(), size)
+    }
 
-    #[test]
-    fn test_order_new() {
-        let order = Order::new(BidOrAsk::Bid, 1.23456789);
-        assert_eq!(order.size, 1.23456789);
-        assert_eq!(order.bid_or_ask, BidOrAsk::Bid);
+    /// Aggregated view of the top `levels` price levels on each side, best
+    /// price first, stamped with the sequence of the last book update.
+    /// Levels holding only hidden orders are left out.
+    pub fn depth(&self, levels: usize) -> DepthSnapshot {
+        self.snapshot().depth(levels)
     }
 
-    #[test]
-    fn test_order_is_filled() {
-        let mut order = Order::new(BidOrAsk::Bid, 1.23456789);
-        assert_eq!(order.is_filled(), false);
-        order.size = 0.0;
-        assert_eq!(order.is_filled(), true);
+    /// An immutable view of the book as it is now, for other threads to
+    /// read while this one keeps matching. Taking one costs two reference
+    /// count bumps; the book copies a side on the first change to it while
+    /// the snapshot is still alive.
+    pub fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            sequence: self.sequence,
+            bids: Arc::clone(&self.bids),
+            asks: Arc::clone(&self.asks),
+        }
     }
 
-    #[test]
-    fn test_limit_new() {
-        let price = dec!(1.23456789);
-        let limit = Limit::new(price);
-        assert_eq!(limit.price, price);
+    /// Best ask minus best bid, `None` unless both sides are quoted.
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.best_ask()? - self.best_bid()?)
     }
 
-    #[test]
-    fn test_orderbook_new() {
-        let orderbook = OrderBook::new();
-        assert_eq!(orderbook.bids.len(), 0);
-        assert_eq!(orderbook.asks.len(), 0);
+    pub fn mid_price(&self) -> Option<Decimal> {
+        Some((self.best_ask()? + self.best_bid()?) / Decimal::TWO)
     }
 
-    #[test]
-    fn test_orderbook_fill_market_order_ask() {
-        let mut orderbook = OrderBook::new();
-        orderbook.add_limit_order(dec!(500), Order::new(BidOrAsk::Ask, 10.0));
-        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, 10.0));
-        orderbook.add_limit_order(dec!(200), Order::new(BidOrAsk::Ask, 10.0));
-        orderbook.add_limit_order(dec!(300), Order::new(BidOrAsk::Ask, 10.0));
+    /// Order-book imbalance over the visible volume priced within `distance`
+    /// of the mid: `(bid - ask) / (bid + ask)`, from -1 (all asks) to 1 (all
+    /// bids). `None` when either side is empty.
+    pub fn imbalance(&self, distance: Decimal) -> Option<Decimal> {
+        let (bid_volume, ask_volume) = self.volume_near_mid(distance)?;
+        let total = bid_volume + ask_volume;
+        if total.is_zero() {
+            return None;
+        }
+        Some((bid_volume - ask_volume) / total)
+    }
+
+    /// Visible `(bid, ask)` volume priced within `ticks` ticks of the mid.
+    /// `None` when either side is empty or the book has no tick size.
+    pub fn volume_within(&self, ticks: u32) -> Option<(Decimal, Decimal)> {
+        self.volume_near_mid(self.tick_size? * Decimal::from(ticks))
+    }
+
+    fn volume_near_mid(&self, distance: Decimal) -> Option<(Decimal, Decimal)> {
+        let mid = self.mid_price()?;
+        let bid_volume = self
+            .bids
+            .range(mid - distance..)
+            .map(|(_, limit)| limit.total_volume())
+            .sum();
+        let ask_volume = self
+            .asks
+            .range(..=mid + distance)
+            .map(|(_, limit)| limit.total_volume())
+            .sum();
+        Some((bid_volume, ask_volume))
+    }
+
+    /// Visible volume an aggressor on `bid_or_ask` has to take to move the
+    /// opposite best price by at least `distance`: everything resting less
+    /// than `distance` away from it.
+    pub fn volume_to_move(&self, bid_or_ask: BidOrAsk, distance: Decimal) -> Decimal {
+        let levels: Vec<&Limit> = match bid_or_ask {
+            BidOrAsk::Bid => self.ask_levels().collect(),
+            BidOrAsk::Ask => self.bid_levels().collect(),
+        };
+        let levels: Vec<&Limit> = levels
+            .into_iter()
+            .filter(|limit| limit.visible_size().is_some())
+            .collect();
+        let Some(best) = levels.first().map(|limit| limit.price) else {
+            return Decimal::ZERO;
+        };
+        levels
+            .iter()
+            .take_while(|limit| (limit.price - best).abs() < distance)
+            .map(|limit| limit.total_volume())
+            .sum()
+    }
+
+    // Like `add_limit_order`, for an order that already carries its id.
+    pub(super) fn place_limit(&mut self, price: Decimal, order: Order) -> ExecutionReport {
+        let timestamp = order.timestamp;
+        let mut report = self.match_limit(price, order);
+        report.timestamp = timestamp;
+        report.sequence = self.order(report.order_id).map(Order::sequence);
+        self.debug_validate();
+        report
+    }
+
+    // Why a limit `order` at `price` would be rejected before matching, if
+    // it would be.
+    fn limit_rejection(&self, price: Decimal, order: &Order) -> Option<OrderBookError> {
+        if let Some((low, high)) = self
+            .price_band
+            .bounds()
+            .filter(|_| !self.price_band.contains(price))
+        {
+            return Some(OrderBookError::OutsidePriceBand { price, low, high });
+        }
+        if self.auction {
+            return (!order.time_in_force.rests()).then_some(OrderBookError::NotAcceptedInAuction);
+        }
+        if let (Some(PostOnly::Reject), Some(best)) =
+            (order.post_only, self.best_opposite_price(order.bid_or_ask))
+        {
+            let crosses = match order.bid_or_ask {
+                BidOrAsk::Bid => price >= best,
+                BidOrAsk::Ask => price <= best,
+            };
+            if crosses {
+                return Some(OrderBookError::PostOnlyWouldCross { price, best });
+            }
+        }
+        None
+    }
+
+    /// The price `order` would rest at if placed at `price`: a crossing
+    /// post-only slide moves one tick behind the best opposite price, where
+    /// without a tick size a tick is one unit in the last decimal place of
+    /// the finer price. Any other order keeps `price`.
+    pub fn slid_price(&self, price: Decimal, order: &Order) -> Decimal {
+        let (Some(PostOnly::Slide), Some(best)) =
+            (order.post_only, self.best_opposite_price(order.bid_or_ask))
+        else {
+            return price;
+        };
+        let increment = self
+            .tick_size
+            .unwrap_or_else(|| Decimal::new(1, price.scale().max(best.scale())));
+        match order.bid_or_ask {
+            BidOrAsk::Bid if price >= best => best - increment,
+            BidOrAsk::Ask if price <= best => best + increment,
+            _ => price,
+        }
+    }
+
+    // Why the price a post-only slide moves `order` to would be refused:
+    // it is not positive or fails the checks the original price passed.
+    fn slide_rejection(&self, price: Decimal, order: &Order) -> Option<OrderBookError> {
+        let slid = self.slid_price(price, order);
+        if self.auction || slid == price {
+            return None;
+        }
+        if slid <= Decimal::ZERO {
+            return Some(OrderBookError::InvalidPrice(slid));
+        }
+        self.limit_rejection(slid, order)
+    }
+
+    fn match_limit(&mut self, mut price: Decimal, mut order: Order) -> ExecutionReport {
+        if let Some(reason) = self.limit_rejection(price, &order) {
+            return ExecutionReport::rejected(order.id, order.remaining_size(), reason);
+        }
+        if self.auction {
+            let remaining_size = order.remaining_size();
+            let order_id = order.id;
+            self.rest_order(price, order);
+            return ExecutionReport::new(order_id, OrderStatus::New, Vec::new(), remaining_size);
+        }
+        // Crossing post-only rejects were refused above; slides move here.
+        if let Some(reason) = self.slide_rejection(price, &order) {
+            return ExecutionReport::rejected(order.id, order.remaining_size(), reason);
+        }
+        price = self.slid_price(price, &order);
+
+        if order.time_in_force == TimeInForce::FillOrKill {
+            let (size, available) = (
+                order.remaining_size(),
+                self.fillable_size(&order, Some(price)),
+            );
+            if available < size {
+                let reason = OrderBookError::InsufficientLiquidity { size, available };
+                return ExecutionReport::rejected(order.id, size, reason);
+            }
+        }
+
+        let result = self.match_order(&mut order, Some(price));
+        let status = if order.is_filled() {
+            OrderStatus::Filled
+        } else if result.taker_cancelled || order.time_in_force == TimeInForce::ImmediateOrCancel {
+            OrderStatus::Cancelled
+        } else if result.trades.is_empty() {
+            OrderStatus::New
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+
+        let mut report =
+            ExecutionReport::new(order.id, status, result.trades, order.remaining_size());
+        report.self_trade_cancelled = result.self_trade_cancelled;
+        if matches!(status, OrderStatus::New | OrderStatus::PartiallyFilled) {
+            self.rest_order(price, order);
+        }
+        report
+    }
+
+    // Appends an order that already carries its id to the back of the queue
+    // at `price`, creating the level if needed. Iceberg orders rest with only
+    // their first clip displayed.
+    fn rest_order(&mut self, price: Decimal, mut order: Order) {
+        if let Some(display_size) = order.display_size.filter(|_| !order.hidden) {
+            let total = order.remaining_size();
+            order.size = total.min(display_size);
+            order.reserve = total - order.size;
+        }
+        order.sequence = self.next_queue_sequence();
+        self.order_index.insert(order.id, (order.bid_or_ask, price));
+
+        let bid_or_ask = order.bid_or_ask;
+        let before = self.level_size(bid_or_ask, price);
+        self.limits_mut(bid_or_ask)
+            .entry(price)
+            .or_insert_with(|| Limit::new(price))
+            .add_order(order);
+        self.record_level_change(bid_or_ask, price, before);
+    }
+
+    pub fn contains_order(&self, id: OrderId) -> bool {
+        self.order_index.contains_key(&id)
+    }
+
+    /// Price a resting order is working at.
+    pub fn order_price(&self, id: OrderId) -> Option<Decimal> {
+        self.order_index.get(&id).map(|&(_, price)| price)
+    }
+
+    pub fn order(&self, id: OrderId) -> Option<&Order> {
+        let (bid_or_ask, price) = self.order_index.get(&id)?;
+        let limits = match bid_or_ask {
+            BidOrAsk::Bid => &self.bids,
+            BidOrAsk::Ask => &self.asks,
+        };
+        limits.get(price)?.order(id)
+    }
+
+    /// Every resting order with its price, bids from the best price down
+    /// followed by asks from the best price up.
+    pub fn orders(&self) -> impl Iterator<Item = (Decimal, &Order)> {
+        self.bids
+            .values()
+            .rev()
+            .chain(self.asks.values())
+            .flat_map(|limit| limit.orders().map(move |order| (limit.price, order)))
+    }
+
+    /// Checks the book's invariants: outside an auction the best bid is
+    /// below the best ask; every level is non-empty, keyed by its own price
+    /// and has intact queues; resting orders have a positive displayed size
+    /// and sit on their own side; and the id index lists exactly the resting
+    /// orders. Level sizes are summed from their orders when asked for, so
+    /// they cannot drift apart. Every violation found is returned.
+    pub fn validate(&self) -> Result<(), Vec<BookViolation>> {
+        let mut violations = Vec::new();
+        if let (false, Some(best_bid), Some(best_ask)) =
+            (self.auction, self.best_bid(), self.best_ask())
+        {
+            if best_bid >= best_ask {
+                violations.push(BookViolation::Crossed { best_bid, best_ask });
+            }
+        }
+        let mut resting = HashSet::new();
+        for (bid_or_ask, limits) in [(BidOrAsk::Bid, &self.bids), (BidOrAsk::Ask, &self.asks)] {
+            for (&price, limit) in limits.iter() {
+                if limit.price != price {
+                    violations.push(BookViolation::LevelPriceMismatch {
+                        bid_or_ask,
+                        key: price,
+                        price: limit.price,
+                    });
+                }
+                if limit.is_empty() {
+                    violations.push(BookViolation::EmptyLevel { bid_or_ask, price });
+                }
+                if !limit.displayed.is_consistent() || !limit.hidden.is_consistent() {
+                    violations.push(BookViolation::CorruptQueue { bid_or_ask, price });
+                }
+                for order in limit.orders() {
+                    if !resting.insert(order.id) {
+                        violations.push(BookViolation::DuplicateOrderId(order.id));
+                    }
+                    if order.size <= Decimal::ZERO || order.reserve < Decimal::ZERO {
+                        violations.push(BookViolation::NonPositiveSize {
+                            order_id: order.id,
+                            size: order.size,
+                        });
+                    }
+                    if order.bid_or_ask != bid_or_ask {
+                        violations.push(BookViolation::WrongSide {
+                            order_id: order.id,
+                            bid_or_ask: order.bid_or_ask,
+                        });
+                    }
+                    if self.order_index.get(&order.id) != Some(&(bid_or_ask, price)) {
+                        violations.push(BookViolation::UnindexedOrder(order.id));
+                    }
+                }
+            }
+        }
+        for (&order_id, &(bid_or_ask, price)) in &self.order_index {
+            let limits = match bid_or_ask {
+                BidOrAsk::Bid => &self.bids,
+                BidOrAsk::Ask => &self.asks,
+            };
+            if limits
+                .get(&price)
+                .and_then(|limit| limit.order(order_id))
+                .is_none()
+            {
+                violations.push(BookViolation::StaleIndexEntry(order_id));
+            }
+        }
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+
+    // Run after every mutation in debug builds, so tests catch a corrupted
+    // book at the operation that corrupted it.
+    fn debug_validate(&self) {
+        #[cfg(debug_assertions)]
+        if let Err(violations) = self.validate() {
+            panic!("Order book invariants violated: {:?}", violations);
+        }
+    }
+
+    /// Removes a resting order from the book, returning it with its
+    /// remaining size. Returns `None` if the id is unknown or already filled.
+    /// The price level is dropped once its last order is cancelled.
+    pub fn cancel_order(&mut self, id: OrderId) -> Option<Order> {
+        let (bid_or_ask, price) = self.order_index.remove(&id)?;
+        let before = self.level_size(bid_or_ask, price);
+        let limits = self.limits_mut(bid_or_ask);
+        let limit = limits.get_mut(&price)?;
+        let order = limit.remove_order(id)?;
+        if limit.is_empty() {
+            limits.remove(&price);
+        }
+        self.record_level_change(bid_or_ask, price, before);
+        self.debug_validate();
+        Some(order)
+    }
+
+    /// Changes the price and/or size of a resting order. Reducing the size at
+    /// the same price keeps the order's place in the queue; any other change
+    /// moves it to the back of the queue at the new price, matching first if
+    /// the new price crosses, and stamps it as newly accepted. Fails, leaving
+    /// the order as it was, for the reasons given by `check_amend`.
+    pub fn amend_order(
+        &mut self,
+        id: OrderId,
+        new_price: Decimal,
+        new_size: Decimal,
+    ) -> Result<ExecutionReport, OrderBookError> {
+        self.check_amend(id, new_price, new_size)?;
+        let (bid_or_ask, price) = self.order_index[&id];
+
+        if new_price == price {
+            let before = self.level_size(bid_or_ask, price);
+            if let Some(order) = self
+                .limits_mut(bid_or_ask)
+                .get_mut(&price)
+                .and_then(|limit| limit.order_mut(id))
+            {
+                if new_size <= order.remaining_size() {
+                    order.resize(new_size);
+                    let mut report =
+                        ExecutionReport::new(id, OrderStatus::New, Vec::new(), new_size);
+                    report.timestamp = order.timestamp;
+                    report.sequence = Some(order.sequence);
+                    self.record_level_change(bid_or_ask, price, before);
+                    self.debug_validate();
+                    return Ok(report);
+                }
+            }
+        }
+
+        let mut order = self
+            .cancel_order(id)
+            .ok_or(OrderBookError::UnknownOrderId(id))?;
+        order.size = new_size;
+        order.reserve = Decimal::ZERO;
+        order.timestamp = self.now();
+        Ok(self.place_limit(new_price, order))
+    }
+
+    /// Why `amend_order` would refuse an amend: the id is unknown or already
+    /// filled, the new size is not positive, or the order would be rejected
+    /// at the new price. Shrinking an order in place is always allowed.
+    pub fn check_amend(
+        &self,
+        id: OrderId,
+        new_price: Decimal,
+        new_size: Decimal,
+    ) -> Result<(), OrderBookError> {
+        let order = self.order(id).ok_or(OrderBookError::UnknownOrderId(id))?;
+        if new_size <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidSize(new_size));
+        }
+        let (_, price) = self.order_index[&id];
+        if new_price == price && new_size <= order.remaining_size() {
+            return Ok(());
+        }
+        match self
+            .limit_rejection(new_price, order)
+            .or_else(|| self.slide_rejection(new_price, order))
+        {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State of an order once the book has finished processing it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OrderStatus {
+    /// Resting in the book without any fills.
+    New,
+    /// Partially filled with the remainder resting in the book.
+    PartiallyFilled,
+    Filled,
+    /// Unfilled remainder was cancelled instead of resting (IOC, market,
+    /// self-trade prevention).
+    Cancelled,
+    /// Rejected without trading (FOK that could not be fully filled, a
+    /// post-only order that would have crossed, or a market order refused
+    /// by the market order policy).
+    Rejected,
+    /// Stop order waiting for its trigger price.
+    Pending,
+}
+
+/// Outcome of uncrossing the book at the end of an auction.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AuctionResult {
+    /// Equilibrium price every trade executed at; `None` if the book did
+    /// not cross.
+    pub price: Option<Decimal>,
+    pub trades: Vec<Trade>,
+    /// Orders cancelled by self-trade prevention while uncrossing.
+    pub self_trade_cancelled: Vec<OrderId>,
+}
+
+impl AuctionResult {
+    pub fn volume(&self) -> Decimal {
+        self.trades.iter().map(|trade| trade.size).sum()
+    }
+}
+
+/// Outcome of matching an incoming order against the book.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExecutionReport {
+    pub order_id: OrderId,
+    pub status: OrderStatus,
+    pub filled_size: Decimal,
+    /// Size-weighted average execution price, `None` if nothing was filled.
+    pub average_price: Option<Decimal>,
+    pub remaining_size: Decimal,
+    pub trades: Vec<Trade>,
+    /// Total taker fee charged on `trades`.
+    pub fee: Decimal,
+    /// Resting orders cancelled by self-trade prevention while matching.
+    pub self_trade_cancelled: Vec<OrderId>,
+    /// Why the order was rejected, or why its remainder was cancelled.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reason: Option<OrderBookError>,
+    /// When the book accepted the order, in milliseconds since the Unix epoch.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timestamp: u64,
+    /// Queue sequence of the order if it is left resting in the book.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sequence: Option<u64>,
+}
+
+impl ExecutionReport {
+    pub fn new(
+        order_id: OrderId,
+        status: OrderStatus,
+        trades: Vec<Trade>,
+        remaining_size: Decimal,
+    ) -> ExecutionReport {
+        let filled_size: Decimal = trades.iter().map(|trade| trade.size).sum();
+        let notional: Decimal = trades.iter().map(|trade| trade.price * trade.size).sum();
+        let average_price = if filled_size.is_zero() {
+            None
+        } else {
+            Some(notional / filled_size)
+        };
+        let fee = trades.iter().map(|trade| trade.taker_fee).sum();
+
+        ExecutionReport {
+            order_id,
+            status,
+            filled_size,
+            average_price,
+            remaining_size,
+            trades,
+            fee,
+            self_trade_cancelled: Vec::new(),
+            reason: None,
+            timestamp: 0,
+            sequence: None,
+        }
+    }
+
+    /// Report of an order refused without trading.
+    pub fn rejected(
+        order_id: OrderId,
+        remaining_size: Decimal,
+        reason: OrderBookError,
+    ) -> ExecutionReport {
+        let mut report =
+            ExecutionReport::new(order_id, OrderStatus::Rejected, Vec::new(), remaining_size);
+        report.reason = Some(reason);
+        report
+    }
+}
+
+/// One aggregated price level of a depth snapshot.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DepthLevel {
+    pub price: Decimal,
+    /// Displayed size; iceberg reserves and hidden orders are not included.
+    pub size: Decimal,
+    /// Number of displayed orders.
+    pub order_count: usize,
+}
+
+impl From<&Limit> for DepthLevel {
+    fn from(limit: &Limit) -> DepthLevel {
+        DepthLevel {
+            price: limit.price,
+            size: limit.total_volume(),
+            order_count: limit.displayed.len(),
+        }
+    }
+}
+
+/// The outcome of a hypothetical market order, from
+/// `OrderBook::cost_to_buy`, `OrderBook::cost_to_sell` and
+/// `OrderBook::estimate_fill`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FillEstimate {
+    /// How much would fill; less than asked when the side runs out.
+    pub size: Decimal,
+    /// Total price paid or received.
+    pub notional: Decimal,
+    /// `None` when nothing would fill.
+    pub average_price: Option<Decimal>,
+    /// Price of the last level reached.
+    pub worst_price: Option<Decimal>,
+}
+
+impl FillEstimate {
+    fn walk<'a>(levels: impl Iterator<Item = &'a Limit>, size: Decimal) -> FillEstimate {
+        let mut estimate = FillEstimate::default();
+        'levels: for limit in levels {
+            for order in limit.orders() {
+                let fill = (size - estimate.size).min(order.remaining_size());
+                if fill <= Decimal::ZERO {
+                    break 'levels;
+                }
+                estimate.size += fill;
+                estimate.notional += fill * limit.price;
+                estimate.worst_price = Some(limit.price);
+            }
+        }
+        if !estimate.size.is_zero() {
+            estimate.average_price = Some(estimate.notional / estimate.size);
+        }
+        estimate
+    }
+}
+
+/// Top-of-book L2 view returned by `OrderBook::depth`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DepthSnapshot {
+    /// Sequence of the last `BookUpdate` reflected in this snapshot.
+    pub sequence: u64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// A point-in-time copy of an `OrderBook`'s levels from
+/// `OrderBook::snapshot`. It shares the levels with the book rather than
+/// copying them, and is `Send`, so it can be handed to a market-data or
+/// analytics thread.
+#[derive(Debug, Clone)]
+pub struct BookSnapshot {
+    sequence: u64,
+    bids: Arc<BTreeMap<Decimal, Limit>>,
+    asks: Arc<BTreeMap<Decimal, Limit>>,
+}
+
+impl BookSnapshot {
+    /// Sequence of the last `BookUpdate` reflected in this snapshot.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.last_key_value().map(|(price, _)| *price)
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.first_key_value().map(|(price, _)| *price)
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        Some(self.best_ask()? - self.best_bid()?)
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        Some((self.best_ask()? + self.best_bid()?) / Decimal::TWO)
+    }
+
+    /// Bid levels from the best price down.
+    pub fn bid_levels(&self) -> impl Iterator<Item = &Limit> {
+        self.bids.values().rev()
+    }
+
+    /// Ask levels from the best price up.
+    pub fn ask_levels(&self) -> impl Iterator<Item = &Limit> {
+        self.asks.values()
+    }
+
+    /// The same view as `OrderBook::depth` at the time of the snapshot.
+    pub fn depth(&self, levels: usize) -> DepthSnapshot {
+        DepthSnapshot {
+            sequence: self.sequence,
+            bids: self
+                .bids
+                .values()
+                .rev()
+                .filter(|limit| limit.visible_size().is_some())
+                .take(levels)
+                .map(DepthLevel::from)
+                .collect(),
+            asks: self
+                .asks
+                .values()
+                .filter(|limit| limit.visible_size().is_some())
+                .take(levels)
+                .map(DepthLevel::from)
+                .collect(),
+        }
+    }
+}
+
+/// Incremental change to a price level. Sequence numbers increase by one
+/// per update, so a consumer applying updates on top of a `DepthSnapshot`
+/// can detect gaps and resynchronize. `size` is the level's new total
+/// displayed size.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BookUpdate {
+    /// A level was created or its size increased.
+    Add {
+        sequence: u64,
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+        size: Decimal,
+    },
+    /// A level's size decreased.
+    Reduce {
+        sequence: u64,
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+        size: Decimal,
+    },
+    /// A level was emptied and removed.
+    Remove {
+        sequence: u64,
+        bid_or_ask: BidOrAsk,
+        price: Decimal,
+    },
+}
+
+impl BookUpdate {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            BookUpdate::Add { sequence, .. }
+            | BookUpdate::Reduce { sequence, .. }
+            | BookUpdate::Remove { sequence, .. } => *sequence,
+        }
+    }
+}
+
+/// Result of matching an incoming order against one or more price levels.
+#[derive(Debug, Default, PartialEq)]
+pub struct MatchResult {
+    pub trades: Vec<Trade>,
+    /// Resting orders removed by self-trade prevention.
+    pub self_trade_cancelled: Vec<OrderId>,
+    /// Resting orders filled completely and removed from the book.
+    pub filled: Vec<OrderId>,
+    /// Self-trade prevention cancelled the rest of the incoming order.
+    pub taker_cancelled: bool,
+}
+
+/// The resting orders at one price. Displayed orders queue ahead of every
+/// hidden one; each kind is kept in arrival order.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "LimitRecord", into = "LimitRecord")
+)]
+pub struct Limit {
+    price: Decimal,
+    displayed: OrderQueue,
+    hidden: OrderQueue,
+}
+
+// How a price level is serialized: its orders in queue priority order.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct LimitRecord {
+    price: Decimal,
+    orders: Vec<Order>,
+}
+
+#[cfg(feature = "serde")]
+impl From<LimitRecord> for Limit {
+    fn from(record: LimitRecord) -> Limit {
+        let mut limit = Limit::new(record.price);
+        for order in record.orders {
+            limit.add_order(order);
+        }
+        limit
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Limit> for LimitRecord {
+    fn from(limit: Limit) -> LimitRecord {
+        LimitRecord {
+            price: limit.price,
+            orders: limit.orders().cloned().collect(),
+        }
+    }
+}
+
+impl Limit {
+    pub fn new(price: Decimal) -> Limit {
+        Limit {
+            price,
+            displayed: OrderQueue::new(),
+            hidden: OrderQueue::new(),
+        }
+    }
+
+    pub fn price(&self) -> Decimal {
+        self.price
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.displayed.len() + self.hidden.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.displayed.is_empty() && self.hidden.is_empty()
+    }
+
+    /// Resting orders in queue priority order, hidden orders included.
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.displayed.iter().chain(self.hidden.iter())
+    }
+
+    pub fn order(&self, id: OrderId) -> Option<&Order> {
+        self.displayed.get(id).or_else(|| self.hidden.get(id))
+    }
+
+    fn order_mut(&mut self, id: OrderId) -> Option<&mut Order> {
+        match self.displayed.get_mut(id) {
+            Some(order) => Some(order),
+            None => self.hidden.get_mut(id),
+        }
+    }
+
+    // The queue the next fill comes from: the displayed orders until there
+    // are none left.
+    fn queue_mut(&mut self) -> &mut OrderQueue {
+        match self.displayed.is_empty() {
+            true => &mut self.hidden,
+            false => &mut self.displayed,
+        }
+    }
+
+    // The order at the front of the queue.
+    fn front_mut(&mut self) -> Option<&mut Order> {
+        self.queue_mut().front_mut()
+    }
+
+    /// Displayed size at this level; iceberg reserves and hidden orders are
+    /// not counted.
+    pub fn total_volume(&self) -> Decimal {
+        self.displayed.iter().map(|order| order.size).sum()
+    }
+
+    // Displayed size, or `None` if the level shows nothing.
+    fn visible_size(&self) -> Option<Decimal> {
+        Some(self.total_volume()).filter(|size| !size.is_zero())
+    }
+
+    /// Size available for matching at this level, including iceberg reserves.
+    pub fn total_quantity(&self) -> Decimal {
+        self.orders().map(|order| order.remaining_size()).sum()
+    }
+
+    /// Fills `market_order` against the resting orders at this level in
+    /// arrival order, displayed orders before hidden ones. An iceberg order
+    /// whose displayed clip is consumed is replenished at the back of the
+    /// queue, taking the next sequence after `queue_sequence`. Resting orders
+    /// from the same owner are handled according to `self_trade_prevention`.
+    pub fn fill_order(
+        &mut self,
+        market_order: &mut Order,
+        self_trade_prevention: Option<SelfTradePrevention>,
+        queue_sequence: &mut u64,
+    ) -> MatchResult {
+        let mut result = MatchResult::default();
+        while !market_order.is_filled() {
+            let price = self.price;
+            let queue = self.queue_mut();
+            let Some(limit_order) = queue.front_mut() else {
+                break;
+            };
+            if let Some(mode) = self_trade_prevention {
+                if limit_order.owner == market_order.owner {
+                    if mode != SelfTradePrevention::CancelNewest {
+                        let maker = queue.pop_front().expect("front order exists");
+                        result.self_trade_cancelled.push(maker.id);
+                    }
+                    if mode != SelfTradePrevention::CancelOldest {
+                        result.taker_cancelled = true;
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let size = market_order.size.min(limit_order.size);
+            market_order.size -= size;
+            limit_order.size -= size;
+            result.trades.push(
+                Trade::new(
+                    limit_order.id,
+                    market_order.id,
+                    price,
+                    size,
+                    market_order.bid_or_ask,
+                )
+                .with_owners(limit_order.owner, market_order.owner),
+            );
+
+            if limit_order.replenish() {
+                let order = queue.pop_front().expect("front order exists");
+                self.requeue(order, queue_sequence);
+            } else if limit_order.is_filled() {
+                let order = queue.pop_front().expect("front order exists");
+                result.filled.push(order.id);
+            }
+        }
+        result
+    }
+
+    /// Fills `market_order` against this level pro rata: each round, every
+    /// resting order gets a share of the fill proportional to its displayed
+    /// size, rounded down to `lot_size`, and the remainder is handed out in
+    /// arrival order. Icebergs whose clip is consumed are replenished at the
+    /// back and take part in the next round. Hidden orders only share what
+    /// the displayed ones leave unfilled. If any resting order belongs
+    /// to the incoming order's owner, `self_trade_prevention` is applied to
+    /// them before anything trades.
+    pub fn fill_order_pro_rata(
+        &mut self,
+        market_order: &mut Order,
+        self_trade_prevention: Option<SelfTradePrevention>,
+        lot_size: Option<Decimal>,
+        queue_sequence: &mut u64,
+    ) -> MatchResult {
+        let mut result = MatchResult::default();
+        let owner = market_order.owner;
+        if let Some(mode) = self_trade_prevention {
+            if self.orders().any(|order| order.owner == owner) {
+                if mode != SelfTradePrevention::CancelNewest {
+                    for queue in [&mut self.displayed, &mut self.hidden] {
+                        let cancelled = queue.retain(|order| order.owner != owner);
+                        result
+                            .self_trade_cancelled
+                            .extend(cancelled.iter().map(|order| order.id));
+                    }
+                }
+                if mode != SelfTradePrevention::CancelOldest {
+                    result.taker_cancelled = true;
+                    return result;
+                }
+            }
+        }
+
+        while !market_order.is_filled() {
+            let price = self.price;
+            let queue = self.queue_mut();
+            let total: Decimal = queue.iter().map(|order| order.size).sum();
+            if total.is_zero() {
+                break;
+            }
+            let fill = market_order.size.min(total);
+            let lot = lot_size.unwrap_or_else(|| {
+                let scale = queue
+                    .iter()
+                    .map(|order| order.size.scale())
+                    .chain([fill.scale()])
+                    .max()
+                    .unwrap_or_default();
+                Decimal::new(1, scale)
+            });
+            let mut allocations: Vec<Decimal> = queue
+                .iter()
+                .map(|order| (fill * order.size / total / lot).floor() * lot)
+                .collect();
+            let mut remainder = fill - allocations.iter().sum::<Decimal>();
+            for (allocation, order) in allocations.iter_mut().zip(queue.iter()) {
+                let extra = remainder.min(order.size - *allocation);
+                *allocation += extra;
+                remainder -= extra;
+            }
+
+            market_order.size -= fill;
+            let mut allocations = allocations.into_iter();
+            queue.for_each_mut(|limit_order| {
+                let size = allocations.next().unwrap_or_default();
+                if size.is_zero() {
+                    return;
+                }
+                limit_order.size -= size;
+                result.trades.push(
+                    Trade::new(
+                        limit_order.id,
+                        market_order.id,
+                        price,
+                        size,
+                        market_order.bid_or_ask,
+                    )
+                    .with_owners(limit_order.owner, market_order.owner),
+                );
+            });
+            let mut replenished = Vec::new();
+            let done = queue.retain(|order| {
+                if order.replenish() {
+                    replenished.push(order.id);
+                }
+                !order.is_filled()
+            });
+            result.filled.extend(done.iter().map(|order| order.id));
+            let replenished: Vec<Order> = replenished
+                .into_iter()
+                .filter_map(|id| queue.remove(id))
+                .collect();
+            for order in replenished {
+                self.requeue(order, queue_sequence);
+            }
+        }
+        result
+    }
+
+    /// Queues `order` behind the orders already at this level; displayed
+    /// orders still go ahead of every hidden one.
+    pub fn add_order(&mut self, order: Order) {
+        match order.hidden {
+            true => self.hidden.push_back(order),
+            false => self.displayed.push_back(order),
+        }
+    }
+
+    // Puts an order back at the end of its queue with the next sequence.
+    fn requeue(&mut self, mut order: Order, queue_sequence: &mut u64) {
+        *queue_sequence += 1;
+        order.sequence = *queue_sequence;
+        self.add_order(order);
+    }
+
+    // Takes the order at the front of the queue.
+    fn pop_front(&mut self) -> Option<Order> {
+        self.queue_mut().pop_front()
+    }
+
+    pub fn remove_order(&mut self, id: OrderId) -> Option<Order> {
+        self.displayed.remove(id).or_else(|| self.hidden.remove(id))
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Order {
+    id: OrderId,
+    owner: AccountId,
+    // Milliseconds since the Unix epoch when the book accepted the order.
+    timestamp: u64,
+    // Position in arrival order among the orders queued in the book.
+    #[cfg_attr(feature = "serde", serde(default))]
+    sequence: u64,
+    // Rests without being displayed, behind the displayed orders at its price.
+    #[cfg_attr(feature = "serde", serde(default))]
+    hidden: bool,
+    // Displayed size; for iceberg orders the current clip.
+    size: Decimal,
+    // Hidden iceberg quantity not yet displayed.
+    reserve: Decimal,
+    display_size: Option<Decimal>,
+    post_only: Option<PostOnly>,
+    self_trade_prevention: Option<SelfTradePrevention>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    protection: Option<PriceProtection>,
+    client_order_id: Option<String>,
+    bid_or_ask: BidOrAsk,
+    time_in_force: TimeInForce,
+}
+
+impl Order {
+    pub fn new(bid_or_ask: BidOrAsk, size: Decimal) -> Order {
+        Order {
+            id: OrderId::default(),
+            owner: AccountId::default(),
+            timestamp: 0,
+            sequence: 0,
+            hidden: false,
+            size,
+            reserve: Decimal::ZERO,
+            display_size: None,
+            post_only: None,
+            self_trade_prevention: None,
+            protection: None,
+            client_order_id: None,
+            bid_or_ask,
+            time_in_force: TimeInForce::default(),
+        }
+    }
+
+    /// Turns the order into an iceberg that only displays `display_size` at a
+    /// time while resting. It still matches its full size when it arrives.
+    /// A non-positive `display_size` leaves the order fully displayed.
+    pub fn with_display_size(mut self, display_size: Decimal) -> Order {
+        self.display_size = Some(display_size).filter(|size| *size > Decimal::ZERO);
+        self
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Order {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    /// Marks a limit order as post-only: it may only ever rest as a maker.
+    pub fn with_post_only(mut self, post_only: PostOnly) -> Order {
+        self.post_only = Some(post_only);
+        self
+    }
+
+    /// Overrides the book's self-trade prevention mode for this order.
+    pub fn with_self_trade_prevention(mut self, mode: SelfTradePrevention) -> Order {
+        self.self_trade_prevention = Some(mode);
+        self
+    }
+
+    /// Limits how far a market order may sweep the book. Limit orders are
+    /// already bounded by their price and ignore it.
+    pub fn with_protection(mut self, protection: PriceProtection) -> Order {
+        self.protection = Some(protection);
+        self
+    }
+
+    /// Hides the order from depth, book updates and displayed volume while
+    /// it rests. It still matches, after the displayed orders at its price.
+    /// Hidden orders show nothing, so any display size is ignored.
+    pub fn with_hidden(mut self, hidden: bool) -> Order {
+        self.hidden = hidden;
+        self
+    }
+
+    pub fn with_owner(mut self, owner: AccountId) -> Order {
+        self.owner = owner;
+        self
+    }
+
+    /// Attaches a caller-chosen id used to detect duplicate submissions.
+    pub fn with_client_order_id(mut self, client_order_id: String) -> Order {
+        self.client_order_id = Some(client_order_id);
+        self
+    }
+
+    pub fn id(&self) -> OrderId {
+        self.id
+    }
+
+    pub fn owner(&self) -> AccountId {
+        self.owner
+    }
+
+    /// When the book accepted the order, in milliseconds since the Unix
+    /// epoch; 0 until placed. An amendment that loses queue priority counts
+    /// as a new acceptance.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Queue priority: the displayed orders at a price level, and the hidden
+    /// ones behind them, are each in increasing sequence order. A new
+    /// sequence is taken whenever the order joins the back of a level,
+    /// including after an amendment that loses priority or when an iceberg
+    /// reloads its clip; 0 until the order rests.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn client_order_id(&self) -> Option<&str> {
+        self.client_order_id.as_deref()
+    }
+
+    pub fn protection(&self) -> Option<PriceProtection> {
+        self.protection
+    }
+
+    pub fn size(&self) -> Decimal {
+        self.size
+    }
+
+    /// Displayed plus reserve size still to be filled.
+    pub fn remaining_size(&self) -> Decimal {
+        self.size + self.reserve
+    }
+
+    pub fn bid_or_ask(&self) -> BidOrAsk {
+        self.bid_or_ask
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn time_in_force(&self) -> TimeInForce {
+        self.time_in_force
+    }
+
+    pub fn is_filled(&self) -> bool {
+        self.size.is_zero() && self.reserve.is_zero()
+    }
+
+    // Loads the next clip from the reserve once the displayed size is used up.
+    fn replenish(&mut self) -> bool {
+        match self.display_size {
+            Some(display_size) if self.size.is_zero() && !self.reserve.is_zero() => {
+                self.size = self.reserve.min(display_size);
+                self.reserve -= self.size;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Sets the remaining size, trimming the reserve before the displayed size.
+    fn resize(&mut self, remaining_size: Decimal) {
+        if remaining_size <= self.size {
+            self.size = remaining_size;
+            self.reserve = Decimal::ZERO;
+        } else {
+            self.reserve = remaining_size - self.size;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_order_new() {
+        let order = Order::new(BidOrAsk::Bid, dec!(1.23456789));
+        assert_eq!(order.size, dec!(1.23456789));
+        assert_eq!(order.bid_or_ask, BidOrAsk::Bid);
+    }
+
+    #[test]
+    fn test_order_is_filled() {
+        let mut order = Order::new(BidOrAsk::Bid, dec!(1.23456789));
+        assert!(!order.is_filled());
+        order.size = Decimal::ZERO;
+        assert!(order.is_filled());
+
+        // Fills are exact: 0.1 and then 0.2 leave nothing of 0.3.
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(0.3)));
+        orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(0.1)));
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(0.2)));
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn test_limit_new() {
+        let price = dec!(1.23456789);
+        let limit = Limit::new(price);
+        assert_eq!(limit.price, price);
+    }
+
+    #[test]
+    fn test_orderbook_new() {
+        let orderbook = OrderBook::new();
+        assert_eq!(orderbook.bids.len(), 0);
+        assert_eq!(orderbook.asks.len(), 0);
+    }
+
+    #[test]
+    fn test_orderbook_fill_market_order_ask() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(500), Order::new(BidOrAsk::Ask, dec!(10.0)));
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0)));
+        orderbook.add_limit_order(dec!(200), Order::new(BidOrAsk::Ask, dec!(10.0)));
+        orderbook.add_limit_order(dec!(300), Order::new(BidOrAsk::Ask, dec!(10.0)));
+    }
+
+    #[test]
+    fn test_orderbook_fill_market_order_report() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(10.0)));
+        orderbook.add_limit_order(dec!(200), Order::new(BidOrAsk::Ask, dec!(10.0)));
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(15.0));
+        let report = orderbook.fill_market_order(&mut market_order);
+
+        assert_eq!(report.filled_size, dec!(15.0));
+        assert_eq!(report.remaining_size, dec!(0.0));
+        assert_eq!(
+            report.average_price.unwrap().round_dp(8),
+            dec!(133.33333333)
+        );
+    }
+
+    #[test]
+    fn test_orderbook_fill_market_order_no_liquidity() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(5.0)));
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(8.0));
+        let report = orderbook.fill_market_order(&mut market_order);
+
+        assert_eq!(report.filled_size, dec!(5.0));
+        assert_eq!(report.remaining_size, dec!(3.0));
+        assert_eq!(report.average_price, Some(dec!(100)));
+    }
+
+    #[test]
+    fn test_orderbook_add_limit_order_assigns_ids() {
+        let mut orderbook = OrderBook::new();
+        let first = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(1.0)))
+            .order_id;
+        let second = orderbook
+            .add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(1.0)))
+            .order_id;
+        assert_ne!(first, second);
+        assert_eq!(orderbook.order_index.len(), 2);
+    }
+
+    #[test]
+    fn test_orderbook_cancel_order() {
+        let mut orderbook = OrderBook::new();
+        let first = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(1.0)))
+            .order_id;
+        let second = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(2.0)))
+            .order_id;
+
+        let cancelled = orderbook.cancel_order(first).unwrap();
+        assert_eq!(cancelled.size, dec!(1.0));
+        assert_eq!(orderbook.bids[&dec!(100)].order_count(), 1);
+        assert!(orderbook.cancel_order(first).is_none());
+
+        orderbook.cancel_order(second).unwrap();
+        assert!(orderbook.bids.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_amend_order_size_down_keeps_priority() {
+        let mut orderbook = OrderBook::new();
+        let first = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(5.0)))
+            .order_id;
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(5.0)));
+
+        assert!(orderbook.amend_order(first, dec!(100), dec!(2.0)).is_ok());
+        let limit = &orderbook.bids[&dec!(100)];
+        assert_eq!(limit.orders().next().unwrap().id, first);
+        assert_eq!(limit.orders().next().unwrap().size, dec!(2.0));
+    }
+
+    #[test]
+    fn test_orderbook_amend_order_size_up_loses_priority() {
+        let mut orderbook = OrderBook::new();
+        let first = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(5.0)))
+            .order_id;
+        let second = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(5.0)))
+            .order_id;
+
+        assert!(orderbook.amend_order(first, dec!(100), dec!(8.0)).is_ok());
+        let limit = &orderbook.bids[&dec!(100)];
+        assert_eq!(limit.orders().next().unwrap().id, second);
+        assert_eq!(limit.orders().nth(1).unwrap().id, first);
+        assert_eq!(limit.orders().nth(1).unwrap().size, dec!(8.0));
+    }
+
+    #[test]
+    fn test_orderbook_amend_order_price_change() {
+        let mut orderbook = OrderBook::new();
+        let id = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(5.0)))
+            .order_id;
+
+        assert!(orderbook.amend_order(id, dec!(101), dec!(5.0)).is_ok());
+        assert!(!orderbook.asks.contains_key(&dec!(100)));
+        assert_eq!(orderbook.asks[&dec!(101)].orders().next().unwrap().id, id);
+        assert_eq!(orderbook.order_index[&id], (BidOrAsk::Ask, dec!(101)));
+        assert_eq!(
+            orderbook.amend_order(OrderId(42), dec!(101), dec!(1.0)),
+            Err(OrderBookError::UnknownOrderId(OrderId(42)))
+        );
+        assert_eq!(
+            orderbook.amend_order(id, dec!(101), Decimal::ZERO),
+            Err(OrderBookError::InvalidSize(Decimal::ZERO))
+        );
+
+        // An amend the book would reject leaves the order where it was.
+        orderbook.price_band_mut().set_percent(Some(dec!(10)));
+        orderbook.price_band_mut().set_reference(dec!(100));
+        assert!(matches!(
+            orderbook.amend_order(id, dec!(120), dec!(5.0)),
+            Err(OrderBookError::OutsidePriceBand { .. })
+        ));
+        assert_eq!(orderbook.order(id).unwrap().size, dec!(5.0));
+        assert_eq!(orderbook.order_index[&id], (BidOrAsk::Ask, dec!(101)));
+    }
+
+    #[test]
+    fn test_orderbook_fill_market_order_trades() {
+        let mut orderbook = OrderBook::new();
+        let maker = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(4.0)))
+            .order_id;
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(3.0));
+        let report = orderbook.fill_market_order(&mut market_order);
+
+        assert_eq!(report.trades.len(), 1);
+        let trade = &report.trades[0];
+        assert_eq!(trade.maker_order_id, maker);
+        assert_eq!(trade.taker_order_id, report.order_id);
+        assert_eq!(trade.price, dec!(100));
+        assert_eq!(trade.size, dec!(3.0));
+    }
+
+    #[test]
+    fn test_orderbook_add_limit_order_crosses_book() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(2.0)));
+        orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Ask, dec!(2.0)));
+
+        let report = orderbook.add_limit_order(dec!(102), Order::new(BidOrAsk::Bid, dec!(5.0)));
+
+        assert_eq!(report.trades.len(), 1);
+        assert_eq!(report.trades[0].price, dec!(100));
+        assert_eq!(report.filled_size, dec!(2.0));
+        assert_eq!(report.remaining_size, dec!(3.0));
+        assert_eq!(orderbook.bids[&dec!(102)].total_volume(), dec!(3.0));
+        assert_eq!(orderbook.asks[&dec!(105)].total_volume(), dec!(2.0));
+    }
+
+    #[test]
+    fn test_orderbook_limits_are_sorted() {
+        let mut orderbook = OrderBook::new();
+        for price in [dec!(300), dec!(100), dec!(200)] {
+            orderbook.add_limit_order(price, Order::new(BidOrAsk::Ask, dec!(1.0)));
+            orderbook.add_limit_order(price / dec!(10), Order::new(BidOrAsk::Bid, dec!(1.0)));
+        }
+
+        let ask_prices: Vec<Decimal> = orderbook.ask_limits().iter().map(|l| l.price).collect();
+        assert_eq!(ask_prices, vec![dec!(100), dec!(200), dec!(300)]);
+        let bid_prices: Vec<Decimal> = orderbook.bid_limits().iter().map(|l| l.price).collect();
+        assert_eq!(bid_prices, vec![dec!(30), dec!(20), dec!(10)]);
+    }
+
+    #[test]
+    fn test_orderbook_removes_filled_orders_and_empty_levels() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)));
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)));
+        let partial = orderbook
+            .add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(2)))
+            .order_id;
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(2)));
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(3));
+        orderbook.fill_market_order(&mut market_order);
+
+        assert!(!orderbook.asks.contains_key(&dec!(100)));
+        let limit = &orderbook.asks[&dec!(101)];
+        assert_eq!(limit.order_count(), 2);
+        assert_eq!(limit.orders().next().unwrap().id, partial);
+        assert_eq!(limit.total_volume(), dec!(3));
+        assert_eq!(orderbook.order_index.len(), 2);
+
+        let report = orderbook.add_limit_order(dec!(90), Order::new(BidOrAsk::Ask, dec!(10)));
+        assert_eq!(report.remaining_size, dec!(10));
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(13));
+        orderbook.fill_market_order(&mut market_order);
+        assert!(orderbook.asks.is_empty());
+        assert!(orderbook.order_index.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_cancel_filled_order() {
+        let mut orderbook = OrderBook::new();
+        let maker = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(1)))
+            .order_id;
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)));
+
+        assert!(orderbook.bids.is_empty());
+        assert!(orderbook.asks.is_empty());
+        assert!(orderbook.cancel_order(maker).is_none());
+    }
+
+    #[test]
+    fn test_orderbook_limit_order_status() {
+        let mut orderbook = OrderBook::new();
+        let report = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(2)));
+        assert_eq!(report.status, OrderStatus::New);
+
+        let report = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(3)));
+        assert_eq!(report.status, OrderStatus::PartiallyFilled);
+
+        let report = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)));
+        assert_eq!(report.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn test_orderbook_immediate_or_cancel() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(2)));
+
+        let order =
+            Order::new(BidOrAsk::Bid, dec!(5)).with_time_in_force(TimeInForce::ImmediateOrCancel);
+        let report = orderbook.add_limit_order(dec!(100), order);
+
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.filled_size, dec!(2));
+        assert_eq!(report.remaining_size, dec!(3));
+        assert!(orderbook.bids.is_empty());
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_fill_or_kill() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(2)));
+        orderbook.add_limit_order(dec!(102), Order::new(BidOrAsk::Ask, dec!(2)));
+
+        let order = Order::new(BidOrAsk::Bid, dec!(3)).with_time_in_force(TimeInForce::FillOrKill);
+        let report = orderbook.add_limit_order(dec!(101), order);
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert!(report.trades.is_empty());
+        assert_eq!(orderbook.asks[&dec!(100)].total_volume(), dec!(2));
+
+        let order = Order::new(BidOrAsk::Bid, dec!(3)).with_time_in_force(TimeInForce::FillOrKill);
+        let report = orderbook.add_limit_order(dec!(102), order);
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert_eq!(report.trades.len(), 2);
+        assert!(orderbook.bids.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_iceberg_order_displays_clip() {
+        let mut orderbook = OrderBook::new();
+        let iceberg = Order::new(BidOrAsk::Ask, dec!(10)).with_display_size(dec!(3));
+        let report = orderbook.add_limit_order(dec!(100), iceberg);
+        assert_eq!(report.remaining_size, dec!(10));
+
+        let limit = &orderbook.asks[&dec!(100)];
+        assert_eq!(limit.total_volume(), dec!(3));
+        assert_eq!(limit.total_quantity(), dec!(10));
+    }
+
+    #[test]
+    fn test_orderbook_iceberg_order_replenishes_at_back() {
+        let mut orderbook = OrderBook::new();
+        let iceberg = Order::new(BidOrAsk::Ask, dec!(5)).with_display_size(dec!(2));
+        let iceberg_id = orderbook.add_limit_order(dec!(100), iceberg).order_id;
+        let other_id = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .order_id;
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(2));
+        let report = orderbook.fill_market_order(&mut market_order);
+        assert_eq!(report.trades.len(), 1);
+        assert_eq!(report.trades[0].maker_order_id, iceberg_id);
+
+        let limit = &orderbook.asks[&dec!(100)];
+        assert_eq!(limit.orders().next().unwrap().id, other_id);
+        assert_eq!(limit.orders().nth(1).unwrap().id, iceberg_id);
+        assert_eq!(limit.total_volume(), dec!(3));
+
+        // A large taker sweeps every clip of the iceberg in one pass.
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(10));
+        let report = orderbook.fill_market_order(&mut market_order);
+        assert_eq!(report.filled_size, dec!(4));
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_queue_sequence_follows_arrival() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_time(Some(1_000));
+        let first = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(5)));
+        let iceberg = orderbook.add_limit_order(
+            dec!(100),
+            Order::new(BidOrAsk::Bid, dec!(4)).with_display_size(dec!(2)),
+        );
+        let third = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(5)));
+        assert_eq!(first.timestamp, 1_000);
+        assert_eq!(
+            [first.sequence, iceberg.sequence, third.sequence],
+            [Some(1), Some(2), Some(3)]
+        );
+
+        // Growing the first order sends it behind the third, as a new arrival.
+        orderbook.set_time(Some(2_000));
+        let amended = orderbook
+            .amend_order(first.order_id, dec!(100), dec!(6))
+            .unwrap();
+        assert_eq!(amended.timestamp, 2_000);
+        assert_eq!(amended.sequence, Some(4));
+        // Shrinking keeps both.
+        let amended = orderbook
+            .amend_order(third.order_id, dec!(100), dec!(4))
+            .unwrap();
+        assert_eq!((amended.timestamp, amended.sequence), (1_000, Some(3)));
+
+        // The iceberg's first clip fills and its reload joins the back.
+        orderbook.fill_market_order(&mut Order::new(BidOrAsk::Ask, dec!(2)));
+        let queue: Vec<(OrderId, u64)> = orderbook
+            .bid_levels()
+            .flat_map(Limit::orders)
+            .map(|order| (order.id(), order.sequence()))
+            .collect();
+        assert_eq!(
+            queue,
+            vec![
+                (third.order_id, 3),
+                (first.order_id, 4),
+                (iceberg.order_id, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_orderbook_hidden_orders_match_behind_displayed() {
+        let mut orderbook = OrderBook::new();
+        let hidden = orderbook.add_limit_order(
+            dec!(100),
+            Order::new(BidOrAsk::Ask, dec!(2)).with_hidden(true),
+        );
+        orderbook.add_limit_order(
+            dec!(101),
+            Order::new(BidOrAsk::Ask, dec!(1)).with_hidden(true),
+        );
+        assert!(orderbook.drain_updates().is_empty());
+        let displayed = orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)));
+
+        let depth = orderbook.depth(10);
+        assert_eq!(depth.asks.len(), 1);
+        assert_eq!(
+            (depth.asks[0].size, depth.asks[0].order_count),
+            (dec!(1), 1)
+        );
+        assert_eq!(orderbook.asks[&dec!(100)].total_quantity(), dec!(3));
+
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(2)));
+        let makers: Vec<OrderId> = report
+            .trades
+            .iter()
+            .map(|trade| trade.maker_order_id)
+            .collect();
+        assert_eq!(makers, vec![displayed.order_id, hidden.order_id]);
+        // Only hidden size is left, so the level disappears from the feed.
+        assert!(orderbook.depth(10).asks.is_empty());
+        assert_eq!(
+            orderbook.drain_updates().last(),
+            Some(&BookUpdate::Remove {
+                sequence: 2,
+                bid_or_ask: BidOrAsk::Ask,
+                price: dec!(100),
+            })
+        );
+        assert_eq!(orderbook.best_ask(), Some(dec!(100)));
+    }
+
+    #[test]
+    fn test_orderbook_market_order_policy() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(
+            dec!(100),
+            Order::new(BidOrAsk::Ask, dec!(1)).with_owner(AccountId(1)),
+        );
+        orderbook.add_limit_order(
+            dec!(101),
+            Order::new(BidOrAsk::Ask, dec!(1)).with_owner(AccountId(2)),
+        );
+
+        orderbook.set_market_order_policy(MarketOrderPolicy::Reject);
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(3)));
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert_eq!(
+            report.reason,
+            Some(OrderBookError::InsufficientLiquidity {
+                size: dec!(3),
+                available: dec!(2),
+            })
+        );
+        assert!(report.trades.is_empty());
+        assert_eq!(orderbook.total_liquidity(BidOrAsk::Bid), dec!(2));
+
+        // Enough is resting, but self-trade prevention would stop account 2
+        // at its own order, so all-or-none refuses it.
+        orderbook.set_market_order_policy(MarketOrderPolicy::AllOrNone);
+        let mut own = Order::new(BidOrAsk::Bid, dec!(2))
+            .with_owner(AccountId(2))
+            .with_self_trade_prevention(SelfTradePrevention::CancelNewest);
+        let report = orderbook.fill_market_order(&mut own);
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert_eq!(orderbook.total_liquidity(BidOrAsk::Bid), dec!(2));
+
+        orderbook.set_market_order_policy(MarketOrderPolicy::CancelRemainder);
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(3)));
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.filled_size, dec!(2));
+        assert_eq!(
+            report.reason,
+            Some(OrderBookError::InsufficientLiquidity {
+                size: dec!(3),
+                available: dec!(2),
+            })
+        );
+    }
+
+    #[test]
+    fn test_orderbook_market_order_price_protection() {
+        let mut orderbook = OrderBook::new();
+        for price in [dec!(100), dec!(101), dec!(105)] {
+            orderbook.add_limit_order(price, Order::new(BidOrAsk::Ask, dec!(1)));
+        }
+        let mut order =
+            Order::new(BidOrAsk::Bid, dec!(3)).with_protection(PriceProtection::Price(dec!(101)));
+        let report = orderbook.fill_market_order(&mut order);
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.filled_size, dec!(2));
+        assert_eq!(
+            report.reason,
+            Some(OrderBookError::ProtectionPriceReached(dec!(101)))
+        );
+        assert_eq!(orderbook.best_ask(), Some(dec!(105)));
+
+        // The mid is 101.5, so 2% slippage keeps a sell at 99.47 or above.
+        orderbook.add_limit_order(dec!(98), Order::new(BidOrAsk::Bid, dec!(1)));
+        let mut order =
+            Order::new(BidOrAsk::Ask, dec!(1)).with_protection(PriceProtection::Percent(dec!(2)));
+        let report = orderbook.fill_market_order(&mut order);
+        assert!(report.trades.is_empty());
+        assert_eq!(
+            report.reason,
+            Some(OrderBookError::ProtectionPriceReached(dec!(99.47)))
+        );
+        assert_eq!(orderbook.best_bid(), Some(dec!(98)));
+    }
+
+    #[test]
+    fn test_orderbook_auction_uncrosses_at_equilibrium() {
+        let mut orderbook = OrderBook::new();
+        orderbook.start_auction();
+        for (bid_or_ask, price, size) in [
+            (BidOrAsk::Bid, dec!(101), dec!(3)),
+            (BidOrAsk::Bid, dec!(100), dec!(2)),
+            (BidOrAsk::Ask, dec!(99), dec!(2)),
+            (BidOrAsk::Ask, dec!(100), dec!(4)),
+        ] {
+            let report = orderbook.add_limit_order(price, Order::new(bid_or_ask, size));
+            assert_eq!(report.status, OrderStatus::New);
+        }
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(1)));
+        assert_eq!(report.status, OrderStatus::Rejected);
+
+        // At 100, 5 of the 6 offered trade; 101 and 99 match less.
+        assert_eq!(orderbook.equilibrium(), Some((dec!(100), dec!(5))));
+        let result = orderbook.uncross();
+        assert!(!orderbook.in_auction());
+        assert_eq!(result.price, Some(dec!(100)));
+        let sizes: Vec<Decimal> = result.trades.iter().map(|trade| trade.size).collect();
+        assert_eq!(sizes, vec![dec!(2), dec!(1), dec!(2)]);
+        assert!(result.trades.iter().all(|trade| trade.price == dec!(100)));
+        // The ask at 99 arrived after the bid at 101, so it is the taker.
+        assert_eq!(result.trades[0].taker_side, BidOrAsk::Ask);
+        assert_eq!(orderbook.best_bid(), None);
+        assert_eq!(orderbook.best_ask(), Some(dec!(100)));
+        assert_eq!(orderbook.equilibrium(), None);
+    }
+
+    #[test]
+    fn test_orderbook_pro_rata_allocation() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_matching_algorithm(MatchingAlgorithm::ProRata);
+        let ids: Vec<OrderId> = [dec!(6), dec!(3), dec!(1)]
+            .into_iter()
+            .map(|size| {
+                orderbook
+                    .add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, size))
+                    .order_id
+            })
+            .collect();
+        let fills = |report: &ExecutionReport| -> Vec<(OrderId, Decimal)> {
+            report
+                .trades
+                .iter()
+                .map(|trade| (trade.maker_order_id, trade.size))
+                .collect()
+        };
+
+        // Shares of 3, 1.5 and 0.5 round down to whole units; the unit left
+        // over goes to the oldest order.
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(5)));
+        assert_eq!(fills(&report), vec![(ids[0], dec!(4)), (ids[1], dec!(1))]);
+
+        orderbook.set_lot_size(Some(dec!(0.5)));
+        let report = orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(4)));
+        assert_eq!(
+            fills(&report),
+            vec![(ids[0], dec!(2)), (ids[1], dec!(1.5)), (ids[2], dec!(0.5))]
+        );
+        assert_eq!(orderbook.asks[&dec!(100)].total_volume(), dec!(1));
+    }
+
+    #[test]
+    fn test_orderbook_post_only_reject() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(1)));
+
+        let order = Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Reject);
+        let report = orderbook.add_limit_order(dec!(100), order);
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert!(orderbook.bids.is_empty());
+
+        let order = Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Reject);
+        let report = orderbook.add_limit_order(dec!(99), order);
+        assert_eq!(report.status, OrderStatus::New);
+    }
+
+    #[test]
+    fn test_orderbook_post_only_slide() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100.50), Order::new(BidOrAsk::Ask, dec!(1)));
+
+        let order = Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Slide);
+        let report = orderbook.add_limit_order(dec!(101), order);
+        assert_eq!(report.status, OrderStatus::New);
+        assert!(report.trades.is_empty());
+        assert_eq!(orderbook.bid_limits()[0].price, dec!(100.49));
+
+        // A slide to a price that is not positive or outside the band is
+        // rejected rather than resting there.
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(1), Order::new(BidOrAsk::Ask, dec!(1)));
+        let order = Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Slide);
+        let report = orderbook.add_limit_order(dec!(5), order);
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert!(orderbook.bids.is_empty());
+
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(90), Order::new(BidOrAsk::Ask, dec!(1)));
+        orderbook.price_band_mut().set_percent(Some(dec!(10)));
+        orderbook.price_band_mut().set_reference(dec!(100));
+        let order = Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Slide);
+        let report = orderbook.add_limit_order(dec!(95), order);
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert!(orderbook.bids.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_post_only_amend() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Ask, dec!(1)));
+        let reject = orderbook
+            .add_limit_order(
+                dec!(100),
+                Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Reject),
+            )
+            .order_id;
+        let slide = orderbook
+            .add_limit_order(
+                dec!(99),
+                Order::new(BidOrAsk::Bid, dec!(1)).with_post_only(PostOnly::Slide),
+            )
+            .order_id;
+
+        // Crossing is refused and the bid keeps resting where it was.
+        assert_eq!(
+            orderbook.amend_order(reject, dec!(106), dec!(1)),
+            Err(OrderBookError::PostOnlyWouldCross {
+                price: dec!(106),
+                best: dec!(105),
+            })
+        );
+        assert_eq!(orderbook.order_price(reject), Some(dec!(100)));
+
+        // A sliding bid moves to just below the ask instead of trading.
+        let report = orderbook.amend_order(slide, dec!(106), dec!(2)).unwrap();
+        assert_eq!(report.status, OrderStatus::New);
+        assert!(report.trades.is_empty());
+        assert_eq!(orderbook.order_price(slide), Some(dec!(104)));
+    }
+
+    fn owned_order(bid_or_ask: BidOrAsk, size: Decimal, owner: u64) -> Order {
+        Order::new(bid_or_ask, size).with_owner(AccountId(owner))
+    }
+
+    #[test]
+    fn test_orderbook_self_trade_allowed_by_default() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(100), owned_order(BidOrAsk::Ask, dec!(1), 1));
+        let report = orderbook.add_limit_order(dec!(100), owned_order(BidOrAsk::Bid, dec!(1), 1));
+        assert_eq!(report.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn test_orderbook_self_trade_cancel_newest() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest));
+        orderbook.add_limit_order(dec!(100), owned_order(BidOrAsk::Ask, dec!(1), 2));
+        let own = orderbook
+            .add_limit_order(dec!(100), owned_order(BidOrAsk::Ask, dec!(1), 1))
+            .order_id;
+
+        // Fill-or-kill counts only what it could fill before its own order.
+        let order =
+            owned_order(BidOrAsk::Bid, dec!(2), 1).with_time_in_force(TimeInForce::FillOrKill);
+        let report = orderbook.add_limit_order(dec!(100), order);
+        assert_eq!(report.status, OrderStatus::Rejected);
+        assert!(report.trades.is_empty());
+
+        let report = orderbook.add_limit_order(dec!(100), owned_order(BidOrAsk::Bid, dec!(3), 1));
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.filled_size, dec!(1));
+        assert!(orderbook.contains_order(own));
+        assert!(orderbook.bids.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_self_trade_cancel_oldest() {
+        let mut orderbook = OrderBook::new();
+        let own = orderbook
+            .add_limit_order(dec!(100), owned_order(BidOrAsk::Ask, dec!(1), 1))
+            .order_id;
+        orderbook.add_limit_order(dec!(101), owned_order(BidOrAsk::Ask, dec!(1), 2));
+
+        let order = owned_order(BidOrAsk::Bid, dec!(2), 1)
+            .with_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        let report = orderbook.add_limit_order(dec!(101), order);
+        assert_eq!(report.self_trade_cancelled, vec![own]);
+        assert_eq!(report.filled_size, dec!(1));
+        assert_eq!(report.status, OrderStatus::PartiallyFilled);
+        assert!(!orderbook.contains_order(own));
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_self_trade_cancel_both() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_self_trade_prevention(Some(SelfTradePrevention::CancelBoth));
+        let own = orderbook
+            .add_limit_order(dec!(100), owned_order(BidOrAsk::Ask, dec!(1), 1))
+            .order_id;
+
+        let mut market_order = owned_order(BidOrAsk::Bid, dec!(1), 1);
+        let report = orderbook.fill_market_order(&mut market_order);
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.self_trade_cancelled, vec![own]);
+        assert!(report.trades.is_empty());
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_best_prices() {
+        let mut orderbook = OrderBook::new();
+        assert_eq!(orderbook.best_bid(), None);
+        assert_eq!(orderbook.spread(), None);
+
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(1)));
+        orderbook.add_limit_order(dec!(98), Order::new(BidOrAsk::Bid, dec!(1)));
+        assert_eq!(orderbook.best_bid(), Some(dec!(99)));
+        assert_eq!(orderbook.mid_price(), None);
+
+        orderbook.add_limit_order(dec!(102), Order::new(BidOrAsk::Ask, dec!(1)));
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(1)));
+        assert_eq!(orderbook.best_ask(), Some(dec!(101)));
+        assert_eq!(orderbook.spread(), Some(dec!(2)));
+        assert_eq!(orderbook.mid_price(), Some(dec!(100)));
+    }
+
+    #[test]
+    fn test_orderbook_depth() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(1)));
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(2)));
+        orderbook.add_limit_order(dec!(98), Order::new(BidOrAsk::Bid, dec!(5)));
+        orderbook.add_limit_order(dec!(97), Order::new(BidOrAsk::Bid, dec!(5)));
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(4)));
+
+        let depth = orderbook.depth(2);
+        assert_eq!(
+            depth.bids,
+            vec![
+                DepthLevel {
+                    price: dec!(99),
+                    size: dec!(3),
+                    order_count: 2,
+                },
+                DepthLevel {
+                    price: dec!(98),
+                    size: dec!(5),
+                    order_count: 1,
+                },
+            ]
+        );
+        assert_eq!(depth.asks.len(), 1);
+        assert_eq!(depth.asks[0].size, dec!(4));
+    }
+
+    #[test]
+    fn test_orderbook_l3_view() {
+        let mut orderbook = OrderBook::new();
+        let first = orderbook
+            .add_limit_order(dec!(99), owned_order(BidOrAsk::Bid, dec!(1), 7))
+            .order_id;
+        let second = orderbook
+            .add_limit_order(dec!(99), owned_order(BidOrAsk::Bid, dec!(2), 8))
+            .order_id;
+        orderbook.add_limit_order(dec!(98), owned_order(BidOrAsk::Bid, dec!(3), 7));
+
+        let levels: Vec<&Limit> = orderbook.bid_levels().collect();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[0].price(), dec!(99));
+
+        let queue: Vec<(OrderId, AccountId, Decimal)> = levels[0]
+            .orders()
+            .map(|order| (order.id(), order.owner(), order.remaining_size()))
+            .collect();
+        assert_eq!(
+            queue,
+            vec![
+                (first, AccountId(7), dec!(1)),
+                (second, AccountId(8), dec!(2))
+            ]
+        );
+        assert!(levels[0].orders().all(|order| order.timestamp() > 0));
+        assert_eq!(orderbook.ask_levels().count(), 0);
+    }
+
+    #[test]
+    fn test_orderbook_book_updates() {
+        let mut orderbook = OrderBook::new();
+        let id = orderbook
+            .add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(2)))
+            .order_id;
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Ask, dec!(3)));
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(1));
+        orderbook.fill_market_order(&mut market_order);
+        orderbook.cancel_order(id);
+
+        let ask = BidOrAsk::Ask;
+        let price = dec!(100);
+        assert_eq!(
+            orderbook.drain_updates(),
+            vec![
+                BookUpdate::Add {
+                    sequence: 1,
+                    bid_or_ask: ask,
+                    price,
+                    size: dec!(2),
+                },
+                BookUpdate::Add {
+                    sequence: 2,
+                    bid_or_ask: ask,
+                    price,
+                    size: dec!(5),
+                },
+                BookUpdate::Reduce {
+                    sequence: 3,
+                    bid_or_ask: ask,
+                    price,
+                    size: dec!(4),
+                },
+                BookUpdate::Reduce {
+                    sequence: 4,
+                    bid_or_ask: ask,
+                    price,
+                    size: dec!(3),
+                },
+            ]
+        );
+
+        let mut market_order = Order::new(BidOrAsk::Bid, dec!(3));
+        orderbook.fill_market_order(&mut market_order);
+        assert_eq!(
+            orderbook.drain_updates(),
+            vec![BookUpdate::Remove {
+                sequence: 5,
+                bid_or_ask: ask,
+                price,
+            }]
+        );
+        assert_eq!(orderbook.depth(1).sequence, 5);
+        assert!(orderbook.drain_updates().is_empty());
+    }
+
+    #[test]
+    fn test_orderbook_imbalance_and_volume_to_move() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(3)));
+        orderbook.add_limit_order(dec!(90), Order::new(BidOrAsk::Bid, dec!(50)));
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(1)));
+        orderbook.add_limit_order(dec!(102), Order::new(BidOrAsk::Ask, dec!(2)));
+        orderbook.add_limit_order(dec!(105), Order::new(BidOrAsk::Ask, dec!(4)));
+
+        // Within 2 of the mid (100): 3 bid against 3 ask.
+        assert_eq!(orderbook.imbalance(dec!(2)), Some(dec!(0)));
+        assert_eq!(orderbook.imbalance(dec!(9)), Some(dec!(-0.4)));
+
+        assert_eq!(orderbook.volume_to_move(BidOrAsk::Bid, dec!(1)), dec!(1));
+        assert_eq!(orderbook.volume_to_move(BidOrAsk::Bid, dec!(4.5)), dec!(7));
+        assert_eq!(orderbook.volume_to_move(BidOrAsk::Ask, dec!(5)), dec!(3));
+    }
+
+    #[test]
+    fn test_orderbook_cost_to_fill_and_volume_within() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(3)));
+        orderbook.add_limit_order(dec!(97), Order::new(BidOrAsk::Bid, dec!(5)));
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(1)));
+        orderbook.add_limit_order(dec!(102), Order::new(BidOrAsk::Ask, dec!(2)));
+
+        let buy = orderbook.cost_to_buy(dec!(2));
+        assert_eq!(buy.size, dec!(2));
+        assert_eq!(buy.notional, dec!(203));
+        assert_eq!(buy.average_price, Some(dec!(101.5)));
+        assert_eq!(buy.worst_price, Some(dec!(102)));
+        // Asking for more than rests fills what there is.
+        assert_eq!(orderbook.cost_to_buy(dec!(10)).size, dec!(3));
+        assert_eq!(orderbook.cost_to_sell(dec!(4)).notional, dec!(394));
+        assert_eq!(
+            OrderBook::new().cost_to_sell(dec!(1)),
+            FillEstimate::default()
+        );
+        assert_eq!(orderbook.best_ask(), Some(dec!(101)));
+
+        assert_eq!(orderbook.volume_within(1), None);
+        orderbook.set_tick_size(Some(dec!(0.5)));
+        assert_eq!(orderbook.volume_within(2), Some((dec!(3), dec!(1))));
+        assert_eq!(orderbook.volume_within(6), Some((dec!(8), dec!(3))));
+    }
+
+    #[test]
+    fn test_orderbook_validate_reports_violations() {
+        let mut orderbook = OrderBook::new();
+        let bid = orderbook
+            .add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(1)))
+            .order_id;
+        let ask = orderbook
+            .add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(1)))
+            .order_id;
+        assert_eq!(orderbook.validate(), Ok(()));
+
+        // Corrupt the book behind its back.
+        let mut crossing = orderbook
+            .limits_mut(BidOrAsk::Bid)
+            .remove(&dec!(99))
+            .unwrap();
+        crossing.price = dec!(102);
+        orderbook
+            .limits_mut(BidOrAsk::Bid)
+            .insert(dec!(102), crossing);
+        orderbook
+            .limits_mut(BidOrAsk::Ask)
+            .insert(dec!(103), Limit::new(dec!(103)));
+        orderbook
+            .order_index
+            .insert(OrderId(42), (BidOrAsk::Ask, dec!(101)));
+        let violations = orderbook.validate().unwrap_err();
+        assert_eq!(violations.len(), 5);
+        for violation in [
+            BookViolation::Crossed {
+                best_bid: dec!(102),
+                best_ask: dec!(101),
+            },
+            BookViolation::UnindexedOrder(bid),
+            BookViolation::EmptyLevel {
+                bid_or_ask: BidOrAsk::Ask,
+                price: dec!(103),
+            },
+            BookViolation::StaleIndexEntry(OrderId(42)),
+            // Its index entry still points at the level's old price.
+            BookViolation::StaleIndexEntry(bid),
+        ] {
+            assert!(violations.contains(&violation), "{:?}", violation);
+        }
+        assert!(orderbook.order(ask).is_some());
+        // A crossed book is expected while orders collect for an auction.
+        orderbook.auction = true;
+        assert!(!orderbook
+            .validate()
+            .unwrap_err()
+            .contains(&BookViolation::Crossed {
+                best_bid: dec!(102),
+                best_ask: dec!(101)
+            }));
+    }
+
+    #[test]
+    fn test_orderbook_snapshot_is_unaffected_by_later_changes() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_limit_order(dec!(99), Order::new(BidOrAsk::Bid, dec!(2)));
+        orderbook.add_limit_order(dec!(101), Order::new(BidOrAsk::Ask, dec!(3)));
+        let snapshot = orderbook.snapshot();
+        assert!(Arc::ptr_eq(&snapshot.bids, &orderbook.bids));
+
+        // A resting bid copies only the bid side.
+        orderbook.add_limit_order(dec!(100), Order::new(BidOrAsk::Bid, dec!(1)));
+        assert!(!Arc::ptr_eq(&snapshot.bids, &orderbook.bids));
+        assert!(Arc::ptr_eq(&snapshot.asks, &orderbook.asks));
+        orderbook.fill_market_order(&mut Order::new(BidOrAsk::Bid, dec!(3)));
+
+        let reader = std::thread::spawn(move || (snapshot.depth(5), snapshot.mid_price()));
+        let (depth, mid_price) = reader.join().unwrap();
+        assert_eq!(depth.bids.len(), 1);
+        assert_eq!(depth.asks[0].size, dec!(3));
+        assert_eq!(mid_price, Some(dec!(100)));
+        assert_eq!(orderbook.best_bid(), Some(dec!(100)));
+        assert_eq!(orderbook.best_ask(), None);
+        assert_eq!(orderbook.depth(5), orderbook.snapshot().depth(5));
     }
 }
@@ -0,0 +1,539 @@
+#![allow(dead_code)]
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use rust_decimal::prelude::*;
+
+use super::accounts::{Accounts, Balance, Reservation};
+use super::clock::{system_clock, Clock};
+use super::engine::TradingPair;
+use super::error::{EngineError, OrderBookError};
+use super::fees::{FeeSchedule, Fees};
+use super::orderbook::{AccountId, BidOrAsk, ExecutionReport, Order, OrderId, OrderStatus};
+use super::trade::Trade;
+
+/// Counterparty of every simulated fill: the external market's liquidity.
+pub const EXTERNAL_ORDER: OrderId = OrderId(0);
+
+/// A simulated order resting against an external market.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperOrder {
+    pub order_id: OrderId,
+    pub owner: AccountId,
+    pub bid_or_ask: BidOrAsk,
+    pub price: Decimal,
+    pub remaining_size: Decimal,
+    /// External size estimated to be queued in front of the order at its
+    /// price, which has to trade before the order fills.
+    pub queue_ahead: Decimal,
+}
+
+impl PaperOrder {
+    // Whether a trade or quote at `price` on the opposite side reaches the
+    // order.
+    fn reached_by(&self, price: Decimal) -> bool {
+        match self.bid_or_ask {
+            BidOrAsk::Bid => price <= self.price,
+            BidOrAsk::Ask => price >= self.price,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PaperMarket {
+    // Latest quoted size by price of the external book, less whatever the
+    // simulated orders have taken from it since.
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    orders: BTreeMap<OrderId, PaperOrder>,
+    reservations: HashMap<OrderId, Reservation>,
+    fees: Fees,
+}
+
+impl PaperMarket {
+    fn level_size(&self, bid_or_ask: BidOrAsk, price: Decimal) -> Decimal {
+        let levels = match bid_or_ask {
+            BidOrAsk::Bid => &self.bids,
+            BidOrAsk::Ask => &self.asks,
+        };
+        levels.get(&price).copied().unwrap_or_default()
+    }
+
+    // Quoted levels a `bid_or_ask` taker limited to `limit_price` can reach,
+    // best first.
+    fn reachable(
+        &self,
+        bid_or_ask: BidOrAsk,
+        limit_price: Option<Decimal>,
+    ) -> Vec<(Decimal, Decimal)> {
+        let levels: Box<dyn Iterator<Item = (&Decimal, &Decimal)>> = match bid_or_ask {
+            BidOrAsk::Bid => Box::new(self.asks.iter()),
+            BidOrAsk::Ask => Box::new(self.bids.iter().rev()),
+        };
+        levels
+            .map(|(price, size)| (*price, *size))
+            .take_while(|(price, _)| match (bid_or_ask, limit_price) {
+                (_, None) => true,
+                (BidOrAsk::Bid, Some(limit)) => *price <= limit,
+                (BidOrAsk::Ask, Some(limit)) => *price >= limit,
+            })
+            .collect()
+    }
+
+    // Takes up to `size` from the quoted levels, as `owner`'s order
+    // `order_id`; what is taken is gone until the next quote.
+    fn take(
+        &mut self,
+        order_id: OrderId,
+        owner: AccountId,
+        bid_or_ask: BidOrAsk,
+        limit_price: Option<Decimal>,
+        mut size: Decimal,
+    ) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        for (price, available) in self.reachable(bid_or_ask, limit_price) {
+            if size.is_zero() {
+                break;
+            }
+            let fill = size.min(available);
+            size -= fill;
+            let levels = match bid_or_ask {
+                BidOrAsk::Bid => &mut self.asks,
+                BidOrAsk::Ask => &mut self.bids,
+            };
+            if fill == available {
+                levels.remove(&price);
+            } else {
+                levels.insert(price, available - fill);
+            }
+            let mut trade = Trade::new(EXTERNAL_ORDER, order_id, price, fill, bid_or_ask)
+                .with_owners(AccountId::default(), owner);
+            self.fees.apply(&mut trade);
+            trades.push(trade);
+        }
+        trades
+    }
+
+    // Resting orders on `bid_or_ask` in the order they would fill: best
+    // price first, then the oldest.
+    fn queue(&self, bid_or_ask: BidOrAsk) -> Vec<OrderId> {
+        let mut orders: Vec<&PaperOrder> = self
+            .orders
+            .values()
+            .filter(|order| order.bid_or_ask == bid_or_ask)
+            .collect();
+        orders.sort_by(|a, b| match bid_or_ask {
+            BidOrAsk::Bid => b.price.cmp(&a.price).then(a.order_id.cmp(&b.order_id)),
+            BidOrAsk::Ask => a.price.cmp(&b.price).then(a.order_id.cmp(&b.order_id)),
+        });
+        orders.iter().map(|order| order.order_id).collect()
+    }
+
+    // Fills `size` of a resting order as a maker at its own price.
+    fn fill_resting(&mut self, order_id: OrderId, size: Decimal) -> Trade {
+        let order = self.orders.get_mut(&order_id).expect("order is resting");
+        order.remaining_size -= size;
+        let taker_side = match order.bid_or_ask {
+            BidOrAsk::Bid => BidOrAsk::Ask,
+            BidOrAsk::Ask => BidOrAsk::Bid,
+        };
+        let mut trade = Trade::new(order_id, EXTERNAL_ORDER, order.price, size, taker_side)
+            .with_owners(order.owner, AccountId::default());
+        if order.remaining_size.is_zero() {
+            self.orders.remove(&order_id);
+        }
+        self.fees.apply(&mut trade);
+        trade
+    }
+}
+
+/// Simulates orders against an external market without touching a real
+/// book. Trades and quotes fed in from a live feed decide when resting
+/// orders fill: an order first waits for the size quoted ahead of it at
+/// its price to trade or be cancelled. Orders that cross the quotes take
+/// the quoted size straight away. Fills settle against virtual balances
+/// deposited up front.
+#[derive(Debug)]
+pub struct PaperExchange {
+    accounts: Accounts,
+    markets: HashMap<TradingPair, PaperMarket>,
+    last_order_id: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for PaperExchange {
+    fn default() -> PaperExchange {
+        PaperExchange::new()
+    }
+}
+
+impl PaperExchange {
+    pub fn new() -> PaperExchange {
+        PaperExchange {
+            accounts: Accounts::default(),
+            markets: HashMap::new(),
+            last_order_id: 0,
+            clock: system_clock(),
+        }
+    }
+
+    /// Replaces the system clock simulated fills are stamped with.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    pub fn add_market(&mut self, pair: TradingPair, schedule: FeeSchedule) {
+        let mut market = PaperMarket::default();
+        market.fees.set_schedule(schedule);
+        self.markets.insert(pair, market);
+    }
+
+    pub fn deposit(
+        &mut self,
+        account: AccountId,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        self.accounts.deposit(account, asset, amount)
+    }
+
+    pub fn balance(&self, account: AccountId, asset: &str) -> Balance {
+        self.accounts.balance(account, asset)
+    }
+
+    /// Resting simulated orders owned by `account` in `pair`, oldest first.
+    pub fn open_orders(&self, pair: &TradingPair, account: AccountId) -> Vec<PaperOrder> {
+        self.markets.get(pair).map_or_else(Vec::new, |market| {
+            market
+                .orders
+                .values()
+                .filter(|order| order.owner == account)
+                .cloned()
+                .collect()
+        })
+    }
+
+    fn market_mut(&mut self, pair: &TradingPair) -> Result<&mut PaperMarket, EngineError> {
+        self.markets
+            .get_mut(pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))
+    }
+
+    fn next_order_id(&mut self) -> OrderId {
+        self.last_order_id += 1;
+        OrderId(self.last_order_id)
+    }
+
+    /// Places a limit order; what crosses the quotes fills at once and the
+    /// rest joins the back of the queue at its price.
+    pub fn place_limit_order(
+        &mut self,
+        pair: TradingPair,
+        price: Decimal,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        if price <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidPrice(price).into());
+        }
+        self.place(pair, Some(price), order)
+    }
+
+    /// Fills what the quotes hold and cancels the rest.
+    pub fn place_market_order(
+        &mut self,
+        pair: TradingPair,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.place(pair, None, order)
+    }
+
+    fn place(
+        &mut self,
+        pair: TradingPair,
+        limit_price: Option<Decimal>,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        let (owner, bid_or_ask, size) = (order.owner(), order.bid_or_ask(), order.remaining_size());
+        if size <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidSize(size).into());
+        }
+        let market = self
+            .markets
+            .get(&pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))?;
+        let amount = match (bid_or_ask, limit_price) {
+            (BidOrAsk::Ask, _) => size,
+            (BidOrAsk::Bid, Some(price)) => size * price,
+            (BidOrAsk::Bid, None) => {
+                let mut left = size;
+                let mut cost = Decimal::ZERO;
+                for (price, available) in market.reachable(BidOrAsk::Bid, None) {
+                    let fill = left.min(available);
+                    cost += fill * price;
+                    left -= fill;
+                }
+                cost
+            }
+        };
+        self.accounts
+            .reserve(owner, pair.funding_asset(bid_or_ask), amount)?;
+
+        let order_id = self.next_order_id();
+        let market = self.markets.get_mut(&pair).expect("market exists");
+        market.reservations.insert(
+            order_id,
+            Reservation {
+                owner,
+                bid_or_ask,
+                amount,
+                price: limit_price,
+            },
+        );
+        let mut trades = market.take(order_id, owner, bid_or_ask, limit_price, size);
+        let remaining_size = size - trades.iter().map(|trade| trade.size).sum::<Decimal>();
+        let status = match limit_price {
+            _ if remaining_size.is_zero() => OrderStatus::Filled,
+            None => OrderStatus::Cancelled,
+            Some(_) if trades.is_empty() => OrderStatus::New,
+            Some(_) => OrderStatus::PartiallyFilled,
+        };
+        if let (Some(price), false) = (limit_price, remaining_size.is_zero()) {
+            let queue_ahead = market.level_size(bid_or_ask, price);
+            market.orders.insert(
+                order_id,
+                PaperOrder {
+                    order_id,
+                    owner,
+                    bid_or_ask,
+                    price,
+                    remaining_size,
+                    queue_ahead,
+                },
+            );
+        }
+        self.settle(&pair, &mut trades);
+        Ok(ExecutionReport::new(
+            order_id,
+            status,
+            trades,
+            remaining_size,
+        ))
+    }
+
+    pub fn cancel_order(
+        &mut self,
+        pair: TradingPair,
+        order_id: OrderId,
+    ) -> Result<PaperOrder, EngineError> {
+        let order = self
+            .market_mut(&pair)?
+            .orders
+            .remove(&order_id)
+            .ok_or(OrderBookError::UnknownOrderId(order_id))?;
+        self.release(&pair, order_id);
+        Ok(order)
+    }
+
+    /// Feeds in a trade printed by the external market. Resting orders the
+    /// aggressor reached fill, better prices first; at the trade price the
+    /// size queued ahead of an order trades before it does.
+    pub fn on_trade(
+        &mut self,
+        pair: TradingPair,
+        price: Decimal,
+        size: Decimal,
+        taker_side: BidOrAsk,
+    ) -> Result<Vec<Trade>, EngineError> {
+        let market = self.market_mut(&pair)?;
+        let resting_side = match taker_side {
+            BidOrAsk::Bid => BidOrAsk::Ask,
+            BidOrAsk::Ask => BidOrAsk::Bid,
+        };
+        let mut left = size;
+        let mut trades = Vec::new();
+        for order_id in market.queue(resting_side) {
+            let order = market.orders.get_mut(&order_id).expect("order is resting");
+            if left.is_zero() || !order.reached_by(price) {
+                break;
+            }
+            if order.price == price {
+                let traded_ahead = left.min(order.queue_ahead);
+                order.queue_ahead -= traded_ahead;
+                left -= traded_ahead;
+            }
+            let fill = left.min(order.remaining_size);
+            if fill > Decimal::ZERO {
+                left -= fill;
+                trades.push(market.fill_resting(order_id, fill));
+            }
+        }
+        self.settle(&pair, &mut trades);
+        Ok(trades)
+    }
+
+    /// Feeds in the external book, replacing the last quote. Size that
+    /// disappeared at an order's price was cancelled, partly from ahead of
+    /// it, so the order moves up the queue; new size joins behind it. If
+    /// the quotes now cross a resting order, it fills against them.
+    pub fn on_quote(
+        &mut self,
+        pair: TradingPair,
+        bids: Vec<(Decimal, Decimal)>,
+        asks: Vec<(Decimal, Decimal)>,
+    ) -> Result<Vec<Trade>, EngineError> {
+        let market = self.market_mut(&pair)?;
+        market.bids = bids.into_iter().collect();
+        market.asks = asks.into_iter().collect();
+        let queues: Vec<(OrderId, Decimal)> = market
+            .orders
+            .values()
+            .map(|order| {
+                let size = market.level_size(order.bid_or_ask, order.price);
+                (order.order_id, order.queue_ahead.min(size))
+            })
+            .collect();
+        for (order_id, queue_ahead) in queues {
+            market
+                .orders
+                .get_mut(&order_id)
+                .expect("order is resting")
+                .queue_ahead = queue_ahead;
+        }
+
+        let mut trades = Vec::new();
+        for bid_or_ask in [BidOrAsk::Bid, BidOrAsk::Ask] {
+            for order_id in market.queue(bid_or_ask) {
+                let order = &market.orders[&order_id];
+                let crossing = market.reachable(bid_or_ask, Some(order.price));
+                let mut left = order.remaining_size;
+                for (level_price, available) in crossing {
+                    let fill = left.min(available);
+                    if fill.is_zero() {
+                        break;
+                    }
+                    left -= fill;
+                    let levels = match bid_or_ask {
+                        BidOrAsk::Bid => &mut market.asks,
+                        BidOrAsk::Ask => &mut market.bids,
+                    };
+                    if fill == available {
+                        levels.remove(&level_price);
+                    } else {
+                        levels.insert(level_price, available - fill);
+                    }
+                    trades.push(market.fill_resting(order_id, fill));
+                }
+            }
+        }
+        self.settle(&pair, &mut trades);
+        Ok(trades)
+    }
+
+    // Stamps `trades`, moves their funds between the simulated owners'
+    // balances and drops the reservations of orders that are done.
+    fn settle(&mut self, pair: &TradingPair, trades: &mut [Trade]) {
+        let now = self.clock.now();
+        for trade in trades.iter_mut() {
+            trade.timestamp = now;
+        }
+        let Some(market) = self.markets.get_mut(pair) else {
+            return;
+        };
+        for trade in trades {
+            self.accounts
+                .settle_trade(pair, trade, &mut market.reservations);
+        }
+        let done: Vec<OrderId> = market
+            .reservations
+            .keys()
+            .filter(|order_id| !market.orders.contains_key(order_id))
+            .copied()
+            .collect();
+        for order_id in done {
+            self.release(pair, order_id);
+        }
+    }
+
+    fn release(&mut self, pair: &TradingPair, order_id: OrderId) {
+        let Some(reservation) = self
+            .markets
+            .get_mut(pair)
+            .and_then(|market| market.reservations.remove(&order_id))
+        else {
+            return;
+        };
+        if reservation.amount > Decimal::ZERO {
+            let asset = pair.funding_asset(reservation.bid_or_ask);
+            self.accounts
+                .release(reservation.owner, asset, reservation.amount);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::clock::ManualClock;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_paper_exchange_models_queue_position() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let account = AccountId(1);
+        let mut paper = PaperExchange::new();
+        paper.set_clock(ManualClock::new(5_000));
+        paper.add_market(pair.clone(), FeeSchedule::default());
+        paper.deposit(account, "USD", dec!(1000)).unwrap();
+        paper
+            .on_quote(
+                pair.clone(),
+                vec![(dec!(99), dec!(3)), (dec!(98), dec!(5))],
+                vec![(dec!(101), dec!(2))],
+            )
+            .unwrap();
+
+        let bid = Order::new(BidOrAsk::Bid, dec!(2)).with_owner(account);
+        let report = paper
+            .place_limit_order(pair.clone(), dec!(99), bid)
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::New);
+        assert_eq!(paper.open_orders(&pair, account)[0].queue_ahead, dec!(3));
+
+        // Two of the three ahead trade, then a cancel leaves one in front.
+        let fills = paper.on_trade(pair.clone(), dec!(99), dec!(2), BidOrAsk::Ask);
+        assert!(fills.unwrap().is_empty());
+        paper
+            .on_quote(
+                pair.clone(),
+                vec![(dec!(99), dec!(2))],
+                vec![(dec!(101), dec!(2))],
+            )
+            .unwrap();
+        let fills = paper
+            .on_trade(pair.clone(), dec!(99), dec!(2), BidOrAsk::Ask)
+            .unwrap();
+        assert_eq!(fills.len(), 1);
+        assert_eq!((fills[0].price, fills[0].size), (dec!(99), dec!(1)));
+        assert_eq!(fills[0].timestamp, 5_000);
+
+        let market = Order::new(BidOrAsk::Bid, dec!(3)).with_owner(account);
+        let report = paper.place_market_order(pair.clone(), market).unwrap();
+        assert_eq!(report.status, OrderStatus::Cancelled);
+        assert_eq!(report.filled_size, dec!(2));
+
+        // The ask dropping through the resting bid fills what is left of it.
+        let fills = paper
+            .on_quote(pair.clone(), vec![], vec![(dec!(98.5), dec!(5))])
+            .unwrap();
+        assert_eq!((fills[0].price, fills[0].size), (dec!(99), dec!(1)));
+        assert!(paper.open_orders(&pair, account).is_empty());
+        assert_eq!(paper.balance(account, "BTC").total(), dec!(4));
+        assert_eq!(
+            paper.balance(account, "USD"),
+            Balance {
+                available: dec!(600),
+                reserved: dec!(0),
+            }
+        );
+    }
+}
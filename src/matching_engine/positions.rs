@@ -0,0 +1,323 @@
+#![allow(dead_code)]
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::orderbook::{AccountId, BidOrAsk, Order};
+use super::trade::Trade;
+use super::triggers::OrderType;
+
+/// An account's net holding in one market, from its fills there.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Position {
+    /// Base bought minus base sold; negative when short.
+    pub size: Decimal,
+    /// Quote paid for the base bought minus quote received for the base sold.
+    pub cost: Decimal,
+}
+
+impl Position {
+    /// Value of the position at `price`, whichever way it faces.
+    pub fn exposure(&self, price: Decimal) -> Decimal {
+        (self.size * price).abs()
+    }
+
+    /// Profit or loss if the position were closed at `price`.
+    pub fn pnl(&self, price: Decimal) -> Decimal {
+        self.size * price - self.cost
+    }
+
+    fn fill(&mut self, bid_or_ask: BidOrAsk, price: Decimal, size: Decimal) {
+        let size = signed(bid_or_ask, size);
+        self.size += size;
+        self.cost += size * price;
+    }
+}
+
+fn signed(bid_or_ask: BidOrAsk, size: Decimal) -> Decimal {
+    match bid_or_ask {
+        BidOrAsk::Bid => size,
+        BidOrAsk::Ask => -size,
+    }
+}
+
+/// Caps on an account's position in each market it trades. An order is
+/// checked against the position the account would hold if it and the
+/// account's other working orders on the same side all filled; orders that
+/// shrink the position are always accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PositionLimits {
+    /// Largest net size, long or short, in the base asset.
+    pub max_position: Option<Decimal>,
+    /// Largest value of that size in the quote asset, at the order's own
+    /// price or else the market's reference price.
+    pub max_exposure: Option<Decimal>,
+}
+
+/// Every account's position in every market it has traded, and the limits
+/// set for accounts.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Positions {
+    #[cfg_attr(feature = "serde", serde(with = "super::engine::map_as_pairs"))]
+    positions: HashMap<(AccountId, TradingPair), Position>,
+    limits: BTreeMap<AccountId, PositionLimits>,
+}
+
+impl Positions {
+    /// The position of `account` in `pair`, flat if it never traded there.
+    pub fn get(&self, account: AccountId, pair: &TradingPair) -> Position {
+        self.positions
+            .get(&(account, pair.clone()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every position of `account`, by market.
+    pub fn of(&self, account: AccountId) -> impl Iterator<Item = (&TradingPair, &Position)> {
+        self.positions
+            .iter()
+            .filter(move |((owner, _), _)| *owner == account)
+            .map(|((_, pair), position)| (pair, position))
+    }
+
+    pub fn limits(&self, account: AccountId) -> Option<PositionLimits> {
+        self.limits.get(&account).copied()
+    }
+
+    // Nets both sides of `trade` into their owners' positions.
+    pub(super) fn record(&mut self, pair: &TradingPair, trade: &Trade) {
+        let maker_side = match trade.taker_side {
+            BidOrAsk::Bid => BidOrAsk::Ask,
+            BidOrAsk::Ask => BidOrAsk::Bid,
+        };
+        for (owner, bid_or_ask) in [
+            (trade.taker_owner, trade.taker_side),
+            (trade.maker_owner, maker_side),
+        ] {
+            self.positions
+                .entry((owner, pair.clone()))
+                .or_default()
+                .fill(bid_or_ask, trade.price, trade.size);
+        }
+    }
+}
+
+impl MatchingEngine {
+    /// Sets the position limits of `account`, or removes them with `None`.
+    /// Orders already working are left alone.
+    pub fn set_position_limits(&mut self, account: AccountId, limits: Option<PositionLimits>) {
+        let positions = self.positions_mut();
+        match limits {
+            Some(limits) => positions.limits.insert(account, limits),
+            None => positions.limits.remove(&account),
+        };
+    }
+
+    pub fn position(&self, account: AccountId, pair: TradingPair) -> Position {
+        self.positions().get(account, &pair)
+    }
+
+    /// Value of the position of `account` in `pair` at the market's
+    /// reference price; `None` before the market has one.
+    pub fn exposure(
+        &self,
+        account: AccountId,
+        pair: TradingPair,
+    ) -> Result<Option<Decimal>, EngineError> {
+        let price = self.reference_price(pair.clone())?.price();
+        Ok(price.map(|price| self.position(account, pair).exposure(price)))
+    }
+
+    // Refuses `order` if it could take its owner past their position limits.
+    pub(super) fn check_position_limits(
+        &self,
+        pair: &TradingPair,
+        order_type: OrderType,
+        order: &Order,
+    ) -> Result<(), EngineError> {
+        let price = match order_type {
+            OrderType::Limit { price }
+            | OrderType::StopLimit {
+                limit_price: price, ..
+            } => Some(price),
+            OrderType::Stop { stop_price } => Some(stop_price),
+            OrderType::Market | OrderType::TrailingStop { .. } => {
+                self.reference_price(pair.clone())?.price()
+            }
+        };
+        self.check_size_increase(
+            pair,
+            order.owner(),
+            order.bid_or_ask(),
+            order.remaining_size(),
+            price,
+        )
+    }
+
+    // Refuses amending the resting `order` to `new_size` if the extra size
+    // could take its owner past their position limits.
+    pub(super) fn check_amend_position_limits(
+        &self,
+        pair: &TradingPair,
+        order: &Order,
+        new_price: Decimal,
+        new_size: Decimal,
+    ) -> Result<(), EngineError> {
+        self.check_size_increase(
+            pair,
+            order.owner(),
+            order.bid_or_ask(),
+            new_size - order.remaining_size(),
+            Some(new_price),
+        )
+    }
+
+    // Checks the position `account` would hold if `increase` more were
+    // working on `bid_or_ask` alongside its other orders there, valuing it
+    // at `price`.
+    fn check_size_increase(
+        &self,
+        pair: &TradingPair,
+        account: AccountId,
+        bid_or_ask: BidOrAsk,
+        increase: Decimal,
+        price: Option<Decimal>,
+    ) -> Result<(), EngineError> {
+        let Some(limits) = self.positions().limits(account) else {
+            return Ok(());
+        };
+        let working: Decimal = self
+            .open_orders_for_market(account, pair.clone())?
+            .into_iter()
+            .filter(|open| open.bid_or_ask == bid_or_ask)
+            .map(|open| open.remaining_size)
+            .sum();
+        let before = self.position(account, pair.clone()).size + signed(bid_or_ask, working);
+        let after = before + signed(bid_or_ask, increase);
+        if after.abs() <= before.abs() {
+            return Ok(());
+        }
+
+        if let Some(max) = limits.max_position {
+            if after.abs() > max {
+                return Err(EngineError::PositionLimitExceeded {
+                    account,
+                    pair: pair.clone(),
+                    position: after,
+                    max,
+                });
+            }
+        }
+        if let (Some(max), Some(price)) = (limits.max_exposure, price) {
+            let exposure = (after * price).abs();
+            if exposure > max {
+                return Err(EngineError::ExposureLimitExceeded {
+                    account,
+                    pair: pair.clone(),
+                    exposure,
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_positions_net_fills_and_enforce_limits() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        let (maker, taker) = (AccountId(1), AccountId(2));
+        let order = |bid_or_ask, size, owner| Order::new(bid_or_ask, size).with_owner(owner);
+
+        engine
+            .place_limit_order(
+                pair.clone(),
+                dec!(100),
+                order(BidOrAsk::Ask, dec!(3), maker),
+            )
+            .unwrap();
+        engine
+            .place_market_order(pair.clone(), order(BidOrAsk::Bid, dec!(2), taker))
+            .unwrap();
+        let long = engine.position(taker, pair.clone());
+        assert_eq!(long.size, dec!(2));
+        assert_eq!(long.pnl(dec!(110)), dec!(20));
+        assert_eq!(engine.position(maker, pair.clone()).cost, dec!(-200));
+        assert_eq!(
+            engine.exposure(taker, pair.clone()).unwrap(),
+            Some(dec!(200))
+        );
+
+        // A working bid counts towards the limit before it fills.
+        let limits = PositionLimits {
+            max_position: Some(dec!(4)),
+            max_exposure: Some(dec!(350)),
+        };
+        engine.set_position_limits(taker, Some(limits));
+        engine
+            .place_limit_order(pair.clone(), dec!(90), order(BidOrAsk::Bid, dec!(1), taker))
+            .unwrap();
+        assert_eq!(
+            engine.place_limit_order(pair.clone(), dec!(90), order(BidOrAsk::Bid, dec!(2), taker)),
+            Err(EngineError::PositionLimitExceeded {
+                account: taker,
+                pair: pair.clone(),
+                position: dec!(5),
+                max: dec!(4),
+            })
+        );
+        assert!(matches!(
+            engine.place_market_order(pair.clone(), order(BidOrAsk::Bid, dec!(1), taker)),
+            Err(EngineError::ExposureLimitExceeded { .. })
+        ));
+
+        // Amends are checked on the size they add, and shrinking is fine.
+        let working = engine
+            .place_limit_order(pair.clone(), dec!(50), order(BidOrAsk::Bid, dec!(1), taker))
+            .unwrap()
+            .order_id;
+        assert_eq!(
+            engine.amend_order(pair.clone(), working, dec!(50), dec!(2)),
+            Err(EngineError::PositionLimitExceeded {
+                account: taker,
+                pair: pair.clone(),
+                position: dec!(5),
+                max: dec!(4),
+            })
+        );
+        engine
+            .amend_order(pair.clone(), working, dec!(50), dec!(0.5))
+            .unwrap();
+
+        // Selling down is allowed whatever the limits.
+        engine.set_position_limits(
+            taker,
+            Some(PositionLimits {
+                max_position: Some(dec!(1)),
+                max_exposure: None,
+            }),
+        );
+        engine
+            .place_limit_order(
+                pair.clone(),
+                dec!(120),
+                order(BidOrAsk::Ask, dec!(1), taker),
+            )
+            .unwrap();
+    }
+}
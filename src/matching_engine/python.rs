@@ -0,0 +1,212 @@
+#![allow(dead_code)]
+use std::sync::mpsc;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::config::MarketConfig;
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::events::EngineEvent;
+use super::orderbook::{AccountId, BidOrAsk, Order, OrderId};
+
+fn engine_error(error: EngineError) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+fn pair(text: &str) -> PyResult<TradingPair> {
+    text.parse().map_err(engine_error)
+}
+
+fn side(text: &str) -> PyResult<BidOrAsk> {
+    match text.to_lowercase().as_str() {
+        "buy" | "bid" => Ok(BidOrAsk::Bid),
+        "sell" | "ask" => Ok(BidOrAsk::Ask),
+        _ => Err(PyValueError::new_err(format!("Invalid side: {}", text))),
+    }
+}
+
+// `value` as the Python object its JSON form decodes to.
+fn to_py<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<Py<PyAny>> {
+    let json =
+        serde_json::to_string(value).map_err(|error| PyValueError::new_err(error.to_string()))?;
+    let loads = py.import("json")?.getattr("loads")?;
+    Ok(loads.call1((json,))?.unbind())
+}
+
+/// The engine as the Python class `rust_trading_engine.Engine`. Prices and
+/// sizes may be passed as `decimal.Decimal`, `int` or `str`. Reports, depth
+/// and events come back as dicts in the shape of the JSON API, with prices
+/// and sizes as strings so no precision is lost.
+///
+/// Callbacks registered with `on_event` are called once the engine call
+/// that raised the events has finished, so they may call into the engine
+/// themselves. An exception in a callback is raised from that call.
+#[pyclass(name = "Engine", unsendable)]
+pub struct PyEngine {
+    engine: MatchingEngine,
+    events: mpsc::Receiver<EngineEvent>,
+    callbacks: Vec<Py<PyAny>>,
+}
+
+impl PyEngine {
+    // Runs `f` on the engine, then hands the events it raised to the
+    // callbacks, with the engine no longer borrowed.
+    fn call<T>(
+        slf: &Bound<'_, PyEngine>,
+        f: impl FnOnce(&mut MatchingEngine) -> Result<T, EngineError>,
+    ) -> PyResult<T> {
+        let py = slf.py();
+        let (result, events, callbacks) = {
+            let mut this = slf.borrow_mut();
+            let result = f(&mut this.engine);
+            let events: Vec<EngineEvent> = this.events.try_iter().collect();
+            let callbacks: Vec<Py<PyAny>> = this
+                .callbacks
+                .iter()
+                .map(|callback| callback.clone_ref(py))
+                .collect();
+            (result, events, callbacks)
+        };
+        for event in &events {
+            let event = to_py(py, event)?;
+            for callback in &callbacks {
+                callback.call1(py, (event.clone_ref(py),))?;
+            }
+        }
+        result.map_err(engine_error)
+    }
+}
+
+#[pymethods]
+impl PyEngine {
+    #[new]
+    fn new() -> PyEngine {
+        let mut engine = MatchingEngine::new();
+        let events = engine.subscribe();
+        PyEngine {
+            engine,
+            events,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Lists `pair`, e.g. `"BTC/USD"`, with the default market config.
+    fn add_market(slf: &Bound<'_, PyEngine>, pair: &str) -> PyResult<()> {
+        let pair = self::pair(pair)?;
+        PyEngine::call(slf, |engine| {
+            engine.add_new_market(pair, MarketConfig::default());
+            Ok(())
+        })
+    }
+
+    #[pyo3(signature = (pair, side, price, size, account = 0))]
+    fn place_limit_order(
+        slf: &Bound<'_, PyEngine>,
+        pair: &str,
+        side: &str,
+        price: Decimal,
+        size: Decimal,
+        account: u64,
+    ) -> PyResult<Py<PyAny>> {
+        let (pair, order) = (
+            self::pair(pair)?,
+            Order::new(self::side(side)?, size).with_owner(AccountId(account)),
+        );
+        let report = PyEngine::call(slf, |engine| engine.place_limit_order(pair, price, order))?;
+        to_py(slf.py(), &report)
+    }
+
+    #[pyo3(signature = (pair, side, size, account = 0))]
+    fn place_market_order(
+        slf: &Bound<'_, PyEngine>,
+        pair: &str,
+        side: &str,
+        size: Decimal,
+        account: u64,
+    ) -> PyResult<Py<PyAny>> {
+        let (pair, order) = (
+            self::pair(pair)?,
+            Order::new(self::side(side)?, size).with_owner(AccountId(account)),
+        );
+        let report = PyEngine::call(slf, |engine| engine.place_market_order(pair, order))?;
+        to_py(slf.py(), &report)
+    }
+
+    /// Cancels a resting order and returns it.
+    fn cancel(slf: &Bound<'_, PyEngine>, pair: &str, order_id: u64) -> PyResult<Py<PyAny>> {
+        let pair = self::pair(pair)?;
+        let order = PyEngine::call(slf, |engine| engine.cancel_order(pair, OrderId(order_id)))?;
+        to_py(slf.py(), &order)
+    }
+
+    #[pyo3(signature = (pair, levels = 10))]
+    fn depth(&self, py: Python<'_>, pair: &str, levels: usize) -> PyResult<Py<PyAny>> {
+        let depth = self
+            .engine
+            .depth(self::pair(pair)?, levels)
+            .map_err(engine_error)?;
+        to_py(py, &depth)
+    }
+
+    /// Calls `callback` with every engine event from now on.
+    fn on_event(&mut self, callback: Py<PyAny>) {
+        self.callbacks.push(callback);
+    }
+}
+
+/// The `rust_trading_engine` Python module.
+#[pymodule]
+fn rust_trading_engine(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyEngine>()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python_engine_trades_and_calls_back() {
+        Python::initialize();
+        Python::attach(|py| {
+            let locals = PyDict::new(py);
+            locals
+                .set_item("Engine", py.get_type::<PyEngine>())
+                .unwrap();
+            py.run(
+                cr#"
+from decimal import Decimal
+
+engine = Engine()
+events = []
+engine.on_event(events.append)
+engine.add_market("BTC/USD")
+maker = engine.place_limit_order("BTC/USD", "sell", Decimal("30000.5"), 2, account=1)
+assert maker["status"] == "New"
+taker = engine.place_market_order("BTC/USD", "buy", "0.5", account=2)
+assert taker["status"] == "Filled"
+assert Decimal(taker["trades"][0]["price"]) == Decimal("30000.5")
+
+depth = engine.depth("BTC/USD")
+assert Decimal(depth["asks"][0]["size"]) == Decimal("1.5")
+engine.cancel("BTC/USD", maker["order_id"])
+assert engine.depth("BTC/USD")["asks"] == []
+assert [name for event in events for name in event][:2] == ["MarketAdded", "OrderAccepted"]
+assert any("TradeExecuted" in event for event in events)
+
+try:
+    engine.cancel("BTC/USD", maker["order_id"])
+    raise AssertionError("cancelled twice")
+except ValueError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,290 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use super::orderbook::{Order, OrderId};
+
+#[derive(Debug, Clone)]
+struct Node {
+    order: Order,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// FIFO queue of the orders resting at one price, kept as a doubly linked
+/// list in a slab. Pushing to the back, popping from the front and removing
+/// any order by id are all O(1); slots freed by removals are reused.
+#[derive(Debug, Clone, Default)]
+pub struct OrderQueue {
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    index: HashMap<OrderId, usize>,
+}
+
+impl OrderQueue {
+    pub fn new() -> OrderQueue {
+        OrderQueue::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    fn node(&self, slot: usize) -> &Node {
+        self.nodes[slot].as_ref().expect("linked slot is occupied")
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut Node {
+        self.nodes[slot].as_mut().expect("linked slot is occupied")
+    }
+
+    pub fn front(&self) -> Option<&Order> {
+        Some(&self.node(self.head?).order)
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut Order> {
+        let head = self.head?;
+        Some(&mut self.node_mut(head).order)
+    }
+
+    pub fn get(&self, id: OrderId) -> Option<&Order> {
+        Some(&self.node(*self.index.get(&id)?).order)
+    }
+
+    pub fn get_mut(&mut self, id: OrderId) -> Option<&mut Order> {
+        let slot = *self.index.get(&id)?;
+        Some(&mut self.node_mut(slot).order)
+    }
+
+    pub fn push_back(&mut self, order: Order) {
+        let node = Node {
+            order,
+            prev: self.tail,
+            next: None,
+        };
+        let id = node.order.id();
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                slot
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        match self.tail {
+            Some(tail) => self.node_mut(tail).next = Some(slot),
+            None => self.head = Some(slot),
+        }
+        self.tail = Some(slot);
+        self.index.insert(id, slot);
+    }
+
+    pub fn pop_front(&mut self) -> Option<Order> {
+        let id = self.front()?.id();
+        self.remove(id)
+    }
+
+    /// Unlinks the order with `id`, wherever it is in the queue.
+    pub fn remove(&mut self, id: OrderId) -> Option<Order> {
+        let slot = self.index.remove(&id)?;
+        let node = self.nodes[slot].take().expect("indexed slot is occupied");
+        match node.prev {
+            Some(prev) => self.node_mut(prev).next = node.next,
+            None => self.head = node.next,
+        }
+        match node.next {
+            Some(next) => self.node_mut(next).prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        self.free.push(slot);
+        Some(node.order)
+    }
+
+    /// Orders from the front of the queue to the back.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            queue: self,
+            next: self.head,
+        }
+    }
+
+    /// Calls `f` on every order from front to back.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(&mut Order)) {
+        let mut next = self.head;
+        while let Some(slot) = next {
+            let node = self.node_mut(slot);
+            next = node.next;
+            f(&mut node.order);
+        }
+    }
+
+    /// Whether the links, free list and id index agree: walking from the
+    /// head visits every occupied slot once and ends at the tail, and the
+    /// index maps each id to its slot.
+    pub fn is_consistent(&self) -> bool {
+        let mut count = 0;
+        let mut prev = None;
+        let mut next = self.head;
+        while let Some(slot) = next {
+            let Some(node) = self.nodes.get(slot).and_then(Option::as_ref) else {
+                return false;
+            };
+            count += 1;
+            if node.prev != prev || self.index.get(&node.order.id()) != Some(&slot) {
+                return false;
+            }
+            // Visiting more nodes than there are slots means the links loop.
+            if count > self.nodes.len() {
+                return false;
+            }
+            prev = Some(slot);
+            next = node.next;
+        }
+        prev == self.tail
+            && count == self.index.len()
+            && count + self.free.len() == self.nodes.len()
+    }
+
+    /// Keeps only the orders `f` returns true for, visiting them front to
+    /// back, and returns the others in that order.
+    pub fn retain(&mut self, mut f: impl FnMut(&mut Order) -> bool) -> Vec<Order> {
+        let mut removed = Vec::new();
+        let mut next = self.head;
+        while let Some(slot) = next {
+            let node = self.node_mut(slot);
+            next = node.next;
+            if !f(&mut node.order) {
+                let id = node.order.id();
+                removed.extend(self.remove(id));
+            }
+        }
+        removed
+    }
+}
+
+pub struct Iter<'a> {
+    queue: &'a OrderQueue,
+    next: Option<usize>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<&'a Order> {
+        let node = self.queue.node(self.next?);
+        self.next = node.next;
+        Some(&node.order)
+    }
+}
+
+impl From<Vec<Order>> for OrderQueue {
+    fn from(orders: Vec<Order>) -> OrderQueue {
+        let mut queue = OrderQueue::new();
+        for order in orders {
+            queue.push_back(order);
+        }
+        queue
+    }
+}
+
+impl From<&OrderQueue> for Vec<Order> {
+    fn from(queue: &OrderQueue) -> Vec<Order> {
+        queue.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::{BidOrAsk, OrderBook};
+    use rust_decimal_macros::dec;
+    use std::time::Instant;
+
+    fn orders(count: usize) -> Vec<Order> {
+        let mut orderbook = OrderBook::new();
+        (0..count)
+            .map(|_| {
+                let mut order = Order::new(BidOrAsk::Bid, dec!(1));
+                orderbook.reserve_order_id(&mut order);
+                order
+            })
+            .collect()
+    }
+
+    fn ids(queue: &OrderQueue) -> Vec<OrderId> {
+        queue.iter().map(Order::id).collect()
+    }
+
+    #[test]
+    fn test_order_queue_removes_anywhere() {
+        let orders = orders(4);
+        let id = |index: usize| orders[index].id();
+        let mut queue = OrderQueue::from(orders[..3].to_vec());
+        assert_eq!(queue.remove(id(1)).map(|order| order.id()), Some(id(1)));
+        assert_eq!(queue.remove(id(1)).map(|order| order.id()), None);
+        assert_eq!(ids(&queue), vec![id(0), id(2)]);
+
+        // The freed slot is reused without disturbing the order.
+        queue.push_back(orders[3].clone());
+        assert_eq!(queue.nodes.len(), 3);
+        assert_eq!(ids(&queue), vec![id(0), id(2), id(3)]);
+        assert_eq!(queue.pop_front().map(|order| order.id()), Some(id(0)));
+        assert_eq!(queue.front().map(Order::id), Some(id(2)));
+
+        let removed = queue.retain(|order| order.id() != id(3));
+        assert_eq!(removed.len(), 1);
+        assert_eq!(ids(&queue), vec![id(2)]);
+        assert_eq!(Vec::from(&queue).len(), queue.len());
+    }
+
+    // Cancel/replace traffic on one deep price level, against the `Vec`
+    // the queue replaced. Run with
+    // `cargo test --release bench_cancel_replace -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_cancel_replace() {
+        const DEPTH: usize = 10_000;
+        const ROUNDS: usize = 20_000;
+        let pool = orders(DEPTH + ROUNDS);
+        // Cancels a pseudo-random resting order, then queues a new one.
+        let victims: Vec<usize> = (0..ROUNDS)
+            .map(|round| (round * 7919 + 13) % DEPTH + round)
+            .collect();
+
+        let mut list: Vec<Order> = pool[..DEPTH].to_vec();
+        let started = Instant::now();
+        for (round, victim) in victims.iter().enumerate() {
+            let id = pool[*victim].id();
+            if let Some(position) = list.iter().position(|order| order.id() == id) {
+                list.remove(position);
+            }
+            list.push(pool[DEPTH + round].clone());
+        }
+        let vec_elapsed = started.elapsed();
+
+        let mut queue = OrderQueue::from(pool[..DEPTH].to_vec());
+        let started = Instant::now();
+        for (round, victim) in victims.iter().enumerate() {
+            queue.remove(pool[*victim].id());
+            queue.push_back(pool[DEPTH + round].clone());
+        }
+        let queue_elapsed = started.elapsed();
+
+        assert_eq!(queue.len(), list.len());
+        println!(
+            "{} cancel/replace at depth {}: Vec {:?}, OrderQueue {:?} ({:.0}x)",
+            ROUNDS,
+            DEPTH,
+            vec_elapsed,
+            queue_elapsed,
+            vec_elapsed.as_secs_f64() / queue_elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::error::EngineError;
+use super::orderbook::AccountId;
+
+/// Sustained rate and burst size of one token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: u32, burst: u32) -> RateLimit {
+        RateLimit { per_second, burst }
+    }
+}
+
+/// Limits applied to every account. Orders and cancels each have their own
+/// bucket, and every action also spends its weight from a shared one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RateLimits {
+    pub orders: Option<RateLimit>,
+    pub cancels: Option<RateLimit>,
+    pub weight: Option<RateLimit>,
+}
+
+/// What an account asked the engine to do, for rate limiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateAction {
+    PlaceOrder,
+    PlaceOcoOrder,
+    AmendOrder,
+    CancelOrder,
+    CancelAll,
+}
+
+impl RateAction {
+    pub fn weight(self) -> u32 {
+        match self {
+            RateAction::PlaceOrder | RateAction::AmendOrder | RateAction::CancelOrder => 1,
+            RateAction::PlaceOcoOrder => 2,
+            RateAction::CancelAll => 10,
+        }
+    }
+
+    fn is_cancel(self) -> bool {
+        matches!(self, RateAction::CancelOrder | RateAction::CancelAll)
+    }
+}
+
+// Tokens are kept in thousandths so that refilling `per_second` tokens a
+// second adds exactly `per_second` per elapsed millisecond.
+const SCALE: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Bucket {
+    tokens: u64,
+    updated: u64,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: u64) -> Bucket {
+        Bucket {
+            tokens: u64::from(limit.burst) * SCALE,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: u64) {
+        let elapsed = now.saturating_sub(self.updated);
+        self.tokens = (self.tokens + elapsed * u64::from(limit.per_second))
+            .min(u64::from(limit.burst) * SCALE);
+        self.updated = self.updated.max(now);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct AccountBuckets {
+    orders: Option<Bucket>,
+    cancels: Option<Bucket>,
+    weight: Option<Bucket>,
+}
+
+/// Token buckets per account. Without limits every action is allowed.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: HashMap<AccountId, AccountBuckets>,
+}
+
+impl RateLimiter {
+    pub fn limits(&self) -> RateLimits {
+        self.limits
+    }
+
+    /// Replaces the limits; buckets start full again.
+    pub fn set_limits(&mut self, limits: RateLimits) {
+        self.limits = limits;
+        self.buckets.clear();
+    }
+
+    /// Spends the tokens `action` needs at time `now` (in milliseconds), or
+    /// refuses it without spending any.
+    pub fn check(
+        &mut self,
+        account: AccountId,
+        action: RateAction,
+        now: u64,
+    ) -> Result<(), EngineError> {
+        let limits = self.limits;
+        let buckets = self.buckets.entry(account).or_default();
+        let (count_limit, count_bucket) = if action.is_cancel() {
+            (limits.cancels, &mut buckets.cancels)
+        } else {
+            (limits.orders, &mut buckets.orders)
+        };
+        let mut spends = Vec::new();
+        if let Some(limit) = count_limit {
+            spends.push((limit, count_bucket, 1));
+        }
+        if let Some(limit) = limits.weight {
+            spends.push((limit, &mut buckets.weight, action.weight()));
+        }
+
+        for (limit, bucket, cost) in &mut spends {
+            let bucket = bucket.get_or_insert_with(|| Bucket::full(*limit, now));
+            bucket.refill(*limit, now);
+            if bucket.tokens < u64::from(*cost) * SCALE {
+                return Err(EngineError::RateLimited {
+                    account,
+                    action,
+                    per_second: limit.per_second,
+                });
+            }
+        }
+        for (_, bucket, cost) in spends {
+            if let Some(bucket) = bucket {
+                bucket.tokens -= u64::from(cost) * SCALE;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        let mut limiter = RateLimiter::default();
+        let account = AccountId(1);
+        assert!(limiter.check(account, RateAction::CancelAll, 0).is_ok());
+
+        limiter.set_limits(RateLimits {
+            orders: Some(RateLimit::new(2, 2)),
+            cancels: None,
+            weight: Some(RateLimit::new(10, 10)),
+        });
+        assert!(limiter.check(account, RateAction::PlaceOrder, 0).is_ok());
+        assert!(limiter.check(account, RateAction::PlaceOcoOrder, 0).is_ok());
+        assert!(limiter.check(account, RateAction::PlaceOrder, 0).is_err());
+        // The refused order did not spend weight, so 7 of the 10 are left.
+        for _ in 0..7 {
+            assert!(limiter.check(account, RateAction::CancelOrder, 0).is_ok());
+        }
+        assert!(limiter.check(account, RateAction::CancelOrder, 0).is_err());
+        assert!(limiter
+            .check(AccountId(2), RateAction::PlaceOrder, 0)
+            .is_ok());
+
+        assert!(limiter.check(account, RateAction::PlaceOrder, 499).is_err());
+        assert!(limiter.check(account, RateAction::PlaceOrder, 500).is_ok());
+    }
+}
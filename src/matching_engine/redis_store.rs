@@ -0,0 +1,230 @@
+#![allow(dead_code)]
+use std::future::Future;
+use std::time::Duration;
+
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, RedisError};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use super::engine::{EngineSnapshot, MatchingEngine};
+use super::service::EngineHandle;
+
+#[derive(Debug, Error)]
+pub enum SnapshotStoreError {
+    #[error("Redis request failed: {0}")]
+    Redis(#[from] RedisError),
+    #[error("Stored snapshot is unreadable: {0}")]
+    Corrupt(#[from] serde_json::Error),
+}
+
+/// The few Redis operations the store needs. Implemented for a real
+/// connection; tests use an in-memory map.
+pub trait SnapshotBackend: Send {
+    fn get(
+        &mut self,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, RedisError>> + Send;
+
+    /// Sets every entry in one atomic transaction.
+    fn set_all(
+        &mut self,
+        entries: Vec<(String, Vec<u8>)>,
+    ) -> impl Future<Output = Result<(), RedisError>> + Send;
+}
+
+impl SnapshotBackend for MultiplexedConnection {
+    async fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, RedisError> {
+        AsyncCommands::get(self, key).await
+    }
+
+    async fn set_all(&mut self, entries: Vec<(String, Vec<u8>)>) -> Result<(), RedisError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in entries {
+            pipe.set(key, value).ignore();
+        }
+        pipe.query_async(self).await
+    }
+}
+
+/// Keeps the latest engine snapshot in Redis under `{prefix}:snapshot`,
+/// with its sequence number under `{prefix}:sequence` so it can be checked
+/// without fetching the whole state. Both are written in one transaction.
+pub struct RedisSnapshotStore<B> {
+    backend: B,
+    prefix: String,
+}
+
+impl RedisSnapshotStore<MultiplexedConnection> {
+    /// Connects to the server at `url`, e.g. `redis://127.0.0.1/`.
+    pub async fn connect(
+        url: &str,
+        prefix: impl Into<String>,
+    ) -> Result<RedisSnapshotStore<MultiplexedConnection>, SnapshotStoreError> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        Ok(RedisSnapshotStore::new(connection, prefix))
+    }
+}
+
+impl<B: SnapshotBackend> RedisSnapshotStore<B> {
+    pub fn new(backend: B, prefix: impl Into<String>) -> RedisSnapshotStore<B> {
+        RedisSnapshotStore {
+            backend,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    /// Replaces the stored snapshot with `snapshot`, unless the stored one
+    /// is already as recent. Returns whether it was written. An engine not
+    /// driven through `process` stays at sequence 0 and is always written.
+    pub async fn save(&mut self, snapshot: &EngineSnapshot) -> Result<bool, SnapshotStoreError> {
+        if let Some(stored) = self.latest_sequence().await? {
+            if stored >= snapshot.sequence() && snapshot.sequence() > 0 {
+                return Ok(false);
+            }
+        }
+        let entries = vec![
+            (self.key("snapshot"), serde_json::to_vec(snapshot)?),
+            (
+                self.key("sequence"),
+                snapshot.sequence().to_string().into_bytes(),
+            ),
+        ];
+        self.backend.set_all(entries).await?;
+        Ok(true)
+    }
+
+    /// Sequence number of the stored snapshot, if there is one.
+    pub async fn latest_sequence(&mut self) -> Result<Option<u64>, SnapshotStoreError> {
+        let key = self.key("sequence");
+        let Some(bytes) = self.backend.get(&key).await? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_slice(&bytes)?))
+    }
+
+    pub async fn load(&mut self) -> Result<Option<EngineSnapshot>, SnapshotStoreError> {
+        let key = self.key("snapshot");
+        match self.backend.get(&key).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// An engine restored from the stored snapshot, or an empty one if
+    /// nothing has been stored yet. To catch up on inputs after the
+    /// snapshot, pass it to `DurableEngine::open_from_snapshot` instead;
+    /// a fresh log works there too.
+    pub async fn bootstrap(&mut self) -> Result<MatchingEngine, SnapshotStoreError> {
+        Ok(self
+            .load()
+            .await?
+            .map_or_else(MatchingEngine::new, MatchingEngine::restore))
+    }
+
+    /// Spawns a task saving a snapshot of the engine behind `handle` every
+    /// `period`. It ends once the engine service stops; failed saves are
+    /// retried on the next tick.
+    pub fn spawn_periodic(mut self, handle: EngineHandle, period: Duration) -> JoinHandle<()>
+    where
+        B: 'static,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let Ok(snapshot) = handle.snapshot().await else {
+                    break;
+                };
+                let _ = self.save(&snapshot).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::command::EngineCommand;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use crate::matching_engine::service::EngineService;
+    use crate::matching_engine::wal::{DurableEngine, FsyncPolicy};
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct MemoryBackend(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+    impl SnapshotBackend for MemoryBackend {
+        async fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, RedisError> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        async fn set_all(&mut self, entries: Vec<(String, Vec<u8>)>) -> Result<(), RedisError> {
+            self.0.lock().unwrap().extend(entries);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redis_snapshot_store_bootstraps_engine() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let backend = MemoryBackend::default();
+        let mut store = RedisSnapshotStore::new(backend.clone(), "engine");
+        assert!(store.load().await.unwrap().is_none());
+
+        let mut engine = MatchingEngine::new();
+        let add = engine.stamp(EngineCommand::AddMarket {
+            pair: pair.clone(),
+            config: MarketConfig::default(),
+        });
+        engine.process(add).unwrap();
+        engine
+            .place_limit_order(pair.clone(), dec!(100), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        assert!(store.save(&engine.snapshot()).await.unwrap());
+        // The same sequence is not written twice.
+        assert!(!store.save(&engine.snapshot()).await.unwrap());
+        assert_eq!(store.latest_sequence().await.unwrap(), Some(1));
+
+        let restored = store.bootstrap().await.unwrap();
+        assert_eq!(
+            restored.depth(pair.clone(), 10).unwrap(),
+            engine.depth(pair.clone(), 10).unwrap()
+        );
+
+        // The stored snapshot starts a durable engine on a fresh log.
+        let path = std::env::temp_dir().join(format!("redis-bootstrap-{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let snapshot = store.load().await.unwrap().unwrap();
+        let mut durable =
+            DurableEngine::open_from_snapshot(&path, snapshot, FsyncPolicy::Always).unwrap();
+        durable
+            .execute(EngineCommand::CancelAll { pair: pair.clone() })
+            .unwrap();
+        assert_eq!(durable.wal().last_sequence(), 2);
+        std::fs::remove_file(&path).unwrap();
+
+        // The periodic task snapshots a running service.
+        let handle = EngineService::spawn(MatchingEngine::new());
+        let periodic = RedisSnapshotStore::new(backend.clone(), "service");
+        periodic.spawn_periodic(handle.clone(), Duration::from_millis(10));
+        handle
+            .add_market(pair.clone(), MarketConfig::default())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut reader = RedisSnapshotStore::new(backend, "service");
+        let engine = reader.bootstrap().await.unwrap();
+        assert!(engine.depth(pair, 10).is_ok());
+    }
+}
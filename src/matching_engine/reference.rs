@@ -0,0 +1,125 @@
+#![allow(dead_code)]
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Where a market's reference price comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReferenceSource {
+    /// The price of the last trade.
+    #[default]
+    LastTrade,
+    /// Halfway between the best bid and ask, whenever both sides are quoted.
+    MidPrice,
+    /// Only prices pushed with `MatchingEngine::set_reference_price`, e.g.
+    /// from an index or another venue.
+    External,
+}
+
+/// The single "current price" of a market: the price band is centred on
+/// it, stops trigger and trail on it, and it is reported as the mark price.
+/// Any source can be seeded with a pushed price before it produces one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReferencePrice {
+    source: ReferenceSource,
+    price: Option<Decimal>,
+}
+
+impl ReferencePrice {
+    pub fn source(&self) -> ReferenceSource {
+        self.source
+    }
+
+    pub fn set_source(&mut self, source: ReferenceSource) {
+        self.source = source;
+    }
+
+    pub fn price(&self) -> Option<Decimal> {
+        self.price
+    }
+
+    pub fn set(&mut self, price: Decimal) {
+        self.price = Some(price);
+    }
+
+    /// Follows the market after something happened to it: `last_trade` is
+    /// the price of its latest trade, if it just traded, and `mid` its
+    /// current mid price. Returns the new reference price if the source
+    /// produced one. Every trade counts for `LastTrade`, even at the same
+    /// price, while the mid price only counts when it moves.
+    pub fn observe(
+        &mut self,
+        last_trade: Option<Decimal>,
+        mid: Option<Decimal>,
+    ) -> Option<Decimal> {
+        let price = match self.source {
+            ReferenceSource::LastTrade => last_trade,
+            ReferenceSource::MidPrice => mid.filter(|&mid| self.price != Some(mid)),
+            ReferenceSource::External => None,
+        }?;
+        self.price = Some(price);
+        Some(price)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::{MatchingEngine, TradingPair};
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use crate::matching_engine::triggers::OrderType;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_reference_price_sources() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        engine.set_price_band(pair.clone(), Some(dec!(10))).unwrap();
+        engine
+            .set_reference_source(pair.clone(), ReferenceSource::External)
+            .unwrap();
+        engine.set_reference_price(pair.clone(), dec!(100)).unwrap();
+        let limit = |engine: &mut MatchingEngine, bid_or_ask, price| {
+            engine
+                .place_limit_order(pair.clone(), price, Order::new(bid_or_ask, dec!(1)))
+                .unwrap()
+        };
+        limit(&mut engine, BidOrAsk::Bid, dec!(90));
+        engine
+            .place_order(
+                pair.clone(),
+                OrderType::Stop {
+                    stop_price: dec!(95),
+                },
+                Order::new(BidOrAsk::Ask, dec!(1)),
+            )
+            .unwrap();
+
+        // Pushed prices trigger the stop; its trade does not move the band.
+        engine.set_reference_price(pair.clone(), dec!(96)).unwrap();
+        assert!(engine.recent_trades(pair.clone(), 1).unwrap().is_empty());
+        engine.set_reference_price(pair.clone(), dec!(95)).unwrap();
+        let stats = engine.stats(pair.clone()).unwrap();
+        assert_eq!(stats.last_price, Some(dec!(90)));
+        assert_eq!(stats.mark_price, Some(dec!(95)));
+        // 104 is inside the band around 95, but not around 90.
+        limit(&mut engine, BidOrAsk::Ask, dec!(104));
+
+        // The mid price follows the quotes, whatever the trades print.
+        engine
+            .set_reference_source(pair.clone(), ReferenceSource::MidPrice)
+            .unwrap();
+        limit(&mut engine, BidOrAsk::Bid, dec!(98));
+        limit(&mut engine, BidOrAsk::Ask, dec!(102));
+        let reference = engine.reference_price(pair.clone()).unwrap();
+        assert_eq!(reference.price(), Some(dec!(100)));
+        limit(&mut engine, BidOrAsk::Bid, dec!(102));
+        let stats = engine.stats(pair).unwrap();
+        assert_eq!(stats.last_price, Some(dec!(102)));
+        assert_eq!(stats.mark_price, Some(dec!(101)));
+    }
+}
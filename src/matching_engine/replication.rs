@@ -0,0 +1,317 @@
+#![allow(dead_code)]
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use super::command::{CommandOutput, EngineCommand, SequencedCommand};
+use super::engine::{EngineSnapshot, MatchingEngine};
+use super::error::EngineError;
+
+/// What a primary streams to its followers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationMessage {
+    /// An input the primary applied, in sequence.
+    Command(Box<SequencedCommand>),
+    /// The primary's `state_hash` right after applying input `sequence`.
+    StateHash { sequence: u64, state_hash: u64 },
+}
+
+/// Why a follower stopped following, or could not be promoted.
+#[derive(Debug, Error)]
+pub enum ReplicationError {
+    #[error("Replication stream skipped inputs: expected {expected}, got {got}")]
+    Gap { expected: u64, got: u64 },
+    #[error("State diverged from the primary at input {sequence}: hash {follower:#x}, primary has {primary:#x}")]
+    Diverged {
+        sequence: u64,
+        primary: u64,
+        follower: u64,
+    },
+    #[error("State is only verified up to input {verified}, but input {sequence} was applied")]
+    Unverified { verified: u64, sequence: u64 },
+    #[error("Replication stream failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Somewhere a primary sends its `ReplicationMessage`s.
+pub trait ReplicationSink: Send {
+    fn send(&mut self, message: &ReplicationMessage) -> io::Result<()>;
+}
+
+impl ReplicationSink for mpsc::Sender<ReplicationMessage> {
+    fn send(&mut self, message: &ReplicationMessage) -> io::Result<()> {
+        mpsc::Sender::send(self, message.clone())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Follower hung up"))
+    }
+}
+
+/// Messages written to a byte stream, e.g. a `TcpStream`, one JSON record
+/// per line. `read_messages` decodes them on the other end.
+#[derive(Debug)]
+pub struct JsonLines<W>(pub W);
+
+impl<W: Write + Send> ReplicationSink for JsonLines<W> {
+    fn send(&mut self, message: &ReplicationMessage) -> io::Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.0.write_all(line.as_bytes())?;
+        self.0.flush()
+    }
+}
+
+/// Decodes the messages `JsonLines` wrote to `reader`, until it ends.
+pub fn read_messages(reader: impl BufRead) -> impl Iterator<Item = io::Result<ReplicationMessage>> {
+    reader.lines().map(|line| Ok(serde_json::from_str(&line?)?))
+}
+
+/// An engine whose every input is streamed to its followers before it is
+/// applied, along with a state hash every `hash_every` inputs for them to
+/// check themselves against. Followers start from `snapshot()` and are
+/// dropped once they stop accepting messages.
+pub struct Primary {
+    engine: MatchingEngine,
+    followers: Vec<Box<dyn ReplicationSink>>,
+    hash_every: u64,
+}
+
+impl Primary {
+    pub fn new(engine: MatchingEngine, hash_every: u64) -> Primary {
+        Primary {
+            engine,
+            followers: Vec::new(),
+            hash_every: hash_every.max(1),
+        }
+    }
+
+    pub fn engine(&self) -> &MatchingEngine {
+        &self.engine
+    }
+
+    /// The state a new follower has to start from.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        self.engine.snapshot()
+    }
+
+    /// Streams every input from now on to `follower`, which must be at the
+    /// state of a `snapshot()` taken since the last input.
+    pub fn add_follower(&mut self, follower: impl ReplicationSink + 'static) {
+        self.followers.push(Box::new(follower));
+    }
+
+    pub fn follower_count(&self) -> usize {
+        self.followers.len()
+    }
+
+    /// Sequences `command`, streams it and applies it. Commands the engine
+    /// rejects are streamed too; followers reject them the same way.
+    pub fn execute(&mut self, command: EngineCommand) -> Result<CommandOutput, EngineError> {
+        let input = self.engine.stamp(command);
+        let sequence = input.sequence;
+        self.broadcast(&ReplicationMessage::Command(Box::new(input.clone())));
+        let output = self.engine.process(input);
+        if sequence.is_multiple_of(self.hash_every) {
+            self.send_state_hash();
+        }
+        output
+    }
+
+    /// Sends the current state hash, e.g. before a planned switchover, so
+    /// followers are verified up to the last input.
+    pub fn send_state_hash(&mut self) {
+        let message = ReplicationMessage::StateHash {
+            sequence: self.engine.sequence(),
+            state_hash: self.engine.state_hash(),
+        };
+        self.broadcast(&message);
+    }
+
+    fn broadcast(&mut self, message: &ReplicationMessage) {
+        self.followers
+            .retain_mut(|follower| match follower.send(message) {
+                Ok(()) => true,
+                Err(err) => {
+                    warn!(%err, "dropping follower");
+                    false
+                }
+            });
+    }
+}
+
+/// A backup engine applying a primary's input stream. Every state hash it
+/// receives is checked against its own; it can take over as primary once
+/// its state is verified up to the last input it applied.
+pub struct Follower {
+    engine: MatchingEngine,
+    verified: u64,
+}
+
+impl Follower {
+    /// Follows from `snapshot`, as taken with `Primary::snapshot`.
+    pub fn new(snapshot: EngineSnapshot) -> Follower {
+        let engine = MatchingEngine::restore(snapshot);
+        let verified = engine.sequence();
+        Follower { engine, verified }
+    }
+
+    pub fn engine(&self) -> &MatchingEngine {
+        &self.engine
+    }
+
+    /// Sequence of the last input at which the state matched the primary's.
+    pub fn verified_sequence(&self) -> u64 {
+        self.verified
+    }
+
+    /// Whether the state was verified at the last input applied.
+    pub fn is_verified(&self) -> bool {
+        self.verified == self.engine.sequence()
+    }
+
+    /// Applies one message. Inputs already applied are skipped; a missing
+    /// input or a state hash that does not match is an error, after which
+    /// the follower must be rebuilt from a fresh snapshot.
+    pub fn apply(&mut self, message: ReplicationMessage) -> Result<(), ReplicationError> {
+        match message {
+            ReplicationMessage::Command(input) => {
+                if input.sequence <= self.engine.sequence() {
+                    return Ok(());
+                }
+                match self.engine.process(*input) {
+                    Err(EngineError::OutOfSequence { expected, got }) => {
+                        Err(ReplicationError::Gap { expected, got })
+                    }
+                    // Failed commands failed on the primary too.
+                    _ => Ok(()),
+                }
+            }
+            ReplicationMessage::StateHash {
+                sequence,
+                state_hash,
+            } => {
+                if sequence != self.engine.sequence() {
+                    return Err(ReplicationError::Gap {
+                        expected: self.engine.sequence(),
+                        got: sequence,
+                    });
+                }
+                let follower = self.engine.state_hash();
+                if follower != state_hash {
+                    return Err(ReplicationError::Diverged {
+                        sequence,
+                        primary: state_hash,
+                        follower,
+                    });
+                }
+                self.verified = sequence;
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies every message until the stream ends, e.g. a channel's
+    /// `Receiver::iter` returning once the primary is gone.
+    pub fn follow(
+        &mut self,
+        messages: impl IntoIterator<Item = ReplicationMessage>,
+    ) -> Result<(), ReplicationError> {
+        messages
+            .into_iter()
+            .try_for_each(|message| self.apply(message))
+    }
+
+    /// Like `follow`, reading the messages `JsonLines` wrote to `reader`.
+    pub fn follow_stream(&mut self, reader: impl BufRead) -> Result<(), ReplicationError> {
+        for message in read_messages(reader) {
+            self.apply(message?)?;
+        }
+        Ok(())
+    }
+
+    /// Takes over as primary, continuing the input sequence. Refused unless
+    /// the state was verified at the last input applied.
+    pub fn promote(self, hash_every: u64) -> Result<Primary, ReplicationError> {
+        if !self.is_verified() {
+            return Err(ReplicationError::Unverified {
+                verified: self.verified,
+                sequence: self.engine.sequence(),
+            });
+        }
+        Ok(Primary::new(self.engine, hash_every))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use crate::matching_engine::triggers::OrderType;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    fn place(price: Decimal, bid_or_ask: BidOrAsk) -> EngineCommand {
+        EngineCommand::PlaceOrder {
+            pair: btc_usd(),
+            order_type: OrderType::Limit { price },
+            order: Order::new(bid_or_ask, dec!(1)),
+        }
+    }
+
+    #[test]
+    fn test_follower_verifies_and_takes_over() {
+        let mut primary = Primary::new(MatchingEngine::new(), 2);
+        let (sender, receiver) = mpsc::channel();
+        let mut backup = Follower::new(primary.snapshot());
+        primary.add_follower(sender);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut remote = Follower::new(primary.snapshot());
+        primary.add_follower(JsonLines(
+            TcpStream::connect(listener.local_addr().unwrap()).unwrap(),
+        ));
+        let (socket, _) = listener.accept().unwrap();
+
+        primary
+            .execute(EngineCommand::AddMarket {
+                pair: btc_usd(),
+                config: MarketConfig::default(),
+            })
+            .unwrap();
+        primary.execute(place(dec!(100), BidOrAsk::Bid)).unwrap();
+        primary.execute(place(dec!(101), BidOrAsk::Ask)).unwrap();
+
+        // The last hash was sent after input 2, so input 3 is unverified.
+        backup.follow(receiver.try_iter()).unwrap();
+        assert_eq!(backup.verified_sequence(), 2);
+        assert!(!backup.is_verified());
+        primary.send_state_hash();
+        backup.follow(receiver.try_iter()).unwrap();
+        assert!(backup.is_verified());
+        assert!(matches!(
+            backup.apply(ReplicationMessage::StateHash {
+                sequence: 3,
+                state_hash: 0,
+            }),
+            Err(ReplicationError::Diverged { sequence: 3, .. })
+        ));
+
+        // The primary fails; the TCP follower reads to the end and takes over.
+        let hash = primary.engine().state_hash();
+        drop(primary);
+        remote.follow_stream(BufReader::new(socket)).unwrap();
+        assert_eq!(remote.engine().state_hash(), hash);
+        let mut primary = remote.promote(2).unwrap();
+        primary.execute(place(dec!(102), BidOrAsk::Ask)).unwrap();
+        assert_eq!(primary.engine().sequence(), 4);
+        assert_eq!(primary.engine().depth(btc_usd(), 5).unwrap().asks.len(), 2);
+    }
+}
@@ -0,0 +1,479 @@
+#![allow(dead_code)]
+use std::io;
+use std::net::SocketAddr;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::command::EngineCommand;
+use super::config::{MarketConfig, NonConformingOrders};
+use super::engine::{MarketState, TradingPair};
+use super::error::{EngineError, OrderBookError};
+use super::fees::FeeSchedule;
+use super::orderbook::{AccountId, BidOrAsk, Order, OrderId, TimeInForce};
+use super::reference::ReferenceSource;
+use super::service::EngineHandle;
+
+/// Levels returned by `GET /orderbook/{pair}` unless `?levels=` says otherwise.
+pub const DEFAULT_DEPTH: usize = 20;
+/// Trades returned by `GET /trades/{pair}` unless `?limit=` says otherwise.
+pub const DEFAULT_TRADES: usize = 100;
+
+/// Body of `POST /orders`. Orders without a price are market orders.
+#[derive(Debug, Deserialize)]
+pub struct NewOrderRequest {
+    pub pair: String,
+    pub side: BidOrAsk,
+    pub price: Option<Decimal>,
+    pub size: Decimal,
+    #[serde(default)]
+    pub owner: AccountId,
+    pub client_order_id: Option<String>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelParams {
+    pub pair: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepthParams {
+    pub levels: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TradesParams {
+    pub limit: Option<usize>,
+}
+
+/// Body of `PUT /admin/markets/{pair}/config`.
+#[derive(Debug, Deserialize)]
+pub struct UpdateConfigRequest {
+    pub config: MarketConfig,
+    #[serde(default)]
+    pub non_conforming: NonConformingOrders,
+}
+
+/// Body of `PUT /admin/markets/{pair}/state`.
+#[derive(Debug, Deserialize)]
+pub struct MarketStateRequest {
+    pub state: MarketState,
+}
+
+/// Body of `PUT /admin/markets/{pair}/price-band`; no `percent` removes
+/// the band.
+#[derive(Debug, Deserialize)]
+pub struct PriceBandRequest {
+    pub percent: Option<Decimal>,
+}
+
+/// Body of `PUT /admin/markets/{pair}/reference`: a new source, a pushed
+/// price, or both.
+#[derive(Debug, Deserialize)]
+pub struct ReferenceRequest {
+    pub source: Option<ReferenceSource>,
+    pub price: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    /// `EngineError::code` of the failure.
+    #[serde(default)]
+    pub code: String,
+}
+
+// An engine error together with the status it is reported under.
+struct ApiError(StatusCode, EngineError);
+
+impl From<EngineError> for ApiError {
+    fn from(err: EngineError) -> ApiError {
+        let status = match &err {
+            EngineError::MarketNotFound(_)
+            | EngineError::OrderRejected {
+                reason: OrderBookError::UnknownOrderId(_),
+            } => StatusCode::NOT_FOUND,
+            EngineError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            EngineError::MarketUnavailable { .. }
+            | EngineError::KillSwitchActive(_)
+            | EngineError::NonConformingOrders { .. } => StatusCode::CONFLICT,
+            EngineError::ServiceStopped => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        ApiError(status, err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            error: self.1.to_string(),
+            code: self.1.code().to_string(),
+        };
+        (self.0, Json(body)).into_response()
+    }
+}
+
+/// Routes for order entry and market data, served from `handle`:
+///
+/// - `POST /orders` places a limit or market order
+/// - `DELETE /orders/{id}?pair=BTC-USD` cancels a resting order
+/// - `GET /orderbook/{pair}?levels=N` returns aggregated depth
+/// - `GET /trades/{pair}?limit=N` returns the most recent trades
+/// - `GET /metrics` returns the engine's metrics for Prometheus to scrape
+pub fn router(handle: EngineHandle) -> Router {
+    Router::new()
+        .route("/orders", post(place_order))
+        .route("/orders/{id}", delete(cancel_order))
+        .route("/orderbook/{pair}", get(order_book))
+        .route("/trades/{pair}", get(trades))
+        .route("/metrics", get(metrics))
+        .with_state(handle)
+}
+
+/// Routes for changing live markets, to be mounted where only operators
+/// can reach them:
+///
+/// - `PUT /admin/markets/{pair}/config` replaces the trading rules and
+///   returns the ids of orders cancelled for breaking them
+/// - `PUT /admin/markets/{pair}/state` halts, resumes or auctions the market
+/// - `PUT /admin/markets/{pair}/fees` sets the fee schedule
+/// - `PUT /admin/markets/{pair}/price-band` resizes or removes the band
+/// - `PUT /admin/markets/{pair}/reference` switches the reference price
+///   source or pushes a price from an external feed
+///
+/// Changes take effect immediately and are announced as engine events.
+pub fn admin_router(handle: EngineHandle) -> Router {
+    Router::new()
+        .route("/admin/markets/{pair}/config", put(update_config))
+        .route("/admin/markets/{pair}/state", put(set_state))
+        .route("/admin/markets/{pair}/fees", put(set_fees))
+        .route("/admin/markets/{pair}/price-band", put(set_price_band))
+        .route("/admin/markets/{pair}/reference", put(set_reference))
+        .with_state(handle)
+}
+
+/// Serves `router(handle)` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, handle: EngineHandle) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(handle)).await
+}
+
+async fn place_order(
+    State(handle): State<EngineHandle>,
+    Json(request): Json<NewOrderRequest>,
+) -> Result<Response, ApiError> {
+    let pair = request.pair.parse::<TradingPair>()?;
+    if request.size <= Decimal::ZERO {
+        return Err(EngineError::from(OrderBookError::InvalidSize(request.size)).into());
+    }
+
+    let mut order = Order::new(request.side, request.size)
+        .with_owner(request.owner)
+        .with_time_in_force(request.time_in_force);
+    if let Some(client_order_id) = request.client_order_id {
+        order = order.with_client_order_id(client_order_id);
+    }
+    let report = match request.price {
+        Some(price) => handle.place_limit_order(pair, price, order).await,
+        None => handle.place_market_order(pair, order).await,
+    }?;
+    Ok(Json(report).into_response())
+}
+
+async fn cancel_order(
+    State(handle): State<EngineHandle>,
+    Path(order_id): Path<u64>,
+    Query(params): Query<CancelParams>,
+) -> Result<Response, ApiError> {
+    let pair = params.pair.parse::<TradingPair>()?;
+    let order = handle.cancel_order(pair, OrderId(order_id)).await?;
+    Ok(Json(order).into_response())
+}
+
+async fn order_book(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Query(params): Query<DepthParams>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    let depth = handle
+        .depth(pair, params.levels.unwrap_or(DEFAULT_DEPTH))
+        .await?;
+    Ok(Json(depth).into_response())
+}
+
+async fn trades(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Query(params): Query<TradesParams>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    let trades = handle
+        .recent_trades(pair, params.limit.unwrap_or(DEFAULT_TRADES))
+        .await?;
+    Ok(Json(trades).into_response())
+}
+
+async fn update_config(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Json(request): Json<UpdateConfigRequest>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    let cancelled = handle
+        .update_market_config(pair, request.config, request.non_conforming)
+        .await?;
+    Ok(Json(cancelled).into_response())
+}
+
+async fn set_state(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Json(request): Json<MarketStateRequest>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    admin(
+        &handle,
+        EngineCommand::SetMarketState {
+            pair,
+            state: request.state,
+        },
+    )
+    .await
+}
+
+async fn set_fees(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Json(schedule): Json<FeeSchedule>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    admin(&handle, EngineCommand::SetFeeSchedule { pair, schedule }).await
+}
+
+async fn set_price_band(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Json(request): Json<PriceBandRequest>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    admin(
+        &handle,
+        EngineCommand::SetPriceBand {
+            pair,
+            percent: request.percent,
+        },
+    )
+    .await
+}
+
+async fn set_reference(
+    State(handle): State<EngineHandle>,
+    Path(pair): Path<String>,
+    Json(request): Json<ReferenceRequest>,
+) -> Result<Response, ApiError> {
+    let pair = pair.parse::<TradingPair>()?;
+    if let Some(source) = request.source {
+        let pair = pair.clone();
+        handle
+            .execute(EngineCommand::SetReferenceSource { pair, source })
+            .await?;
+    }
+    if let Some(price) = request.price {
+        handle
+            .execute(EngineCommand::SetReferencePrice { pair, price })
+            .await?;
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// Applies an admin command that has nothing to return.
+async fn admin(handle: &EngineHandle, command: EngineCommand) -> Result<Response, ApiError> {
+    handle.execute(command).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// Prometheus scrape endpoint.
+async fn metrics(State(handle): State<EngineHandle>) -> Result<Response, ApiError> {
+    let text = handle.metrics().await?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::orderbook::{DepthSnapshot, ExecutionReport, OrderStatus};
+    use crate::matching_engine::service::EngineService;
+    use crate::matching_engine::trade::Trade;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use rust_decimal_macros::dec;
+    use serde::de::DeserializeOwned;
+    use tower::ServiceExt;
+
+    async fn call<T: DeserializeOwned>(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: Option<&str>,
+    ) -> (StatusCode, T) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rest_order_entry_and_market_data() {
+        let handle = EngineService::spawn(MatchingEngine::new());
+        handle
+            .add_market(
+                TradingPair::new("BTC".to_string(), "USD".to_string()),
+                MarketConfig::default(),
+            )
+            .await
+            .unwrap();
+        let app = router(handle);
+
+        let (status, maker): (_, ExecutionReport) = call(
+            &app,
+            "POST",
+            "/orders",
+            Some(r#"{"pair": "BTC-USD", "side": "Ask", "price": "100", "size": "3"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(maker.status, OrderStatus::New);
+        assert_eq!(maker.remaining_size, dec!(3));
+        assert_eq!(maker.reason, None);
+
+        let (_, taker): (_, ExecutionReport) = call(
+            &app,
+            "POST",
+            "/orders",
+            Some(r#"{"pair": "BTC/USD", "side": "Bid", "size": "1"}"#),
+        )
+        .await;
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(taker.filled_size, dec!(1));
+        assert_eq!(taker.average_price, Some(dec!(100)));
+        assert_eq!(taker.trades[0].maker_order_id, maker.order_id);
+
+        // The book's own rejections are acknowledged with their reason.
+        let (status, killed): (_, ExecutionReport) = call(
+            &app,
+            "POST",
+            "/orders",
+            Some(r#"{"pair": "BTC-USD", "side": "Bid", "price": "100", "size": "5", "time_in_force": "FillOrKill"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(killed.status, OrderStatus::Rejected);
+        assert_eq!(
+            killed.reason.as_ref().map(OrderBookError::code),
+            Some("insufficient_liquidity")
+        );
+
+        let (_, depth): (_, DepthSnapshot) = call(&app, "GET", "/orderbook/BTC-USD", None).await;
+        assert_eq!(depth.asks[0].size, dec!(2));
+
+        let (_, trades): (_, Vec<Trade>) =
+            call(&app, "GET", "/trades/BTC-USD?limit=10", None).await;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(100));
+
+        let uri = format!("/orders/{}?pair=BTC-USD", maker.order_id);
+        let (status, _): (_, Order) = call(&app, "DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, error): (_, ErrorResponse) = call(&app, "DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(error.error.contains("No open order"));
+        assert_eq!(error.code, "unknown_order_id");
+
+        let (status, error): (_, ErrorResponse) =
+            call(&app, "GET", "/orderbook/ETH-USD", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error.code, "market_not_found");
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(text.contains("engine_trades_total{pair=\"BTC/USD\"} 1"));
+        assert!(
+            text.contains("engine_command_duration_seconds_count{command=\"place_limit_order\"} 2")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rest_admin_updates_live_market() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let handle = EngineService::spawn(MatchingEngine::new());
+        handle
+            .add_market(pair.clone(), MarketConfig::default())
+            .await
+            .unwrap();
+        let resting = handle
+            .place_limit_order(
+                pair.clone(),
+                dec!(100.3),
+                Order::new(BidOrAsk::Ask, dec!(1)),
+            )
+            .await
+            .unwrap();
+        let app = admin_router(handle.clone());
+        let config = r#"{"config": {"tick_size": "0.5", "lot_size": null, "min_notional": null, "max_order_size": null}}"#;
+
+        let (status, error): (_, ErrorResponse) =
+            call(&app, "PUT", "/admin/markets/BTC-USD/config", Some(config)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(error.error.contains("break the new market config"));
+
+        let cancelling = config.replacen('}', r#"}, "non_conforming": "Cancel""#, 1);
+        let (status, cancelled): (_, Vec<OrderId>) = call(
+            &app,
+            "PUT",
+            "/admin/markets/BTC-USD/config",
+            Some(&cancelling),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cancelled, [resting.order_id]);
+
+        let request = Request::put("/admin/markets/BTC-USD/reference")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"source": "External", "price": "101.5"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let stats = handle.stats(pair.clone()).await.unwrap();
+        assert_eq!(stats.mark_price, Some(dec!(101.5)));
+
+        let request = Request::put("/admin/markets/BTC-USD/state")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"state": "Halted"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(handle
+            .place_limit_order(pair, dec!(100), Order::new(BidOrAsk::Ask, dec!(1)))
+            .await
+            .is_err());
+    }
+}
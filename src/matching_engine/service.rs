@@ -0,0 +1,587 @@
+#![allow(dead_code)]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_decimal::prelude::*;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use super::command::{CommandOutput, EngineCommand};
+use super::config::{MarketConfig, NonConformingOrders};
+use super::engine::{fnv1a, EngineSnapshot, MatchingEngine, OpenOrder, TradingPair};
+use super::error::EngineError;
+use super::events::EngineEvent;
+use super::orderbook::{AccountId, DepthSnapshot, ExecutionReport, Order, OrderId};
+use super::stats::MarketSummary;
+use super::trade::Trade;
+
+/// Number of commands that may queue up before senders have to wait.
+pub const COMMAND_BUFFER: usize = 1024;
+/// Market data events buffered per subscriber before a slow one falls
+/// behind and has to resynchronize.
+pub const EVENT_BUFFER: usize = 4096;
+
+/// A request to the engine task. Each carries the channel its result is
+/// sent back on.
+#[derive(Debug)]
+pub enum Command {
+    AddMarket {
+        pair: TradingPair,
+        config: MarketConfig,
+        reply: oneshot::Sender<()>,
+    },
+    PlaceLimit {
+        pair: TradingPair,
+        price: Decimal,
+        order: Order,
+        reply: oneshot::Sender<Result<ExecutionReport, EngineError>>,
+    },
+    PlaceMarket {
+        pair: TradingPair,
+        order: Order,
+        reply: oneshot::Sender<Result<ExecutionReport, EngineError>>,
+    },
+    Cancel {
+        pair: TradingPair,
+        order_id: OrderId,
+        reply: oneshot::Sender<Result<Order, EngineError>>,
+    },
+    Query {
+        query: Query,
+        reply: oneshot::Sender<Result<QueryResult, EngineError>>,
+    },
+    ExpireOrders {
+        reply: oneshot::Sender<Vec<(TradingPair, OrderId)>>,
+    },
+    Snapshot {
+        reply: oneshot::Sender<EngineSnapshot>,
+    },
+    /// Any other state change, e.g. from the admin API.
+    Execute {
+        command: Box<EngineCommand>,
+        reply: oneshot::Sender<Result<CommandOutput, EngineError>>,
+    },
+}
+
+/// Read-only requests answered from the engine's current state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Depth {
+        pair: TradingPair,
+        levels: usize,
+    },
+    OpenOrders {
+        account: AccountId,
+    },
+    RecentTrades {
+        pair: TradingPair,
+        count: usize,
+    },
+    Stats {
+        pair: TradingPair,
+    },
+    /// Every metric, in the Prometheus text format.
+    Metrics,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    Depth(DepthSnapshot),
+    OpenOrders(Vec<OpenOrder>),
+    Trades(Vec<Trade>),
+    Stats(MarketSummary),
+    Metrics(String),
+}
+
+/// Runs a `MatchingEngine` on its own task. The task is the engine's only
+/// owner, so commands are applied one at a time in arrival order without
+/// any locking; callers talk to it through cloneable `EngineHandle`s.
+pub struct EngineService {
+    engine: MatchingEngine,
+    commands: mpsc::Receiver<Command>,
+}
+
+impl EngineService {
+    /// Spawns the engine task on the current tokio runtime. The task stops
+    /// once every handle has been dropped.
+    pub fn spawn(engine: MatchingEngine) -> EngineHandle {
+        let (sender, commands) = mpsc::channel(COMMAND_BUFFER);
+        let service = EngineService { engine, commands };
+        tokio::spawn(service.run());
+        EngineHandle { commands: sender }
+    }
+
+    /// Like `spawn`, but runs the engine on a dedicated OS thread so it
+    /// never competes with other tasks for a runtime worker.
+    pub fn spawn_thread(engine: MatchingEngine) -> EngineHandle {
+        let (sender, commands) = mpsc::channel(COMMAND_BUFFER);
+        let mut service = EngineService { engine, commands };
+        thread::spawn(move || {
+            while let Some(command) = service.commands.blocking_recv() {
+                service.handle(command);
+            }
+        });
+        EngineHandle { commands: sender }
+    }
+
+    async fn run(mut self) {
+        while let Some(command) = self.commands.recv().await {
+            self.handle(command);
+        }
+    }
+
+    // Times every command but queries in the engine's metrics; `execute`
+    // times its own.
+    fn handle(&mut self, command: Command) {
+        let kind = match &command {
+            Command::AddMarket { .. } => "add_market",
+            Command::PlaceLimit { .. } => "place_limit_order",
+            Command::PlaceMarket { .. } => "place_market_order",
+            Command::Cancel { .. } => "cancel_order",
+            Command::ExpireOrders { .. } => "expire_orders",
+            Command::Query { .. } | Command::Snapshot { .. } | Command::Execute { .. } => {
+                return self.dispatch(command)
+            }
+        };
+        let started = Instant::now();
+        self.dispatch(command);
+        self.engine
+            .metrics_mut()
+            .record_command(kind, started.elapsed());
+    }
+
+    // Replies are dropped if the caller has stopped waiting.
+    fn dispatch(&mut self, command: Command) {
+        match command {
+            Command::AddMarket {
+                pair,
+                config,
+                reply,
+            } => {
+                self.engine.add_new_market(pair, config);
+                let _ = reply.send(());
+            }
+            Command::PlaceLimit {
+                pair,
+                price,
+                order,
+                reply,
+            } => {
+                let _ = reply.send(self.engine.place_limit_order(pair, price, order));
+            }
+            Command::PlaceMarket { pair, order, reply } => {
+                let _ = reply.send(self.engine.place_market_order(pair, order));
+            }
+            Command::Cancel {
+                pair,
+                order_id,
+                reply,
+            } => {
+                let _ = reply.send(self.engine.cancel_order(pair, order_id));
+            }
+            Command::Query { query, reply } => {
+                let _ = reply.send(self.query(query));
+            }
+            Command::ExpireOrders { reply } => {
+                let _ = reply.send(self.engine.expire_orders());
+            }
+            Command::Snapshot { reply } => {
+                let _ = reply.send(self.engine.snapshot());
+            }
+            Command::Execute { command, reply } => {
+                let _ = reply.send(self.engine.execute(*command));
+            }
+        }
+    }
+
+    fn query(&self, query: Query) -> Result<QueryResult, EngineError> {
+        match query {
+            Query::Depth { pair, levels } => {
+                self.engine.depth(pair, levels).map(QueryResult::Depth)
+            }
+            Query::OpenOrders { account } => {
+                Ok(QueryResult::OpenOrders(self.engine.open_orders(account)))
+            }
+            Query::RecentTrades { pair, count } => self
+                .engine
+                .recent_trades(pair, count)
+                .map(QueryResult::Trades),
+            Query::Stats { pair } => self.engine.stats(pair).map(QueryResult::Stats),
+            Query::Metrics => Ok(QueryResult::Metrics(self.engine.prometheus_metrics())),
+        }
+    }
+}
+
+/// Cloneable client for an `EngineService`.
+#[derive(Debug, Clone)]
+pub struct EngineHandle {
+    commands: mpsc::Sender<Command>,
+}
+
+impl EngineHandle {
+    // Sends the command built around a fresh reply channel and waits for
+    // the answer.
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T, EngineError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| EngineError::ServiceStopped)?;
+        response.await.map_err(|_| EngineError::ServiceStopped)
+    }
+
+    pub async fn add_market(
+        &self,
+        pair: TradingPair,
+        config: MarketConfig,
+    ) -> Result<(), EngineError> {
+        self.request(|reply| Command::AddMarket {
+            pair,
+            config,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn place_limit_order(
+        &self,
+        pair: TradingPair,
+        price: Decimal,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.request(|reply| Command::PlaceLimit {
+            pair,
+            price,
+            order,
+            reply,
+        })
+        .await?
+    }
+
+    pub async fn place_market_order(
+        &self,
+        pair: TradingPair,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.request(|reply| Command::PlaceMarket { pair, order, reply })
+            .await?
+    }
+
+    pub async fn cancel_order(
+        &self,
+        pair: TradingPair,
+        order_id: OrderId,
+    ) -> Result<Order, EngineError> {
+        self.request(|reply| Command::Cancel {
+            pair,
+            order_id,
+            reply,
+        })
+        .await?
+    }
+
+    /// Cancels the good-till-date orders that have expired, in every market.
+    pub async fn expire_orders(&self) -> Result<Vec<(TradingPair, OrderId)>, EngineError> {
+        self.request(|reply| Command::ExpireOrders { reply }).await
+    }
+
+    /// The engine's complete state as of now.
+    pub async fn snapshot(&self) -> Result<EngineSnapshot, EngineError> {
+        self.request(|reply| Command::Snapshot { reply }).await
+    }
+
+    /// Starts a task that times out overdue sessions every `period`,
+    /// cancelling the orders of accounts that opted in. The task ends once
+    /// the service stops.
+    pub fn spawn_session_sweep(&self, period: Duration) -> JoinHandle<()> {
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if handle.execute(EngineCommand::ExpireSessions).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Applies `command` through `MatchingEngine::execute`.
+    pub async fn execute(&self, command: EngineCommand) -> Result<CommandOutput, EngineError> {
+        self.request(|reply| Command::Execute {
+            command: Box::new(command),
+            reply,
+        })
+        .await?
+    }
+
+    /// See `MatchingEngine::update_market_config`.
+    pub async fn update_market_config(
+        &self,
+        pair: TradingPair,
+        config: MarketConfig,
+        non_conforming: NonConformingOrders,
+    ) -> Result<Vec<OrderId>, EngineError> {
+        let command = EngineCommand::UpdateMarketConfig {
+            pair,
+            config,
+            non_conforming,
+        };
+        match self.execute(command).await? {
+            CommandOutput::CancelledIds(cancelled) => Ok(cancelled),
+            output => unreachable!("update_market_config returned {:?}", output),
+        }
+    }
+
+    /// Starts a task that calls `expire_orders` every `period`, so expired
+    /// orders leave quiet markets too. The task ends once the service stops.
+    pub fn spawn_expiry_sweep(&self, period: Duration) -> JoinHandle<()> {
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if handle.expire_orders().await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    pub async fn query(&self, query: Query) -> Result<QueryResult, EngineError> {
+        self.request(|reply| Command::Query { query, reply })
+            .await?
+    }
+
+    pub async fn depth(
+        &self,
+        pair: TradingPair,
+        levels: usize,
+    ) -> Result<DepthSnapshot, EngineError> {
+        match self.query(Query::Depth { pair, levels }).await? {
+            QueryResult::Depth(depth) => Ok(depth),
+            other => unreachable!("Unexpected query result: {:?}", other),
+        }
+    }
+
+    pub async fn open_orders(&self, account: AccountId) -> Result<Vec<OpenOrder>, EngineError> {
+        match self.query(Query::OpenOrders { account }).await? {
+            QueryResult::OpenOrders(orders) => Ok(orders),
+            other => unreachable!("Unexpected query result: {:?}", other),
+        }
+    }
+
+    pub async fn recent_trades(
+        &self,
+        pair: TradingPair,
+        count: usize,
+    ) -> Result<Vec<Trade>, EngineError> {
+        match self.query(Query::RecentTrades { pair, count }).await? {
+            QueryResult::Trades(trades) => Ok(trades),
+            other => unreachable!("Unexpected query result: {:?}", other),
+        }
+    }
+
+    pub async fn stats(&self, pair: TradingPair) -> Result<MarketSummary, EngineError> {
+        match self.query(Query::Stats { pair }).await? {
+            QueryResult::Stats(stats) => Ok(stats),
+            other => unreachable!("Unexpected query result: {:?}", other),
+        }
+    }
+
+    pub async fn metrics(&self) -> Result<String, EngineError> {
+        match self.query(Query::Metrics).await? {
+            QueryResult::Metrics(text) => Ok(text),
+            other => unreachable!("Unexpected query result: {:?}", other),
+        }
+    }
+}
+
+/// Fans the engine's trade and book events out to any number of async
+/// subscribers, such as WebSocket and gRPC streams.
+#[derive(Debug, Clone)]
+pub struct MarketDataFeed {
+    events: broadcast::Sender<EngineEvent>,
+}
+
+impl MarketDataFeed {
+    /// Registers the feed as a listener on `engine`; call before handing the
+    /// engine to an `EngineService`.
+    pub fn attach(engine: &mut MatchingEngine) -> MarketDataFeed {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let sender = events.clone();
+        engine.add_listener(move |event: &EngineEvent| {
+            if matches!(
+                event,
+                EngineEvent::TradeExecuted { .. } | EngineEvent::BookUpdated { .. }
+            ) {
+                let _ = sender.send(event.clone());
+            }
+        });
+        MarketDataFeed { events }
+    }
+
+    /// Receives every event published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
+}
+
+/// Spreads markets over several independent engines. Books for different
+/// pairs share no state, so each shard owns a disjoint set of markets and
+/// processes its own command queue on its own thread; commands are routed
+/// by a hash of the pair that is the same in every process and build.
+#[derive(Debug, Clone)]
+pub struct EngineRouter {
+    shards: Vec<EngineHandle>,
+}
+
+impl EngineRouter {
+    /// Starts `shard_count` engines, each on its own thread.
+    pub fn spawn(shard_count: usize) -> EngineRouter {
+        let shards = (0..shard_count.max(1))
+            .map(|_| EngineService::spawn_thread(MatchingEngine::new()))
+            .collect();
+        EngineRouter { shards }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The shard owning `pair`.
+    pub fn shard(&self, pair: &TradingPair) -> &EngineHandle {
+        let hash = fnv1a(pair.to_string().as_bytes());
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    pub async fn add_market(
+        &self,
+        pair: TradingPair,
+        config: MarketConfig,
+    ) -> Result<(), EngineError> {
+        self.shard(&pair).add_market(pair, config).await
+    }
+
+    pub async fn place_limit_order(
+        &self,
+        pair: TradingPair,
+        price: Decimal,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.shard(&pair)
+            .place_limit_order(pair, price, order)
+            .await
+    }
+
+    pub async fn place_market_order(
+        &self,
+        pair: TradingPair,
+        order: Order,
+    ) -> Result<ExecutionReport, EngineError> {
+        self.shard(&pair).place_market_order(pair, order).await
+    }
+
+    pub async fn cancel_order(
+        &self,
+        pair: TradingPair,
+        order_id: OrderId,
+    ) -> Result<Order, EngineError> {
+        self.shard(&pair).cancel_order(pair, order_id).await
+    }
+
+    pub async fn depth(
+        &self,
+        pair: TradingPair,
+        levels: usize,
+    ) -> Result<DepthSnapshot, EngineError> {
+        self.shard(&pair).depth(pair, levels).await
+    }
+
+    /// Open orders across all shards; every shard is asked in turn.
+    pub async fn open_orders(&self, account: AccountId) -> Result<Vec<OpenOrder>, EngineError> {
+        let mut orders = Vec::new();
+        for shard in &self.shards {
+            orders.extend(shard.open_orders(account).await?);
+        }
+        Ok(orders)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::{BidOrAsk, OrderStatus};
+    use rust_decimal_macros::dec;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_service_processes_commands() {
+        let handle = EngineService::spawn(MatchingEngine::new());
+        handle
+            .add_market(btc_usd(), MarketConfig::default())
+            .await
+            .unwrap();
+
+        let maker = handle
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(3)))
+            .await
+            .unwrap();
+        let other = handle.clone();
+        let taker = tokio::spawn(async move {
+            other
+                .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(1)))
+                .await
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(taker.status, OrderStatus::Filled);
+
+        let depth = handle.depth(btc_usd(), 1).await.unwrap();
+        assert_eq!(depth.asks[0].size, dec!(2));
+
+        handle
+            .cancel_order(btc_usd(), maker.order_id)
+            .await
+            .unwrap();
+        assert!(handle
+            .open_orders(AccountId::default())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(handle.depth(btc_usd(), 1).await.unwrap().asks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_router_shards_markets() {
+        let router = EngineRouter::spawn(4);
+        let pairs: Vec<TradingPair> = ["BTC", "ETH", "SOL", "XRP", "ADA"]
+            .iter()
+            .map(|base| TradingPair::new(base.to_string(), "USD".to_string()))
+            .collect();
+        for pair in &pairs {
+            router
+                .add_market(pair.clone(), MarketConfig::default())
+                .await
+                .unwrap();
+            router
+                .place_limit_order(pair.clone(), dec!(10), Order::new(BidOrAsk::Bid, dec!(1)))
+                .await
+                .unwrap();
+        }
+
+        // Each market only exists on its own shard.
+        for pair in &pairs {
+            assert_eq!(router.depth(pair.clone(), 1).await.unwrap().bids.len(), 1);
+        }
+        let open_orders = router.open_orders(AccountId::default()).await.unwrap();
+        assert_eq!(open_orders.len(), pairs.len());
+    }
+}
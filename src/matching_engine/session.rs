@@ -0,0 +1,218 @@
+#![allow(dead_code)]
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::events::EngineEvent;
+use super::orderbook::{AccountId, OrderId};
+
+/// Identifier the engine assigns to a session when it opens.
+#[derive(Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SessionId(pub u64);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Why a session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SessionEnd {
+    /// The client logged out; its orders are left working.
+    Logout,
+    /// The connection dropped.
+    Disconnected,
+    /// No heartbeat arrived within the session's timeout.
+    TimedOut,
+}
+
+/// A logged-in connection of one account.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Session {
+    pub account: AccountId,
+    /// Cancel the account's orders when its last session drops.
+    pub cancel_on_disconnect: bool,
+    /// Milliseconds without a heartbeat after which the session times out;
+    /// `None` never does.
+    pub heartbeat_timeout: Option<u64>,
+    pub last_heartbeat: u64,
+}
+
+impl Session {
+    fn timed_out(&self, now: u64) -> bool {
+        self.heartbeat_timeout
+            .is_some_and(|timeout| now.saturating_sub(self.last_heartbeat) >= timeout)
+    }
+}
+
+/// The engine's open sessions. They are part of its snapshot, so sessions
+/// that never reconnect after a restart time out like any other.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sessions {
+    last_id: u64,
+    open: BTreeMap<SessionId, Session>,
+}
+
+impl Sessions {
+    pub fn iter(&self) -> impl Iterator<Item = (SessionId, &Session)> {
+        self.open.iter().map(|(id, session)| (*id, session))
+    }
+
+    pub fn get(&self, id: SessionId) -> Option<&Session> {
+        self.open.get(&id)
+    }
+}
+
+impl MatchingEngine {
+    /// Opens a session for `account`. With `cancel_on_disconnect`, every
+    /// working order of the account is cancelled, in all markets, once its
+    /// last session disconnects or times out. Timeouts are only noticed by
+    /// `expire_sessions`, which should run periodically.
+    pub fn login(
+        &mut self,
+        account: AccountId,
+        cancel_on_disconnect: bool,
+        heartbeat_timeout: Option<u64>,
+    ) -> SessionId {
+        let now = self.now();
+        let sessions = self.sessions_mut();
+        sessions.last_id += 1;
+        let session = SessionId(sessions.last_id);
+        sessions.open.insert(
+            session,
+            Session {
+                account,
+                cancel_on_disconnect,
+                heartbeat_timeout,
+                last_heartbeat: now,
+            },
+        );
+        self.publish(&EngineEvent::SessionOpened { session, account });
+        session
+    }
+
+    pub fn heartbeat(&mut self, session: SessionId) -> Result<(), EngineError> {
+        let now = self.now();
+        let session = self
+            .sessions_mut()
+            .open
+            .get_mut(&session)
+            .ok_or(EngineError::UnknownSession(session))?;
+        session.last_heartbeat = now;
+        Ok(())
+    }
+
+    /// Ends `session`, returning the orders cancelled because of it.
+    pub fn close_session(
+        &mut self,
+        session: SessionId,
+        reason: SessionEnd,
+    ) -> Result<Vec<(TradingPair, OrderId)>, EngineError> {
+        let closed = self
+            .sessions_mut()
+            .open
+            .remove(&session)
+            .ok_or(EngineError::UnknownSession(session))?;
+        let account = closed.account;
+        self.publish(&EngineEvent::SessionClosed {
+            session,
+            account,
+            reason,
+        });
+
+        let still_connected = self
+            .sessions()
+            .any(|(_, session)| session.account == account);
+        if !closed.cancel_on_disconnect || reason == SessionEnd::Logout || still_connected {
+            return Ok(Vec::new());
+        }
+        Ok(self.cancel_everywhere(Some(account)))
+    }
+
+    /// Times out every session whose heartbeat is overdue, returning the
+    /// orders cancelled because of them.
+    pub fn expire_sessions(&mut self) -> Vec<(TradingPair, OrderId)> {
+        let now = self.now();
+        let overdue: Vec<SessionId> = self
+            .sessions()
+            .filter(|(_, session)| session.timed_out(now))
+            .map(|(id, _)| id)
+            .collect();
+        overdue
+            .into_iter()
+            .flat_map(|session| {
+                self.close_session(session, SessionEnd::TimedOut)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::clock::ManualClock;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_session_cancels_on_disconnect_and_timeout() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let clock = ManualClock::new(0);
+        let mut engine = MatchingEngine::new();
+        engine.set_clock(clock.clone());
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        let (maker, other) = (AccountId(1), AccountId(2));
+        let quote = |engine: &mut MatchingEngine, account| {
+            engine
+                .place_limit_order(
+                    pair.clone(),
+                    dec!(100),
+                    Order::new(BidOrAsk::Ask, dec!(1)).with_owner(account),
+                )
+                .unwrap()
+                .order_id
+        };
+
+        // Both connections have to drop before the orders are pulled.
+        let first = engine.login(maker, true, None);
+        let second = engine.login(maker, true, Some(1_000));
+        let resting = quote(&mut engine, maker);
+        quote(&mut engine, other);
+        assert_eq!(
+            engine.close_session(first, SessionEnd::Disconnected),
+            Ok(vec![])
+        );
+        clock.advance(999);
+        engine.heartbeat(second).unwrap();
+        clock.advance(999);
+        assert!(engine.expire_sessions().is_empty());
+        clock.advance(1);
+        assert_eq!(engine.expire_sessions(), [(pair.clone(), resting)]);
+        assert_eq!(engine.sessions().count(), 0);
+        assert_eq!(
+            engine.heartbeat(second),
+            Err(EngineError::UnknownSession(second))
+        );
+
+        // A clean logout leaves the orders working.
+        let session = engine.login(maker, true, None);
+        quote(&mut engine, maker);
+        assert_eq!(
+            engine.close_session(session, SessionEnd::Logout),
+            Ok(vec![])
+        );
+        let depth = engine.depth(pair, 1).unwrap();
+        assert_eq!(depth.asks[0].order_count, 2);
+    }
+}
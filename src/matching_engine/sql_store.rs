@@ -0,0 +1,469 @@
+#![allow(dead_code)]
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rust_decimal::prelude::*;
+use sqlx::any::AnyRow;
+use sqlx::{AnyPool, Row};
+use thiserror::Error;
+
+use super::clock::{system_clock, Clock};
+use super::engine::TradingPair;
+use super::events::EngineEvent;
+use super::export::{order_row, trade_row, Field, ORDER_COLUMNS, TRADE_COLUMNS};
+use super::ledger::{Ledger, LedgerEntry, Posting};
+use super::orderbook::{AccountId, BidOrAsk, OrderId};
+use super::trade::Trade;
+
+#[derive(Debug, Error)]
+pub enum SqlStoreError {
+    #[error("Database request failed: {0}")]
+    Sql(#[from] sqlx::Error),
+    #[error("Stored value is unreadable: {0}")]
+    Corrupt(String),
+}
+
+// Columns holding ids and timestamps; everything else is text, so prices
+// and sizes keep their exact decimal representation.
+fn is_integer(column: &str) -> bool {
+    column == "timestamp" || column.ends_with("_id") || column.ends_with("account")
+}
+
+fn create_table(table: &str, columns: &[&str]) -> String {
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|column| match *column {
+            "timestamp" => "timestamp BIGINT NOT NULL".to_string(),
+            "pair" => "pair TEXT NOT NULL".to_string(),
+            column if is_integer(column) => format!("{} BIGINT", column),
+            column => format!("{} TEXT", column),
+        })
+        .collect();
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (seq BIGINT PRIMARY KEY, {})",
+        table,
+        columns.join(", ")
+    )
+}
+
+fn insert(table: &str, columns: &[&str]) -> String {
+    let placeholders: Vec<String> = (1..=columns.len() + 1).map(|i| format!("${}", i)).collect();
+    format!(
+        "INSERT INTO {} (seq, {}) VALUES ({})",
+        table,
+        columns.join(", "),
+        placeholders.join(", ")
+    )
+}
+
+const LEDGER_SCHEMA: [&str; 2] = [
+    "CREATE TABLE IF NOT EXISTS ledger_entries (id BIGINT PRIMARY KEY, pair TEXT NOT NULL, \
+     maker_order_id BIGINT NOT NULL, taker_order_id BIGINT NOT NULL, timestamp BIGINT NOT NULL)",
+    "CREATE TABLE IF NOT EXISTS ledger_postings (entry_id BIGINT NOT NULL, \
+     position BIGINT NOT NULL, account BIGINT NOT NULL, asset TEXT NOT NULL, \
+     amount TEXT NOT NULL, PRIMARY KEY (entry_id, position))",
+];
+
+const INDEXES: [&str; 4] = [
+    "CREATE INDEX IF NOT EXISTS trades_maker_account ON trades (maker_account, timestamp)",
+    "CREATE INDEX IF NOT EXISTS trades_taker_account ON trades (taker_account, timestamp)",
+    "CREATE INDEX IF NOT EXISTS orders_order_id ON orders (order_id)",
+    "CREATE INDEX IF NOT EXISTS ledger_postings_account ON ledger_postings (account)",
+];
+
+// Ids are u64 but SQL integers are signed; they are stored bit for bit, so
+// `FEE_ACCOUNT` reads as -1 in the database.
+fn to_sql(value: u64) -> i64 {
+    value as i64
+}
+
+// Timestamps past `i64::MAX` only show up as open range ends.
+fn timestamp_bound(timestamp: u64) -> i64 {
+    timestamp.min(i64::MAX as u64) as i64
+}
+
+fn integer(row: &AnyRow, column: &str) -> Result<u64, SqlStoreError> {
+    Ok(row.try_get::<i64, _>(column)? as u64)
+}
+
+fn optional_integer(row: &AnyRow, column: &str) -> Result<Option<u64>, SqlStoreError> {
+    Ok(row
+        .try_get::<Option<i64>, _>(column)?
+        .map(|value| value as u64))
+}
+
+fn parse<T: FromStr>(column: &str, text: String) -> Result<T, SqlStoreError> {
+    text.parse()
+        .map_err(|_| SqlStoreError::Corrupt(format!("{} {:?}", column, text)))
+}
+
+fn optional_text<T: FromStr>(row: &AnyRow, column: &str) -> Result<Option<T>, SqlStoreError> {
+    row.try_get::<Option<String>, _>(column)?
+        .map(|text| parse(column, text))
+        .transpose()
+}
+
+fn text<T: FromStr>(row: &AnyRow, column: &str) -> Result<T, SqlStoreError> {
+    parse(column, row.try_get(column)?)
+}
+
+fn side(row: &AnyRow, column: &str) -> Result<Option<BidOrAsk>, SqlStoreError> {
+    match row.try_get::<Option<String>, _>(column)?.as_deref() {
+        Some("buy") => Ok(Some(BidOrAsk::Bid)),
+        Some("sell") => Ok(Some(BidOrAsk::Ask)),
+        Some(other) => Err(SqlStoreError::Corrupt(format!("{} {:?}", column, other))),
+        None => Ok(None),
+    }
+}
+
+/// One stored order lifecycle event, with the columns of `ORDER_COLUMNS`.
+/// Fields an event does not carry are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRecord {
+    pub timestamp: u64,
+    pub pair: TradingPair,
+    pub event: String,
+    pub order_id: Option<OrderId>,
+    pub account: Option<AccountId>,
+    pub bid_or_ask: Option<BidOrAsk>,
+    pub order_type: Option<String>,
+    pub price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
+    pub size: Option<Decimal>,
+    pub reason: Option<String>,
+}
+
+/// Persists trades, order lifecycle events and ledger entries to SQLite or
+/// Postgres, so execution history outlives the process. The `trades` and
+/// `orders` tables have the columns of the history export, plus a `seq`
+/// numbering rows in the order they were recorded. The store assumes it is the
+/// only writer.
+pub struct SqlStore {
+    pool: AnyPool,
+    trade_seq: AtomicU64,
+    order_seq: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+impl SqlStore {
+    /// Connects to `url`, e.g. `sqlite://history.db?mode=rwc` or
+    /// `postgres://user@localhost/engine`, and creates any missing tables.
+    pub async fn connect(url: &str) -> Result<SqlStore, SqlStoreError> {
+        sqlx::any::install_default_drivers();
+        SqlStore::new(AnyPool::connect(url).await?).await
+    }
+
+    /// Uses `pool`, creating any missing tables. An in-memory SQLite
+    /// database is per connection, so give it a pool of one.
+    pub async fn new(pool: AnyPool) -> Result<SqlStore, SqlStoreError> {
+        let tables = [
+            create_table("trades", &TRADE_COLUMNS),
+            create_table("orders", &ORDER_COLUMNS),
+        ];
+        let statements = tables
+            .iter()
+            .map(String::as_str)
+            .chain(LEDGER_SCHEMA)
+            .chain(INDEXES);
+        for statement in statements {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        let mut store = SqlStore {
+            pool,
+            trade_seq: AtomicU64::new(0),
+            order_seq: AtomicU64::new(0),
+            clock: system_clock(),
+        };
+        store.trade_seq = AtomicU64::new(store.max("seq", "trades").await?);
+        store.order_seq = AtomicU64::new(store.max("seq", "orders").await?);
+        Ok(store)
+    }
+
+    /// Replaces the system clock order events are stamped with, e.g. with
+    /// the engine's own when replaying or backtesting.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    async fn max(&self, column: &str, table: &str) -> Result<u64, SqlStoreError> {
+        let max: Option<i64> =
+            sqlx::query_scalar(&format!("SELECT MAX({}) FROM {}", column, table))
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(max.unwrap_or_default() as u64)
+    }
+
+    async fn insert_row(
+        &self,
+        table: &str,
+        columns: &[&str],
+        seq: &AtomicU64,
+        row: Vec<Field>,
+    ) -> Result<(), SqlStoreError> {
+        let sql = insert(table, columns);
+        let mut query = sqlx::query(&sql).bind(to_sql(seq.fetch_add(1, Ordering::SeqCst) + 1));
+        for (column, field) in columns.iter().zip(row) {
+            query = match field {
+                Field::Number(number) => query.bind(to_sql(number)),
+                Field::Text(text) => query.bind(text),
+                Field::Empty if is_integer(column) => query.bind(None::<i64>),
+                Field::Empty => query.bind(None::<String>),
+            };
+        }
+        query.execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Stores `event` if it is a trade or an order lifecycle event; returns
+    /// whether it was. As in the export, order events are stamped with the
+    /// time they are recorded.
+    pub async fn record(&self, event: &EngineEvent) -> Result<bool, SqlStoreError> {
+        if let EngineEvent::TradeExecuted { pair, trade } = event {
+            let row = trade_row(&pair.to_string(), trade);
+            self.insert_row("trades", &TRADE_COLUMNS, &self.trade_seq, row)
+                .await?;
+            return Ok(true);
+        }
+        match order_row(event, self.clock.now()) {
+            Some(row) => {
+                self.insert_row("orders", &ORDER_COLUMNS, &self.order_seq, row)
+                    .await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Stores the entries of `ledger` newer than the latest stored one,
+    /// each in its own transaction. Returns how many were stored.
+    pub async fn record_ledger(&self, ledger: &Ledger) -> Result<usize, SqlStoreError> {
+        let stored = self.max("id", "ledger_entries").await?;
+        let mut recorded = 0;
+        for entry in ledger.entries().iter().filter(|entry| entry.id > stored) {
+            let mut transaction = self.pool.begin().await?;
+            sqlx::query(
+                "INSERT INTO ledger_entries (id, pair, maker_order_id, taker_order_id, timestamp) \
+                 VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(to_sql(entry.id))
+            .bind(entry.pair.to_string())
+            .bind(to_sql(entry.maker_order_id.0))
+            .bind(to_sql(entry.taker_order_id.0))
+            .bind(to_sql(entry.timestamp))
+            .execute(&mut *transaction)
+            .await?;
+            for (position, posting) in entry.postings.iter().enumerate() {
+                sqlx::query(
+                    "INSERT INTO ledger_postings (entry_id, position, account, asset, amount) \
+                     VALUES ($1, $2, $3, $4, $5)",
+                )
+                .bind(to_sql(entry.id))
+                .bind(position as i64)
+                .bind(to_sql(posting.account.0))
+                .bind(posting.asset.clone())
+                .bind(posting.amount.to_string())
+                .execute(&mut *transaction)
+                .await?;
+            }
+            transaction.commit().await?;
+            recorded += 1;
+        }
+        Ok(recorded)
+    }
+
+    /// Trades `account` was on either side of, with timestamps in `range`,
+    /// oldest first.
+    pub async fn trades_for_account(
+        &self,
+        account: AccountId,
+        range: Range<u64>,
+    ) -> Result<Vec<(TradingPair, Trade)>, SqlStoreError> {
+        let sql = format!(
+            "SELECT {} FROM trades WHERE (maker_account = $1 OR taker_account = $1) \
+             AND timestamp >= $2 AND timestamp < $3 ORDER BY seq",
+            TRADE_COLUMNS.join(", ")
+        );
+        let rows = sqlx::query(&sql)
+            .bind(to_sql(account.0))
+            .bind(timestamp_bound(range.start))
+            .bind(timestamp_bound(range.end))
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let trade = Trade {
+                    maker_order_id: OrderId(integer(row, "maker_order_id")?),
+                    taker_order_id: OrderId(integer(row, "taker_order_id")?),
+                    price: text(row, "price")?,
+                    size: text(row, "size")?,
+                    timestamp: integer(row, "timestamp")?,
+                    taker_side: side(row, "taker_side")?
+                        .ok_or_else(|| SqlStoreError::Corrupt("taker_side NULL".to_string()))?,
+                    maker_owner: AccountId(integer(row, "maker_account")?),
+                    taker_owner: AccountId(integer(row, "taker_account")?),
+                    maker_fee: text(row, "maker_fee")?,
+                    taker_fee: text(row, "taker_fee")?,
+                };
+                Ok((text(row, "pair")?, trade))
+            })
+            .collect()
+    }
+
+    /// Every recorded lifecycle event of `order_id`, in the order they were
+    /// recorded.
+    pub async fn order_history(
+        &self,
+        order_id: OrderId,
+    ) -> Result<Vec<OrderRecord>, SqlStoreError> {
+        let sql = format!(
+            "SELECT {} FROM orders WHERE order_id = $1 ORDER BY seq",
+            ORDER_COLUMNS.join(", ")
+        );
+        let rows = sqlx::query(&sql)
+            .bind(to_sql(order_id.0))
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(OrderRecord {
+                    timestamp: integer(row, "timestamp")?,
+                    pair: text(row, "pair")?,
+                    event: row.try_get("event")?,
+                    order_id: optional_integer(row, "order_id")?.map(OrderId),
+                    account: optional_integer(row, "account")?.map(AccountId),
+                    bid_or_ask: side(row, "side")?,
+                    order_type: row.try_get("order_type")?,
+                    price: optional_text(row, "price")?,
+                    stop_price: optional_text(row, "stop_price")?,
+                    size: optional_text(row, "size")?,
+                    reason: row.try_get("reason")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Ledger entries with postings to `account` and timestamps in `range`,
+    /// oldest first, each with all of its postings.
+    pub async fn ledger_for_account(
+        &self,
+        account: AccountId,
+        range: Range<u64>,
+    ) -> Result<Vec<LedgerEntry>, SqlStoreError> {
+        let rows = sqlx::query(
+            "SELECT e.id, e.pair, e.maker_order_id, e.taker_order_id, e.timestamp, \
+             p.account, p.asset, p.amount \
+             FROM ledger_entries e JOIN ledger_postings p ON p.entry_id = e.id \
+             WHERE e.id IN (SELECT entry_id FROM ledger_postings WHERE account = $1) \
+             AND e.timestamp >= $2 AND e.timestamp < $3 \
+             ORDER BY e.id, p.position",
+        )
+        .bind(to_sql(account.0))
+        .bind(timestamp_bound(range.start))
+        .bind(timestamp_bound(range.end))
+        .fetch_all(&self.pool)
+        .await?;
+        let mut entries: Vec<LedgerEntry> = Vec::new();
+        for row in &rows {
+            let id = integer(row, "id")?;
+            if entries.last().map(|entry| entry.id) != Some(id) {
+                entries.push(LedgerEntry {
+                    id,
+                    pair: text(row, "pair")?,
+                    maker_order_id: OrderId(integer(row, "maker_order_id")?),
+                    taker_order_id: OrderId(integer(row, "taker_order_id")?),
+                    timestamp: integer(row, "timestamp")?,
+                    postings: Vec::new(),
+                });
+            }
+            if let Some(entry) = entries.last_mut() {
+                entry.postings.push(Posting {
+                    account: AccountId(integer(row, "account")?),
+                    asset: row.try_get("asset")?,
+                    amount: text(row, "amount")?,
+                });
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::clock::ManualClock;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::orderbook::Order;
+    use rust_decimal_macros::dec;
+    use sqlx::any::AnyPoolOptions;
+
+    #[tokio::test]
+    async fn test_sql_store_round_trips_history() {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let mut store = SqlStore::new(pool).await.unwrap();
+        store.set_clock(ManualClock::new(7_000));
+
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let (alice, bob) = (AccountId(1), AccountId(2));
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        let events = engine.subscribe();
+        let maker = engine
+            .place_limit_order(
+                pair.clone(),
+                dec!(30000.5),
+                Order::new(BidOrAsk::Ask, dec!(2)).with_owner(alice),
+            )
+            .unwrap();
+        engine
+            .place_market_order(
+                pair.clone(),
+                Order::new(BidOrAsk::Bid, dec!(0.25)).with_owner(bob),
+            )
+            .unwrap();
+        engine.cancel_order(pair.clone(), maker.order_id).unwrap();
+        for event in events.try_iter() {
+            store.record(&event).await.unwrap();
+        }
+        assert_eq!(store.record_ledger(engine.ledger()).await.unwrap(), 1);
+        assert_eq!(store.record_ledger(engine.ledger()).await.unwrap(), 0);
+
+        let trades = store.trades_for_account(bob, 0..u64::MAX).await.unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(
+            trades[0],
+            (
+                pair.clone(),
+                engine.recent_trades(pair.clone(), 1).unwrap()[0].clone()
+            )
+        );
+        let timestamp = trades[0].1.timestamp;
+        assert!(store
+            .trades_for_account(alice, 0..timestamp)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .trades_for_account(AccountId(3), 0..u64::MAX)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let history = store.order_history(maker.order_id).await.unwrap();
+        let names: Vec<&str> = history.iter().map(|record| record.event.as_str()).collect();
+        assert_eq!(names, ["accepted", "cancelled"]);
+        assert_eq!(history[0].timestamp, 7_000);
+        assert_eq!(history[0].account, Some(alice));
+        assert_eq!(history[0].price, Some(dec!(30000.5)));
+        assert_eq!(history[1].size, None);
+
+        let ledger = store.ledger_for_account(alice, 0..u64::MAX).await.unwrap();
+        assert_eq!(ledger, engine.ledger().entries());
+    }
+}
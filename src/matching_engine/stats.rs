@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+use std::collections::VecDeque;
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::trade::Trade;
+
+/// Length of the rolling statistics window, in milliseconds.
+pub const STATS_WINDOW: u64 = 24 * 60 * 60 * 1000;
+
+/// Ticker numbers for a market, as of some point in time. Everything but
+/// `last_price` and `mark_price` covers only the trades inside the rolling
+/// window.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MarketSummary {
+    pub last_price: Option<Decimal>,
+    pub open: Option<Decimal>,
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+    pub volume: Decimal,
+    pub trade_count: usize,
+    /// The market's reference price, as filled in by `MatchingEngine::stats`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mark_price: Option<Decimal>,
+}
+
+impl MarketSummary {
+    /// Last price minus the first price traded inside the window.
+    pub fn price_change(&self) -> Option<Decimal> {
+        Some(self.last_price? - self.open?)
+    }
+
+    /// `price_change` as a percentage of the window's opening price.
+    pub fn price_change_percent(&self) -> Option<Decimal> {
+        let open = self.open?;
+        if open.is_zero() {
+            return None;
+        }
+        Some(self.price_change()? / open * Decimal::ONE_HUNDRED)
+    }
+}
+
+/// Rolling per-market statistics, updated on every trade. Aggregates are
+/// maintained incrementally; high and low are only rescanned when the
+/// trade that set them leaves the window.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MarketStats {
+    window: u64,
+    // (timestamp, price, size) of every trade inside the window, oldest first.
+    trades: VecDeque<(u64, Decimal, Decimal)>,
+    summary: MarketSummary,
+}
+
+impl Default for MarketStats {
+    fn default() -> MarketStats {
+        MarketStats::new(STATS_WINDOW)
+    }
+}
+
+impl MarketStats {
+    pub fn new(window: u64) -> MarketStats {
+        MarketStats {
+            window,
+            trades: VecDeque::new(),
+            summary: MarketSummary::default(),
+        }
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        self.trades
+            .push_back((trade.timestamp, trade.price, trade.size));
+        let summary = &mut self.summary;
+        summary.last_price = Some(trade.price);
+        summary.open = summary.open.or(Some(trade.price));
+        summary.high = Some(
+            summary
+                .high
+                .map_or(trade.price, |high| high.max(trade.price)),
+        );
+        summary.low = Some(summary.low.map_or(trade.price, |low| low.min(trade.price)));
+        summary.volume += trade.size;
+        summary.trade_count += 1;
+
+        self.expire(trade.timestamp);
+    }
+
+    /// Drops trades that fell out of the window as of `now`.
+    pub fn expire(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.window);
+        let mut rescan = false;
+        while let Some(&(timestamp, price, size)) = self.trades.front() {
+            if timestamp >= cutoff {
+                break;
+            }
+            self.trades.pop_front();
+            self.summary.volume -= size;
+            self.summary.trade_count -= 1;
+            rescan |= Some(price) == self.summary.high || Some(price) == self.summary.low;
+        }
+
+        self.summary.open = self.trades.front().map(|&(_, price, _)| price);
+        if rescan {
+            let prices = self.trades.iter().map(|&(_, price, _)| price);
+            self.summary.high = prices.clone().max();
+            self.summary.low = prices.min();
+        }
+    }
+
+    /// The statistics as of the most recent trade.
+    pub fn summary(&self) -> &MarketSummary {
+        &self.summary
+    }
+
+    /// The statistics as of `now`, leaving out trades that have since
+    /// fallen out of the window.
+    pub fn summary_at(&self, now: u64) -> MarketSummary {
+        let cutoff = now.saturating_sub(self.window);
+        if self
+            .trades
+            .front()
+            .is_none_or(|&(timestamp, _, _)| timestamp >= cutoff)
+        {
+            return self.summary.clone();
+        }
+        let mut stats = self.clone();
+        stats.expire(now);
+        stats.summary
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::{BidOrAsk, OrderId};
+    use rust_decimal_macros::dec;
+
+    fn trade_at(timestamp: u64, price: Decimal, size: Decimal) -> Trade {
+        let mut trade = Trade::new(OrderId(1), OrderId(2), price, size, BidOrAsk::Bid);
+        trade.timestamp = timestamp;
+        trade
+    }
+
+    #[test]
+    fn test_stats_rolling_window() {
+        let mut stats = MarketStats::new(1000);
+        stats.record(&trade_at(0, dec!(100), dec!(1)));
+        stats.record(&trade_at(500, dec!(120), dec!(2)));
+        stats.record(&trade_at(900, dec!(110), dec!(3)));
+
+        let summary = stats.summary();
+        assert_eq!(summary.last_price, Some(dec!(110)));
+        assert_eq!(summary.high, Some(dec!(120)));
+        assert_eq!(summary.low, Some(dec!(100)));
+        assert_eq!(summary.volume, dec!(6));
+        assert_eq!(summary.price_change(), Some(dec!(10)));
+        assert_eq!(summary.price_change_percent(), Some(dec!(10)));
+
+        // The first trade leaves the window, taking the low with it.
+        stats.record(&trade_at(1200, dec!(115), dec!(1)));
+        let summary = stats.summary();
+        assert_eq!(summary.open, Some(dec!(120)));
+        assert_eq!(summary.low, Some(dec!(110)));
+        assert_eq!(summary.volume, dec!(6));
+        assert_eq!(summary.trade_count, 3);
+
+        let summary = stats.summary_at(5000);
+        assert_eq!(summary.last_price, Some(dec!(115)));
+        assert_eq!(summary.volume, Decimal::ZERO);
+        assert_eq!(summary.high, None);
+        assert_eq!(summary.price_change(), None);
+    }
+}
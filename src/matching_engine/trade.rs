@@ -0,0 +1,60 @@
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::orderbook::{AccountId, BidOrAsk, OrderId};
+
+/// A single execution between a resting (maker) order and an incoming
+/// (taker) order. `timestamp` is in milliseconds since the Unix epoch.
+/// Fees are in the asset each side receives, see `FeeSchedule`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Trade {
+    pub maker_order_id: OrderId,
+    pub taker_order_id: OrderId,
+    pub price: Decimal,
+    pub size: Decimal,
+    pub timestamp: u64,
+    /// Side of the incoming order, i.e. whether the trade was a buy or a
+    /// sell from the aggressor's point of view.
+    pub taker_side: BidOrAsk,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub maker_owner: AccountId,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub taker_owner: AccountId,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub maker_fee: Decimal,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub taker_fee: Decimal,
+}
+
+impl Trade {
+    /// A trade with no owners or fees, stamped at time 0: whatever matched
+    /// it stamps it with the time from its own clock.
+    pub fn new(
+        maker_order_id: OrderId,
+        taker_order_id: OrderId,
+        price: Decimal,
+        size: Decimal,
+        taker_side: BidOrAsk,
+    ) -> Trade {
+        Trade {
+            maker_order_id,
+            taker_order_id,
+            price,
+            size,
+            timestamp: 0,
+            taker_side,
+            maker_owner: AccountId::default(),
+            taker_owner: AccountId::default(),
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+        }
+    }
+
+    pub fn with_owners(mut self, maker_owner: AccountId, taker_owner: AccountId) -> Trade {
+        self.maker_owner = maker_owner;
+        self.taker_owner = taker_owner;
+        self
+    }
+}
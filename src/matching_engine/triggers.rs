@@ -0,0 +1,349 @@
+#![allow(dead_code)]
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::error::OrderBookError;
+use super::orderbook::{BidOrAsk, Order, OrderId};
+
+/// How an order enters the market. Stop orders are held by the
+/// `TriggerManager` until the last trade price touches their stop price,
+/// after which they are submitted as a market or limit order. Trailing stops
+/// trigger as market orders.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OrderType {
+    Market,
+    Limit {
+        price: Decimal,
+    },
+    Stop {
+        stop_price: Decimal,
+    },
+    StopLimit {
+        stop_price: Decimal,
+        limit_price: Decimal,
+    },
+    /// A stop whose price follows the best price traded since it was placed:
+    /// `offset` below the high for sells, above the low for buys.
+    TrailingStop {
+        offset: TrailingOffset,
+    },
+}
+
+impl OrderType {
+    /// The fixed stop price; trailing stops have none.
+    pub fn stop_price(&self) -> Option<Decimal> {
+        match self {
+            OrderType::Stop { stop_price } | OrderType::StopLimit { stop_price, .. } => {
+                Some(*stop_price)
+            }
+            OrderType::Market | OrderType::Limit { .. } | OrderType::TrailingStop { .. } => None,
+        }
+    }
+}
+
+/// Distance a trailing stop keeps from the market.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TrailingOffset {
+    Amount(Decimal),
+    /// Percentage of the watermark price, between 0 and 100.
+    Percent(Decimal),
+}
+
+impl TrailingOffset {
+    pub fn is_valid(self) -> bool {
+        match self {
+            TrailingOffset::Amount(amount) => amount > Decimal::ZERO,
+            TrailingOffset::Percent(percent) => {
+                percent > Decimal::ZERO && percent < Decimal::ONE_HUNDRED
+            }
+        }
+    }
+
+    /// Stop price of a `bid_or_ask` trailing stop whose best price so far
+    /// is `watermark`.
+    pub fn stop_price(self, bid_or_ask: BidOrAsk, watermark: Decimal) -> Decimal {
+        let distance = match self {
+            TrailingOffset::Amount(amount) => amount,
+            TrailingOffset::Percent(percent) => watermark * percent / Decimal::ONE_HUNDRED,
+        };
+        match bid_or_ask {
+            BidOrAsk::Bid => watermark + distance,
+            BidOrAsk::Ask => watermark - distance,
+        }
+    }
+}
+
+/// Pending stop orders for a single market, keyed by stop price.
+///
+/// Buy stops trigger once the last trade price rises to or above their stop
+/// price, sell stops once it falls to or below it.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TriggerManager {
+    last_trade_price: Option<Decimal>,
+    buy_stops: BTreeMap<Decimal, Vec<(OrderType, Order)>>,
+    sell_stops: BTreeMap<Decimal, Vec<(OrderType, Order)>>,
+    index: HashMap<OrderId, (BidOrAsk, Decimal)>,
+    // Best price traded since each trailing stop was placed: the high for
+    // sells, the low for buys.
+    #[cfg_attr(feature = "serde", serde(default))]
+    watermarks: HashMap<OrderId, Decimal>,
+}
+
+impl TriggerManager {
+    pub fn new() -> TriggerManager {
+        TriggerManager::default()
+    }
+
+    pub fn last_trade_price(&self) -> Option<Decimal> {
+        self.last_trade_price
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains_order(&self, id: OrderId) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    /// Every pending stop order, buy stops then sell stops.
+    pub fn orders(&self) -> impl Iterator<Item = &(OrderType, Order)> {
+        self.buy_stops
+            .values()
+            .chain(self.sell_stops.values())
+            .flatten()
+    }
+
+    /// Current trigger price of a pending stop.
+    pub fn current_stop_price(&self, id: OrderId) -> Option<Decimal> {
+        self.index.get(&id).map(|(_, stop_price)| *stop_price)
+    }
+
+    /// Queues a stop order. The order must already carry its id and
+    /// `order_type` must be `Stop`, `StopLimit` or `TrailingStop`. Trailing
+    /// stops start trailing from the last trade price, so one is required.
+    pub fn add_order(&mut self, order_type: OrderType, order: Order) -> Result<(), OrderBookError> {
+        let stop_price = match order_type {
+            OrderType::TrailingStop { offset } => {
+                let reference = self
+                    .last_trade_price
+                    .ok_or(OrderBookError::NoLastTradePrice)?;
+                self.watermarks.insert(order.id(), reference);
+                offset.stop_price(order.bid_or_ask(), reference)
+            }
+            _ => order_type
+                .stop_price()
+                .ok_or(OrderBookError::NotAStopOrder(order_type))?,
+        };
+        self.insert(stop_price, order_type, order);
+        Ok(())
+    }
+
+    fn insert(&mut self, stop_price: Decimal, order_type: OrderType, order: Order) {
+        self.index
+            .insert(order.id(), (order.bid_or_ask(), stop_price));
+        let stops = match order.bid_or_ask() {
+            BidOrAsk::Bid => &mut self.buy_stops,
+            BidOrAsk::Ask => &mut self.sell_stops,
+        };
+        stops
+            .entry(stop_price)
+            .or_default()
+            .push((order_type, order));
+    }
+
+    pub fn order(&self, id: OrderId) -> Option<&Order> {
+        let (bid_or_ask, stop_price) = self.index.get(&id)?;
+        let stops = match bid_or_ask {
+            BidOrAsk::Bid => &self.buy_stops,
+            BidOrAsk::Ask => &self.sell_stops,
+        };
+        stops
+            .get(stop_price)?
+            .iter()
+            .map(|(_, order)| order)
+            .find(|order| order.id() == id)
+    }
+
+    pub fn cancel_order(&mut self, id: OrderId) -> Option<Order> {
+        self.watermarks.remove(&id);
+        self.take(id).map(|(_, order)| order)
+    }
+
+    fn take(&mut self, id: OrderId) -> Option<(OrderType, Order)> {
+        let (bid_or_ask, stop_price) = self.index.remove(&id)?;
+        let stops = match bid_or_ask {
+            BidOrAsk::Bid => &mut self.buy_stops,
+            BidOrAsk::Ask => &mut self.sell_stops,
+        };
+
+        let queue = stops.get_mut(&stop_price)?;
+        let position = queue.iter().position(|(_, order)| order.id() == id)?;
+        let entry = queue.remove(position);
+        if queue.is_empty() {
+            stops.remove(&stop_price);
+        }
+        Some(entry)
+    }
+
+    // Moves the watermarks of trailing stops to `price` where it improves on
+    // them, re-queueing those stops at their new stop price.
+    fn trail(&mut self, price: Decimal) {
+        let mut moved: Vec<OrderId> = self
+            .watermarks
+            .iter_mut()
+            .filter_map(|(id, watermark)| {
+                let improves = match self.index.get(id)?.0 {
+                    BidOrAsk::Bid => price < *watermark,
+                    BidOrAsk::Ask => price > *watermark,
+                };
+                improves.then(|| {
+                    *watermark = price;
+                    *id
+                })
+            })
+            .collect();
+        // Re-queue in arrival order, whatever order the map yields.
+        moved.sort();
+        for id in moved {
+            let Some((order_type, order)) = self.take(id) else {
+                continue;
+            };
+            if let OrderType::TrailingStop { offset } = order_type {
+                let stop_price = offset.stop_price(order.bid_or_ask(), price);
+                self.insert(stop_price, order_type, order);
+            }
+        }
+    }
+
+    /// Records a new last trade price and returns the stop orders it
+    /// triggered, in stop price then arrival order. Trailing stops follow a
+    /// favourable price before triggers are checked.
+    pub fn on_trade(&mut self, price: Decimal) -> Vec<(OrderType, Order)> {
+        self.last_trade_price = Some(price);
+        self.trail(price);
+
+        // Buy stops at or below the price, sell stops at or above it.
+        let mut remaining_buys = self.buy_stops.split_off(&price);
+        if let Some(at_price) = remaining_buys.remove(&price) {
+            self.buy_stops.insert(price, at_price);
+        }
+        let triggered_buys = std::mem::replace(&mut self.buy_stops, remaining_buys);
+        let triggered_sells = self.sell_stops.split_off(&price);
+
+        let triggered: Vec<(OrderType, Order)> = triggered_buys
+            .into_values()
+            .chain(triggered_sells.into_values().rev())
+            .flatten()
+            .collect();
+        for (_, order) in &triggered {
+            self.index.remove(&order.id());
+            self.watermarks.remove(&order.id());
+        }
+        triggered
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::super::orderbook::OrderBook;
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn stop_order(orderbook: &mut OrderBook, bid_or_ask: BidOrAsk) -> Order {
+        let mut order = Order::new(bid_or_ask, dec!(1));
+        orderbook.reserve_order_id(&mut order);
+        order
+    }
+
+    #[test]
+    fn test_trigger_manager_on_trade() {
+        let mut orderbook = OrderBook::new();
+        let mut triggers = TriggerManager::new();
+        let stop = |stop_price| OrderType::Stop { stop_price };
+        for (stop_price, bid_or_ask) in [
+            (dec!(105), BidOrAsk::Bid),
+            (dec!(110), BidOrAsk::Bid),
+            (dec!(95), BidOrAsk::Ask),
+        ] {
+            let order = stop_order(&mut orderbook, bid_or_ask);
+            triggers.add_order(stop(stop_price), order).unwrap();
+        }
+
+        assert!(triggers.on_trade(dec!(100)).is_empty());
+        assert_eq!(triggers.last_trade_price(), Some(dec!(100)));
+
+        let triggered = triggers.on_trade(dec!(105));
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].1.id(), OrderId(1));
+
+        let triggered = triggers.on_trade(dec!(94));
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].1.id(), OrderId(3));
+        assert_eq!(triggers.len(), 1);
+    }
+
+    #[test]
+    fn test_trigger_manager_trailing_stop() {
+        let mut orderbook = OrderBook::new();
+        let mut triggers = TriggerManager::new();
+        let sell = stop_order(&mut orderbook, BidOrAsk::Ask);
+        let trailing = |offset| OrderType::TrailingStop { offset };
+        assert_eq!(
+            triggers.add_order(trailing(TrailingOffset::Amount(dec!(5))), sell.clone()),
+            Err(OrderBookError::NoLastTradePrice)
+        );
+
+        triggers.on_trade(dec!(100));
+        triggers
+            .add_order(trailing(TrailingOffset::Amount(dec!(5))), sell)
+            .unwrap();
+        assert_eq!(triggers.current_stop_price(OrderId(1)), Some(dec!(95)));
+
+        // A rally lifts the sell stop; the pullback leaves it where it was.
+        assert!(triggers.on_trade(dec!(110)).is_empty());
+        assert!(triggers.on_trade(dec!(106)).is_empty());
+        assert_eq!(triggers.current_stop_price(OrderId(1)), Some(dec!(105)));
+        let triggered = triggers.on_trade(dec!(105));
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].1.id(), OrderId(1));
+
+        let buy = stop_order(&mut orderbook, BidOrAsk::Bid);
+        triggers
+            .add_order(trailing(TrailingOffset::Percent(dec!(10))), buy)
+            .unwrap();
+        assert_eq!(triggers.current_stop_price(OrderId(2)), Some(dec!(115.5)));
+        assert!(triggers.on_trade(dec!(90)).is_empty());
+        assert_eq!(triggers.current_stop_price(OrderId(2)), Some(dec!(99)));
+        assert_eq!(triggers.on_trade(dec!(99)).len(), 1);
+        assert!(triggers.is_empty());
+    }
+
+    #[test]
+    fn test_trigger_manager_cancel_order() {
+        let mut orderbook = OrderBook::new();
+        let mut triggers = TriggerManager::new();
+        let order_type = OrderType::StopLimit {
+            stop_price: dec!(90),
+            limit_price: dec!(89),
+        };
+        let order = stop_order(&mut orderbook, BidOrAsk::Ask);
+        triggers.add_order(order_type, order).unwrap();
+
+        assert!(triggers.cancel_order(OrderId(1)).is_some());
+        assert!(triggers.cancel_order(OrderId(1)).is_none());
+        assert!(triggers.on_trade(dec!(80)).is_empty());
+        let order = stop_order(&mut orderbook, BidOrAsk::Ask);
+        assert!(triggers.add_order(OrderType::Market, order).is_err());
+    }
+}
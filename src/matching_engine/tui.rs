@@ -0,0 +1,238 @@
+#![allow(dead_code)]
+use std::collections::{BTreeMap, VecDeque};
+
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::Frame;
+use rust_decimal::prelude::*;
+
+use super::engine::TradingPair;
+use super::events::EngineEvent;
+use super::orderbook::{BidOrAsk, BookUpdate, DepthSnapshot};
+use super::stats::MarketStats;
+use super::trade::Trade;
+
+/// Number of recent trades a view keeps.
+pub const VIEW_TRADES: usize = 50;
+
+/// What the viewer shows of one market, kept current from the engine's
+/// events: the full depth ladder, the latest trades and rolling stats.
+#[derive(Debug, Clone)]
+pub struct MarketView {
+    pair: TradingPair,
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    // Newest first.
+    trades: VecDeque<Trade>,
+    stats: MarketStats,
+}
+
+impl MarketView {
+    /// Starts from `depth`, which should be taken right before subscribing
+    /// so no update is missed.
+    pub fn new(pair: TradingPair, depth: DepthSnapshot) -> MarketView {
+        MarketView {
+            pair,
+            bids: depth
+                .bids
+                .iter()
+                .map(|level| (level.price, level.size))
+                .collect(),
+            asks: depth
+                .asks
+                .iter()
+                .map(|level| (level.price, level.size))
+                .collect(),
+            trades: VecDeque::new(),
+            stats: MarketStats::default(),
+        }
+    }
+
+    pub fn pair(&self) -> &TradingPair {
+        &self.pair
+    }
+
+    fn side_mut(&mut self, bid_or_ask: BidOrAsk) -> &mut BTreeMap<Decimal, Decimal> {
+        match bid_or_ask {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
+        }
+    }
+
+    /// Applies `event` if it is about this market.
+    pub fn on_event(&mut self, event: &EngineEvent) {
+        match event {
+            EngineEvent::BookUpdated { pair, update } if *pair == self.pair => match update {
+                BookUpdate::Add {
+                    bid_or_ask,
+                    price,
+                    size,
+                    ..
+                }
+                | BookUpdate::Reduce {
+                    bid_or_ask,
+                    price,
+                    size,
+                    ..
+                } => {
+                    self.side_mut(*bid_or_ask).insert(*price, *size);
+                }
+                BookUpdate::Remove {
+                    bid_or_ask, price, ..
+                } => {
+                    self.side_mut(*bid_or_ask).remove(price);
+                }
+            },
+            EngineEvent::TradeExecuted { pair, trade } if *pair == self.pair => {
+                self.stats.record(trade);
+                self.trades.push_front(trade.clone());
+                self.trades.truncate(VIEW_TRADES);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn trades(&self) -> impl Iterator<Item = &Trade> {
+        self.trades.iter()
+    }
+}
+
+fn or_dash(value: Option<Decimal>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+/// Draws `view`: the depth ladder on the left, stats and recent trades on
+/// the right. The ladder shows as many levels per side as fit.
+pub fn draw(frame: &mut Frame, view: &MarketView) {
+    let [ladder_area, side_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(frame.area());
+    let [stats_area, trades_area] =
+        Layout::vertical([Constraint::Length(8), Constraint::Min(0)]).areas(side_area);
+
+    // Borders and the header take three rows; the rest is split between
+    // the sides.
+    let per_side = (ladder_area.height.saturating_sub(3) / 2) as usize;
+    let asks: Vec<Row> = view
+        .asks
+        .iter()
+        .take(per_side)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .map(|(price, size)| {
+            Row::new(vec![String::new(), price.to_string(), size.to_string()])
+                .style(Style::new().fg(Color::Red))
+        })
+        .collect();
+    let bids = view.bids.iter().rev().take(per_side).map(|(price, size)| {
+        Row::new(vec![size.to_string(), price.to_string(), String::new()])
+            .style(Style::new().fg(Color::Green))
+    });
+    let ladder = Table::new(asks.into_iter().chain(bids), [Constraint::Ratio(1, 3); 3])
+        .header(Row::new(vec!["bid size", "price", "ask size"]).bold())
+        .block(Block::bordered().title(format!(" {} ", view.pair)));
+    frame.render_widget(ladder, ladder_area);
+
+    let summary = view.stats.summary();
+    let spread = view
+        .best_ask()
+        .zip(view.best_bid())
+        .map(|(ask, bid)| ask - bid);
+    let stats = Paragraph::new(vec![
+        Line::from(format!("last    {}", or_dash(summary.last_price))),
+        Line::from(format!("change  {}", or_dash(summary.price_change()))),
+        Line::from(format!(
+            "high    {}  low {}",
+            or_dash(summary.high),
+            or_dash(summary.low)
+        )),
+        Line::from(format!(
+            "volume  {}  ({} trades)",
+            summary.volume, summary.trade_count
+        )),
+        Line::from(format!(
+            "bid {}  ask {}  spread {}",
+            or_dash(view.best_bid()),
+            or_dash(view.best_ask()),
+            or_dash(spread)
+        )),
+    ])
+    .block(Block::bordered().title(" stats "));
+    frame.render_widget(stats, stats_area);
+
+    let trades = view.trades.iter().map(|trade| {
+        let color = match trade.taker_side {
+            BidOrAsk::Bid => Color::Green,
+            BidOrAsk::Ask => Color::Red,
+        };
+        Row::new(vec![trade.price.to_string(), trade.size.to_string()])
+            .style(Style::new().fg(color))
+    });
+    let trades = Table::new(trades, [Constraint::Ratio(1, 2); 2])
+        .header(Row::new(vec!["price", "size"]).bold())
+        .block(Block::bordered().title(" trades "));
+    frame.render_widget(trades, trades_area);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::orderbook::Order;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_market_view_follows_events_and_draws() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_new_market(pair.clone(), MarketConfig::default());
+        engine
+            .place_limit_order(pair.clone(), dec!(101), Order::new(BidOrAsk::Ask, dec!(1)))
+            .unwrap();
+        let mut view = MarketView::new(pair.clone(), engine.depth(pair.clone(), 100).unwrap());
+        let events = engine.subscribe();
+
+        engine
+            .place_limit_order(pair.clone(), dec!(102), Order::new(BidOrAsk::Ask, dec!(2)))
+            .unwrap();
+        engine
+            .place_limit_order(pair.clone(), dec!(99), Order::new(BidOrAsk::Bid, dec!(3)))
+            .unwrap();
+        engine
+            .place_market_order(pair.clone(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .unwrap();
+        for event in events.try_iter() {
+            view.on_event(&event);
+        }
+        assert_eq!(view.best_ask(), Some(dec!(102)));
+        assert_eq!(view.best_bid(), Some(dec!(99)));
+        assert_eq!(view.trades().count(), 1);
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|frame| draw(frame, &view)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("BTC/USD"));
+        assert!(screen.contains("last    101"));
+        assert!(screen.contains("spread 3"));
+    }
+}
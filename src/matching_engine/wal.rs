@@ -0,0 +1,396 @@
+#![allow(dead_code)]
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use super::command::{CommandOutput, EngineCommand, SequencedCommand};
+use super::engine::{EngineSnapshot, MatchingEngine};
+use super::error::EngineError;
+
+/// When appended records are forced to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every record; nothing acknowledged is ever lost.
+    Always,
+    /// After every `n` records; a crash may lose up to the last `n - 1`.
+    EveryN(usize),
+    /// Left to the operating system.
+    Never,
+}
+
+/// Why `DurableEngine::execute` failed: the input could not be logged, in
+/// which case it was not applied, or the engine refused it.
+#[derive(Debug, Error)]
+pub enum WalError {
+    #[error("Failed to write command log: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+}
+
+/// Append-only log of sequenced engine inputs, one JSON record per line.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+    policy: FsyncPolicy,
+    last_sequence: u64,
+    unsynced: usize,
+    // Byte length of the records written so far.
+    len: u64,
+    // Set once a failed write could not be cut off again; see `append`.
+    failed: bool,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at `path` for appending, creating it if needed.
+    /// Returns the log along with the records already in it. A torn final
+    /// record is cut off so new records start on a fresh line.
+    pub fn open(
+        path: impl AsRef<Path>,
+        policy: FsyncPolicy,
+    ) -> io::Result<(WriteAheadLog, Vec<SequencedCommand>)> {
+        let path = path.as_ref().to_path_buf();
+        let (records, valid_len) = if path.exists() {
+            WriteAheadLog::read_records(&path)?
+        } else {
+            (Vec::new(), 0)
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(valid_len)?;
+        let log = WriteAheadLog {
+            path,
+            file,
+            policy,
+            last_sequence: records.last().map_or(0, |record| record.sequence),
+            unsynced: 0,
+            len: valid_len,
+            failed: false,
+        };
+        Ok((log, records))
+    }
+
+    /// Reads every record in the log. A torn final line, left by a crash
+    /// mid-write, is ignored; corruption anywhere else is an error.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<SequencedCommand>> {
+        Ok(WriteAheadLog::read_records(path)?.0)
+    }
+
+    // The records and the byte length of the intact part of the log.
+    fn read_records(path: impl AsRef<Path>) -> io::Result<(Vec<SequencedCommand>, u64)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        let mut line = String::new();
+        for line_number in 1.. {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            // Records are written with their newline in one go, so a line
+            // without one is the torn tail.
+            if !line.ends_with('\n') {
+                break;
+            }
+            let record = serde_json::from_str(&line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupt log record on line {}: {}", line_number, err),
+                )
+            })?;
+            records.push(record);
+            valid_len += line.len() as u64;
+        }
+        Ok((records, valid_len))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Appends `record` and syncs according to the policy. Records must be
+    /// appended in sequence. A write that fails partway is cut off again;
+    /// if even that fails, every later append fails too, so no record is
+    /// ever written after a corrupt one.
+    pub fn append(&mut self, record: &SequencedCommand) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other(
+                "Command log is unusable after a failed write",
+            ));
+        }
+        if record.sequence != self.last_sequence + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Out of sequence log record: expected {}, got {}",
+                    self.last_sequence + 1,
+                    record.sequence
+                ),
+            ));
+        }
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        if let Err(err) = self.file.write_all(line.as_bytes()) {
+            self.failed = self.file.set_len(self.len).is_err();
+            return Err(err);
+        }
+        self.len += line.len() as u64;
+        self.last_sequence = record.sequence;
+
+        self.unsynced += 1;
+        let sync = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryN(n) => self.unsynced >= n,
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// A `MatchingEngine` whose every command is logged before it is applied,
+/// so the state can be rebuilt after a crash by replaying the log.
+pub struct DurableEngine {
+    engine: MatchingEngine,
+    wal: WriteAheadLog,
+}
+
+impl DurableEngine {
+    /// Opens the log at `path` and replays it into a fresh engine.
+    pub fn open(path: impl AsRef<Path>, policy: FsyncPolicy) -> io::Result<DurableEngine> {
+        DurableEngine::recover(MatchingEngine::new(), path, policy)
+    }
+
+    /// Restores the checkpoint at `checkpoint_path`, then replays the log
+    /// records written after it.
+    pub fn open_from_checkpoint(
+        path: impl AsRef<Path>,
+        checkpoint_path: impl AsRef<Path>,
+        policy: FsyncPolicy,
+    ) -> io::Result<DurableEngine> {
+        let snapshot: EngineSnapshot =
+            serde_json::from_reader(BufReader::new(File::open(checkpoint_path)?))?;
+        DurableEngine::open_from_snapshot(path, snapshot, policy)
+    }
+
+    /// Restores `snapshot`, wherever it was kept, then replays the log
+    /// records written after it. The log may also be fresh or end before
+    /// the snapshot; new records then follow on from the snapshot.
+    pub fn open_from_snapshot(
+        path: impl AsRef<Path>,
+        snapshot: EngineSnapshot,
+        policy: FsyncPolicy,
+    ) -> io::Result<DurableEngine> {
+        DurableEngine::recover(MatchingEngine::restore(snapshot), path, policy)
+    }
+
+    // Replays the records `engine` has not seen yet. A log missing some of
+    // them is an error, rather than leaving the engine silently behind. A
+    // log that ends before the engine's state, such as a fresh one, carries
+    // on numbering from the engine.
+    fn recover(
+        mut engine: MatchingEngine,
+        path: impl AsRef<Path>,
+        policy: FsyncPolicy,
+    ) -> io::Result<DurableEngine> {
+        let (mut wal, records) = WriteAheadLog::open(path, policy)?;
+        wal.last_sequence = wal.last_sequence.max(engine.sequence());
+        let after = engine.sequence();
+        for record in records.into_iter().filter(|record| record.sequence > after) {
+            // Commands that failed originally fail again the same way.
+            if let Err(err @ EngineError::OutOfSequence { .. }) = engine.process(record) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        Ok(DurableEngine { engine, wal })
+    }
+
+    /// Writes a snapshot of the current state to `checkpoint_path`,
+    /// replacing the file atomically. The snapshot carries the sequence of
+    /// the last logged input.
+    pub fn checkpoint(&self, checkpoint_path: impl AsRef<Path>) -> io::Result<()> {
+        let checkpoint_path = checkpoint_path.as_ref();
+        let checkpoint = self.engine.snapshot();
+        let temp_path = checkpoint_path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        serde_json::to_writer(&mut file, &checkpoint)?;
+        file.sync_all()?;
+        std::fs::rename(temp_path, checkpoint_path)
+    }
+
+    pub fn engine(&self) -> &MatchingEngine {
+        &self.engine
+    }
+
+    pub fn wal(&self) -> &WriteAheadLog {
+        &self.wal
+    }
+
+    /// Sequences and logs `command`, then applies it. Nothing is applied if
+    /// the log write fails. Commands the engine rejects stay in the log;
+    /// replaying them rejects them again.
+    pub fn execute(&mut self, command: EngineCommand) -> Result<CommandOutput, WalError> {
+        let input = self.engine.stamp(command);
+        self.wal.append(&input)?;
+        Ok(self.engine.process(input)?)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use crate::matching_engine::triggers::OrderType;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::fs;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.wal", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    fn record(sequence: u64, command: EngineCommand) -> SequencedCommand {
+        SequencedCommand {
+            sequence,
+            timestamp: 0,
+            command,
+        }
+    }
+
+    fn place(price: Decimal, bid_or_ask: BidOrAsk) -> EngineCommand {
+        EngineCommand::PlaceOrder {
+            pair: btc_usd(),
+            order_type: OrderType::Limit { price },
+            order: Order::new(bid_or_ask, dec!(1)),
+        }
+    }
+
+    #[test]
+    fn test_durable_engine_replays_log() {
+        let path = temp_log("test_durable_engine_replays_log");
+        {
+            let mut engine = DurableEngine::open(&path, FsyncPolicy::Always).unwrap();
+            engine
+                .execute(EngineCommand::AddMarket {
+                    pair: btc_usd(),
+                    config: MarketConfig::default(),
+                })
+                .unwrap();
+            engine.execute(place(dec!(100), BidOrAsk::Bid)).unwrap();
+            engine.execute(place(dec!(101), BidOrAsk::Ask)).unwrap();
+            assert_eq!(engine.wal().last_sequence(), 3);
+        }
+
+        let mut engine = DurableEngine::open(&path, FsyncPolicy::EveryN(10)).unwrap();
+        let depth = engine.engine().depth(btc_usd(), 5).unwrap();
+        assert_eq!(depth.bids[0].price, dec!(100));
+        assert_eq!(depth.asks[0].price, dec!(101));
+
+        // Numbering carries on from the records already in the log.
+        engine.execute(place(dec!(99), BidOrAsk::Bid)).unwrap();
+        assert_eq!(engine.wal().last_sequence(), 4);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_ignores_torn_final_record() {
+        let path = temp_log("test_wal_ignores_torn_final_record");
+        {
+            let (mut wal, _) = WriteAheadLog::open(&path, FsyncPolicy::Never).unwrap();
+            wal.append(&record(
+                1,
+                EngineCommand::AddMarket {
+                    pair: btc_usd(),
+                    config: MarketConfig::default(),
+                },
+            ))
+            .unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"sequence\":2,\"comm").unwrap();
+
+        let records = WriteAheadLog::read(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].sequence, 1);
+
+        // Reopening cuts the torn record off before appending.
+        let (mut wal, _) = WriteAheadLog::open(&path, FsyncPolicy::Never).unwrap();
+        wal.append(&record(2, EngineCommand::CancelAll { pair: btc_usd() }))
+            .unwrap();
+        assert!(wal
+            .append(&record(4, EngineCommand::CancelAll { pair: btc_usd() }))
+            .is_err());
+        let records = WriteAheadLog::read(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].sequence, 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_durable_engine_recovers_from_checkpoint() {
+        let path = temp_log("test_durable_engine_recovers_from_checkpoint");
+        let checkpoint_path = path.with_extension("checkpoint");
+        {
+            let mut engine = DurableEngine::open(&path, FsyncPolicy::Always).unwrap();
+            engine
+                .execute(EngineCommand::AddMarket {
+                    pair: btc_usd(),
+                    config: MarketConfig::default(),
+                })
+                .unwrap();
+            engine.execute(place(dec!(100), BidOrAsk::Bid)).unwrap();
+            engine.checkpoint(&checkpoint_path).unwrap();
+            engine.execute(place(dec!(101), BidOrAsk::Ask)).unwrap();
+        }
+
+        let engine =
+            DurableEngine::open_from_checkpoint(&path, &checkpoint_path, FsyncPolicy::Always)
+                .unwrap();
+        let depth = engine.engine().depth(btc_usd(), 5).unwrap();
+        // Only the record after the checkpoint was replayed.
+        assert_eq!(depth.bids[0].order_count, 1);
+        assert_eq!(depth.asks.len(), 1);
+
+        // A log that skips inputs after the checkpoint is refused.
+        let mut line = serde_json::to_string(&record(4, place(dec!(102), BidOrAsk::Ask))).unwrap();
+        line.push('\n');
+        fs::write(&path, line).unwrap();
+        let err = DurableEngine::open_from_checkpoint(&path, &checkpoint_path, FsyncPolicy::Always)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A fresh log picks up numbering from the checkpoint.
+        fs::remove_file(&path).unwrap();
+        let mut engine =
+            DurableEngine::open_from_checkpoint(&path, &checkpoint_path, FsyncPolicy::Always)
+                .unwrap();
+        assert_eq!(engine.wal().last_sequence(), 2);
+        engine.execute(place(dec!(101), BidOrAsk::Ask)).unwrap();
+        assert_eq!(WriteAheadLog::read(&path).unwrap()[0].sequence, 3);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&checkpoint_path).unwrap();
+    }
+}
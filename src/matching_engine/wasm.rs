@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+use std::sync::mpsc;
+
+use js_sys::{Function, JSON};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use super::config::MarketConfig;
+use super::engine::{MatchingEngine, TradingPair};
+use super::error::EngineError;
+use super::events::EngineEvent;
+use super::orderbook::{AccountId, BidOrAsk, Order, OrderId};
+
+fn engine_error(error: EngineError) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+fn pair(text: &str) -> Result<TradingPair, JsError> {
+    text.parse().map_err(engine_error)
+}
+
+fn side(text: &str) -> Result<BidOrAsk, JsError> {
+    match text.to_lowercase().as_str() {
+        "buy" | "bid" => Ok(BidOrAsk::Bid),
+        "sell" | "ask" => Ok(BidOrAsk::Ask),
+        _ => Err(JsError::new(&format!("Invalid side: {}", text))),
+    }
+}
+
+fn decimal(text: &str, what: &str) -> Result<Decimal, JsError> {
+    text.parse()
+        .map_err(|_| JsError::new(&format!("Invalid {}: {}", what, text)))
+}
+
+// `value` as the JavaScript object its JSON form parses to.
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    let json = serde_json::to_string(value)?;
+    JSON::parse(&json).map_err(|_| JsError::new("Unparseable JSON"))
+}
+
+/// The engine as the JavaScript class `Engine`, for simulators and teaching
+/// tools running in the browser. Prices and sizes are passed as strings
+/// and come back as strings in reports, depth and events, which have the
+/// shape of the JSON API. Accounts and order ids are plain numbers.
+///
+/// Callbacks registered with `onEvent` are called at the end of the call
+/// that raised the events. The engine is still in use then, so they must
+/// not call back into it.
+#[wasm_bindgen(js_name = Engine)]
+pub struct WasmEngine {
+    engine: MatchingEngine,
+    events: mpsc::Receiver<EngineEvent>,
+    callbacks: Vec<Function>,
+}
+
+impl WasmEngine {
+    fn dispatch(&mut self) -> Result<(), JsError> {
+        let events: Vec<EngineEvent> = self.events.try_iter().collect();
+        for event in &events {
+            let event = to_js(event)?;
+            for callback in &self.callbacks {
+                callback
+                    .call1(&JsValue::NULL, &event)
+                    .map_err(|_| JsError::new("Event callback threw"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish<T: Serialize>(&mut self, result: Result<T, EngineError>) -> Result<JsValue, JsError> {
+        self.dispatch()?;
+        to_js(&result.map_err(engine_error)?)
+    }
+}
+
+#[wasm_bindgen(js_class = Engine)]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmEngine {
+        let mut engine = MatchingEngine::new();
+        let events = engine.subscribe();
+        WasmEngine {
+            engine,
+            events,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Lists `pair`, e.g. `"BTC/USD"`, with the default market config.
+    #[wasm_bindgen(js_name = addMarket)]
+    pub fn add_market(&mut self, pair: &str) -> Result<(), JsError> {
+        self.engine
+            .add_new_market(self::pair(pair)?, MarketConfig::default());
+        self.dispatch()
+    }
+
+    #[wasm_bindgen(js_name = placeLimitOrder)]
+    pub fn place_limit_order(
+        &mut self,
+        pair: &str,
+        side: &str,
+        price: &str,
+        size: &str,
+        account: u32,
+    ) -> Result<JsValue, JsError> {
+        let order = Order::new(self::side(side)?, decimal(size, "size")?)
+            .with_owner(AccountId(account.into()));
+        let report =
+            self.engine
+                .place_limit_order(self::pair(pair)?, decimal(price, "price")?, order);
+        self.finish(report)
+    }
+
+    #[wasm_bindgen(js_name = placeMarketOrder)]
+    pub fn place_market_order(
+        &mut self,
+        pair: &str,
+        side: &str,
+        size: &str,
+        account: u32,
+    ) -> Result<JsValue, JsError> {
+        let order = Order::new(self::side(side)?, decimal(size, "size")?)
+            .with_owner(AccountId(account.into()));
+        let report = self.engine.place_market_order(self::pair(pair)?, order);
+        self.finish(report)
+    }
+
+    /// Cancels a resting order and returns it.
+    pub fn cancel(&mut self, pair: &str, order_id: u32) -> Result<JsValue, JsError> {
+        let order = self
+            .engine
+            .cancel_order(self::pair(pair)?, OrderId(order_id.into()));
+        self.finish(order)
+    }
+
+    pub fn depth(&self, pair: &str, levels: usize) -> Result<JsValue, JsError> {
+        to_js(
+            &self
+                .engine
+                .depth(self::pair(pair)?, levels)
+                .map_err(engine_error)?,
+        )
+    }
+
+    /// Calls `callback` with every engine event from now on.
+    #[wasm_bindgen(js_name = onEvent)]
+    pub fn on_event(&mut self, callback: Function) {
+        self.callbacks.push(callback);
+    }
+}
+
+impl Default for WasmEngine {
+    fn default() -> WasmEngine {
+        WasmEngine::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    // Only the paths that succeed can run natively: building a `JsError`
+    // needs a JavaScript host.
+    #[test]
+    fn test_wasm_parses_arguments() {
+        assert!(matches!(side("Buy"), Ok(BidOrAsk::Bid)));
+        assert!(matches!(side("ask"), Ok(BidOrAsk::Ask)));
+        assert!(matches!(decimal("100.25", "price"), Ok(price) if price == dec!(100.25)));
+        assert!(matches!(
+            pair("BTC/USD"),
+            Ok(pair) if pair == TradingPair::new("BTC".to_string(), "USD".to_string())
+        ));
+    }
+}
@@ -0,0 +1,449 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use super::conflation::{Conflated, ConflationPolicy, Conflator};
+use super::engine::TradingPair;
+use super::error::EngineError;
+use super::events::EngineEvent;
+use super::orderbook::{BookUpdate, DepthSnapshot};
+use super::service::{EngineHandle, MarketDataFeed};
+use super::stats::MarketSummary;
+use super::trade::Trade;
+
+/// Requests a client sends, as JSON text frames:
+/// `{"op": "subscribe", "pair": "BTC-USD"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe { pair: String },
+    Unsubscribe { pair: String },
+}
+
+/// Messages the server sends, as JSON text frames tagged by `type`.
+///
+/// A subscription starts with a `snapshot` of the full book; `book` updates
+/// follow with sequence numbers continuing from the snapshot's, so a gap
+/// means the client missed data and should resubscribe. On a conflated
+/// feed `book_diff` messages take their place.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedMessage {
+    Snapshot {
+        pair: TradingPair,
+        depth: DepthSnapshot,
+        ticker: MarketSummary,
+    },
+    Trade {
+        pair: TradingPair,
+        trade: Trade,
+    },
+    Book {
+        pair: TradingPair,
+        update: BookUpdate,
+    },
+    /// The latest update of every level changed since the previous diff,
+    /// bringing the book up to `sequence`.
+    BookDiff {
+        pair: TradingPair,
+        sequence: u64,
+        updates: Vec<BookUpdate>,
+    },
+    Ticker {
+        pair: TradingPair,
+        ticker: MarketSummary,
+    },
+    Unsubscribed {
+        pair: TradingPair,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// How a conflated feed throttles each connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conflation {
+    /// How often book changes and tickers are sent, e.g. 100ms.
+    pub interval: Duration,
+    pub policy: ConflationPolicy,
+}
+
+#[derive(Clone)]
+struct FeedState {
+    handle: EngineHandle,
+    feed: MarketDataFeed,
+    conflation: Option<Conflation>,
+}
+
+/// Serves the feed on `GET /ws`.
+pub fn router(handle: EngineHandle, feed: MarketDataFeed) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(FeedState {
+            handle,
+            feed,
+            conflation: None,
+        })
+}
+
+/// Like `router`, but every connection is sent its book changes and
+/// tickers at most once per `conflation.interval`, so slow clients get
+/// fewer, coalesced messages instead of falling behind. Trades are still
+/// sent as they happen.
+pub fn conflated_router(
+    handle: EngineHandle,
+    feed: MarketDataFeed,
+    conflation: Conflation,
+) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(FeedState {
+            handle,
+            feed,
+            conflation: Some(conflation),
+        })
+}
+
+async fn upgrade(State(state): State<FeedState>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| run_connection(socket, state))
+}
+
+// What has been sent for a subscription.
+enum Subscription {
+    // The book sequence its snapshot was taken at, so updates it already
+    // reflects are skipped.
+    Live(u64),
+    // Changes held back until the next flush, and whether it traded since.
+    Conflated { book: Conflator, traded: bool },
+}
+
+struct Connection {
+    state: FeedState,
+    subscriptions: HashMap<TradingPair, Subscription>,
+}
+
+// Waits for the next conflation flush; forever on a live feed.
+async fn next_flush(flush: &mut Option<Interval>) {
+    match flush {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn run_connection(mut socket: WebSocket, state: FeedState) {
+    // Subscribe before any snapshot is taken so no update can fall between.
+    let mut events = state.feed.subscribe();
+    let mut flush = state.conflation.map(|conflation| {
+        let mut interval = tokio::time::interval(conflation.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    });
+    let mut connection = Connection {
+        state,
+        subscriptions: HashMap::new(),
+    };
+    loop {
+        let messages = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => connection.on_client_message(&text).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) => connection.on_event(event).await,
+                Err(broadcast::error::RecvError::Lagged(_)) => connection.resync().await,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = next_flush(&mut flush) => connection.flush().await,
+        };
+        for message in messages {
+            let text = serde_json::to_string(&message).expect("feed messages serialize");
+            if socket.send(Message::Text(text.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Connection {
+    async fn on_client_message(&mut self, text: &str) -> Vec<FeedMessage> {
+        let request = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(request) => request,
+            Err(err) => {
+                return vec![FeedMessage::Error {
+                    message: format!("Invalid request: {}", err),
+                }]
+            }
+        };
+        let result = match request {
+            ClientMessage::Subscribe { pair } => match pair.parse::<TradingPair>() {
+                Ok(pair) => self.subscribe(pair).await,
+                Err(err) => Err(err),
+            },
+            ClientMessage::Unsubscribe { pair } => pair.parse::<TradingPair>().map(|pair| {
+                self.subscriptions.remove(&pair);
+                FeedMessage::Unsubscribed { pair }
+            }),
+        };
+        vec![result.unwrap_or_else(|err| FeedMessage::Error {
+            message: err.to_string(),
+        })]
+    }
+
+    async fn subscribe(&mut self, pair: TradingPair) -> Result<FeedMessage, EngineError> {
+        let handle = &self.state.handle;
+        let depth = handle.depth(pair.clone(), usize::MAX).await?;
+        let ticker = handle.stats(pair.clone()).await?;
+        let subscription = match self.state.conflation {
+            Some(conflation) => Subscription::Conflated {
+                book: Conflator::new(conflation.policy, depth.sequence),
+                traded: false,
+            },
+            None => Subscription::Live(depth.sequence),
+        };
+        self.subscriptions.insert(pair.clone(), subscription);
+        Ok(FeedMessage::Snapshot {
+            pair,
+            depth,
+            ticker,
+        })
+    }
+
+    async fn on_event(&mut self, event: EngineEvent) -> Vec<FeedMessage> {
+        match event {
+            EngineEvent::TradeExecuted { pair, trade }
+                if self.subscriptions.contains_key(&pair) =>
+            {
+                let mut messages = vec![FeedMessage::Trade {
+                    pair: pair.clone(),
+                    trade,
+                }];
+                if let Some(Subscription::Conflated { traded, .. }) =
+                    self.subscriptions.get_mut(&pair)
+                {
+                    *traded = true;
+                } else if let Ok(ticker) = self.state.handle.stats(pair.clone()).await {
+                    messages.push(FeedMessage::Ticker { pair, ticker });
+                }
+                messages
+            }
+            EngineEvent::BookUpdated { pair, update } => match self.subscriptions.get_mut(&pair) {
+                Some(Subscription::Live(snapshot_sequence))
+                    if update.sequence() > *snapshot_sequence =>
+                {
+                    vec![FeedMessage::Book { pair, update }]
+                }
+                Some(Subscription::Conflated { book, .. }) => {
+                    book.push(update);
+                    Vec::new()
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    // Sends what conflated subscriptions held back since the last flush. A
+    // subscription whose changes were dropped starts over from a snapshot.
+    async fn flush(&mut self) -> Vec<FeedMessage> {
+        let mut messages = Vec::new();
+        let mut resync = Vec::new();
+        let mut traded = Vec::new();
+        for (pair, subscription) in &mut self.subscriptions {
+            let Subscription::Conflated {
+                book,
+                traded: ticker_due,
+            } = subscription
+            else {
+                continue;
+            };
+            match book.flush() {
+                Some(Conflated::Diff { sequence, updates }) => {
+                    messages.push(FeedMessage::BookDiff {
+                        pair: pair.clone(),
+                        sequence,
+                        updates,
+                    })
+                }
+                Some(Conflated::Resync) => resync.push(pair.clone()),
+                None => {}
+            }
+            if std::mem::take(ticker_due) {
+                traded.push(pair.clone());
+            }
+        }
+        for pair in traded {
+            if let Ok(ticker) = self.state.handle.stats(pair.clone()).await {
+                messages.push(FeedMessage::Ticker { pair, ticker });
+            }
+        }
+        for pair in resync {
+            match self.subscribe(pair).await {
+                Ok(message) => messages.push(message),
+                Err(err) => messages.push(FeedMessage::Error {
+                    message: err.to_string(),
+                }),
+            }
+        }
+        messages
+    }
+
+    // After falling behind the broadcast, updates were lost; start every
+    // subscription over from a fresh snapshot.
+    async fn resync(&mut self) -> Vec<FeedMessage> {
+        let pairs: Vec<TradingPair> = self.subscriptions.keys().cloned().collect();
+        let mut messages = Vec::new();
+        for pair in pairs {
+            match self.subscribe(pair).await {
+                Ok(message) => messages.push(message),
+                Err(err) => messages.push(FeedMessage::Error {
+                    message: err.to_string(),
+                }),
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::batch::BatchOp;
+    use crate::matching_engine::command::EngineCommand;
+    use crate::matching_engine::config::MarketConfig;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::orderbook::{BidOrAsk, Order};
+    use crate::matching_engine::service::EngineService;
+    use crate::matching_engine::triggers::OrderType;
+    use futures_util::{SinkExt, StreamExt};
+    use rust_decimal_macros::dec;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    async fn receive(client: &mut Client, count: usize) -> Vec<FeedMessage> {
+        let mut received = Vec::new();
+        while received.len() < count {
+            if let tungstenite::Message::Text(text) = client.next().await.unwrap().unwrap() {
+                received.push(serde_json::from_str(&text).unwrap());
+            }
+        }
+        received
+    }
+
+    // Serves `app` on a free port and subscribes a client to BTC-USD.
+    async fn subscribe(app: Router) -> Client {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+        let subscribe = serde_json::to_string(&ClientMessage::Subscribe {
+            pair: "BTC-USD".to_string(),
+        })
+        .unwrap();
+        client
+            .send(tungstenite::Message::Text(subscribe.into()))
+            .await
+            .unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_websocket_feed_streams_subscribed_market() {
+        let mut engine = MatchingEngine::new();
+        let feed = MarketDataFeed::attach(&mut engine);
+        let handle = EngineService::spawn(engine);
+        handle
+            .add_market(btc_usd(), MarketConfig::default())
+            .await
+            .unwrap();
+        handle
+            .place_limit_order(btc_usd(), dec!(100), Order::new(BidOrAsk::Ask, dec!(3)))
+            .await
+            .unwrap();
+
+        let mut client = subscribe(router(handle.clone(), feed)).await;
+        let snapshot_sequence = match &receive(&mut client, 1).await[0] {
+            FeedMessage::Snapshot { depth, .. } => {
+                assert_eq!(depth.asks[0].size, dec!(3));
+                depth.sequence
+            }
+            other => panic!("Expected a snapshot, got {:?}", other),
+        };
+
+        handle
+            .place_market_order(btc_usd(), Order::new(BidOrAsk::Bid, dec!(1)))
+            .await
+            .unwrap();
+        let messages = receive(&mut client, 3).await;
+        assert!(matches!(&messages[0], FeedMessage::Trade { trade, .. } if trade.size == dec!(1)));
+        assert!(matches!(&messages[1], FeedMessage::Ticker { ticker, .. }
+            if ticker.last_price == Some(dec!(100))));
+        match &messages[2] {
+            FeedMessage::Book { update, .. } => {
+                assert_eq!(update.sequence(), snapshot_sequence + 1);
+            }
+            other => panic!("Expected a book update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conflated_feed_coalesces_book_updates() {
+        let mut engine = MatchingEngine::new();
+        let feed = MarketDataFeed::attach(&mut engine);
+        let handle = EngineService::spawn(engine);
+        handle
+            .add_market(btc_usd(), MarketConfig::default())
+            .await
+            .unwrap();
+        let conflation = Conflation {
+            interval: Duration::from_millis(50),
+            policy: ConflationPolicy::Merge,
+        };
+        let mut client = subscribe(conflated_router(handle.clone(), feed, conflation)).await;
+        assert!(matches!(
+            &receive(&mut client, 1).await[0],
+            FeedMessage::Snapshot { .. }
+        ));
+
+        // One batch, so all three updates land well inside one interval.
+        let quote = BatchOp::Place {
+            order_type: OrderType::Limit { price: dec!(100) },
+            order: Order::new(BidOrAsk::Ask, dec!(1)),
+        };
+        let ops = vec![quote.clone(), quote.clone(), quote];
+        handle
+            .execute(EngineCommand::Batch {
+                pair: btc_usd(),
+                ops,
+            })
+            .await
+            .unwrap();
+        match &receive(&mut client, 1).await[0] {
+            FeedMessage::BookDiff { updates, .. } => {
+                assert!(matches!(updates[..], [BookUpdate::Add { size, .. }] if size == dec!(3)));
+            }
+            other => panic!("Expected a book diff, got {:?}", other),
+        }
+    }
+}